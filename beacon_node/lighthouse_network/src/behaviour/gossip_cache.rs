@@ -9,15 +9,29 @@ use crate::GossipTopic;
 
 use tokio_util::time::delay_queue::{DelayQueue, Key};
 
+/// The delay before the first timed retry of a cached message. Each subsequent retry waits twice
+/// as long as the previous one.
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
 /// Store of gossip messages that we failed to publish and will try again later. By default, all
 /// messages are ignored. This behaviour can be changed using `GossipCacheBuilder::default_timeout`
 /// to apply the same delay to every kind. Individual timeouts for specific kinds can be set and
 /// will overwrite the default_timeout if present.
+///
+/// Cached messages are retried when a peer subscribes to their topic. Additionally, up to
+/// `max_retries` timed retries with exponential backoff can be enabled using
+/// `GossipCacheBuilder::max_retries`.
 pub struct GossipCache {
     /// Expire timeouts for each topic-msg pair.
     expirations: DelayQueue<(GossipTopic, Vec<u8>)>,
+    /// Timed retries for each topic-msg pair.
+    retries: DelayQueue<(GossipTopic, Vec<u8>)>,
     /// Messages cached for each topic.
-    topic_msgs: HashMap<GossipTopic, HashMap<Vec<u8>, Key>>,
+    topic_msgs: HashMap<GossipTopic, HashMap<Vec<u8>, CachedMessage>>,
+    /// The maximum number of timed retries of a message.
+    max_retries: u32,
+    /// The delay before the first timed retry of a message.
+    retry_backoff: Duration,
     /// Timeout for blocks.
     beacon_block: Option<Duration>,
     /// Timeout for aggregate attestations.
@@ -36,9 +50,32 @@ pub struct GossipCache {
     sync_committee_message: Option<Duration>,
 }
 
-#[derive(Default)]
+/// The state of a cached message.
+struct CachedMessage {
+    /// Key of the message's expiration.
+    expiration: Key,
+    /// Key of the message's next timed retry, if any.
+    retry: Option<Key>,
+    /// The number of timed retries done so far.
+    retries: u32,
+}
+
+/// Events produced by the `GossipCache`.
+#[derive(Debug, PartialEq)]
+pub enum GossipCacheEvent {
+    /// A message expired before it could be published.
+    Expired(GossipTopic),
+    /// A message is due to be published again. The message remains in the cache until it is
+    /// removed or expires.
+    Retry(GossipTopic, Vec<u8>),
+}
+
 pub struct GossipCacheBuilder {
     default_timeout: Option<Duration>,
+    /// The maximum number of timed retries of a message.
+    max_retries: u32,
+    /// The delay before the first timed retry of a message.
+    retry_backoff: Duration,
     /// Timeout for blocks.
     beacon_block: Option<Duration>,
     /// Timeout for aggregate attestations.
@@ -57,6 +94,24 @@ pub struct GossipCacheBuilder {
     sync_committee_message: Option<Duration>,
 }
 
+impl Default for GossipCacheBuilder {
+    fn default() -> Self {
+        GossipCacheBuilder {
+            default_timeout: None,
+            max_retries: 0,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+            beacon_block: None,
+            aggregates: None,
+            attestation: None,
+            voluntary_exit: None,
+            proposer_slashing: None,
+            attester_slashing: None,
+            signed_contribution_and_proof: None,
+            sync_committee_message: None,
+        }
+    }
+}
+
 #[allow(dead_code)]
 impl GossipCacheBuilder {
     /// By default, all timeouts all disabled. Setting a default timeout will enable all timeout
@@ -65,6 +120,20 @@ impl GossipCacheBuilder {
         self.default_timeout = Some(timeout);
         self
     }
+    /// The number of times a message is retried before waiting for a peer to subscribe to its
+    /// topic. By default, there are no timed retries.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// The delay before the first timed retry of a message. Each subsequent retry waits twice as
+    /// long as the previous one.
+    pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry_backoff = backoff;
+        self
+    }
+
     /// Timeout for blocks.
     pub fn beacon_block_timeout(mut self, timeout: Duration) -> Self {
         self.beacon_block = Some(timeout);
@@ -116,6 +185,8 @@ impl GossipCacheBuilder {
     pub fn build(self) -> GossipCache {
         let GossipCacheBuilder {
             default_timeout,
+            max_retries,
+            retry_backoff,
             beacon_block,
             aggregates,
             attestation,
@@ -127,7 +198,10 @@ impl GossipCacheBuilder {
         } = self;
         GossipCache {
             expirations: DelayQueue::default(),
+            retries: DelayQueue::default(),
             topic_msgs: HashMap::default(),
+            max_retries,
+            retry_backoff,
             beacon_block: beacon_block.or(default_timeout),
             aggregates: aggregates.or(default_timeout),
             attestation: attestation.or(default_timeout),
@@ -158,6 +232,8 @@ impl GossipCache {
             GossipKind::AttesterSlashing => self.attester_slashing,
             GossipKind::SignedContributionAndProof => self.signed_contribution_and_proof,
             GossipKind::SyncCommitteeMessage(_) => self.sync_committee_message,
+            // application messages are not cached
+            GossipKind::Application(_) => None,
         };
         let expire_timeout = match expire_timeout {
             Some(expire_timeout) => expire_timeout,
@@ -169,10 +245,20 @@ impl GossipCache {
             .or_default()
             .entry(data.clone())
         {
-            Entry::Occupied(key) => self.expirations.reset(key.get(), expire_timeout),
+            Entry::Occupied(entry) => self
+                .expirations
+                .reset(&entry.get().expiration, expire_timeout),
             Entry::Vacant(entry) => {
-                let key = self.expirations.insert((topic, data), expire_timeout);
-                entry.insert(key);
+                let expiration = self
+                    .expirations
+                    .insert((topic.clone(), data.clone()), expire_timeout);
+                let retry = (self.max_retries > 0)
+                    .then(|| self.retries.insert((topic, data), self.retry_backoff));
+                entry.insert(CachedMessage {
+                    expiration,
+                    retry,
+                    retries: 0,
+                });
             }
         }
     }
@@ -180,39 +266,98 @@ impl GossipCache {
     // Get the registered messages for this topic.
     pub fn retrieve(&mut self, topic: &GossipTopic) -> Option<impl Iterator<Item = Vec<u8>> + '_> {
         if let Some(msgs) = self.topic_msgs.remove(topic) {
-            for (_, key) in msgs.iter() {
-                self.expirations.remove(key);
+            for (_, msg) in msgs.iter() {
+                self.remove_delays(msg);
             }
             Some(msgs.into_keys())
         } else {
             None
         }
     }
+
+    // Remove a message that no longer needs to be published.
+    pub fn remove(&mut self, topic: &GossipTopic, data: &[u8]) {
+        if let Some(msgs) = self.topic_msgs.get_mut(topic) {
+            if let Some(msg) = msgs.remove(data) {
+                if msgs.is_empty() {
+                    self.topic_msgs.remove(topic);
+                }
+                self.remove_delays(&msg);
+            }
+        }
+    }
+
+    fn remove_delays(&mut self, msg: &CachedMessage) {
+        self.expirations.remove(&msg.expiration);
+        if let Some(retry) = &msg.retry {
+            self.retries.remove(retry);
+        }
+    }
 }
 
 impl futures::stream::Stream for GossipCache {
-    type Item = Result<GossipTopic, String>; // We don't care to retrieve the expired data.
+    type Item = Result<GossipCacheEvent, String>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.retries.poll_expired(cx) {
+            Poll::Ready(Some(Ok(expired))) => {
+                let (topic, data) = expired.into_inner();
+                let (max_retries, retry_backoff) = (self.max_retries, self.retry_backoff);
+                let GossipCache {
+                    retries,
+                    topic_msgs,
+                    ..
+                } = &mut *self;
+                match topic_msgs
+                    .get_mut(&topic)
+                    .and_then(|msgs| msgs.get_mut(&data))
+                {
+                    Some(msg) => {
+                        msg.retries += 1;
+                        msg.retry = (msg.retries < max_retries).then(|| {
+                            retries.insert(
+                                (topic.clone(), data.clone()),
+                                retry_backoff.saturating_mul(2u32.saturating_pow(msg.retries)),
+                            )
+                        });
+                    }
+                    None => {
+                        #[cfg(debug_assertions)]
+                        panic!("Retried message is not present.")
+                    }
+                }
+                return Poll::Ready(Some(Ok(GossipCacheEvent::Retry(topic, data))));
+            }
+            Poll::Ready(Some(Err(x))) => return Poll::Ready(Some(Err(x.to_string()))),
+            // the cache is polled for expirations regardless of pending retries
+            Poll::Ready(None) | Poll::Pending => {}
+        }
+
         match self.expirations.poll_expired(cx) {
             Poll::Ready(Some(Ok(expired))) => {
                 let expected_key = expired.key();
                 let (topic, data) = expired.into_inner();
                 match self.topic_msgs.get_mut(&topic) {
                     Some(msgs) => {
-                        let key = msgs.remove(&data);
-                        debug_assert_eq!(key, Some(expected_key));
+                        let msg = msgs.remove(&data);
+                        debug_assert_eq!(
+                            msg.as_ref().map(|msg| msg.expiration.clone()),
+                            Some(expected_key)
+                        );
                         if msgs.is_empty() {
                             // no more messages for this topic.
                             self.topic_msgs.remove(&topic);
                         }
+                        if let Some(retry) = msg.and_then(|msg| msg.retry) {
+                            self.retries.remove(&retry);
+                        }
                     }
                     None => {
                         #[cfg(debug_assertions)]
                         panic!("Topic for registered message is not present.")
                     }
                 }
-                Poll::Ready(Some(Ok(topic)))
+                Poll::Ready(Some(Ok(GossipCacheEvent::Expired(topic))))
             }
             Poll::Ready(Some(Err(x))) => Poll::Ready(Some(Err(x.to_string()))),
             Poll::Ready(None) => Poll::Ready(None),
@@ -244,4 +389,60 @@ mod tests {
         assert!(cache.expirations.is_empty());
         assert!(cache.topic_msgs.is_empty());
     }
+
+    fn test_topic() -> GossipTopic {
+        GossipTopic::new(
+            GossipKind::BeaconBlock,
+            crate::types::GossipEncoding::SSZSnappy,
+            [0u8; 4],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_timed_retries() {
+        let mut cache = GossipCache::builder()
+            .default_timeout(Duration::from_secs(10))
+            .max_retries(2)
+            .retry_backoff(Duration::from_millis(50))
+            .build();
+        let topic = test_topic();
+        cache.insert(topic.clone(), vec![1]);
+
+        // The message is retried, with backoff, up to `max_retries` times.
+        for _ in 0..2 {
+            let event = tokio::time::timeout(Duration::from_secs(1), cache.next())
+                .await
+                .expect("Message should be retried");
+            assert_eq!(
+                event,
+                Some(Ok(GossipCacheEvent::Retry(topic.clone(), vec![1])))
+            );
+        }
+        assert!(cache.retries.is_empty());
+
+        // The message is still cached for when a peer subscribes to its topic.
+        let msgs: Vec<_> = cache.retrieve(&topic).unwrap().collect();
+        assert_eq!(msgs, vec![vec![1]]);
+        assert!(cache.expirations.is_empty());
+        assert!(cache.topic_msgs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_removed_messages_are_not_retried() {
+        let mut cache = GossipCache::builder()
+            .default_timeout(Duration::from_secs(10))
+            .max_retries(2)
+            .build();
+        let topic = test_topic();
+        cache.insert(topic.clone(), vec![1]);
+        cache.insert(topic.clone(), vec![2]);
+
+        cache.remove(&topic, &[1]);
+        let msgs: Vec<_> = cache.retrieve(&topic).unwrap().collect();
+        assert_eq!(msgs, vec![vec![2]]);
+
+        assert!(cache.retries.is_empty());
+        assert!(cache.expirations.is_empty());
+        assert!(cache.topic_msgs.is_empty());
+    }
 }
@@ -3,6 +3,7 @@ use crate::{error, TopicHash};
 use libp2p::gossipsub::{
     GossipsubConfig, IdentTopic as Topic, PeerScoreParams, PeerScoreThresholds, TopicScoreParams,
 };
+use serde_derive::{Deserialize, Serialize};
 use std::cmp::max;
 use std::collections::HashMap;
 use std::marker::PhantomData;
@@ -23,6 +24,16 @@ const MESH_MESSAGE_DELIVERIES_WINDOW: u64 = 2;
 // Const as this is used in the peer manager to prevent gossip from disconnecting peers.
 pub const GREYLIST_THRESHOLD: f64 = -16000.0;
 
+/// The kinds of topics that are scored, whose weights can be overridden.
+const SCORED_TOPIC_KINDS: [&str; 6] = [
+    "beacon_block",
+    "beacon_aggregate_and_proof",
+    "beacon_attestation",
+    "voluntary_exit",
+    "proposer_slashing",
+    "attester_slashing",
+];
+
 /// Builds the peer score thresholds.
 pub fn lighthouse_gossip_thresholds() -> PeerScoreThresholds {
     PeerScoreThresholds {
@@ -34,6 +45,76 @@ pub fn lighthouse_gossip_thresholds() -> PeerScoreThresholds {
     }
 }
 
+/// Overrides of the gossipsub peer scoring parameters. The fields that are set replace the
+/// values computed by `PeerScoreSettings` and `lighthouse_gossip_thresholds`. The graylist
+/// threshold can't be overridden: the peer manager weighs gossipsub scores against the fixed
+/// `GREYLIST_THRESHOLD`, so that they never disconnect a peer by themselves.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GossipScoreParamsOverride {
+    /* Thresholds, see `PeerScoreThresholds` */
+    pub gossip_threshold: Option<f64>,
+    pub publish_threshold: Option<f64>,
+    pub accept_px_threshold: Option<f64>,
+    pub opportunistic_graft_threshold: Option<f64>,
+
+    /* Decay and topic weights */
+    /// The interval at which the scores decay.
+    pub decay_interval: Option<Duration>,
+    /// The value below which a decaying score is set to zero.
+    pub decay_to_zero: Option<f64>,
+    /// The weights of the scored topics, by topic kind name, e.g. `beacon_block`. The weight of
+    /// `beacon_attestation` applies to each attestation subnet.
+    pub topic_weights: HashMap<String, f64>,
+}
+
+impl GossipScoreParamsOverride {
+    /// Returns the peer score thresholds with the overrides applied, or an error if they are
+    /// invalid.
+    pub fn thresholds(&self) -> Result<PeerScoreThresholds, String> {
+        let defaults = lighthouse_gossip_thresholds();
+        let thresholds = PeerScoreThresholds {
+            gossip_threshold: self.gossip_threshold.unwrap_or(defaults.gossip_threshold),
+            publish_threshold: self.publish_threshold.unwrap_or(defaults.publish_threshold),
+            graylist_threshold: defaults.graylist_threshold,
+            accept_px_threshold: self
+                .accept_px_threshold
+                .unwrap_or(defaults.accept_px_threshold),
+            opportunistic_graft_threshold: self
+                .opportunistic_graft_threshold
+                .unwrap_or(defaults.opportunistic_graft_threshold),
+        };
+        thresholds
+            .validate()
+            .map_err(|e| format!("Invalid gossipsub score thresholds: {}", e))?;
+        Ok(thresholds)
+    }
+}
+
+/// The range of gossipsub scores a peer is in, delimited by the `PeerScoreThresholds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GossipScoreBand {
+    /// The score is above the publish threshold.
+    Healthy,
+    /// The score is below the publish threshold; we don't publish our messages to the peer.
+    NoPublish,
+    /// The score is below the graylist threshold; all of the peer's messages are ignored.
+    Graylisted,
+}
+
+impl GossipScoreBand {
+    /// Returns the band of `score`.
+    pub fn from_score(score: f64, thresholds: &PeerScoreThresholds) -> Self {
+        if score < thresholds.graylist_threshold {
+            GossipScoreBand::Graylisted
+        } else if score < thresholds.publish_threshold {
+            GossipScoreBand::NoPublish
+        } else {
+            GossipScoreBand::Healthy
+        }
+    }
+}
+
 pub struct PeerScoreSettings<TSpec: EthSpec> {
     slot: Duration,
     epoch: Duration,
@@ -45,10 +126,12 @@ pub struct PeerScoreSettings<TSpec: EthSpec> {
     decay_to_zero: f64,
 
     mesh_n: usize,
+    topic_weights: HashMap<String, f64>,
     max_committees_per_slot: usize,
     target_committee_size: usize,
     target_aggregators_per_committee: usize,
     attestation_subnet_count: u64,
+    thresholds: PeerScoreThresholds,
     phantom: PhantomData<TSpec>,
 }
 
@@ -56,28 +139,92 @@ impl<TSpec: EthSpec> PeerScoreSettings<TSpec> {
     pub fn new(chain_spec: &ChainSpec, gs_config: &GossipsubConfig) -> PeerScoreSettings<TSpec> {
         let slot = Duration::from_secs(chain_spec.seconds_per_slot);
         let beacon_attestation_subnet_weight = 1.0 / chain_spec.attestation_subnet_count as f64;
-        let max_positive_score = (MAX_IN_MESH_SCORE + MAX_FIRST_MESSAGE_DELIVERIES_SCORE)
-            * (BEACON_BLOCK_WEIGHT
-                + BEACON_AGGREGATE_PROOF_WEIGHT
-                + beacon_attestation_subnet_weight * chain_spec.attestation_subnet_count as f64
-                + VOLUNTARY_EXIT_WEIGHT
-                + PROPOSER_SLASHING_WEIGHT
-                + ATTESTER_SLASHING_WEIGHT);
-
-        PeerScoreSettings {
+
+        let mut settings = PeerScoreSettings {
             slot,
             epoch: slot * TSpec::slots_per_epoch() as u32,
             beacon_attestation_subnet_weight,
-            max_positive_score,
+            max_positive_score: 0.0,
             decay_interval: max(Duration::from_secs(1), slot),
             decay_to_zero: 0.01,
             mesh_n: gs_config.mesh_n(),
+            topic_weights: HashMap::new(),
             max_committees_per_slot: chain_spec.max_committees_per_slot,
             target_committee_size: chain_spec.target_committee_size,
             target_aggregators_per_committee: chain_spec.target_aggregators_per_committee as usize,
             attestation_subnet_count: chain_spec.attestation_subnet_count,
+            thresholds: lighthouse_gossip_thresholds(),
             phantom: PhantomData,
+        };
+        settings.max_positive_score = settings.compute_max_positive_score();
+        settings
+    }
+
+    /// Applies the decay and topic weight overrides. Returns an error if a topic kind is not
+    /// scored or a value is out of range.
+    pub fn apply_override(
+        &mut self,
+        score_override: &GossipScoreParamsOverride,
+    ) -> Result<(), String> {
+        self.thresholds = score_override.thresholds()?;
+        if let Some(decay_interval) = score_override.decay_interval {
+            if decay_interval < Duration::from_secs(1) {
+                return Err("Invalid gossipsub score decay interval, must be at least 1s".into());
+            }
+            self.decay_interval = decay_interval;
+        }
+        if let Some(decay_to_zero) = score_override.decay_to_zero {
+            if decay_to_zero <= 0.0 || decay_to_zero >= 1.0 {
+                return Err(format!(
+                    "Invalid gossipsub score decay to zero ({}), must be between 0 and 1",
+                    decay_to_zero
+                ));
+            }
+            self.decay_to_zero = decay_to_zero;
         }
+        for (kind, weight) in &score_override.topic_weights {
+            if !SCORED_TOPIC_KINDS.contains(&kind.as_str()) {
+                return Err(format!("Unknown scored gossipsub topic kind: {}", kind));
+            }
+            if *weight <= 0.0 {
+                return Err(format!(
+                    "Invalid gossipsub topic weight for {} ({}), must be positive",
+                    kind, weight
+                ));
+            }
+        }
+        self.topic_weights = score_override.topic_weights.clone();
+        self.max_positive_score = self.compute_max_positive_score();
+        Ok(())
+    }
+
+    /// The peer score thresholds, with any override applied.
+    pub fn thresholds(&self) -> &PeerScoreThresholds {
+        &self.thresholds
+    }
+
+    /// The weight of the topics of the given kind.
+    fn topic_weight(&self, kind: &GossipKind, default: f64) -> f64 {
+        self.topic_weights
+            .get(kind.as_ref())
+            .copied()
+            .unwrap_or(default)
+    }
+
+    fn compute_max_positive_score(&self) -> f64 {
+        (MAX_IN_MESH_SCORE + MAX_FIRST_MESSAGE_DELIVERIES_SCORE)
+            * (self.topic_weight(&GossipKind::BeaconBlock, BEACON_BLOCK_WEIGHT)
+                + self.topic_weight(
+                    &GossipKind::BeaconAggregateAndProof,
+                    BEACON_AGGREGATE_PROOF_WEIGHT,
+                )
+                + self.topic_weight(
+                    &GossipKind::Attestation(SubnetId::new(0)),
+                    self.beacon_attestation_subnet_weight,
+                ) * self.attestation_subnet_count as f64
+                + self.topic_weight(&GossipKind::VoluntaryExit, VOLUNTARY_EXIT_WEIGHT)
+                + self.topic_weight(&GossipKind::ProposerSlashing, PROPOSER_SLASHING_WEIGHT)
+                + self.topic_weight(&GossipKind::AttesterSlashing, ATTESTER_SLASHING_WEIGHT))
     }
 
     pub fn get_peer_score_params(
@@ -120,7 +267,7 @@ impl<TSpec: EthSpec> PeerScoreSettings<TSpec> {
             get_hash(GossipKind::VoluntaryExit),
             Self::get_topic_params(
                 self,
-                VOLUNTARY_EXIT_WEIGHT,
+                self.topic_weight(&GossipKind::VoluntaryExit, VOLUNTARY_EXIT_WEIGHT),
                 4.0 / TSpec::slots_per_epoch() as f64,
                 self.epoch * 100,
                 None,
@@ -130,7 +277,7 @@ impl<TSpec: EthSpec> PeerScoreSettings<TSpec> {
             get_hash(GossipKind::AttesterSlashing),
             Self::get_topic_params(
                 self,
-                ATTESTER_SLASHING_WEIGHT,
+                self.topic_weight(&GossipKind::AttesterSlashing, ATTESTER_SLASHING_WEIGHT),
                 1.0 / 5.0 / TSpec::slots_per_epoch() as f64,
                 self.epoch * 100,
                 None,
@@ -140,7 +287,7 @@ impl<TSpec: EthSpec> PeerScoreSettings<TSpec> {
             get_hash(GossipKind::ProposerSlashing),
             Self::get_topic_params(
                 self,
-                PROPOSER_SLASHING_WEIGHT,
+                self.topic_weight(&GossipKind::ProposerSlashing, PROPOSER_SLASHING_WEIGHT),
                 1.0 / 5.0 / TSpec::slots_per_epoch() as f64,
                 self.epoch * 100,
                 None,
@@ -182,7 +329,7 @@ impl<TSpec: EthSpec> PeerScoreSettings<TSpec> {
 
         let beacon_block_params = Self::get_topic_params(
             self,
-            BEACON_BLOCK_WEIGHT,
+            self.topic_weight(&GossipKind::BeaconBlock, BEACON_BLOCK_WEIGHT),
             1.0,
             self.epoch * 20,
             Some((TSpec::slots_per_epoch() * 5, 3.0, self.epoch, current_slot)),
@@ -190,14 +337,20 @@ impl<TSpec: EthSpec> PeerScoreSettings<TSpec> {
 
         let beacon_aggregate_proof_params = Self::get_topic_params(
             self,
-            BEACON_AGGREGATE_PROOF_WEIGHT,
+            self.topic_weight(
+                &GossipKind::BeaconAggregateAndProof,
+                BEACON_AGGREGATE_PROOF_WEIGHT,
+            ),
             aggregators_per_slot,
             self.epoch,
             Some((TSpec::slots_per_epoch() * 2, 4.0, self.epoch, current_slot)),
         );
         let beacon_attestation_subnet_params = Self::get_topic_params(
             self,
-            self.beacon_attestation_subnet_weight,
+            self.topic_weight(
+                &GossipKind::Attestation(SubnetId::new(0)),
+                self.beacon_attestation_subnet_weight,
+            ),
             active_validators as f64
                 / self.attestation_subnet_count as f64
                 / TSpec::slots_per_epoch() as f64,
@@ -357,3 +510,97 @@ impl<TSpec: EthSpec> PeerScoreSettings<TSpec> {
         t_params
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::MainnetEthSpec;
+
+    type E = MainnetEthSpec;
+
+    fn build_params(
+        score_override: &GossipScoreParamsOverride,
+    ) -> Result<(PeerScoreThresholds, PeerScoreParams), String> {
+        let mut settings =
+            PeerScoreSettings::<E>::new(&ChainSpec::mainnet(), &GossipsubConfig::default());
+        settings.apply_override(score_override)?;
+        let thresholds = settings.thresholds().clone();
+        let params = settings
+            .get_peer_score_params(
+                E::minimum_validator_count(),
+                &thresholds,
+                &EnrForkId::default(),
+                Slot::new(0),
+            )
+            .map_err(|e| format!("{:?}", e))?;
+        Ok((thresholds, params))
+    }
+
+    #[test]
+    fn test_score_params_override() {
+        let (default_thresholds, default_params) =
+            build_params(&GossipScoreParamsOverride::default()).unwrap();
+        assert_eq!(
+            default_thresholds.gossip_threshold,
+            lighthouse_gossip_thresholds().gossip_threshold
+        );
+
+        let score_override: GossipScoreParamsOverride = serde_json::from_str(
+            r#"{"gossip_threshold": -2000.0, "topic_weights": {"beacon_block": 0.8}}"#,
+        )
+        .unwrap();
+        let (thresholds, params) = build_params(&score_override).unwrap();
+        assert_eq!(thresholds.gossip_threshold, -2000.0);
+        assert_eq!(
+            thresholds.publish_threshold,
+            default_thresholds.publish_threshold
+        );
+        // the behaviour penalty is scaled to the gossip threshold
+        assert!(
+            (params.behaviour_penalty_weight - default_params.behaviour_penalty_weight / 2.0).abs()
+                < 1e-9
+        );
+        let block_topic: Topic = GossipTopic::new(
+            GossipKind::BeaconBlock,
+            GossipEncoding::default(),
+            EnrForkId::default().fork_digest,
+        )
+        .into();
+        assert_eq!(params.topics[&block_topic.hash()].topic_weight, 0.8);
+    }
+
+    #[test]
+    fn test_invalid_score_params_override() {
+        // the publish threshold must not be above the gossip threshold
+        let score_override = GossipScoreParamsOverride {
+            publish_threshold: Some(-1000.0),
+            ..Default::default()
+        };
+        assert!(build_params(&score_override).is_err());
+
+        let mut score_override = GossipScoreParamsOverride::default();
+        score_override
+            .topic_weights
+            .insert("sync_committee".into(), 1.0);
+        assert!(build_params(&score_override).is_err());
+
+        let score_override = GossipScoreParamsOverride {
+            decay_to_zero: Some(1.5),
+            ..Default::default()
+        };
+        assert!(build_params(&score_override).is_err());
+    }
+
+    #[test]
+    fn test_score_params_override_rejects_unknown_fields() {
+        // the peer manager relies on the default graylist threshold
+        assert!(serde_json::from_str::<GossipScoreParamsOverride>(
+            r#"{"graylist_threshold": -20000.0}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<GossipScoreParamsOverride>(
+            r#"{"gossip_treshold": -2000.0}"#
+        )
+        .is_err());
+    }
+}
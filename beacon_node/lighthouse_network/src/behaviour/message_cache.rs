@@ -0,0 +1,123 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use libp2p::gossipsub::{GossipsubConfig, MessageId};
+
+use crate::GossipTopic;
+
+/// Mirrors the ids and topics of the messages held in the gossipsub message cache, which
+/// gossipsub does not expose.
+///
+/// Gossipsub keeps a message for `history_length` heartbeats after it was published or received,
+/// so the entries here expire after the same window. The mirror is approximate: gossipsub shifts
+/// its cache on its own heartbeats, so a message may expire here up to a heartbeat before or after
+/// it leaves the gossipsub cache.
+pub struct MessageCache {
+    /// How long a message stays in the gossipsub message cache.
+    window: Duration,
+    /// The cached messages with the time they were cached, oldest first. Removed messages stay
+    /// here until they expire, but are no longer in `topics`.
+    messages: VecDeque<(Instant, MessageId)>,
+    /// The topic and the time of caching of each cached message.
+    topics: HashMap<MessageId, (Instant, GossipTopic)>,
+}
+
+impl MessageCache {
+    pub fn new(gs_config: &GossipsubConfig) -> Self {
+        MessageCache {
+            window: gs_config.heartbeat_interval() * gs_config.history_length() as u32,
+            messages: VecDeque::new(),
+            topics: HashMap::new(),
+        }
+    }
+
+    /// Records a message that gossipsub has just cached.
+    pub fn insert(&mut self, message_id: MessageId, topic: GossipTopic, now: Instant) {
+        self.prune(now);
+        self.messages.push_back((now, message_id.clone()));
+        self.topics.insert(message_id, (now, topic));
+    }
+
+    /// Forgets a message that gossipsub has dropped from its cache, such as a rejected message.
+    pub fn remove(&mut self, message_id: &MessageId) {
+        self.topics.remove(message_id);
+    }
+
+    /// The topic of a cached message.
+    pub fn topic(&self, message_id: &MessageId) -> Option<&GossipTopic> {
+        self.topics.get(message_id).map(|(_, topic)| topic)
+    }
+
+    /// The ids and topics of the messages still cached, oldest first.
+    pub fn snapshot(&mut self, now: Instant) -> Vec<(MessageId, GossipTopic)> {
+        self.prune(now);
+        self.messages
+            .iter()
+            .filter_map(|(inserted, id)| match self.topics.get(id) {
+                Some((cached, topic)) if cached == inserted => Some((id.clone(), topic.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some((inserted, _)) = self.messages.front() {
+            if *inserted + self.window > now {
+                break;
+            }
+            if let Some((inserted, id)) = self.messages.pop_front() {
+                // the message may have been cached again since
+                if matches!(self.topics.get(&id), Some((cached, _)) if *cached == inserted) {
+                    self.topics.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{GossipEncoding, GossipKind};
+    use libp2p::gossipsub::GossipsubConfigBuilder;
+
+    #[test]
+    fn test_messages_expire_after_window() {
+        let gs_config = GossipsubConfigBuilder::default()
+            .heartbeat_interval(Duration::from_secs(1))
+            .history_length(3)
+            .build()
+            .unwrap();
+        let mut cache = MessageCache::new(&gs_config);
+        let topic = GossipTopic::new(GossipKind::VoluntaryExit, GossipEncoding::default(), [0; 4]);
+        let now = Instant::now();
+
+        cache.insert(MessageId::new(&[1]), topic.clone(), now);
+        cache.insert(
+            MessageId::new(&[2]),
+            topic.clone(),
+            now + Duration::from_secs(1),
+        );
+        cache.insert(
+            MessageId::new(&[3]),
+            topic.clone(),
+            now + Duration::from_secs(2),
+        );
+        cache.remove(&MessageId::new(&[3]));
+        assert_eq!(cache.topic(&MessageId::new(&[1])), Some(&topic));
+        assert_eq!(cache.topic(&MessageId::new(&[3])), None);
+
+        let ids = |snapshot: Vec<(MessageId, GossipTopic)>| {
+            snapshot.into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(cache.snapshot(now + Duration::from_secs(2))),
+            vec![MessageId::new(&[1]), MessageId::new(&[2])]
+        );
+        assert_eq!(
+            ids(cache.snapshot(now + Duration::from_secs(3))),
+            vec![MessageId::new(&[2])]
+        );
+        assert!(cache.snapshot(now + Duration::from_secs(4)).is_empty());
+    }
+}
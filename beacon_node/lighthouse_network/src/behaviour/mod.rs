@@ -1,19 +1,17 @@
-use crate::behaviour::gossipsub_scoring_parameters::{
-    lighthouse_gossip_thresholds, PeerScoreSettings,
-};
+use crate::behaviour::gossipsub_scoring_parameters::{GossipScoreBand, PeerScoreSettings};
 use crate::config::gossipsub_config;
 use crate::discovery::{
     subnet_predicate, Discovery, DiscoveryEvent, FIND_NODE_QUERY_CLOSEST_PEERS,
 };
 use crate::peer_manager::{
-    config::Config as PeerManagerCfg, peerdb::score::PeerAction, peerdb::score::ReportSource,
-    ConnectionDirection, PeerManager, PeerManagerEvent,
+    config::Config as PeerManagerCfg, diversity_subnet, peerdb::score::PeerAction,
+    peerdb::score::ReportSource, ConnectionDirection, PeerManager, PeerManagerEvent,
 };
 use crate::rpc::*;
 use crate::service::{Context as ServiceContext, METADATA_FILENAME};
 use crate::types::{
-    subnet_from_topic_hash, GossipEncoding, GossipKind, GossipTopic, SnappyTransform, Subnet,
-    SubnetDiscovery,
+    subnet_from_topic_hash, ApplicationCodec, GossipEncoding, GossipKind, GossipTopic,
+    SnappyTransform, Subnet, SubnetDiscovery, TopicFilterConfig,
 };
 use crate::Eth2Enr;
 use crate::{error, metrics, Enr, NetworkGlobals, PubsubMessage, TopicHash};
@@ -26,8 +24,8 @@ use libp2p::{
     gossipsub::{
         metrics::Config as GossipsubMetricsConfig,
         subscription_filter::{MaxCountSubscriptionFilter, WhitelistSubscriptionFilter},
-        Gossipsub as BaseGossipsub, GossipsubEvent, IdentTopic as Topic, MessageAcceptance,
-        MessageAuthenticity, MessageId,
+        Gossipsub as BaseGossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage,
+        IdentTopic as Topic, MessageAcceptance, MessageAuthenticity, MessageId,
     },
     identify::{Identify, IdentifyConfig, IdentifyEvent},
     swarm::{
@@ -37,33 +35,47 @@ use libp2p::{
     },
     NetworkBehaviour, PeerId,
 };
+use lru::LruCache;
 use slog::{crit, debug, o, trace, warn};
 use ssz::Encode;
-use std::collections::HashSet;
+use std::collections::{hash_map::Entry, HashMap, HashSet};
 use std::fs::File;
 use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::{
     collections::VecDeque,
     marker::PhantomData,
     sync::Arc,
     task::{Context, Poll},
+    time::Instant,
 };
 use types::{
-    consts::altair::SYNC_COMMITTEE_SUBNET_COUNT, EnrForkId, EthSpec, ForkContext,
+    consts::altair::SYNC_COMMITTEE_SUBNET_COUNT, EnrForkId, EthSpec, ForkContext, Hash256,
     SignedBeaconBlock, Slot, SubnetId, SyncSubnetId,
 };
 
-use self::gossip_cache::GossipCache;
+use self::gossip_cache::{GossipCache, GossipCacheEvent};
+use self::message_cache::MessageCache;
 
 mod gossip_cache;
 pub mod gossipsub_scoring_parameters;
+mod message_cache;
 
 /// The number of peers we target per subnet for discovery queries.
 pub const TARGET_SUBNET_PEERS: usize = 6;
 
 const MAX_IDENTIFY_ADDRESSES: usize = 10;
 
+/// The number of peers whose observed address of us is remembered.
+const MAX_OBSERVING_PEERS: usize = 50;
+
+/// The maximum length of a custom identify agent version.
+pub const MAX_IDENTIFY_AGENT_VERSION_LEN: usize = 128;
+
+/// The number of received gossip messages awaiting validation whose topic kind is remembered.
+const MAX_PENDING_VALIDATIONS: usize = 16_384;
+
 /// Identifier of requests sent by a peer.
 pub type PeerRequestId = (ConnectionId, SubstreamId);
 
@@ -75,6 +87,9 @@ pub type Gossipsub = BaseGossipsub<SnappyTransform, SubscriptionFilter>;
 pub enum RequestId<AppReqId> {
     Application(AppReqId),
     Behaviour,
+    /// A `BlocksByRoot` request answering all the identical application requests coalesced into
+    /// it.
+    Coalesced(u64),
 }
 
 /// The types of events than can be obtained from polling the behaviour.
@@ -96,6 +111,8 @@ pub enum BehaviourEvent<AppReqId: ReqId, TSpec: EthSpec> {
         id: AppReqId,
         /// The peer to which this request was sent.
         peer_id: PeerId,
+        /// The reason the request failed.
+        error: RPCError,
     },
     RequestReceived {
         /// The peer that sent the request.
@@ -114,9 +131,11 @@ pub enum BehaviourEvent<AppReqId: ReqId, TSpec: EthSpec> {
         response: Response<TSpec>,
     },
     PubsubMessage {
-        /// The gossipsub message id. Used when propagating blocks after validation.
+        /// The gossipsub message id. Used to report the validation result of the message, which
+        /// propagates it if accepted.
         id: MessageId,
-        /// The peer from which we received this message, not the peer that published it.
+        /// The peer from which we received this message, not the peer that published it. This is
+        /// the peer the validation result of the message is attributed to.
         source: PeerId,
         /// The topic that this message was sent on.
         topic: TopicHash,
@@ -125,6 +144,40 @@ pub enum BehaviourEvent<AppReqId: ReqId, TSpec: EthSpec> {
     },
     /// Inform the network to send a Status to this peer.
     StatusPeer(PeerId),
+    /// A peer sent a Status with a fork digest that does not match ours. This is reported once
+    /// per connection.
+    ForkDigestMismatch {
+        /// The peer that sent the Status.
+        peer_id: PeerId,
+        /// The fork digest of the peer's Status.
+        their_fork_digest: [u8; 4],
+        /// Our current fork digest.
+        our_fork_digest: [u8; 4],
+    },
+    /// The gossipsub score of a peer moved to another band.
+    GossipScoreThresholdCrossed {
+        /// The peer whose score changed.
+        peer_id: PeerId,
+        /// The band the score was in.
+        from: GossipScoreBand,
+        /// The band the score is now in.
+        to: GossipScoreBand,
+    },
+    /// A peer sent a Goodbye before disconnecting.
+    GoodbyeReceived {
+        /// The peer that sent the Goodbye.
+        peer_id: PeerId,
+        /// The reason the peer gave for disconnecting.
+        reason: GoodbyeReason,
+    },
+    /// Discovery found a peer that is banned. The peer was not dialed.
+    DiscoveredBannedPeer {
+        /// The banned peer.
+        peer_id: PeerId,
+    },
+    /// Enough peers have observed us at a new external address through identify. This is
+    /// reported by the service as `Libp2pEvent::NewExternalAddr`.
+    ExternalAddrConfirmed(Multiaddr),
 }
 
 /// Internal type to pass messages from sub-behaviours to the poll of the global behaviour to be
@@ -193,6 +246,64 @@ pub struct Behaviour<AppReqId: ReqId, TSpec: EthSpec> {
     update_gossipsub_scores: tokio::time::Interval,
     #[behaviour(ignore)]
     gossip_cache: GossipCache,
+    /// The messages in the gossipsub message cache.
+    #[behaviour(ignore)]
+    message_cache: Option<MessageCache>,
+    /// The topic kinds of the received gossip messages whose validation result is not reported
+    /// yet.
+    #[behaviour(ignore)]
+    pending_validation_kinds: LruCache<MessageId, GossipKind>,
+    /// Restricts the topics we subscribe to.
+    #[behaviour(ignore)]
+    gossip_topic_filter: Option<TopicFilterConfig>,
+    /// The kinds of topics flood published by `publish`, gossipsub publishing the others to the
+    /// mesh. Empty when gossipsub flood publishes on every topic.
+    #[behaviour(ignore)]
+    flood_publish_kinds: HashSet<GossipKind>,
+    /// The codecs of the application topics, by topic name.
+    #[behaviour(ignore)]
+    application_codecs: HashMap<String, ApplicationCodec>,
+    /// The in-flight outbound `BlocksByRoot` requests, by peer and sorted block roots.
+    #[behaviour(ignore)]
+    blocks_by_root_requests: HashMap<(PeerId, Vec<Hash256>), u64>,
+    /// The application requests waiting on each in-flight `BlocksByRoot` request.
+    #[behaviour(ignore)]
+    coalesced_requests: HashMap<u64, ((PeerId, Vec<Hash256>), Vec<AppReqId>)>,
+    /// The id of the next coalesced `BlocksByRoot` request.
+    #[behaviour(ignore)]
+    next_coalesced_id: u64,
+    /// The number of events taken from `events` so far.
+    #[behaviour(ignore)]
+    delivered_events: u64,
+    /// The response chunks waiting to be taken by the application, as the value of
+    /// `delivered_events` once their last event is taken, with the peer and connection that
+    /// received them.
+    #[behaviour(ignore)]
+    pending_chunk_acks: VecDeque<(u64, PeerId, ConnectionId)>,
+    /// Whether inbound `BlocksByRange` and `BlocksByRoot` requests are served. When unset they
+    /// are answered with a `ServerError`.
+    #[behaviour(ignore)]
+    serving_requests: bool,
+    /// Inspects the inbound requests before they are handed to the application.
+    #[behaviour(ignore)]
+    request_interceptor: Option<RequestInterceptor>,
+    /// Adjusts the Status messages we send before they are sent.
+    #[behaviour(ignore)]
+    status_hook: Option<StatusHook>,
+    /// The latest address each peer observed us at through identify, with the IP subnet of the
+    /// peer.
+    #[behaviour(ignore)]
+    observed_addrs: LruCache<PeerId, (Multiaddr, Option<IpAddr>)>,
+    /// The number of distinct IP subnets of the peers that must observe the same external address
+    /// before it is confirmed.
+    #[behaviour(ignore)]
+    observed_addr_quorum: usize,
+    /// The last confirmed external address.
+    #[behaviour(ignore)]
+    external_addr: Option<Multiaddr>,
+    /// Whether the local ENR is updated with the confirmed external address.
+    #[behaviour(ignore)]
+    update_enr_from_observed: bool,
     /// Logger for behaviour actions.
     #[behaviour(ignore)]
     log: slog::Logger,
@@ -211,6 +322,17 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
         let mut config = ctx.config.clone();
 
         // Set up the Identify Behaviour
+        let agent_version = match &config.identify_agent_version {
+            Some(agent_version) if agent_version.len() > MAX_IDENTIFY_AGENT_VERSION_LEN => {
+                return Err(format!(
+                    "Identify agent version is longer than {} bytes",
+                    MAX_IDENTIFY_AGENT_VERSION_LEN
+                )
+                .into());
+            }
+            Some(agent_version) => agent_version.clone(),
+            None => lighthouse_version::version_with_platform(),
+        };
         let identify_config = if config.private {
             IdentifyConfig::new(
                 "".into(),
@@ -219,7 +341,7 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
             .with_cache_size(0)
         } else {
             IdentifyConfig::new("eth2/1.0.0".into(), local_key.public())
-                .with_agent_version(lighthouse_version::version_with_platform())
+                .with_agent_version(agent_version)
                 .with_cache_size(0)
         };
 
@@ -235,25 +357,48 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
             .eth2()
             .expect("Local ENR must have a fork id");
 
+        if let Some(name) = config
+            .application_topics
+            .iter()
+            .find(|name| name.is_empty() || name.contains('/'))
+        {
+            return Err(format!("Invalid application topic name: {:?}", name).into());
+        }
+
         let possible_fork_digests = ctx.fork_context.all_fork_digests();
         let filter = MaxCountSubscriptionFilter {
             filter: Self::create_whitelist_filter(
                 possible_fork_digests,
                 ctx.chain_spec.attestation_subnet_count,
                 SYNC_COMMITTEE_SUBNET_COUNT,
+                &config.application_topics,
             ),
             max_subscribed_topics: 200,
             max_subscriptions_per_request: 150, // 148 in theory = (64 attestation + 4 sync committee + 6 core topics) * 2
         };
 
-        config.gs_config = gossipsub_config(config.network_load, ctx.fork_context.clone());
+        config.gs_config = gossipsub_config(
+            config.network_load,
+            config.gossipsub_duplicate_cache_time,
+            config.gossipsub_do_px,
+            ctx.fork_context.clone(),
+        );
+        config.gs_config = config.apply_gossipsub_overrides(config.gs_config.clone())?;
 
         // If metrics are enabled for gossipsub build the configuration
         let gossipsub_metrics = ctx
             .gossipsub_registry
             .map(|registry| (registry, GossipsubMetricsConfig::default()));
 
-        let snappy_transform = SnappyTransform::new(config.gs_config.max_transmit_size());
+        let snappy_transform = SnappyTransform::new(config.gs_config.max_transmit_size())
+            .with_max_size_per_kind(config.gossip_max_size_per_kind.iter().cloned());
+        // the per topic limits are enforced by the transform, gossipsub must fit the largest one
+        if snappy_transform.max_size() > config.gs_config.max_transmit_size() {
+            config.gs_config = GossipsubConfigBuilder::from(config.gs_config.clone())
+                .max_transmit_size(snappy_transform.max_size())
+                .build()
+                .map_err(|e| format!("Could not build gossipsub config: {}", e))?;
+        }
         let mut gossipsub = Gossipsub::new_with_subscription_filter_and_transform(
             MessageAuthenticity::Anonymous,
             config.gs_config.clone(),
@@ -268,9 +413,11 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
         let active_validators = TSpec::minimum_validator_count();
         let current_slot = Slot::new(0);
 
-        let thresholds = lighthouse_gossip_thresholds();
-
-        let score_settings = PeerScoreSettings::new(ctx.chain_spec, &config.gs_config);
+        let mut score_settings = PeerScoreSettings::new(ctx.chain_spec, &config.gs_config);
+        if let Some(score_override) = &config.gossip_score_params {
+            score_settings.apply_override(score_override)?;
+        }
+        let thresholds = score_settings.thresholds().clone();
 
         // Prepare scoring parameters
         let params = score_settings.get_peer_score_params(
@@ -286,13 +433,25 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
         let update_gossipsub_scores = tokio::time::interval(params.decay_interval);
 
         gossipsub
-            .with_peer_score(params, thresholds)
-            .expect("Valid score params and thresholds");
+            .with_peer_score(params, thresholds.clone())
+            .map_err(|e| format!("Invalid gossipsub score params: {}", e))?;
 
         let peer_manager_cfg = PeerManagerCfg {
             discovery_enabled: !config.disable_discovery,
             metrics_enabled: config.metrics_enabled,
             target_peer_count: config.target_peers,
+            target_peers_per_sync_subnet: config.target_peers_per_sync_subnet,
+            ip_subnet_ban_duration: config.ip_subnet_ban_duration,
+            idle_peer_timeout: config.idle_peer_timeout,
+            max_concurrent_dials: config.max_concurrent_dials,
+            dial_backoff_base: config.dial_backoff_base,
+            dial_backoff_max: config.dial_backoff_max,
+            dial_backoff_max_attempts: config.dial_backoff_max_attempts,
+            max_peer_db_entries: config.max_peer_db_entries,
+            gossip_thresholds: thresholds,
+            trusted_peers_only: config.trusted_peers_only,
+            min_peer_protocol_version: config.min_peer_protocol_version.clone(),
+            eviction_policy: config.peer_eviction_policy,
             ..Default::default()
         };
 
@@ -301,8 +460,9 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
             ctx.chain_spec.seconds_per_slot * TSpec::slots_per_epoch() / 2,
         );
         let gossip_cache = GossipCache::builder()
-            .beacon_block_timeout(slot_duration)
-            .aggregates_timeout(half_epoch)
+            .max_retries(config.gossip_publish_max_retries)
+            .beacon_block_timeout(half_epoch)
+            .aggregates_timeout(slot_duration)
             .attestation_timeout(half_epoch)
             .voluntary_exit_timeout(half_epoch * 2)
             .proposer_slashing_timeout(half_epoch * 2)
@@ -314,7 +474,18 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
         Ok(Behaviour {
             // Sub-behaviours
             gossipsub,
-            eth2_rpc: RPC::new(ctx.fork_context.clone(), log.clone()),
+            eth2_rpc: RPC::new(
+                ctx.fork_context.clone(),
+                config.rpc_rate_limits.as_ref(),
+                config.outbound_rpc_rate_limits.clone(),
+                config.rpc_response_byte_budget,
+                config.rpc_snappy_frame_size,
+                config.rpc_request_timeouts.clone(),
+                config.max_inbound_substreams_per_peer,
+                network_globals.clone(),
+                log.clone(),
+            )
+            .map_err(|e| format!("Invalid RPC configuration: {}", e))?,
             discovery,
             identify: Identify::new(identify_config),
             // Auxiliary fields
@@ -329,17 +500,52 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
             score_settings,
             fork_context: ctx.fork_context,
             gossip_cache,
+            message_cache: config
+                .track_gossip_message_cache
+                .then(|| MessageCache::new(&config.gs_config)),
+            pending_validation_kinds: LruCache::new(MAX_PENDING_VALIDATIONS),
+            gossip_topic_filter: config.gossip_topic_filter.clone(),
+            flood_publish_kinds: config
+                .flood_publish_kinds
+                .iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            application_codecs: HashMap::new(),
+            blocks_by_root_requests: HashMap::new(),
+            coalesced_requests: HashMap::new(),
+            next_coalesced_id: 0,
+            delivered_events: 0,
+            pending_chunk_acks: VecDeque::new(),
+            serving_requests: true,
+            request_interceptor: None,
+            status_hook: None,
+            observed_addrs: LruCache::new(MAX_OBSERVING_PEERS),
+            external_addr: None,
+            observed_addr_quorum: config.observed_addr_quorum,
+            update_enr_from_observed: config.update_enr_from_observed
+                && config.enr_address.is_none(),
             update_gossipsub_scores,
         })
     }
 
     /* Public Accessible Functions to interact with the behaviour */
 
+    /// Get a reference to the underlying discovery sub-behaviour.
+    pub fn discovery(&self) -> &Discovery<TSpec> {
+        &self.discovery
+    }
+
     /// Get a mutable reference to the underlying discovery sub-behaviour.
     pub fn discovery_mut(&mut self) -> &mut Discovery<TSpec> {
         &mut self.discovery
     }
 
+    /// Get a reference to the network globals shared with the peer manager.
+    pub fn network_globals(&self) -> &Arc<NetworkGlobals<TSpec>> {
+        &self.network_globals
+    }
+
     /// Get a mutable reference to the peer manager.
     pub fn peer_manager_mut(&mut self) -> &mut PeerManager<TSpec> {
         &mut self.peer_manager
@@ -355,29 +561,48 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
         &self.gossipsub
     }
 
+    /// Returns the number of gossipsub mesh peers of each subscribed topic.
+    pub fn mesh_peers_per_topic(&self) -> HashMap<GossipTopic, usize> {
+        let subscriptions: HashMap<TopicHash, GossipTopic> = self
+            .network_globals
+            .gossipsub_subscriptions
+            .read()
+            .iter()
+            .map(|topic| (Topic::from(topic.clone()).hash(), topic.clone()))
+            .collect();
+        self.gossipsub
+            .topics()
+            .filter_map(|topic_hash| {
+                let topic = subscriptions.get(topic_hash)?;
+                Some((topic.clone(), self.gossipsub.mesh_peers(topic_hash).count()))
+            })
+            .collect()
+    }
+
     /* Pubsub behaviour functions */
 
     /// Subscribes to a gossipsub topic kind, letting the network service determine the
     /// encoding and fork version.
     pub fn subscribe_kind(&mut self, kind: GossipKind) -> bool {
-        let gossip_topic = GossipTopic::new(
-            kind,
-            GossipEncoding::default(),
-            self.enr_fork_id.fork_digest,
-        );
-
+        let gossip_topic = self.kind_topic(kind);
         self.subscribe(gossip_topic)
     }
 
     /// Unsubscribes from a gossipsub topic kind, letting the network service determine the
     /// encoding and fork version.
     pub fn unsubscribe_kind(&mut self, kind: GossipKind) -> bool {
-        let gossip_topic = GossipTopic::new(
+        let gossip_topic = self.kind_topic(kind);
+        self.unsubscribe(gossip_topic)
+    }
+
+    /// The topic of a gossipsub topic kind, with the default encoding and our current fork
+    /// digest.
+    pub fn kind_topic(&self, kind: GossipKind) -> GossipTopic {
+        GossipTopic::new(
             kind,
             GossipEncoding::default(),
             self.enr_fork_id.fork_digest,
-        );
-        self.unsubscribe(gossip_topic)
+        )
     }
 
     /// Subscribe to all currently subscribed topics with the new fork digest.
@@ -405,6 +630,13 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
     ///
     /// Returns `true` if the subscription was successful and `false` otherwise.
     pub fn subscribe(&mut self, topic: GossipTopic) -> bool {
+        if let Some(filter) = &self.gossip_topic_filter {
+            if !filter.permits(topic.kind()) {
+                warn!(self.log, "Subscription blocked by the topic filter"; "topic" => %topic.kind());
+                return false;
+            }
+        }
+
         // update the network globals
         self.network_globals
             .gossipsub_subscriptions
@@ -425,6 +657,22 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
         }
     }
 
+    /// Unsubscribes from all subscribed topics that the topic filter does not permit.
+    pub fn unsubscribe_filtered_topics(&mut self) {
+        let filter = match &self.gossip_topic_filter {
+            Some(filter) => filter.clone(),
+            None => return,
+        };
+        let subscriptions = self.network_globals.gossipsub_subscriptions.read().clone();
+        for topic in subscriptions
+            .into_iter()
+            .filter(|topic| !filter.permits(topic.kind()))
+        {
+            warn!(self.log, "Unsubscribing from topic blocked by the topic filter"; "topic" => %topic.kind());
+            self.unsubscribe(topic);
+        }
+    }
+
     /// Unsubscribe from a gossipsub topic.
     pub fn unsubscribe(&mut self, topic: GossipTopic) -> bool {
         // update the network globals
@@ -449,53 +697,218 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
         }
     }
 
+    /// Registers the codec used for the messages of an application topic. The topic must be one
+    /// of the configured `application_topics` to be subscribed to.
+    pub fn register_application_codec(&mut self, topic: String, codec: ApplicationCodec) {
+        self.application_codecs.insert(topic, codec);
+    }
+
     /// Publishes a list of messages on the pubsub (gossipsub) behaviour, choosing the encoding.
     pub fn publish(&mut self, messages: Vec<PubsubMessage<TSpec>>) {
         for message in messages {
             for topic in message.topics(GossipEncoding::default(), self.enr_fork_id.fork_digest) {
-                let message_data = message.encode(GossipEncoding::default());
-                if let Err(e) = self
-                    .gossipsub
-                    .publish(topic.clone().into(), message_data.clone())
-                {
-                    slog::warn!(self.log, "Could not publish message"; "error" => ?e);
-
-                    // add to metrics
-                    match topic.kind() {
-                        GossipKind::Attestation(subnet_id) => {
-                            if let Some(v) = metrics::get_int_gauge(
-                                &metrics::FAILED_ATTESTATION_PUBLISHES_PER_SUBNET,
-                                &[subnet_id.as_ref()],
-                            ) {
-                                v.inc()
-                            };
-                        }
-                        kind => {
-                            if let Some(v) = metrics::get_int_gauge(
-                                &metrics::FAILED_PUBLISHES_PER_MAIN_TOPIC,
-                                &[&format!("{:?}", kind)],
-                            ) {
-                                v.inc()
-                            };
+                let message_data = match self.encode_pubsub_message(&message) {
+                    Ok(message_data) => message_data,
+                    Err(e) => {
+                        warn!(self.log, "Could not encode message"; "topic" => %topic, "error" => e);
+                        continue;
+                    }
+                };
+                match self.gossipsub_publish(&topic, message_data.clone()) {
+                    Ok(message_id) => {
+                        if let Some(message_cache) = &mut self.message_cache {
+                            message_cache.insert(message_id, topic, Instant::now());
                         }
                     }
+                    Err(e) => {
+                        slog::warn!(self.log, "Could not publish message"; "error" => ?e);
+
+                        // add to metrics
+                        match topic.kind() {
+                            GossipKind::Attestation(subnet_id) => {
+                                if let Some(v) = metrics::get_int_gauge(
+                                    &metrics::FAILED_ATTESTATION_PUBLISHES_PER_SUBNET,
+                                    &[subnet_id.as_ref()],
+                                ) {
+                                    v.inc()
+                                };
+                            }
+                            kind => {
+                                if let Some(v) = metrics::get_int_gauge(
+                                    &metrics::FAILED_PUBLISHES_PER_MAIN_TOPIC,
+                                    &[&format!("{:?}", kind)],
+                                ) {
+                                    v.inc()
+                                };
+                            }
+                        }
 
-                    if let PublishError::InsufficientPeers = e {
-                        self.gossip_cache.insert(topic, message_data);
+                        if let PublishError::InsufficientPeers = e {
+                            self.gossip_cache.insert(topic, message_data);
+                        }
                     }
                 }
             }
         }
     }
 
+    /// Publishes `data` on `topic` as-is, without encoding or validating it. The data is still
+    /// snappy compressed and must not exceed the gossip message size limit.
+    pub fn publish_raw(
+        &mut self,
+        topic: GossipTopic,
+        data: Vec<u8>,
+    ) -> Result<MessageId, PublishError> {
+        let message_id = self.gossipsub_publish(&topic, data)?;
+        if let Some(message_cache) = &mut self.message_cache {
+            message_cache.insert(message_id.clone(), topic, Instant::now());
+        }
+        Ok(message_id)
+    }
+
+    /// Publishes `data` on `topic` as-is, sending it to each of `peers` that subscribes to the
+    /// topic whether or not it is in our mesh or above the publish threshold. This does not
+    /// restrict the recipients: with flood publishing, gossipsub still sends the message to every
+    /// other subscribed peer above the publish threshold. Peers that are not connected are skipped.
+    pub fn publish_to_peers(
+        &mut self,
+        topic: GossipTopic,
+        data: Vec<u8>,
+        peers: &[PeerId],
+    ) -> Result<MessageId, PublishError> {
+        let mut direct_peers = Vec::with_capacity(peers.len());
+        for peer_id in peers {
+            if self.peer_manager.is_connected(peer_id) {
+                direct_peers.push(*peer_id);
+            } else {
+                warn!(
+                    self.log,
+                    "Not publishing to peer that is not connected";
+                    "peer_id" => %peer_id,
+                    "topic" => %topic
+                );
+            }
+        }
+
+        // gossipsub sends every published message to its explicit peers. They are only explicit
+        // for this message, so that they otherwise take part in the mesh as before.
+        for peer_id in &direct_peers {
+            self.gossipsub.add_explicit_peer(peer_id);
+        }
+        let result = self.publish_raw(topic, data);
+        for peer_id in &direct_peers {
+            self.gossipsub.remove_explicit_peer(peer_id);
+        }
+        result
+    }
+
+    /// Publishes `data` on `topic` with gossipsub. On the topics of `flood_publish_kinds`, the
+    /// subscribed peers above the publish threshold are made explicit peers for this message, as
+    /// gossipsub only flood publishes on all topics or none.
+    fn gossipsub_publish(
+        &mut self,
+        topic: &GossipTopic,
+        data: Vec<u8>,
+    ) -> Result<MessageId, PublishError> {
+        if !self.flood_publish_kinds.contains(topic.kind()) {
+            return self.gossipsub.publish(topic.clone().into(), data);
+        }
+
+        let gossipsub_topic = Topic::from(topic.clone());
+        let topic_hash = gossipsub_topic.hash();
+        let publish_threshold = self.score_settings.thresholds().publish_threshold;
+        let flood_peers: Vec<PeerId> = self
+            .gossipsub
+            .all_peers()
+            .filter(|(peer_id, topics)| {
+                topics.contains(&&topic_hash)
+                    && self
+                        .gossipsub
+                        .peer_score(peer_id)
+                        .map_or(true, |score| score >= publish_threshold)
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in &flood_peers {
+            self.gossipsub.add_explicit_peer(peer_id);
+        }
+        let result = self.gossipsub.publish(gossipsub_topic, data);
+        for peer_id in &flood_peers {
+            self.gossipsub.remove_explicit_peer(peer_id);
+        }
+        result
+    }
+
+    /// The topic of a message held in the gossipsub message cache, if the cache is tracked.
+    pub fn cached_message_topic(&self, message_id: &MessageId) -> Option<&GossipTopic> {
+        self.message_cache.as_ref()?.topic(message_id)
+    }
+
+    /// The ids and topics of the messages currently held in the gossipsub message cache, which
+    /// gossipsub serves to peers asking for them. Empty unless `track_gossip_message_cache` is
+    /// set.
+    pub fn gossip_cache_snapshot(&mut self) -> Vec<(MessageId, GossipTopic)> {
+        self.message_cache
+            .as_mut()
+            .map_or_else(Vec::new, |message_cache| {
+                message_cache.snapshot(Instant::now())
+            })
+    }
+
+    /// Encodes a message for gossipsub, applying the codec of application topics.
+    fn encode_pubsub_message(&self, message: &PubsubMessage<TSpec>) -> Result<Vec<u8>, String> {
+        match message {
+            PubsubMessage::Application(data) => self
+                .application_codecs
+                .get(&data.topic)
+                .ok_or_else(|| format!("No codec registered for topic {}", data.topic))?
+                .encode(&data.payload),
+            message => Ok(message.encode(GossipEncoding::default())),
+        }
+    }
+
+    /// Decodes a gossipsub message, applying the codec of application topics.
+    fn decode_pubsub_message(
+        &self,
+        message: &GossipsubMessage,
+    ) -> Result<PubsubMessage<TSpec>, String> {
+        match PubsubMessage::decode(&message.topic, &message.data, &self.fork_context)? {
+            PubsubMessage::Application(mut data) => {
+                data.payload = self
+                    .application_codecs
+                    .get(&data.topic)
+                    .ok_or_else(|| format!("No codec registered for topic {}", data.topic))?
+                    .decode(&data.payload)?;
+                Ok(PubsubMessage::Application(data))
+            }
+            message => Ok(message),
+        }
+    }
+
     /// Informs the gossipsub about the result of a message validation.
     /// If the message is valid it will get propagated by gossipsub.
+    ///
+    /// The result is counted per topic kind of the message.
     pub fn report_message_validation_result(
         &mut self,
         propagation_source: &PeerId,
         message_id: MessageId,
         validation_result: MessageAcceptance,
     ) {
+        // the kind of messages we did not receive, or forgot, is not known
+        let topic_kind = self.pending_validation_kinds.pop(&message_id);
+        metrics::inc_counter_vec(
+            &metrics::GOSSIP_VALIDATION_RESULTS_PER_TOPIC_KIND,
+            &[
+                topic_kind.as_ref().map_or("unknown", |kind| kind.as_ref()),
+                match validation_result {
+                    MessageAcceptance::Accept => "accept",
+                    MessageAcceptance::Ignore => "ignore",
+                    MessageAcceptance::Reject => "reject",
+                },
+            ],
+        );
+
         if let Some(result) = match validation_result {
             MessageAcceptance::Accept => None,
             MessageAcceptance::Ignore => Some("ignore"),
@@ -515,6 +928,13 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
             }
         }
 
+        // Gossipsub drops the messages that are not accepted from its cache.
+        if !matches!(validation_result, MessageAcceptance::Accept) {
+            if let Some(message_cache) = &mut self.message_cache {
+                message_cache.remove(&message_id);
+            }
+        }
+
         if let Err(e) = self.gossipsub.report_message_validation_result(
             &message_id,
             propagation_source,
@@ -569,9 +989,24 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
     /* Eth2 RPC behaviour functions */
 
     /// Send a request to a peer over RPC.
-    pub fn send_request(&mut self, peer_id: PeerId, request_id: AppReqId, request: Request) {
-        self.eth2_rpc
-            .send_request(peer_id, RequestId::Application(request_id), request.into())
+    ///
+    /// A `BlocksByRoot` request identical to one in flight to the same peer, which has not received
+    /// a response yet, is not sent again: the responses to the request in flight are delivered for
+    /// both.
+    pub fn send_request(&mut self, peer_id: PeerId, request_id: AppReqId, mut request: Request) {
+        if let (Request::Status(status), Some(hook)) = (&mut request, &self.status_hook) {
+            hook.apply(&peer_id, status);
+        }
+        let id = match &request {
+            Request::BlocksByRoot(req) => {
+                match self.coalesce_blocks_by_root(peer_id, request_id, req) {
+                    Some(coalesced_id) => RequestId::Coalesced(coalesced_id),
+                    None => return,
+                }
+            }
+            _ => RequestId::Application(request_id),
+        };
+        self.eth2_rpc.send_request(peer_id, id, request.into())
     }
 
     /// Send a successful response to a peer over RPC.
@@ -579,8 +1014,11 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
         &mut self,
         peer_id: PeerId,
         id: PeerRequestId,
-        response: Response<TSpec>,
+        mut response: Response<TSpec>,
     ) {
+        if let (Response::Status(status), Some(hook)) = (&mut response, &self.status_hook) {
+            hook.apply(&peer_id, status);
+        }
         self.eth2_rpc.send_response(peer_id, id, response.into())
     }
 
@@ -596,6 +1034,40 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
             .send_response(peer_id, id, RPCCodedResponse::Error(error, reason.into()))
     }
 
+    /// Sets whether inbound `BlocksByRange` and `BlocksByRoot` requests are served, for instance
+    /// to refuse them while we are syncing from genesis. Refused requests are answered with a
+    /// `ServerError` and never reach the application. Other requests are unaffected.
+    pub fn set_serving_requests(&mut self, serving: bool) {
+        self.serving_requests = serving;
+    }
+
+    /// Sets the interceptor inspecting the inbound requests before they are handed to the
+    /// application. Requests it rejects are answered with an error instead. `None` removes it.
+    pub fn set_request_interceptor(&mut self, interceptor: Option<RequestInterceptor>) {
+        self.request_interceptor = interceptor;
+    }
+
+    /// Sets the hook adjusting the Status requests and responses we send. `None` removes it.
+    pub fn set_status_hook(&mut self, hook: Option<StatusHook>) {
+        self.status_hook = hook;
+    }
+
+    /// Answers an inbound block request with an error if block requests are not being served.
+    /// Returns whether the request was refused.
+    fn refuse_block_request(&mut self, peer_id: PeerId, id: PeerRequestId) -> bool {
+        if self.serving_requests {
+            return false;
+        }
+        debug!(self.log, "Refusing block request while not serving requests"; "peer_id" => %peer_id);
+        self.send_error_reponse(
+            peer_id,
+            id,
+            RPCResponseErrorCode::ServerError,
+            "Not serving block requests".into(),
+        );
+        true
+    }
+
     /* Peer management functions */
 
     /// Disconnects from a peer providing a reason.
@@ -656,14 +1128,15 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
                     .read()
                     .good_peers_on_subnet(s.subnet)
                     .count();
-                if peers_on_subnet >= TARGET_SUBNET_PEERS {
+                let target_subnet_peers = self.discovery.target_subnet_peers(&s.subnet);
+                if peers_on_subnet >= target_subnet_peers {
                     trace!(
                         self.log,
                         "Discovery query ignored";
                         "subnet" => ?s.subnet,
                         "reason" => "Already connected to desired peers",
                         "connected_peers_on_subnet" => peers_on_subnet,
-                        "target_subnet_peers" => TARGET_SUBNET_PEERS,
+                        "target_subnet_peers" => target_subnet_peers,
                     );
                     false
                 // Queue an outgoing connection request to the cached peers that are on `s.subnet_id`.
@@ -694,16 +1167,15 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
 
     /// Updates the current meta data of the node to match the local ENR.
     fn update_metadata_bitfields(&mut self) {
+        // the bitfields include the changes not yet published in the ENR
         let local_attnets = self
             .discovery
-            .local_enr()
-            .attestation_bitfield::<TSpec>()
+            .attestation_bitfield()
             .expect("Local discovery must have attestation bitfield");
 
         let local_syncnets = self
             .discovery
-            .local_enr()
-            .sync_committee_bitfield::<TSpec>()
+            .sync_committee_bitfield()
             .expect("Local discovery must have sync committee bitfield");
 
         {
@@ -766,6 +1238,55 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
         &mut self.peer_manager
     }
 
+    /// Registers an outbound `BlocksByRoot` request. Returns the id to send it with, or `None` if
+    /// an identical request to the peer is in flight and has not received a response yet, in
+    /// which case its responses are also delivered for `request_id`.
+    fn coalesce_blocks_by_root(
+        &mut self,
+        peer_id: PeerId,
+        request_id: AppReqId,
+        request: &BlocksByRootRequest,
+    ) -> Option<u64> {
+        let mut block_roots = request.block_roots.to_vec();
+        block_roots.sort();
+        match self.blocks_by_root_requests.entry((peer_id, block_roots)) {
+            Entry::Occupied(entry) => {
+                if let Some((_, request_ids)) = self.coalesced_requests.get_mut(entry.get()) {
+                    request_ids.push(request_id);
+                }
+                trace!(self.log, "Coalesced identical BlocksByRoot request"; "peer_id" => %peer_id, "request_id" => ?request_id);
+                None
+            }
+            Entry::Vacant(entry) => {
+                let coalesced_id = self.next_coalesced_id;
+                self.next_coalesced_id += 1;
+                self.coalesced_requests
+                    .insert(coalesced_id, (entry.key().clone(), vec![request_id]));
+                entry.insert(coalesced_id);
+                Some(coalesced_id)
+            }
+        }
+    }
+
+    /// Stops coalescing new requests onto a coalesced request. Identical requests made afterwards
+    /// are sent on their own.
+    fn stop_coalescing(&mut self, coalesced_id: u64) {
+        if let Some((key, _)) = self.coalesced_requests.get(&coalesced_id) {
+            if self.blocks_by_root_requests.get(key) == Some(&coalesced_id) {
+                self.blocks_by_root_requests.remove(key);
+            }
+        }
+    }
+
+    /// Stops tracking a coalesced request, returning the application requests waiting on it.
+    fn remove_coalesced_request(&mut self, coalesced_id: u64) -> Vec<AppReqId> {
+        self.stop_coalescing(coalesced_id);
+        self.coalesced_requests
+            .remove(&coalesced_id)
+            .map(|(_, request_ids)| request_ids)
+            .unwrap_or_default()
+    }
+
     // RPC Propagation methods
     /// Queues the response to be sent upwards as long at it was requested outside the Behaviour.
     fn propagate_response(
@@ -780,10 +1301,51 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
                 id,
                 response,
             }),
+            RequestId::Coalesced(coalesced_id) => {
+                // the stream termination completes the request
+                let request_ids = if matches!(response, Response::BlocksByRoot(None)) {
+                    self.remove_coalesced_request(coalesced_id)
+                } else {
+                    // a request joining after this chunk would miss it
+                    self.stop_coalescing(coalesced_id);
+                    self.coalesced_requests
+                        .get(&coalesced_id)
+                        .map(|(_, request_ids)| request_ids.clone())
+                        .unwrap_or_default()
+                };
+                for id in request_ids {
+                    self.add_event(BehaviourEvent::ResponseReceived {
+                        peer_id,
+                        id,
+                        response: response.clone(),
+                    });
+                }
+            }
             RequestId::Behaviour => {}
         }
     }
 
+    /// Acknowledges a response chunk received on `conn_id` once the events it was propagated as
+    /// have been taken by the application, which lets the handler read the next chunk.
+    fn ack_chunk_once_delivered(&mut self, peer_id: PeerId, conn_id: ConnectionId) {
+        let delivered_at = self.delivered_events + self.events.len() as u64;
+        if delivered_at > self.delivered_events {
+            self.pending_chunk_acks
+                .push_back((delivered_at, peer_id, conn_id));
+        } else {
+            // the chunk was not propagated
+            self.eth2_rpc.chunk_consumed(peer_id, conn_id);
+        }
+    }
+
+    /// The response chunks received from `peer_id` that the application has not taken yet.
+    pub fn unconsumed_response_chunks(&self, peer_id: &PeerId) -> usize {
+        self.pending_chunk_acks
+            .iter()
+            .filter(|(_, chunk_peer_id, _)| chunk_peer_id == peer_id)
+            .count()
+    }
+
     /// Convenience function to propagate a request.
     fn propagate_request(&mut self, id: PeerRequestId, peer_id: PeerId, request: Request) {
         // Increment metrics
@@ -797,6 +1359,27 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
             Request::BlocksByRoot { .. } => {
                 metrics::inc_counter_vec(&metrics::TOTAL_RPC_REQUESTS, &["blocks_by_root"])
             }
+            Request::BlobsByRange { .. } => {
+                metrics::inc_counter_vec(&metrics::TOTAL_RPC_REQUESTS, &["blobs_by_range"])
+            }
+            Request::LightClientBootstrap { .. } => {
+                metrics::inc_counter_vec(&metrics::TOTAL_RPC_REQUESTS, &["light_client_bootstrap"])
+            }
+        }
+        let action = self
+            .request_interceptor
+            .as_ref()
+            .map_or(RequestAction::Allow, |interceptor| {
+                interceptor.intercept(&peer_id, &request)
+            });
+        if let RequestAction::Reject(error) = action {
+            debug!(self.log, "Inbound request rejected by the interceptor"; "peer_id" => %peer_id, "error" => %error);
+            let (error_code, reason) = match error {
+                RPCError::ErrorResponse(error_code, reason) => (error_code, reason),
+                error => (RPCResponseErrorCode::ServerError, error.to_string()),
+            };
+            self.send_error_reponse(peer_id, id, error_code, reason);
+            return;
         }
         self.add_event(BehaviourEvent::RequestReceived {
             peer_id,
@@ -836,9 +1419,7 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
             self.discovery.remove_cached_enr(&peer_id);
             // For any dial event, inform the peer manager
             let enr = self.discovery_mut().enr_of_peer(&peer_id);
-            self.peer_manager.inject_dialing(&peer_id, enr);
-            self.internal_events
-                .push_back(InternalBehaviourMessage::DialPeer(peer_id));
+            self.peer_manager.dial_peer(&peer_id, enr);
         }
     }
 
@@ -848,6 +1429,7 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
         possible_fork_digests: Vec<[u8; 4]>,
         attestation_subnet_count: u64,
         sync_committee_subnet_count: u64,
+        application_topics: &[String],
     ) -> WhitelistSubscriptionFilter {
         let mut possible_hashes = HashSet::new();
         for fork_digest in possible_fork_digests {
@@ -870,6 +1452,9 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Behaviour<AppReqId, TSpec> {
             for id in 0..sync_committee_subnet_count {
                 add(SyncCommitteeMessage(SyncSubnetId::new(id)));
             }
+            for name in application_topics {
+                add(Application(name.clone()));
+            }
         }
         WhitelistSubscriptionFilter(possible_hashes)
     }
@@ -896,7 +1481,7 @@ where
             } => {
                 // Note: We are keeping track here of the peer that sent us the message, not the
                 // peer that originally published the message.
-                match PubsubMessage::decode(&gs_msg.topic, &gs_msg.data, &self.fork_context) {
+                match self.decode_pubsub_message(&gs_msg) {
                     Err(e) => {
                         debug!(self.log, "Could not decode gossipsub message"; "topic" => ?gs_msg.topic,"error" => e);
                         //reject the message
@@ -909,6 +1494,14 @@ where
                         }
                     }
                     Ok(msg) => {
+                        if let Ok(topic) = GossipTopic::decode(gs_msg.topic.as_str()) {
+                            self.pending_validation_kinds
+                                .put(id.clone(), topic.kind().clone());
+                            if let Some(message_cache) = &mut self.message_cache {
+                                message_cache.insert(id.clone(), topic, Instant::now());
+                            }
+                        }
+                        self.peer_manager.peer_activity(&propagation_source);
                         // Notify the network
                         self.add_event(BehaviourEvent::PubsubMessage {
                             id,
@@ -932,7 +1525,14 @@ where
                         for data in msgs {
                             let topic_str: &str = topic.kind().as_ref();
                             match self.gossipsub.publish(topic.clone().into(), data) {
-                                Ok(_) => {
+                                Ok(message_id) => {
+                                    if let Some(message_cache) = &mut self.message_cache {
+                                        message_cache.insert(
+                                            message_id,
+                                            topic.clone(),
+                                            Instant::now(),
+                                        );
+                                    }
                                     warn!(self.log, "Gossip message published on retry"; "topic" => topic_str);
                                     if let Some(v) = metrics::get_int_counter(
                                         &metrics::GOSSIP_LATE_PUBLISH_PER_TOPIC_KIND,
@@ -1028,8 +1628,20 @@ where
                             ConnectionDirection::Outgoing,
                         );
                         // inform failures of requests comming outside the behaviour
-                        if let RequestId::Application(id) = id {
-                            self.add_event(BehaviourEvent::RPCFailed { peer_id, id });
+                        match id {
+                            RequestId::Application(id) => {
+                                self.add_event(BehaviourEvent::RPCFailed { peer_id, id, error })
+                            }
+                            RequestId::Coalesced(coalesced_id) => {
+                                for id in self.remove_coalesced_request(coalesced_id) {
+                                    self.add_event(BehaviourEvent::RPCFailed {
+                                        peer_id,
+                                        id,
+                                        error: error.clone(),
+                                    });
+                                }
+                            }
+                            RequestId::Behaviour => {}
                         }
                     }
                 }
@@ -1056,26 +1668,53 @@ where
                             "reason" => %reason,
                             "client" => %self.network_globals.client(&peer_id),
                         );
-                        // NOTE: We currently do not inform the application that we are
-                        // disconnecting here. The RPC handler will automatically
-                        // disconnect for us.
-                        // The actual disconnection event will be relayed to the application.
+                        // NOTE: The RPC handler will automatically disconnect for us. The
+                        // actual disconnection event will be relayed to the application.
+                        self.peer_manager.goodbye_received(&peer_id, reason.clone());
+                        self.add_event(BehaviourEvent::GoodbyeReceived { peer_id, reason });
                     }
                     /* Protocols propagated to the Network */
                     InboundRequest::Status(msg) => {
                         // inform the peer manager that we have received a status from a peer
                         self.peer_manager.peer_statusd(&peer_id);
+                        self.peer_manager.check_fork_digest(
+                            &peer_id,
+                            msg.fork_digest,
+                            self.enr_fork_id.fork_digest,
+                        );
                         // propagate the STATUS message upwards
                         self.propagate_request(peer_request_id, peer_id, Request::Status(msg))
                     }
-                    InboundRequest::BlocksByRange(req) => self.propagate_request(
-                        peer_request_id,
-                        peer_id,
-                        Request::BlocksByRange(req),
-                    ),
+                    InboundRequest::BlocksByRange(req) => {
+                        if self.refuse_block_request(peer_id, peer_request_id) {
+                            return;
+                        }
+                        self.peer_manager.peer_activity(&peer_id);
+                        self.propagate_request(
+                            peer_request_id,
+                            peer_id,
+                            Request::BlocksByRange(req),
+                        )
+                    }
                     InboundRequest::BlocksByRoot(req) => {
+                        if self.refuse_block_request(peer_id, peer_request_id) {
+                            return;
+                        }
+                        self.peer_manager.peer_activity(&peer_id);
                         self.propagate_request(peer_request_id, peer_id, Request::BlocksByRoot(req))
                     }
+                    InboundRequest::BlobsByRange(req) => {
+                        self.peer_manager.peer_activity(&peer_id);
+                        self.propagate_request(peer_request_id, peer_id, Request::BlobsByRange(req))
+                    }
+                    InboundRequest::LightClientBootstrap(req) => {
+                        self.peer_manager.peer_activity(&peer_id);
+                        self.propagate_request(
+                            peer_request_id,
+                            peer_id,
+                            Request::LightClientBootstrap(req),
+                        )
+                    }
                 }
             }
             Ok(RPCReceived::Response(id, resp)) => {
@@ -1089,14 +1728,32 @@ where
                     RPCResponse::Status(msg) => {
                         // inform the peer manager that we have received a status from a peer
                         self.peer_manager.peer_statusd(&peer_id);
+                        self.peer_manager.check_fork_digest(
+                            &peer_id,
+                            msg.fork_digest,
+                            self.enr_fork_id.fork_digest,
+                        );
                         // propagate the STATUS message upwards
                         self.propagate_response(id, peer_id, Response::Status(msg));
                     }
                     RPCResponse::BlocksByRange(resp) => {
-                        self.propagate_response(id, peer_id, Response::BlocksByRange(Some(resp)))
+                        self.peer_manager.peer_activity(&peer_id);
+                        self.propagate_response(id, peer_id, Response::BlocksByRange(Some(resp)));
+                        self.ack_chunk_once_delivered(peer_id, handler_id);
                     }
                     RPCResponse::BlocksByRoot(resp) => {
-                        self.propagate_response(id, peer_id, Response::BlocksByRoot(Some(resp)))
+                        self.peer_manager.peer_activity(&peer_id);
+                        self.propagate_response(id, peer_id, Response::BlocksByRoot(Some(resp)));
+                        self.ack_chunk_once_delivered(peer_id, handler_id);
+                    }
+                    RPCResponse::BlobsByRange(resp) => {
+                        self.peer_manager.peer_activity(&peer_id);
+                        self.propagate_response(id, peer_id, Response::BlobsByRange(Some(resp)));
+                        self.ack_chunk_once_delivered(peer_id, handler_id);
+                    }
+                    RPCResponse::LightClientBootstrap(resp) => {
+                        self.peer_manager.peer_activity(&peer_id);
+                        self.propagate_response(id, peer_id, Response::LightClientBootstrap(resp))
                     }
                 }
             }
@@ -1104,6 +1761,7 @@ where
                 let response = match termination {
                     ResponseTermination::BlocksByRange => Response::BlocksByRange(None),
                     ResponseTermination::BlocksByRoot => Response::BlocksByRoot(None),
+                    ResponseTermination::BlobsByRange => Response::BlobsByRange(None),
                 };
                 self.propagate_response(id, peer_id, response);
             }
@@ -1129,15 +1787,20 @@ where
                 self.internal_events
                     .push_back(InternalBehaviourMessage::SocketUpdated(multiaddr));
             }
-            DiscoveryEvent::QueryResult(results) => {
+            DiscoveryEvent::QueryResult(mut results) => {
+                // Banned peers are reported rather than dialed
+                results.retain(|peer_id, _| {
+                    let enr = self.discovery.enr_of_peer(peer_id);
+                    !self
+                        .peer_manager
+                        .discovered_peer_is_banned(peer_id, enr.as_ref())
+                });
                 let to_dial_peers = self.peer_manager.peers_discovered(results);
                 for peer_id in to_dial_peers {
                     debug!(self.log, "Dialing discovered peer"; "peer_id" => %peer_id);
                     // For any dial event, inform the peer manager
                     let enr = self.discovery_mut().enr_of_peer(&peer_id);
-                    self.peer_manager.inject_dialing(&peer_id, enr);
-                    self.internal_events
-                        .push_back(InternalBehaviourMessage::DialPeer(peer_id));
+                    self.peer_manager.dial_peer(&peer_id, enr);
                 }
             }
         }
@@ -1162,6 +1825,7 @@ where
                 }
                 // send peer info to the peer manager.
                 self.peer_manager.identify(&peer_id, &info);
+                self.on_observed_addr(peer_id, info.observed_addr);
             }
             IdentifyEvent::Sent { .. } => {}
             IdentifyEvent::Error { .. } => {}
@@ -1178,6 +1842,52 @@ where
     TSpec: EthSpec,
     AppReqId: ReqId,
 {
+    /// Records the address a peer observed us at, confirming it as our external address once
+    /// peers of `observed_addr_quorum` distinct IP subnets agree on it.
+    fn on_observed_addr(&mut self, peer_id: PeerId, observed_addr: Multiaddr) {
+        // peers of a single subnet count once, so that a cluster of peers can't decide our address
+        let subnet = self
+            .network_globals
+            .peers
+            .read()
+            .peer_info(&peer_id)
+            .and_then(diversity_subnet);
+        self.observed_addrs
+            .put(peer_id, (observed_addr.clone(), subnet));
+        if self.external_addr.as_ref() == Some(&observed_addr) {
+            return;
+        }
+        let confirming_subnets = self
+            .observed_addrs
+            .iter()
+            .filter(|(_, (addr, _))| *addr == observed_addr)
+            .filter_map(|(_, (_, subnet))| *subnet)
+            .collect::<HashSet<_>>();
+        if confirming_subnets.len() < self.observed_addr_quorum {
+            return;
+        }
+
+        debug!(self.log, "External address confirmed"; "address" => %observed_addr);
+        if self.update_enr_from_observed {
+            match tcp_socket_addr(&observed_addr) {
+                Some(socket_addr) => {
+                    if let Err(e) = self.discovery.update_enr_tcp_socket(socket_addr) {
+                        warn!(self.log, "Failed to update ENR"; "error" => e);
+                    }
+                }
+                None => {
+                    debug!(
+                        self.log,
+                        "Observed address is not a TCP address";
+                        "address" => %observed_addr
+                    );
+                }
+            }
+        }
+        self.external_addr = Some(observed_addr.clone());
+        self.add_event(BehaviourEvent::ExternalAddrConfirmed(observed_addr));
+    }
+
     /// Consumes the events list and drives the Lighthouse global NetworkBehaviour.
     fn poll(
         &mut self,
@@ -1215,6 +1925,15 @@ where
         }
 
         if let Some(event) = self.events.pop_front() {
+            self.delivered_events += 1;
+            // the application takes the event, acknowledge the response chunks it completes
+            while let Some(&(delivered_at, peer_id, conn_id)) = self.pending_chunk_acks.front() {
+                if delivered_at > self.delivered_events {
+                    break;
+                }
+                self.pending_chunk_acks.pop_front();
+                self.eth2_rpc.chunk_consumed(peer_id, conn_id);
+            }
             return Poll::Ready(NBAction::GenerateEvent(event));
         }
 
@@ -1223,11 +1942,11 @@ where
             self.peer_manager.update_gossipsub_scores(&self.gossipsub);
         }
 
-        // poll the gossipsub cache to clear expired messages
+        // poll the gossipsub cache to retry cached messages and clear expired ones
         while let Poll::Ready(Some(result)) = self.gossip_cache.poll_next_unpin(cx) {
             match result {
                 Err(e) => warn!(self.log, "Gossip cache error"; "error" => e),
-                Ok(expired_topic) => {
+                Ok(GossipCacheEvent::Expired(expired_topic)) => {
                     if let Some(v) = metrics::get_int_counter(
                         &metrics::GOSSIP_EXPIRED_LATE_PUBLISH_PER_TOPIC_KIND,
                         &[expired_topic.kind().as_ref()],
@@ -1235,6 +1954,36 @@ where
                         v.inc()
                     };
                 }
+                Ok(GossipCacheEvent::Retry(topic, data)) => {
+                    let topic_str: &str = topic.kind().as_ref();
+                    match self.gossipsub.publish(topic.clone().into(), data.clone()) {
+                        Ok(message_id) => {
+                            if let Some(message_cache) = &mut self.message_cache {
+                                message_cache.insert(message_id, topic.clone(), Instant::now());
+                            }
+                            debug!(self.log, "Gossip message published on timed retry"; "topic" => topic_str);
+                            if let Some(v) = metrics::get_int_counter(
+                                &metrics::GOSSIP_LATE_PUBLISH_PER_TOPIC_KIND,
+                                &[topic_str],
+                            ) {
+                                v.inc()
+                            };
+                            self.gossip_cache.remove(&topic, &data);
+                        }
+                        // keep the message until a peer subscribes or it expires
+                        Err(PublishError::InsufficientPeers) => {}
+                        Err(e) => {
+                            warn!(self.log, "Gossip message publish failed on timed retry"; "topic" => topic_str, "error" => %e);
+                            if let Some(v) = metrics::get_int_counter(
+                                &metrics::GOSSIP_FAILED_LATE_PUBLISH_PER_TOPIC_KIND,
+                                &[topic_str],
+                            ) {
+                                v.inc()
+                            };
+                            self.gossip_cache.remove(&topic, &data);
+                        }
+                    }
+                }
             }
         }
 
@@ -1254,6 +2003,11 @@ impl<AppReqId: ReqId, TSpec: EthSpec> NetworkBehaviourEventProcess<PeerManagerEv
                 self.add_event(BehaviourEvent::PeerConnectedOutgoing(peer_id));
             }
             PeerManagerEvent::PeerDisconnected(peer_id) => {
+                // requests to the peer will not be answered, the disconnection is reported instead
+                self.blocks_by_root_requests
+                    .retain(|(request_peer_id, _), _| *request_peer_id != peer_id);
+                self.coalesced_requests
+                    .retain(|_, ((request_peer_id, _), _)| *request_peer_id != peer_id);
                 self.add_event(BehaviourEvent::PeerDisconnected(peer_id));
             }
             PeerManagerEvent::Banned(peer_id, associated_ips) => {
@@ -1269,6 +2023,27 @@ impl<AppReqId: ReqId, TSpec: EthSpec> NetworkBehaviourEventProcess<PeerManagerEv
                 // the network to send a status to this peer
                 self.add_event(BehaviourEvent::StatusPeer(peer_id));
             }
+            PeerManagerEvent::ForkDigestMismatch {
+                peer_id,
+                their_fork_digest,
+                our_fork_digest,
+            } => {
+                self.add_event(BehaviourEvent::ForkDigestMismatch {
+                    peer_id,
+                    their_fork_digest,
+                    our_fork_digest,
+                });
+            }
+            PeerManagerEvent::GossipScoreThresholdCrossed { peer_id, from, to } => {
+                self.add_event(BehaviourEvent::GossipScoreThresholdCrossed { peer_id, from, to });
+            }
+            PeerManagerEvent::DiscoveredBannedPeer { peer_id } => {
+                self.add_event(BehaviourEvent::DiscoveredBannedPeer { peer_id });
+            }
+            PeerManagerEvent::DialPeer(peer_id) => {
+                self.internal_events
+                    .push_back(InternalBehaviourMessage::DialPeer(peer_id));
+            }
             PeerManagerEvent::DiscoverPeers(peers_to_find) => {
                 // Peer manager has requested a discovery query for more peers.
                 self.discovery.discover_peers(peers_to_find);
@@ -1310,6 +2085,65 @@ pub enum Request {
     BlocksByRange(BlocksByRangeRequest),
     /// A request blocks root request.
     BlocksByRoot(BlocksByRootRequest),
+    /// A blobs by range request.
+    BlobsByRange(BlobsByRangeRequest),
+    /// A light client bootstrap request.
+    LightClientBootstrap(LightClientBootstrapRequest),
+}
+
+/// What to do with an inbound request, as decided by a `RequestInterceptor`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestAction {
+    /// The request is handed to the application.
+    Allow,
+    /// The request is answered with an error and dropped. The code and reason of an
+    /// `RPCError::ErrorResponse` are sent as is, other errors are sent as a `ServerError`.
+    Reject(RPCError),
+}
+
+/// Inspects the inbound requests before they are handed to the application.
+#[derive(Clone)]
+pub struct RequestInterceptor(Arc<dyn Fn(&PeerId, &Request) -> RequestAction + Send + Sync>);
+
+impl RequestInterceptor {
+    pub fn new(
+        interceptor: impl Fn(&PeerId, &Request) -> RequestAction + Send + Sync + 'static,
+    ) -> Self {
+        RequestInterceptor(Arc::new(interceptor))
+    }
+
+    /// Decides what to do with `request`, received from `peer_id`.
+    pub fn intercept(&self, peer_id: &PeerId, request: &Request) -> RequestAction {
+        (self.0)(peer_id, request)
+    }
+}
+
+impl std::fmt::Debug for RequestInterceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RequestInterceptor")
+    }
+}
+
+/// Inspects and modifies the Status messages we send, as requests or responses, before they are
+/// sent.
+#[derive(Clone)]
+pub struct StatusHook(Arc<dyn Fn(&PeerId, &mut StatusMessage) + Send + Sync>);
+
+impl StatusHook {
+    pub fn new(hook: impl Fn(&PeerId, &mut StatusMessage) + Send + Sync + 'static) -> Self {
+        StatusHook(Arc::new(hook))
+    }
+
+    /// Applies the hook to `status`, about to be sent to `peer_id`.
+    pub fn apply(&self, peer_id: &PeerId, status: &mut StatusMessage) {
+        (self.0)(peer_id, status)
+    }
+}
+
+impl std::fmt::Debug for StatusHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StatusHook")
+    }
 }
 
 impl<TSpec: EthSpec> std::convert::From<Request> for OutboundRequest<TSpec> {
@@ -1317,6 +2151,8 @@ impl<TSpec: EthSpec> std::convert::From<Request> for OutboundRequest<TSpec> {
         match req {
             Request::BlocksByRoot(r) => OutboundRequest::BlocksByRoot(r),
             Request::BlocksByRange(r) => OutboundRequest::BlocksByRange(r),
+            Request::BlobsByRange(r) => OutboundRequest::BlobsByRange(r),
+            Request::LightClientBootstrap(r) => OutboundRequest::LightClientBootstrap(r),
             Request::Status(s) => OutboundRequest::Status(s),
         }
     }
@@ -1336,6 +2172,10 @@ pub enum Response<TSpec: EthSpec> {
     BlocksByRange(Option<Box<SignedBeaconBlock<TSpec>>>),
     /// A response to a get BLOCKS_BY_ROOT request.
     BlocksByRoot(Option<Box<SignedBeaconBlock<TSpec>>>),
+    /// A response to a get BLOBS_BY_RANGE request. A None response signals the end of the batch.
+    BlobsByRange(Option<Box<BlobsSidecar>>),
+    /// A response to a LIGHT_CLIENT_BOOTSTRAP request.
+    LightClientBootstrap(Box<LightClientBootstrap<TSpec>>),
 }
 
 impl<TSpec: EthSpec> std::convert::From<Response<TSpec>> for RPCCodedResponse<TSpec> {
@@ -1349,11 +2189,32 @@ impl<TSpec: EthSpec> std::convert::From<Response<TSpec>> for RPCCodedResponse<TS
                 Some(b) => RPCCodedResponse::Success(RPCResponse::BlocksByRange(b)),
                 None => RPCCodedResponse::StreamTermination(ResponseTermination::BlocksByRange),
             },
+            Response::BlobsByRange(r) => match r {
+                Some(b) => RPCCodedResponse::Success(RPCResponse::BlobsByRange(b)),
+                None => RPCCodedResponse::StreamTermination(ResponseTermination::BlobsByRange),
+            },
+            Response::LightClientBootstrap(b) => {
+                RPCCodedResponse::Success(RPCResponse::LightClientBootstrap(b))
+            }
             Response::Status(s) => RPCCodedResponse::Success(RPCResponse::Status(s)),
         }
     }
 }
 
+/// Extracts the IP address and TCP port of a multiaddr.
+fn tcp_socket_addr(addr: &Multiaddr) -> Option<SocketAddr> {
+    let mut protocols = addr.iter();
+    let ip = match protocols.next()? {
+        MProtocol::Ip4(ip) => IpAddr::V4(ip),
+        MProtocol::Ip6(ip) => IpAddr::V6(ip),
+        _ => return None,
+    };
+    match protocols.next()? {
+        MProtocol::Tcp(port) => Some(SocketAddr::new(ip, port)),
+        _ => None,
+    }
+}
+
 /// Persist metadata to disk
 pub fn save_metadata_to_disk<E: EthSpec>(dir: &Path, metadata: MetaData<E>, log: &slog::Logger) {
     let _ = std::fs::create_dir_all(&dir);
@@ -1383,6 +2244,9 @@ impl<AppReqId: std::fmt::Debug> slog::Value for RequestId<AppReqId> {
     ) -> slog::Result {
         match self {
             RequestId::Behaviour => slog::Value::serialize("Behaviour", record, key, serializer),
+            RequestId::Coalesced(ref id) => {
+                slog::Value::serialize(&format_args!("Coalesced({})", id), record, key, serializer)
+            }
             RequestId::Application(ref id) => {
                 slog::Value::serialize(&format_args!("{:?}", id), record, key, serializer)
             }
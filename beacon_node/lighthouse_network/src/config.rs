@@ -1,4 +1,14 @@
-use crate::types::GossipKind;
+use crate::behaviour::gossipsub_scoring_parameters::GossipScoreParamsOverride;
+use crate::discovery::{DiscoveryMode, EnrFilter};
+use crate::inbound_rate_limiter::InboundRateLimit;
+use crate::peer_manager::config::{
+    PeerEvictionPolicy, DEFAULT_DIAL_BACKOFF_BASE, DEFAULT_DIAL_BACKOFF_MAX,
+    DEFAULT_DIAL_BACKOFF_MAX_ATTEMPTS, DEFAULT_IP_SUBNET_BAN_DURATION,
+    DEFAULT_TARGET_PEERS_PER_SYNC_SUBNET,
+};
+use crate::rpc::{Protocol, RateLimiterConfig};
+use crate::subscription_rate_limiter::SubscriptionChangeLimit;
+use crate::types::{GossipKind, TopicFilterConfig};
 use crate::{Enr, PeerIdSerialized};
 use directory::{
     DEFAULT_BEACON_NODE_DIR, DEFAULT_HARDCODED_NETWORK, DEFAULT_NETWORK_DIR, DEFAULT_ROOT_DIR,
@@ -11,6 +21,7 @@ use libp2p::gossipsub::{
 use libp2p::Multiaddr;
 use serde_derive::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
@@ -56,9 +67,18 @@ pub struct Config {
     /// Data directory where node's keyfile is stored
     pub network_dir: PathBuf,
 
+    /// The file the node's libp2p key is loaded from, or created at if it does not exist. Defaults
+    /// to the `key` file of the `network_dir`. Sharing the file keeps a stable peer id.
+    pub network_key_file: Option<PathBuf>,
+
     /// IP address to listen on.
     pub listen_address: std::net::IpAddr,
 
+    /// Additional IP addresses libp2p listens on, with the same TCP port. This allows listening
+    /// on both an IPv4 and an IPv6 address. The IPv6 address is advertised in the ENR with the
+    /// port, unless it is unspecified and `enr_address` is not an IPv6 address.
+    pub extra_listen_addresses: Vec<std::net::IpAddr>,
+
     /// The TCP port that libp2p listens on.
     pub libp2p_port: u16,
 
@@ -69,15 +89,43 @@ pub struct Config {
     /// that no discovery address has been set in the CLI args.
     pub enr_address: Option<std::net::IpAddr>,
 
+    /// Whether to update the IP address and TCP port of the local ENR from the external address
+    /// observed by our peers through identify. Only applies when `enr_address` is not set.
+    pub update_enr_from_observed: bool,
+
+    /// The number of distinct /24 (IPv4) or /48 (IPv6) subnets the peers observing the same
+    /// external address must be on before the address is confirmed.
+    pub observed_addr_quorum: usize,
+
+    /// Additional addresses to broadcast to peers, for instance of a VPN, each made of an `ip4` or
+    /// `ip6` address and a `tcp` or `udp` port. They are advertised in a custom ENR field.
+    pub enr_secondary_addresses: Vec<Multiaddr>,
+
+    /// An application field to set in the local ENR at startup, as a key and a value. The key is
+    /// namespaced so that it cannot collide with the eth2 fields.
+    pub enr_app_field: Option<(String, Vec<u8>)>,
+
     /// The udp port to broadcast to peers in order to reach back for discovery.
     pub enr_udp_port: Option<u16>,
 
     /// The tcp port to broadcast to peers in order to reach back for libp2p services.
     pub enr_tcp_port: Option<u16>,
 
+    /// How often the changes to the subnet bitfields of the local ENR are published, each
+    /// publication re-advertising the ENR with a new sequence number. `None` publishes each
+    /// change immediately.
+    pub enr_update_interval: Option<Duration>,
+
     /// Target number of connected peers.
     pub target_peers: usize,
 
+    /// The highest target peer count that may be set at runtime, which sizes the connection
+    /// limits of the swarm. `None` keeps the target at most at `target_peers`.
+    pub max_target_peers: Option<usize>,
+
+    /// Target number of connected peers on each sync committee subnet we are subscribed to.
+    pub target_peers_per_sync_subnet: usize,
+
     /// Gossipsub configuration parameters.
     #[serde(skip)]
     pub gs_config: GossipsubConfig,
@@ -86,24 +134,59 @@ pub struct Config {
     #[serde(skip)]
     pub discv5_config: Discv5Config,
 
+    /// Overrides the number of sessions discv5 keeps in its cache. `None` keeps the capacity of
+    /// `discv5_config`.
+    pub discv5_session_cache_capacity: Option<usize>,
+
+    /// Overrides the time discv5 waits for a response to a request. `None` keeps the timeout of
+    /// `discv5_config`.
+    pub discv5_request_timeout: Option<Duration>,
+
+    /// Overrides the number of peers discv5 queries in parallel during a lookup. `None` keeps the
+    /// parallelism of `discv5_config`.
+    pub discv5_query_parallelism: Option<usize>,
+
     /// List of nodes to initially connect to.
     pub boot_nodes_enr: Vec<Enr>,
 
     /// List of nodes to initially connect to, on Multiaddr format.
     pub boot_nodes_multiaddr: Vec<Multiaddr>,
 
+    /// Weights of boot nodes, by peer id. Boot nodes are dialed at startup one at a time in a
+    /// random order, in which nodes of a larger weight tend to come first. The next boot node is
+    /// only dialed if we have no peers two seconds after the previous dial. Boot nodes without a
+    /// weight, including multiaddr boot nodes without a peer id, have a weight of 1. A weight of 0
+    /// dials the node last.
+    pub boot_node_weights: Vec<(PeerIdSerialized, u32)>,
+
+    /// List of EIP-1459 ENR tree URLs (`enrtree://<key>@<domain>`) to resolve boot nodes from.
+    pub boot_nodes_dns: Vec<String>,
+
     /// List of libp2p nodes to initially connect to.
     pub libp2p_nodes: Vec<Multiaddr>,
 
     /// List of trusted libp2p nodes which are not scored.
     pub trusted_peers: Vec<PeerIdSerialized>,
 
+    /// A file listing further trusted peers, one base58 encoded peer id per line. Blank lines and
+    /// lines starting with `#` are ignored. The file is read again on
+    /// `Service::reload_trusted_peers`.
+    pub trusted_peers_file: Option<PathBuf>,
+
+    /// Only connect to the `trusted_peers`. Inbound connections and dials of other peers are
+    /// refused and discovery queries are suspended.
+    pub trusted_peers_only: bool,
+
     /// Client version
     pub client_version: String,
 
     /// Disables the discovery protocol from starting.
     pub disable_discovery: bool,
 
+    /// Omits the TCP transport from the libp2p transport stack. TCP is currently the only
+    /// transport, so the service refuses to start when it is disabled.
+    pub disable_tcp: bool,
+
     /// Attempt to construct external port mappings with UPnP.
     pub upnp_enabled: bool,
 
@@ -128,8 +211,184 @@ pub struct Config {
     /// List of extra topics to initially subscribe to as strings.
     pub topics: Vec<GossipKind>,
 
+    /// Names of the application topics that may be subscribed to, on top of the eth2 topics.
+    pub application_topics: Vec<String>,
+
+    /// Maximum size of the gossip messages of specific topic kinds, overriding the global gossip
+    /// message size limit. The subnet of attestation and sync committee kinds is ignored. Larger
+    /// messages are rejected when published and penalised when received. A kind listed more than
+    /// once takes its last limit.
+    pub gossip_max_size_per_kind: Vec<(GossipKind, usize)>,
+
     /// Whether metrics are enabled.
     pub metrics_enabled: bool,
+
+    /// Quotas for inbound RPC requests. Protocols not present here use the default quotas.
+    pub rpc_rate_limits: Option<RateLimiterConfig>,
+
+    /// Quotas for the RPC requests we send to each peer. Requests exceeding them are queued until
+    /// allowed. Protocols not present here use the default quotas. `None` disables the limit.
+    pub outbound_rpc_rate_limits: Option<RateLimiterConfig>,
+
+    /// The maximum number of block bytes sent in response to a single `BlocksByRange` or
+    /// `BlocksByRoot` request. The response is terminated before the block that would exceed it.
+    /// `None` does not limit the response size.
+    pub rpc_response_byte_budget: Option<usize>,
+
+    /// The maximum uncompressed bytes in each snappy frame of our RPC responses, at most the
+    /// `MAX_SNAPPY_FRAME_SIZE` of the snappy framing format. Smaller frames let the peer start
+    /// decompressing a large response sooner, at the cost of more framing overhead.
+    pub rpc_snappy_frame_size: usize,
+
+    /// How long the peers may take to send each response chunk to our requests of specific
+    /// protocols, overriding the default RPC response timeout. A request whose response is late
+    /// fails with a timeout error.
+    pub rpc_request_timeouts: HashMap<Protocol, Duration>,
+
+    /// The maximum number of inbound RPC substreams a peer may keep open awaiting our response,
+    /// of any protocol including Status, Ping and MetaData. Further substreams opened by the peer
+    /// are reset. The limit is enforced by the connection handler, which is per peer as a peer
+    /// has at most one connection. `None` does not limit the substreams.
+    pub max_inbound_substreams_per_peer: Option<usize>,
+
+    /// Restricts the gossipsub topics the node subscribes to.
+    pub gossip_topic_filter: Option<TopicFilterConfig>,
+
+    /// Overrides of the gossipsub peer scoring thresholds, decay and topic weights.
+    pub gossip_score_params: Option<GossipScoreParamsOverride>,
+
+    /// The number of timed retries of a gossip message that could not be published due to a lack
+    /// of peers. Messages are also retried when a peer subscribes to their topic.
+    pub gossip_publish_max_retries: u32,
+
+    /// The time in seconds for which peers with an address in a banned IP subnet are refused.
+    pub ip_subnet_ban_duration: u64,
+
+    /// Limits the inbound connections accepted from each IP address. Connections over the limit
+    /// are dropped before any protocol is negotiated. `None` disables the limit.
+    pub inbound_rate_limit_per_ip: Option<InboundRateLimit>,
+
+    /// Limits the changes to the subscription of each gossipsub topic made through the `Service`.
+    /// Changes over the limit are deferred until the limit allows them. `None` disables the limit.
+    pub subscription_change_limit: Option<SubscriptionChangeLimit>,
+
+    /// Whether discovery searches for peers or only answers the queries of other nodes.
+    pub discovery_mode: DiscoveryMode,
+
+    /// Filters the ENRs found by discovery before their peers are dialed. `None` accepts all.
+    #[serde(skip)]
+    pub discovery_enr_filter: Option<EnrFilter>,
+
+    /// A subnet whose discovery query found peers less than this long ago is not searched again.
+    /// Requests for it are answered with the peers that query found. Zero disables the cache.
+    ///
+    /// The default of one slot covers the requests made for the same subnet by the duties of
+    /// several validators, without holding on to peers that may since have left the subnet.
+    pub subnet_discovery_cache_window: Duration,
+
+    /// How long to wait on shutdown for the Goodbye messages sent to connected peers to be
+    /// flushed before the connections are dropped.
+    pub shutdown_grace_period: Duration,
+
+    /// Peers that have not sent us gossip or a blocks request or response for this long are
+    /// disconnected while we are above our target peer count. `None` keeps idle peers.
+    pub idle_peer_timeout: Option<Duration>,
+
+    /// The maximum number of outbound dials in flight at once. Peers to dial beyond this limit are
+    /// queued until a dial completes or fails. `None` does not limit the dials.
+    pub max_concurrent_dials: Option<usize>,
+
+    /// The delay before redialing a peer after its first failed dial. The delay doubles with each
+    /// consecutive failure, up to `dial_backoff_max`.
+    pub dial_backoff_base: Duration,
+
+    /// The maximum delay between dials to a peer whose dials keep failing.
+    pub dial_backoff_max: Duration,
+
+    /// The number of consecutive failed dials after which a peer is not dialed again for
+    /// `dial_backoff_max`.
+    pub dial_backoff_max_attempts: u32,
+
+    /// The maximum number of peers remembered in the peer database. When exceeded, the lowest
+    /// scoring and longest disconnected peers are forgotten. Connected and banned peers are always
+    /// kept. `None` only limits the number of disconnected and banned peers kept.
+    pub max_peer_db_entries: Option<usize>,
+
+    /// Maximum number of gossip messages buffered while gossip is paused. Once full, the oldest
+    /// buffered messages are discarded. With 0, all gossip received while paused is discarded.
+    /// Discarded messages are reported to gossipsub as ignored.
+    pub paused_gossip_buffer_size: usize,
+
+    /// The agent version advertised to peers over identify, instead of the Lighthouse version. It
+    /// can be at most `MAX_IDENTIFY_AGENT_VERSION_LEN` bytes long.
+    pub identify_agent_version: Option<String>,
+
+    /// The minimum Lighthouse version, such as `v2.0.0`, of the Lighthouse peers we stay
+    /// connected to. Lighthouse peers whose identify agent version advertises an older version are
+    /// disconnected. Peers of other clients, or of an unknown client or version, are kept.
+    pub min_peer_protocol_version: Option<String>,
+
+    /// How the peers to disconnect are chosen among the low-scored ones when pruning to the
+    /// target peer count. `IpDiversity` favours disconnecting peers from the IP subnets we hold
+    /// the most peers on.
+    pub peer_eviction_policy: PeerEvictionPolicy,
+
+    /// How long gossipsub remembers the ids of seen messages to ignore their duplicates.
+    pub gossipsub_duplicate_cache_time: Duration,
+
+    /// Tracks the messages held in the gossipsub message cache, for `gossip_cache_snapshot` and
+    /// to count gossip validation results per topic kind. The tracking is approximate.
+    pub track_gossip_message_cache: bool,
+
+    /// Accept inbound connections from, and ENRs advertising, private IP addresses (RFC1918, IPv6
+    /// unique local and link-local addresses). Meant for private testnets. The discv5 table
+    /// filter is then replaced by one accepting the ENRs of global and private addresses, as
+    /// discv5 takes its filter as a function pointer which can't extend the configured one.
+    pub allow_private_addresses: bool,
+
+    /// Enables gossipsub peer exchange: peers pruned from our mesh are sent other peers of the
+    /// topic to connect to. This reveals our peers, so it is only meant for trusted networks.
+    pub gossipsub_do_px: bool,
+
+    /// The kinds of topics whose messages are flood published to every subscribed peer above
+    /// the publish threshold, the messages of the other kinds being published to the mesh only.
+    /// `None` flood publishes on every topic, as gossipsub does by default.
+    pub flood_publish_kinds: Option<Vec<GossipKind>>,
+
+    /// Overrides the target number of peers in each gossipsub mesh (D) set by `network_load`.
+    pub gossipsub_mesh_n: Option<usize>,
+
+    /// Overrides the number of mesh peers under which gossipsub grafts more peers (D_low).
+    pub gossipsub_mesh_n_low: Option<usize>,
+
+    /// Overrides the number of mesh peers above which gossipsub prunes peers (D_high).
+    pub gossipsub_mesh_n_high: Option<usize>,
+
+    /// Overrides the minimum number of outbound peers gossipsub keeps in each mesh.
+    pub gossipsub_mesh_outbound_min: Option<usize>,
+
+    /// The maximum number of message ids requested with IWANT from a peer in each heartbeat,
+    /// out of those it advertised with IHAVE.
+    pub gossipsub_max_ihave_length: usize,
+
+    /// The maximum number of IHAVE messages accepted from a peer in each heartbeat.
+    pub gossipsub_max_ihave_messages: usize,
+
+    /// How long a peer has to deliver a message we requested with IWANT. Peers breaking this
+    /// promise receive a behaviour penalty in their gossipsub score.
+    pub gossipsub_iwant_followup_time: Duration,
+
+    /// Overrides the number of heartbeats for which gossipsub caches published and forwarded
+    /// messages to answer IWANT requests.
+    pub gossipsub_history_length: Option<usize>,
+
+    /// Overrides the number of the most recent heartbeats whose messages are advertised with
+    /// IHAVE. Must not exceed the history length.
+    pub gossipsub_history_gossip: Option<usize>,
+
+    /// Overrides the fraction of the non-mesh peers of a topic that gossipsub emits IHAVE
+    /// messages to in each heartbeat.
+    pub gossipsub_gossip_factor: Option<f64>,
 }
 
 impl Default for Config {
@@ -184,21 +443,38 @@ impl Default for Config {
         // NOTE: Some of these get overridden by the corresponding CLI default values.
         Config {
             network_dir,
+            network_key_file: None,
             listen_address: "0.0.0.0".parse().expect("valid ip address"),
+            extra_listen_addresses: vec![],
             libp2p_port: 9000,
             discovery_port: 9000,
             enr_address: None,
+            update_enr_from_observed: false,
+            observed_addr_quorum: 4,
+            enr_secondary_addresses: vec![],
+            enr_app_field: None,
             enr_udp_port: None,
             enr_tcp_port: None,
+            enr_update_interval: None,
             target_peers: 50,
+            max_target_peers: None,
+            target_peers_per_sync_subnet: DEFAULT_TARGET_PEERS_PER_SYNC_SUBNET,
             gs_config,
             discv5_config,
+            discv5_session_cache_capacity: None,
+            discv5_request_timeout: None,
+            discv5_query_parallelism: None,
             boot_nodes_enr: vec![],
             boot_nodes_multiaddr: vec![],
+            boot_node_weights: vec![],
+            boot_nodes_dns: vec![],
             libp2p_nodes: vec![],
             trusted_peers: vec![],
+            trusted_peers_file: None,
+            trusted_peers_only: false,
             client_version: lighthouse_version::version_with_platform(),
             disable_discovery: false,
+            disable_tcp: false,
             upnp_enabled: true,
             network_load: 3,
             private: false,
@@ -206,8 +482,135 @@ impl Default for Config {
             import_all_attestations: false,
             shutdown_after_sync: false,
             topics: Vec::new(),
+            application_topics: Vec::new(),
+            gossip_max_size_per_kind: Vec::new(),
             metrics_enabled: false,
+            rpc_rate_limits: None,
+            outbound_rpc_rate_limits: None,
+            rpc_response_byte_budget: None,
+            rpc_snappy_frame_size: crate::rpc::MAX_SNAPPY_FRAME_SIZE,
+            rpc_request_timeouts: HashMap::new(),
+            max_inbound_substreams_per_peer: None,
+            gossip_topic_filter: None,
+            gossip_score_params: None,
+            gossip_publish_max_retries: 3,
+            ip_subnet_ban_duration: DEFAULT_IP_SUBNET_BAN_DURATION,
+            inbound_rate_limit_per_ip: None,
+            subscription_change_limit: None,
+            discovery_mode: DiscoveryMode::Full,
+            discovery_enr_filter: None,
+            subnet_discovery_cache_window: Duration::from_secs(12),
+            shutdown_grace_period: Duration::from_millis(250),
+            paused_gossip_buffer_size: 1024,
+            idle_peer_timeout: None,
+            max_concurrent_dials: None,
+            dial_backoff_base: Duration::from_secs(DEFAULT_DIAL_BACKOFF_BASE),
+            dial_backoff_max: Duration::from_secs(DEFAULT_DIAL_BACKOFF_MAX),
+            dial_backoff_max_attempts: DEFAULT_DIAL_BACKOFF_MAX_ATTEMPTS,
+            max_peer_db_entries: None,
+            identify_agent_version: None,
+            min_peer_protocol_version: None,
+            peer_eviction_policy: PeerEvictionPolicy::default(),
+            gossipsub_duplicate_cache_time: DUPLICATE_CACHE_TIME,
+            track_gossip_message_cache: false,
+            allow_private_addresses: false,
+            gossipsub_do_px: false,
+            flood_publish_kinds: None,
+            gossipsub_mesh_n: None,
+            gossipsub_mesh_n_low: None,
+            gossipsub_mesh_n_high: None,
+            gossipsub_mesh_outbound_min: None,
+            gossipsub_max_ihave_length: 5000,
+            gossipsub_max_ihave_messages: 10,
+            gossipsub_iwant_followup_time: Duration::from_secs(3),
+            gossipsub_history_length: None,
+            gossipsub_history_gossip: None,
+            gossipsub_gossip_factor: None,
+        }
+    }
+}
+
+impl Config {
+    /// Returns all the IP addresses libp2p listens on.
+    pub fn listen_addresses(&self) -> impl Iterator<Item = std::net::IpAddr> + '_ {
+        std::iter::once(self.listen_address).chain(self.extra_listen_addresses.iter().copied())
+    }
+
+    /// Returns the discv5 configuration with the overrides of this configuration applied.
+    pub fn discv5_config(&self) -> Discv5Config {
+        let mut discv5_config = self.discv5_config.clone();
+        if let Some(capacity) = self.discv5_session_cache_capacity {
+            discv5_config.session_cache_capacity = capacity;
+        }
+        if let Some(timeout) = self.discv5_request_timeout {
+            discv5_config.request_timeout = timeout;
+        }
+        if let Some(parallelism) = self.discv5_query_parallelism {
+            discv5_config.query_parallelism = parallelism;
+        }
+        if self.allow_private_addresses {
+            discv5_config.table_filter = private_table_filter;
+        }
+        discv5_config
+    }
+
+    /// Applies the gossipsub mesh degree, history and gossip overrides and the IHAVE/IWANT limits
+    /// to `gs_config`, turning the global flood publishing off if it is restricted to
+    /// `flood_publish_kinds`. Returns an error if the resulting degrees do not satisfy
+    /// `mesh_n_low <= mesh_n <= mesh_n_high` or if `history_gossip` exceeds `history_length`.
+    pub fn apply_gossipsub_overrides(
+        &self,
+        gs_config: GossipsubConfig,
+    ) -> Result<GossipsubConfig, String> {
+        let mesh_n = self.gossipsub_mesh_n.unwrap_or_else(|| gs_config.mesh_n());
+        let mesh_n_low = self
+            .gossipsub_mesh_n_low
+            .unwrap_or_else(|| gs_config.mesh_n_low());
+        let mesh_n_high = self
+            .gossipsub_mesh_n_high
+            .unwrap_or_else(|| gs_config.mesh_n_high());
+        let mesh_outbound_min = self
+            .gossipsub_mesh_outbound_min
+            .unwrap_or_else(|| gs_config.mesh_outbound_min());
+
+        if mesh_n_low > mesh_n || mesh_n > mesh_n_high {
+            return Err(format!(
+                "Invalid gossipsub mesh degrees, mesh_n_low ({}) <= mesh_n ({}) <= mesh_n_high ({}) must hold",
+                mesh_n_low, mesh_n, mesh_n_high
+            ));
+        }
+
+        let history_length = self
+            .gossipsub_history_length
+            .unwrap_or_else(|| gs_config.history_length());
+        let history_gossip = self
+            .gossipsub_history_gossip
+            .unwrap_or_else(|| gs_config.history_gossip());
+        let gossip_factor = self
+            .gossipsub_gossip_factor
+            .unwrap_or_else(|| gs_config.gossip_factor());
+
+        if history_gossip > history_length {
+            return Err(format!(
+                "Invalid gossipsub history, history_gossip ({}) <= history_length ({}) must hold",
+                history_gossip, history_length
+            ));
         }
+
+        GossipsubConfigBuilder::from(gs_config)
+            .mesh_n(mesh_n)
+            .mesh_n_low(mesh_n_low)
+            .mesh_n_high(mesh_n_high)
+            .mesh_outbound_min(mesh_outbound_min)
+            .history_length(history_length)
+            .history_gossip(history_gossip)
+            .gossip_factor(gossip_factor)
+            .max_ihave_length(self.gossipsub_max_ihave_length)
+            .max_ihave_messages(self.gossipsub_max_ihave_messages)
+            .iwant_followup_time(self.gossipsub_iwant_followup_time)
+            .flood_publish(self.flood_publish_kinds.is_none())
+            .build()
+            .map_err(|e| format!("Invalid gossipsub config: {}", e))
     }
 }
 
@@ -281,22 +684,65 @@ impl From<u8> for NetworkLoad {
     }
 }
 
-/// Return a Lighthouse specific `GossipsubConfig` where the `message_id_fn` depends on the current fork.
-pub fn gossipsub_config(network_load: u8, fork_context: Arc<ForkContext>) -> GossipsubConfig {
+/// The ways a gossipsub message-id can be derived, depending on the fork of the message's topic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MessageIdDerivation {
+    /// The message-id is derived from the message data only.
+    Data,
+    /// The message-id is derived from the message topic and data.
+    TopicAndData,
+}
+
+impl From<ForkName> for MessageIdDerivation {
+    fn from(fork_name: ForkName) -> Self {
+        match fork_name {
+            ForkName::Base => MessageIdDerivation::Data,
+            // according to: https://github.com/ethereum/consensus-specs/blob/dev/specs/merge/p2p-interface.md#the-gossip-domain-gossipsub
+            // the derivation of the message-id remains the same in the merge
+            ForkName::Altair | ForkName::Merge => MessageIdDerivation::TopicAndData,
+        }
+    }
+}
+
+/// Returns the fork digest of a topic of the form `/eth2/{fork_digest}/{name}/{encoding}`.
+fn topic_fork_digest(topic: &str) -> Option<[u8; 4]> {
+    let digest_bytes = hex::decode(topic.split('/').nth(2)?).ok()?;
+    digest_bytes.try_into().ok()
+}
+
+/// Return a Lighthouse specific `GossipsubConfig` where the `message_id_fn` depends on the fork
+/// of the message's topic.
+pub fn gossipsub_config(
+    network_load: u8,
+    duplicate_cache_time: Duration,
+    do_px: bool,
+    fork_context: Arc<ForkContext>,
+) -> GossipsubConfig {
     // The function used to generate a gossipsub message id
     // We use the first 8 bytes of SHA256(data) for content addressing
     let fast_gossip_message_id =
         |message: &RawGossipsubMessage| FastMessageId::from(&Sha256::digest(&message.data)[..8]);
+
+    // The derivation to use for each of the fork digests we know of. Messages on topics of
+    // unknown forks use the derivation of the current fork.
+    let derivations: HashMap<[u8; 4], MessageIdDerivation> = fork_context
+        .all_fork_digests()
+        .into_iter()
+        .filter_map(|digest| {
+            fork_context
+                .from_context_bytes(digest)
+                .map(|fork_name| (digest, MessageIdDerivation::from(*fork_name)))
+        })
+        .collect();
+
     fn prefix(
         prefix: [u8; 4],
         message: &GossipsubMessage,
-        fork_context: Arc<ForkContext>,
+        derivation: MessageIdDerivation,
     ) -> Vec<u8> {
         let topic_bytes = message.topic.as_str().as_bytes();
-        match fork_context.current_fork() {
-            // according to: https://github.com/ethereum/consensus-specs/blob/dev/specs/merge/p2p-interface.md#the-gossip-domain-gossipsub
-            // the derivation of the message-id remains the same in the merge
-            ForkName::Altair | ForkName::Merge => {
+        match derivation {
+            MessageIdDerivation::TopicAndData => {
                 let topic_len_bytes = topic_bytes.len().to_le_bytes();
                 let mut vec = Vec::with_capacity(
                     prefix.len() + topic_len_bytes.len() + topic_bytes.len() + message.data.len(),
@@ -307,7 +753,7 @@ pub fn gossipsub_config(network_load: u8, fork_context: Arc<ForkContext>) -> Gos
                 vec.extend_from_slice(&message.data);
                 vec
             }
-            ForkName::Base => {
+            MessageIdDerivation::Data => {
                 let mut vec = Vec::with_capacity(prefix.len() + message.data.len());
                 vec.extend_from_slice(&prefix);
                 vec.extend_from_slice(&message.data);
@@ -318,16 +764,19 @@ pub fn gossipsub_config(network_load: u8, fork_context: Arc<ForkContext>) -> Gos
 
     let is_merge_enabled = fork_context.fork_exists(ForkName::Merge);
     let gossip_message_id = move |message: &GossipsubMessage| {
+        let derivation = topic_fork_digest(message.topic.as_str())
+            .and_then(|digest| derivations.get(&digest).copied())
+            .unwrap_or_else(|| fork_context.current_fork().into());
         MessageId::from(
-            &Sha256::digest(
-                prefix(MESSAGE_DOMAIN_VALID_SNAPPY, message, fork_context.clone()).as_slice(),
-            )[..20],
+            &Sha256::digest(prefix(MESSAGE_DOMAIN_VALID_SNAPPY, message, derivation).as_slice())
+                [..20],
         )
     };
 
     let load = NetworkLoad::from(network_load);
 
-    GossipsubConfigBuilder::default()
+    let mut builder = GossipsubConfigBuilder::default();
+    builder
         .max_transmit_size(gossip_max_size(is_merge_enabled))
         .heartbeat_interval(load.heartbeat_interval)
         .mesh_n(load.mesh_n)
@@ -341,12 +790,33 @@ pub fn gossipsub_config(network_load: u8, fork_context: Arc<ForkContext>) -> Gos
         .history_gossip(load.history_gossip)
         .validate_messages() // require validation before propagation
         .validation_mode(ValidationMode::Anonymous)
-        .duplicate_cache_time(DUPLICATE_CACHE_TIME)
+        .duplicate_cache_time(duplicate_cache_time)
         .message_id_fn(gossip_message_id)
         .fast_message_id_fn(fast_gossip_message_id)
-        .allow_self_origin(true)
-        .build()
-        .expect("valid gossipsub configuration")
+        .allow_self_origin(true);
+    if do_px {
+        builder.do_px();
+    }
+    builder.build().expect("valid gossipsub configuration")
+}
+
+/// Returns true if `ip` is in a private range: RFC1918 or link-local for IPv4, unique local
+/// (`fc00::/7`) or link-local (`fe80::/10`) for IPv6.
+pub(crate) fn is_private_ip(ip: &std::net::IpAddr) -> bool {
+    match ip {
+        std::net::IpAddr::V4(ip) => ip.is_private() || ip.is_link_local(),
+        std::net::IpAddr::V6(ip) => {
+            ip.segments()[0] & 0xfe00 == 0xfc00 || ip.segments()[0] & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// The discv5 table filter with `allow_private_addresses`: an ENR is accepted if it advertises a
+/// global IPv4 address, as with the default filter, or a private IP address.
+fn private_table_filter(enr: &Enr) -> bool {
+    enr.ip()
+        .map_or(false, |ip| is_global(&ip) || is_private_ip(&ip.into()))
+        || enr.ip6().map_or(false, |ip| is_private_ip(&ip.into()))
 }
 
 /// Helper function to determine if the IpAddr is a global address or not. The `is_global()`
@@ -373,3 +843,290 @@ fn is_global(addr: &std::net::Ipv4Addr) -> bool {
             // Make sure the address is not in 0.0.0.0/8
             && addr.octets()[0] != 0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::gossipsub::TopicHash;
+    use types::{EthSpec, Hash256, MainnetEthSpec, Slot, SubnetId};
+
+    fn message_on_fork(fork_digest: [u8; 4], data: &[u8]) -> GossipsubMessage {
+        GossipsubMessage {
+            source: None,
+            data: data.to_vec(),
+            sequence_number: None,
+            topic: TopicHash::from_raw(format!(
+                "/eth2/{}/beacon_block/ssz_snappy",
+                hex::encode(fork_digest)
+            )),
+        }
+    }
+
+    #[test]
+    fn test_message_id_derivation_follows_topic_fork() {
+        let mut spec = MainnetEthSpec::default_spec();
+        spec.altair_fork_epoch = Some(types::Epoch::new(1));
+        let fork_context = Arc::new(ForkContext::new::<MainnetEthSpec>(
+            Slot::new(0),
+            Hash256::zero(),
+            &spec,
+        ));
+        // both the pre and post altair topics are live post-fork
+        fork_context.update_current_fork(ForkName::Altair);
+        let base_digest = fork_context.to_context_bytes(ForkName::Base).unwrap();
+        let altair_digest = fork_context.to_context_bytes(ForkName::Altair).unwrap();
+        let config = gossipsub_config(3, DUPLICATE_CACHE_TIME, false, fork_context);
+
+        let data = b"message data";
+        let base_message = message_on_fork(base_digest, data);
+        let altair_message = message_on_fork(altair_digest, data);
+
+        let message_id = |bytes: Vec<u8>| MessageId::from(&Sha256::digest(&bytes)[..20]);
+        let topic_bytes = altair_message.topic.as_str().as_bytes();
+        let base_id = message_id([&MESSAGE_DOMAIN_VALID_SNAPPY[..], data].concat());
+        let altair_id = message_id(
+            [
+                &MESSAGE_DOMAIN_VALID_SNAPPY[..],
+                &topic_bytes.len().to_le_bytes(),
+                topic_bytes,
+                data,
+            ]
+            .concat(),
+        );
+
+        assert_eq!(config.message_id(&base_message), base_id);
+        assert_eq!(config.message_id(&altair_message), altair_id);
+        assert_ne!(base_id, altair_id);
+    }
+
+    #[test]
+    fn test_gossipsub_duplicate_cache_time() {
+        let fork_context = Arc::new(ForkContext::new::<MainnetEthSpec>(
+            Slot::new(0),
+            Hash256::zero(),
+            &MainnetEthSpec::default_spec(),
+        ));
+        let mut config = Config::default();
+        let gs_config = gossipsub_config(
+            config.network_load,
+            config.gossipsub_duplicate_cache_time,
+            config.gossipsub_do_px,
+            fork_context.clone(),
+        );
+        assert_eq!(gs_config.duplicate_cache_time(), DUPLICATE_CACHE_TIME);
+
+        config.gossipsub_duplicate_cache_time = Duration::from_secs(60);
+        let gs_config = gossipsub_config(
+            config.network_load,
+            config.gossipsub_duplicate_cache_time,
+            config.gossipsub_do_px,
+            fork_context,
+        );
+        assert_eq!(gs_config.duplicate_cache_time(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_discv5_config_overrides() {
+        let mut config = Config::default();
+        let default_timeout = config.discv5_config.request_timeout;
+        assert_eq!(config.discv5_config().session_cache_capacity, 5000);
+
+        config.discv5_session_cache_capacity = Some(2);
+        let discv5_config = config.discv5_config();
+        assert_eq!(discv5_config.session_cache_capacity, 2);
+        // unset values are left untouched
+        assert_eq!(discv5_config.request_timeout, default_timeout);
+
+        config.discv5_request_timeout = Some(Duration::from_millis(200));
+        assert_eq!(
+            config.discv5_config().request_timeout,
+            Duration::from_millis(200)
+        );
+    }
+
+    #[test]
+    fn test_discv5_query_parallelism() {
+        let mut config = Config::default();
+        assert_eq!(config.discv5_config().query_parallelism, 5);
+
+        config.discv5_query_parallelism = Some(10);
+        assert_eq!(config.discv5_config().query_parallelism, 10);
+    }
+
+    #[test]
+    fn test_private_addresses_table_filter() {
+        let enr_key = discv5::enr::CombinedKey::generate_secp256k1();
+        let enr = |ip: &str| {
+            discv5::enr::EnrBuilder::new("v4")
+                .ip(ip.parse().unwrap())
+                .build(&enr_key)
+                .unwrap()
+        };
+
+        let default_filter = Config::default().discv5_config().table_filter;
+        assert!(!default_filter(&enr("10.0.0.1")));
+
+        let config = Config {
+            allow_private_addresses: true,
+            ..Config::default()
+        };
+        let table_filter = config.discv5_config().table_filter;
+        assert!(table_filter(&enr("8.8.8.8")));
+        assert!(table_filter(&enr("10.0.0.1")));
+        assert!(table_filter(&enr("169.254.0.1")));
+        assert!(table_filter(&enr("fd00::1")));
+        assert!(table_filter(&enr("fe80::1")));
+        assert!(!table_filter(&enr("127.0.0.1")));
+        assert!(!table_filter(&enr("::1")));
+    }
+
+    #[test]
+    fn test_gossipsub_do_px() {
+        let fork_context = Arc::new(ForkContext::new::<MainnetEthSpec>(
+            Slot::new(0),
+            Hash256::zero(),
+            &MainnetEthSpec::default_spec(),
+        ));
+        let config = Config::default();
+        assert!(!config.gossipsub_do_px);
+        assert!(!gossipsub_config(3, DUPLICATE_CACHE_TIME, false, fork_context.clone()).do_px());
+        assert!(gossipsub_config(3, DUPLICATE_CACHE_TIME, true, fork_context).do_px());
+    }
+
+    fn default_gossipsub_config() -> GossipsubConfig {
+        let fork_context = Arc::new(ForkContext::new::<MainnetEthSpec>(
+            Slot::new(0),
+            Hash256::zero(),
+            &MainnetEthSpec::default_spec(),
+        ));
+        gossipsub_config(3, DUPLICATE_CACHE_TIME, false, fork_context)
+    }
+
+    #[test]
+    fn test_gossipsub_mesh_overrides() {
+        let mut config = Config::default();
+        let gs_config = config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .unwrap();
+        assert_eq!(gs_config.mesh_n(), 5);
+        assert_eq!(gs_config.mesh_n_low(), 3);
+        assert_eq!(gs_config.mesh_n_high(), 10);
+        assert_eq!(gs_config.mesh_outbound_min(), 2);
+
+        config.gossipsub_mesh_n = Some(12);
+        config.gossipsub_mesh_n_low = Some(8);
+        config.gossipsub_mesh_n_high = Some(16);
+        config.gossipsub_mesh_outbound_min = Some(4);
+        let gs_config = config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .unwrap();
+        assert_eq!(gs_config.mesh_n(), 12);
+        assert_eq!(gs_config.mesh_n_low(), 8);
+        assert_eq!(gs_config.mesh_n_high(), 16);
+        assert_eq!(gs_config.mesh_outbound_min(), 4);
+    }
+
+    #[test]
+    fn test_gossipsub_ihave_iwant_limits() {
+        let mut config = Config::default();
+        let gs_config = config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .unwrap();
+        assert_eq!(gs_config.max_ihave_length(), 5000);
+        assert_eq!(gs_config.max_ihave_messages(), 10);
+        assert_eq!(gs_config.iwant_followup_time(), Duration::from_secs(3));
+
+        config.gossipsub_max_ihave_length = 1000;
+        config.gossipsub_max_ihave_messages = 3;
+        config.gossipsub_iwant_followup_time = Duration::from_secs(1);
+        let gs_config = config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .unwrap();
+        assert_eq!(gs_config.max_ihave_length(), 1000);
+        assert_eq!(gs_config.max_ihave_messages(), 3);
+        assert_eq!(gs_config.iwant_followup_time(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_gossipsub_mesh_overrides_invalid_ordering() {
+        let mut config = Config::default();
+        config.gossipsub_mesh_n_low = Some(6);
+        assert!(config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .is_err());
+
+        let mut config = Config::default();
+        config.gossipsub_mesh_n = Some(20);
+        assert!(config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .is_err());
+    }
+
+    #[test]
+    fn test_gossipsub_history_and_gossip_factor() {
+        let mut config = Config::default();
+        let gs_config = config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .unwrap();
+        assert_eq!(gs_config.history_length(), 12);
+        assert_eq!(gs_config.history_gossip(), 3);
+        assert_eq!(gs_config.gossip_factor(), 0.25);
+
+        config.gossipsub_history_length = Some(8);
+        config.gossipsub_history_gossip = Some(4);
+        config.gossipsub_gossip_factor = Some(0.5);
+        let gs_config = config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .unwrap();
+        assert_eq!(gs_config.history_length(), 8);
+        assert_eq!(gs_config.history_gossip(), 4);
+        assert_eq!(gs_config.gossip_factor(), 0.5);
+    }
+
+    #[test]
+    fn test_gossip_max_size_per_kind_json_round_trip() {
+        let mut config = Config::default();
+        config.gossip_max_size_per_kind = vec![
+            (GossipKind::BeaconBlock, 2_000_000),
+            (GossipKind::Attestation(SubnetId::new(0)), 1_000),
+        ];
+        let json = serde_json::to_string(&config.gossip_max_size_per_kind).unwrap();
+        let decoded: Vec<(GossipKind, usize)> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, config.gossip_max_size_per_kind);
+    }
+
+    #[test]
+    fn test_flood_publish_kinds() {
+        let mut config = Config::default();
+        let gs_config = config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .unwrap();
+        assert!(gs_config.flood_publish());
+
+        config.flood_publish_kinds = Some(vec![GossipKind::BeaconBlock]);
+        let gs_config = config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .unwrap();
+        assert!(!gs_config.flood_publish());
+    }
+
+    #[test]
+    fn test_gossipsub_history_gossip_exceeds_history_length() {
+        let mut config = Config::default();
+        config.gossipsub_history_gossip = Some(13);
+        assert!(config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .is_err());
+
+        let mut config = Config::default();
+        config.gossipsub_history_length = Some(2);
+        assert!(config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .is_err());
+
+        config.gossipsub_history_gossip = Some(2);
+        assert!(config
+            .apply_gossipsub_overrides(default_gossipsub_config())
+            .is_ok());
+    }
+}
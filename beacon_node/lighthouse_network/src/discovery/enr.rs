@@ -23,6 +23,15 @@ pub const ETH2_ENR_KEY: &str = "eth2";
 pub const ATTESTATION_BITFIELD_ENR_KEY: &str = "attnets";
 /// The ENR field specifying the sync committee subnet bitfield.
 pub const SYNC_COMMITTEE_BITFIELD_ENR_KEY: &str = "syncnets";
+/// The ENR field specifying additional addresses the node is reachable at.
+pub const SECONDARY_ADDRESSES_ENR_KEY: &str = "addrs";
+/// The prefix of the ENR fields set by applications, keeping them apart from the eth2 fields.
+pub const APP_ENR_KEY_PREFIX: &str = "app-";
+
+/// Returns the ENR key of the application field `key`.
+pub fn app_enr_key(key: &str) -> String {
+    format!("{}{}", APP_ENR_KEY_PREFIX, key)
+}
 
 /// Extension trait for ENR's within Eth2.
 pub trait Eth2Enr {
@@ -155,6 +164,34 @@ pub fn create_enr_builder_from_config<T: EnrKey>(
     if enable_tcp {
         let tcp_port = config.enr_tcp_port.unwrap_or(config.libp2p_port);
         builder.tcp(tcp_port);
+        // an additional IPv6 listening address is reachable on the same port. The port is only
+        // advertised along with an IPv6 address, which an unspecified listening address is not.
+        if config.listen_address.is_ipv4()
+            && config.extra_listen_addresses.iter().any(|ip| ip.is_ipv6())
+        {
+            let ip6 = config.enr_address.filter(|ip| ip.is_ipv6()).or_else(|| {
+                config
+                    .extra_listen_addresses
+                    .iter()
+                    .copied()
+                    .find(|ip| ip.is_ipv6() && !ip.is_unspecified())
+            });
+            if let Some(ip6) = ip6 {
+                builder.ip(ip6);
+                builder.tcp6(tcp_port);
+            }
+        }
+    }
+    if !config.enr_secondary_addresses.is_empty() {
+        let addresses: Vec<Vec<u8>> = config
+            .enr_secondary_addresses
+            .iter()
+            .map(|multiaddr| multiaddr.to_vec())
+            .collect();
+        builder.add_value(SECONDARY_ADDRESSES_ENR_KEY, &addresses.as_ssz_bytes());
+    }
+    if let Some((key, value)) = &config.enr_app_field {
+        builder.add_value(app_enr_key(key), value);
     }
     builder
 }
@@ -190,8 +227,11 @@ pub fn build_enr<T: EthSpec>(
 fn compare_enr(local_enr: &Enr, disk_enr: &Enr) -> bool {
     // take preference over disk_enr address if one is not specified
     (local_enr.ip().is_none() || local_enr.ip() == disk_enr.ip())
+        && (local_enr.ip6().is_none() || local_enr.ip6() == disk_enr.ip6())
         // tcp ports must match
         && local_enr.tcp() == disk_enr.tcp()
+        && local_enr.tcp6() == disk_enr.tcp6()
+        && local_enr.get(SECONDARY_ADDRESSES_ENR_KEY) == disk_enr.get(SECONDARY_ADDRESSES_ENR_KEY)
         // must match on the same fork
         && local_enr.get(ETH2_ENR_KEY) == disk_enr.get(ETH2_ENR_KEY)
         // take preference over disk udp port if one is not specified
@@ -200,6 +240,16 @@ fn compare_enr(local_enr: &Enr, disk_enr: &Enr) -> bool {
         // otherwise we use a new ENR. This will likely only be true for non-validating nodes
         && local_enr.get(ATTESTATION_BITFIELD_ENR_KEY) == disk_enr.get(ATTESTATION_BITFIELD_ENR_KEY)
         && local_enr.get(SYNC_COMMITTEE_BITFIELD_ENR_KEY) == disk_enr.get(SYNC_COMMITTEE_BITFIELD_ENR_KEY)
+        // the application fields must match the configured one
+        && app_fields(local_enr) == app_fields(disk_enr)
+}
+
+/// Returns the application fields of an ENR.
+fn app_fields(enr: &Enr) -> Vec<(&[u8], &[u8])> {
+    enr.iter()
+        .filter(|(key, _)| key.starts_with(APP_ENR_KEY_PREFIX.as_bytes()))
+        .map(|(key, value)| (key.as_slice(), value))
+        .collect()
 }
 
 /// Loads enr from the given directory
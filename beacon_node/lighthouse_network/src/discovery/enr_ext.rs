@@ -1,7 +1,9 @@
 //! ENR extension trait to support libp2p integration.
+use super::enr::{app_enr_key, SECONDARY_ADDRESSES_ENR_KEY};
 use crate::{Enr, Multiaddr, PeerId};
 use discv5::enr::{CombinedKey, CombinedPublicKey};
 use libp2p::core::{identity::Keypair, identity::PublicKey, multiaddr::Protocol};
+use ssz::Decode;
 use tiny_keccak::{Hasher, Keccak};
 
 /// Extend ENR for libp2p types.
@@ -11,6 +13,8 @@ pub trait EnrExt {
 
     /// Returns a list of multiaddrs if the ENR has an `ip` and either a `tcp` or `udp` key **or** an `ip6` and either a `tcp6` or `udp6`.
     /// The vector remains empty if these fields are not defined.
+    ///
+    /// The additional addresses advertised in the `addrs` field are returned after these.
     fn multiaddr(&self) -> Vec<Multiaddr>;
 
     /// Returns a list of multiaddrs with the `PeerId` prepended.
@@ -24,6 +28,18 @@ pub trait EnrExt {
 
     /// Returns any multiaddrs that contain the TCP protocol.
     fn multiaddr_tcp(&self) -> Vec<Multiaddr>;
+
+    /// Returns the value of the application field `key`, if set.
+    fn app_field(&self, key: &str) -> Option<Vec<u8>>;
+
+    /// Sets the application field `key` and re-signs the record with `enr_key`, increasing its
+    /// sequence number. The field is stored under a namespaced key, see `app_enr_key`.
+    fn set_app_field(
+        &mut self,
+        key: &str,
+        value: Vec<u8>,
+        enr_key: &CombinedKey,
+    ) -> Result<(), String>;
 }
 
 /// Extend ENR CombinedPublicKey for libp2p types.
@@ -74,6 +90,7 @@ impl EnrExt for Enr {
                 multiaddrs.push(multiaddr);
             }
         }
+        multiaddrs.extend(secondary_multiaddrs(self));
         multiaddrs
     }
 
@@ -114,6 +131,10 @@ impl EnrExt for Enr {
                 multiaddrs.push(multiaddr);
             }
         }
+        multiaddrs.extend(secondary_multiaddrs(self).into_iter().map(|mut multiaddr| {
+            multiaddr.push(Protocol::P2p(peer_id.into()));
+            multiaddr
+        }));
         multiaddrs
     }
 
@@ -140,6 +161,12 @@ impl EnrExt for Enr {
                 multiaddrs.push(multiaddr);
             }
         }
+        multiaddrs.extend(secondary_multiaddrs(self).into_iter().filter(is_tcp).map(
+            |mut multiaddr| {
+                multiaddr.push(Protocol::P2p(peer_id.into()));
+                multiaddr
+            },
+        ));
         multiaddrs
     }
 
@@ -166,6 +193,12 @@ impl EnrExt for Enr {
                 multiaddrs.push(multiaddr);
             }
         }
+        multiaddrs.extend(secondary_multiaddrs(self).into_iter().filter(is_udp).map(
+            |mut multiaddr| {
+                multiaddr.push(Protocol::P2p(peer_id.into()));
+                multiaddr
+            },
+        ));
         multiaddrs
     }
 
@@ -187,8 +220,58 @@ impl EnrExt for Enr {
                 multiaddrs.push(multiaddr);
             }
         }
+        multiaddrs.extend(secondary_multiaddrs(self).into_iter().filter(is_tcp));
         multiaddrs
     }
+
+    /// Returns the value of the application field `key`, if set.
+    fn app_field(&self, key: &str) -> Option<Vec<u8>> {
+        self.get(app_enr_key(key)).map(|value| value.to_vec())
+    }
+
+    /// Sets the application field `key` and re-signs the record with `enr_key`.
+    fn set_app_field(
+        &mut self,
+        key: &str,
+        value: Vec<u8>,
+        enr_key: &CombinedKey,
+    ) -> Result<(), String> {
+        self.insert(app_enr_key(key), &value, enr_key)
+            .map(|_| ())
+            .map_err(|e| format!("Could not set the application ENR field: {:?}", e))
+    }
+}
+
+/// Returns the additional addresses advertised in the ENR. Only addresses made of an IP address
+/// and a TCP or UDP port are returned.
+fn secondary_multiaddrs(enr: &Enr) -> Vec<Multiaddr> {
+    enr.get(SECONDARY_ADDRESSES_ENR_KEY)
+        .and_then(|bytes| Vec::<Vec<u8>>::from_ssz_bytes(bytes).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|bytes| Multiaddr::try_from(bytes).ok())
+        .filter(|multiaddr| {
+            let mut protocols = multiaddr.iter();
+            matches!(
+                (protocols.next(), protocols.next(), protocols.next()),
+                (
+                    Some(Protocol::Ip4(_) | Protocol::Ip6(_)),
+                    Some(Protocol::Tcp(_) | Protocol::Udp(_)),
+                    None
+                )
+            )
+        })
+        .collect()
+}
+
+/// Returns true if `multiaddr` has a TCP port.
+fn is_tcp(multiaddr: &Multiaddr) -> bool {
+    multiaddr.iter().any(|p| matches!(p, Protocol::Tcp(_)))
+}
+
+/// Returns true if `multiaddr` has a UDP port.
+fn is_udp(multiaddr: &Multiaddr) -> bool {
+    multiaddr.iter().any(|p| matches!(p, Protocol::Udp(_)))
 }
 
 impl CombinedKeyPublicExt for CombinedPublicKey {
@@ -311,4 +394,62 @@ mod tests {
 
         assert_eq!(enr.node_id(), node_id);
     }
+
+    #[test]
+    fn test_secondary_addresses() {
+        let mut config = crate::NetworkConfig::default();
+        config.enr_address = Some("1.2.3.4".parse().unwrap());
+        config.enr_tcp_port = Some(9000);
+        config.enr_secondary_addresses = vec![
+            "/ip4/10.0.0.1/tcp/9001".parse().unwrap(),
+            "/ip6/::1/udp/9002".parse().unwrap(),
+        ];
+        let enr = super::super::enr::build_enr::<types::MainnetEthSpec>(
+            &CombinedKey::generate_secp256k1(),
+            &config,
+            &types::EnrForkId::default(),
+        )
+        .unwrap();
+
+        let expected: Vec<Multiaddr> = vec![
+            "/ip4/1.2.3.4/tcp/9000".parse().unwrap(),
+            "/ip4/10.0.0.1/tcp/9001".parse().unwrap(),
+            "/ip6/::1/udp/9002".parse().unwrap(),
+        ];
+        assert_eq!(enr.multiaddr(), expected);
+        assert_eq!(enr.multiaddr_tcp(), expected[..2].to_vec());
+        assert_eq!(
+            enr.multiaddr_p2p_udp(),
+            vec![expected[2]
+                .clone()
+                .with(Protocol::P2p(enr.peer_id().into()))]
+        );
+    }
+
+    #[test]
+    fn test_app_field() {
+        let mut config = crate::NetworkConfig::default();
+        config.enr_app_field = Some(("version".into(), vec![1]));
+        let enr_key = CombinedKey::generate_secp256k1();
+        let mut enr = super::super::enr::build_enr::<types::MainnetEthSpec>(
+            &enr_key,
+            &config,
+            &types::EnrForkId::default(),
+        )
+        .unwrap();
+        assert_eq!(enr.app_field("version"), Some(vec![1]));
+        // The field is namespaced
+        assert_eq!(enr.get("version"), None);
+
+        let seq = enr.seq();
+        enr.set_app_field("version", vec![2], &enr_key).unwrap();
+        assert_eq!(enr.seq(), seq + 1);
+        assert!(enr.verify());
+
+        // A peer reads the field from the record it received
+        let received: Enr = enr.to_base64().parse().unwrap();
+        assert!(received.verify());
+        assert_eq!(received.app_field("version"), Some(vec![2]));
+        assert_eq!(received.app_field("other"), None);
+    }
 }
@@ -0,0 +1,267 @@
+//! Resolves boot nodes published as an ENR tree in DNS, as specified in
+//! [EIP-1459](https://eips.ethereum.org/EIPS/eip-1459).
+//!
+//! A tree is located by a URL of the form `enrtree://<public key>@<domain>`. The TXT record at
+//! `<domain>` holds the signed root of the tree, which points at branches and ENR leaves stored
+//! at `<hash>.<domain>`. Link subtrees, which point to other trees, are not followed.
+
+use crate::Enr;
+use data_encoding::{BASE32_NOPAD, BASE64URL_NOPAD};
+use discv5::enr::{k256::ecdsa::VerifyingKey, EnrPublicKey};
+use futures::future::join_all;
+use slog::debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use tiny_keccak::{Hasher, Keccak};
+use trust_dns_resolver::TokioAsyncResolver;
+
+const URL_PREFIX: &str = "enrtree://";
+const ROOT_PREFIX: &str = "enrtree-root:v1";
+const BRANCH_PREFIX: &str = "enrtree-branch:";
+const ENR_PREFIX: &str = "enr:";
+
+/// The maximum number of tree entries looked up while resolving a single tree.
+const MAX_TREE_LOOKUPS: usize = 1000;
+
+/// A future resolving to the content of a TXT record.
+pub type TxtLookup<'a> = Pin<Box<dyn Future<Output = Result<String, String>> + Send + 'a>>;
+
+/// Looks up TXT records in DNS.
+pub trait TxtResolver: Send + Sync {
+    /// Returns the concatenated strings of the TXT record at `name`.
+    fn lookup_txt<'a>(&'a self, name: &'a str) -> TxtLookup<'a>;
+}
+
+/// Resolves TXT records using the system's DNS configuration.
+pub struct SystemTxtResolver(TokioAsyncResolver);
+
+impl SystemTxtResolver {
+    /// NOTE: Creating the resolver requires running within a tokio execution environment.
+    pub fn new() -> Result<Self, String> {
+        TokioAsyncResolver::tokio_from_system_conf()
+            .map(SystemTxtResolver)
+            .map_err(|e| format!("Could not create DNS resolver: {}", e))
+    }
+}
+
+impl TxtResolver for SystemTxtResolver {
+    fn lookup_txt<'a>(&'a self, name: &'a str) -> TxtLookup<'a> {
+        Box::pin(async move {
+            let lookup = self.0.txt_lookup(name).await.map_err(|e| e.to_string())?;
+            let record = lookup
+                .iter()
+                .next()
+                .ok_or_else(|| format!("No TXT record at {}", name))?;
+            Ok(record
+                .txt_data()
+                .iter()
+                .map(|data| String::from_utf8_lossy(data))
+                .collect())
+        })
+    }
+}
+
+/// Resolves all the ENRs of the tree at `url`.
+///
+/// Fails if the root of the tree can't be resolved or verified. Entries of the tree that can't be
+/// resolved are skipped.
+pub async fn resolve_enr_tree(
+    resolver: &dyn TxtResolver,
+    url: &str,
+    log: &slog::Logger,
+) -> Result<Vec<Enr>, String> {
+    let (public_key, domain) = parse_url(url)?;
+    let root = resolver.lookup_txt(domain).await?;
+    let enr_root = verify_root(&root, &public_key)?;
+
+    // the entries of each level of the tree are looked up concurrently
+    let mut enrs = Vec::new();
+    let mut pending = vec![enr_root];
+    let mut lookups = 0;
+    while !pending.is_empty() {
+        if lookups + pending.len() > MAX_TREE_LOOKUPS {
+            debug!(log, "ENR tree lookup limit reached"; "url" => url);
+            pending.truncate(MAX_TREE_LOOKUPS - lookups);
+            if pending.is_empty() {
+                break;
+            }
+        }
+        lookups += pending.len();
+
+        let names = pending
+            .drain(..)
+            .map(|hash| (format!("{}.{}", hash, domain), hash))
+            .collect::<Vec<_>>();
+        let entries = join_all(names.iter().map(|(name, _)| resolver.lookup_txt(name))).await;
+        for ((name, hash), entry) in names.iter().zip(entries) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    debug!(log, "Could not resolve ENR tree entry"; "name" => name, "error" => e);
+                    continue;
+                }
+            };
+            if !entry_hash(&entry).eq_ignore_ascii_case(hash) {
+                debug!(log, "ENR tree entry does not match its hash"; "name" => name);
+                continue;
+            }
+
+            if let Some(children) = entry.strip_prefix(BRANCH_PREFIX) {
+                pending.extend(
+                    children
+                        .split(',')
+                        .filter(|child| !child.is_empty())
+                        .map(String::from),
+                );
+            } else if entry.starts_with(ENR_PREFIX) {
+                match Enr::from_str(&entry) {
+                    Ok(enr) => enrs.push(enr),
+                    Err(e) => debug!(log, "Invalid ENR in ENR tree"; "name" => name, "error" => e),
+                }
+            }
+        }
+    }
+    Ok(enrs)
+}
+
+/// Splits a tree URL into the public key signing the tree and the domain of its root.
+fn parse_url(url: &str) -> Result<(VerifyingKey, &str), String> {
+    let (encoded_key, domain) = url
+        .strip_prefix(URL_PREFIX)
+        .and_then(|location| location.split_once('@'))
+        .ok_or_else(|| format!("Invalid ENR tree URL: {}", url))?;
+    let key_bytes = BASE32_NOPAD
+        .decode(encoded_key.to_ascii_uppercase().as_bytes())
+        .map_err(|e| format!("Invalid ENR tree public key: {}", e))?;
+    let public_key = VerifyingKey::from_sec1_bytes(&key_bytes)
+        .map_err(|e| format!("Invalid ENR tree public key: {}", e))?;
+    Ok((public_key, domain))
+}
+
+/// Verifies the signature of a root record and returns the hash of the ENR subtree.
+fn verify_root(root: &str, public_key: &VerifyingKey) -> Result<String, String> {
+    let (signed, signature) = root
+        .split_once(" sig=")
+        .ok_or_else(|| format!("Invalid ENR tree root: {}", root))?;
+    let signature = BASE64URL_NOPAD
+        .decode(signature.trim_end_matches('=').as_bytes())
+        .map_err(|e| format!("Invalid ENR tree root signature: {}", e))?;
+    // the signature may carry a trailing recovery id
+    if signature.len() < 64 || !public_key.verify_v4(signed.as_bytes(), &signature[..64]) {
+        return Err("Invalid ENR tree root signature".into());
+    }
+
+    let mut fields = signed.split(' ');
+    if fields.next() != Some(ROOT_PREFIX) {
+        return Err(format!("Invalid ENR tree root: {}", root));
+    }
+    fields
+        .find_map(|field| field.strip_prefix("e="))
+        .map(String::from)
+        .ok_or_else(|| format!("ENR tree root has no ENR subtree: {}", root))
+}
+
+/// The subdomain of a tree entry: the base32 encoding of the first 16 bytes of its keccak256
+/// hash.
+fn entry_hash(entry: &str) -> String {
+    let mut hasher = Keccak::v256();
+    hasher.update(entry.as_bytes());
+    let mut output = [0_u8; 32];
+    hasher.finalize(&mut output);
+    BASE32_NOPAD.encode(&output[..16])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use discv5::enr::{CombinedKey, EnrBuilder, EnrKey};
+    use std::collections::{HashMap, HashSet};
+
+    /// Serves TXT records from memory.
+    #[derive(Default)]
+    pub(crate) struct StaticResolver(pub HashMap<String, String>);
+
+    impl TxtResolver for StaticResolver {
+        fn lookup_txt<'a>(&'a self, name: &'a str) -> TxtLookup<'a> {
+            let record = self
+                .0
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("No TXT record at {}", name));
+            Box::pin(async move { record })
+        }
+    }
+
+    /// Publishes `enrs` as a tree under `domain`, with a single branch. Returns the resolver
+    /// serving the tree and its URL.
+    pub(crate) fn build_tree(domain: &str, enrs: &[Enr]) -> (StaticResolver, String) {
+        let mut resolver = StaticResolver::default();
+        let mut add_entry = |entry: String| {
+            let hash = entry_hash(&entry);
+            resolver.0.insert(format!("{}.{}", hash, domain), entry);
+            hash
+        };
+
+        let leaves: Vec<_> = enrs.iter().map(|enr| add_entry(enr.to_base64())).collect();
+        let branch = add_entry(format!("{}{}", BRANCH_PREFIX, leaves.join(",")));
+
+        let key = match CombinedKey::generate_secp256k1() {
+            CombinedKey::Secp256k1(key) => key,
+            _ => unreachable!(),
+        };
+        let signed = format!("{} e={} l={} seq=1", ROOT_PREFIX, branch, branch);
+        let mut signature = key.sign_v4(signed.as_bytes()).unwrap();
+        // recovery id, which is not checked
+        signature.push(0);
+        resolver.0.insert(
+            domain.to_string(),
+            format!("{} sig={}", signed, BASE64URL_NOPAD.encode(&signature)),
+        );
+
+        let url = format!(
+            "{}{}@{}",
+            URL_PREFIX,
+            BASE32_NOPAD.encode(&key.public().encode()),
+            domain
+        );
+        (resolver, url)
+    }
+
+    fn build_enr() -> Enr {
+        EnrBuilder::new("v4")
+            .build(&CombinedKey::generate_secp256k1())
+            .unwrap()
+    }
+
+    fn build_log() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    #[tokio::test]
+    async fn resolves_all_tree_enrs() {
+        let enrs = vec![build_enr(), build_enr()];
+        let (resolver, url) = build_tree("nodes.example.org", &enrs);
+
+        let resolved = resolve_enr_tree(&resolver, &url, &build_log())
+            .await
+            .unwrap();
+        let resolved: HashSet<_> = resolved.iter().map(Enr::node_id).collect();
+        let expected: HashSet<_> = enrs.iter().map(Enr::node_id).collect();
+        assert_eq!(resolved, expected);
+    }
+
+    #[tokio::test]
+    async fn rejects_root_signed_by_another_key() {
+        let (mut resolver, url) = build_tree("nodes.example.org", &[build_enr()]);
+        let (other, _) = build_tree("nodes.example.org", &[build_enr()]);
+        resolver.0.insert(
+            "nodes.example.org".into(),
+            other.0["nodes.example.org"].clone(),
+        );
+
+        assert!(resolve_enr_tree(&resolver, &url, &build_log())
+            .await
+            .is_err());
+    }
+}
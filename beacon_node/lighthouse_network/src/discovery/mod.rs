@@ -8,9 +8,11 @@ pub mod enr_ext;
 
 // Allow external use of the lighthouse ENR builder
 use crate::behaviour::TARGET_SUBNET_PEERS;
+use crate::config::is_private_ip;
 use crate::metrics;
+use crate::types::{EnrAttestationBitfield, EnrSyncCommitteeBitfield};
 use crate::{error, Enr, NetworkConfig, NetworkGlobals, Subnet, SubnetDiscovery};
-use discv5::{enr::NodeId, Discv5, Discv5Event};
+use discv5::{enr::NodeId, kbucket, Discv5, Discv5Event};
 pub use enr::{
     build_enr, create_enr_builder_from_config, load_enr_from_disk, use_or_load_enr, CombinedKey,
     Eth2Enr,
@@ -29,8 +31,9 @@ pub use libp2p::{
     },
 };
 use lru::LruCache;
+use serde_derive::{Deserialize, Serialize};
 use slog::{crit, debug, error, info, trace, warn};
-use ssz::Encode;
+use ssz::{Decode, Encode};
 use std::{
     collections::{HashMap, VecDeque},
     net::{IpAddr, SocketAddr},
@@ -40,10 +43,13 @@ use std::{
     task::{Context, Poll},
     time::{Duration, Instant},
 };
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Interval;
 use types::{EnrForkId, EthSpec};
 
+mod enr_tree;
 mod subnet_predicate;
+use enr_tree::{resolve_enr_tree, SystemTxtResolver, TxtResolver};
 pub use subnet_predicate::subnet_predicate;
 
 /// Local ENR storage filename.
@@ -66,6 +72,62 @@ const MAX_SUBNETS_IN_QUERY: usize = 3;
 pub const FIND_NODE_QUERY_CLOSEST_PEERS: usize = 16;
 /// The threshold for updating `min_ttl` on a connected peer.
 const DURATION_DIFFERENCE: Duration = Duration::from_millis(1);
+/// How long the ENR trees of the DNS boot nodes may take to resolve at startup.
+const DNS_BOOT_NODES_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A predicate the ENRs found by discovery must satisfy for their peers to be dialed.
+#[derive(Clone)]
+pub struct EnrFilter(Arc<dyn Fn(&Enr) -> bool + Send + Sync>);
+
+impl EnrFilter {
+    pub fn new(filter: impl Fn(&Enr) -> bool + Send + Sync + 'static) -> Self {
+        EnrFilter(Arc::new(filter))
+    }
+
+    /// Whether the peer of `enr` may be dialed.
+    pub fn accepts(&self, enr: &Enr) -> bool {
+        (self.0)(enr)
+    }
+}
+
+impl std::fmt::Debug for EnrFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EnrFilter")
+    }
+}
+
+/// How actively discovery takes part in the discv5 network.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DiscoveryMode {
+    /// Answer the queries of other nodes and search for peers.
+    Full,
+    /// Only answer the queries of other nodes. The routing table is maintained from inbound
+    /// traffic and no queries are started, so no peers are found through discovery.
+    PassiveRespond,
+}
+
+impl Default for DiscoveryMode {
+    fn default() -> Self {
+        DiscoveryMode::Full
+    }
+}
+
+/// The number of k-buckets of the discovery routing table.
+const NUM_BUCKETS: usize = 256;
+
+/// A summary of the entries of the discovery routing table.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiscoveryTableStats {
+    /// The number of entries in the routing table.
+    pub total_entries: usize,
+    /// The number of entries whose node is currently connected.
+    pub connected_entries: usize,
+    /// The number of entries whose node is currently disconnected.
+    pub disconnected_entries: usize,
+    /// The number of entries of each k-bucket. The bucket at index `i` holds the nodes at log2
+    /// distance `i + 1` of the local node.
+    pub bucket_entries: Vec<usize>,
+}
 
 /// The events emitted by polling discovery.
 pub enum DiscoveryEvent {
@@ -166,9 +228,29 @@ pub struct Discovery<TSpec: EthSpec> {
     /// A queue of subnet queries to be processed.
     queued_queries: VecDeque<SubnetQuery>,
 
+    /// The ENRs found on each subnet by the subnet queries completed within
+    /// `subnet_query_cache_window`, with the time the query completed.
+    subnet_query_cache: HashMap<Subnet, (Instant, Vec<Enr>)>,
+
+    /// How long the results of a subnet query answer new requests for the subnet.
+    subnet_query_cache_window: Duration,
+
+    /// The cached peers answering subnet requests, waiting to be returned to the peer manager.
+    cached_query_results: HashMap<PeerId, Option<Instant>>,
+
+    /// Filters the ENRs found by queries before their peers are dialed.
+    enr_filter: Option<EnrFilter>,
+
+    /// Whether the ENRs found by queries may advertise private IP addresses.
+    allow_private_addresses: bool,
+
     /// Active discovery queries.
     active_queries: FuturesUnordered<std::pin::Pin<Box<dyn Future<Output = QueryResult> + Send>>>,
 
+    /// Active queries requested through `Discovery::discover_subnet_enrs`. Their results are sent
+    /// to the requester rather than to the peer manager.
+    requested_queries: FuturesUnordered<std::pin::Pin<Box<dyn Future<Output = ()> + Send>>>,
+
     /// The discv5 event stream.
     event_stream: EventStream,
 
@@ -176,6 +258,26 @@ pub struct Discovery<TSpec: EthSpec> {
     /// always false.
     pub started: bool,
 
+    /// Whether we search for peers or only answer the queries of other nodes.
+    mode: DiscoveryMode,
+
+    /// Whether our queries are suspended, as they are while only trusted peers may connect.
+    queries_suspended: bool,
+
+    /// The boot node ENRs given in the config, added to the routing table again by
+    /// `reseed_boot_nodes`.
+    boot_nodes: Vec<Enr>,
+
+    /// The number of peers we want on each sync committee subnet.
+    target_peers_per_sync_subnet: usize,
+
+    /// Publishes the pending changes of our ENR subnet bitfields on each tick. `None` publishes
+    /// each change immediately.
+    enr_update_interval: Option<Interval>,
+
+    /// The subnet bitfields waiting for the next tick of `enr_update_interval`, by ENR key.
+    pending_enr_updates: HashMap<&'static str, Vec<u8>>,
+
     /// Logger for the discovery behaviour.
     log: slog::Logger,
 }
@@ -204,7 +306,7 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
         // convert the keypair into an ENR key
         let enr_key: CombinedKey = CombinedKey::from_libp2p(local_key)?;
 
-        let mut discv5 = Discv5::new(local_enr, enr_key, config.discv5_config.clone())
+        let mut discv5 = Discv5::new(local_enr, enr_key, config.discv5_config())
             .map_err(|e| format!("Discv5 service failed. Error: {:?}", e))?;
 
         // Add bootnodes to routing table
@@ -229,6 +331,23 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
             });
         }
 
+        // Add the boot nodes published in DNS
+        if !config.boot_nodes_dns.is_empty() {
+            match SystemTxtResolver::new() {
+                Ok(resolver) => {
+                    add_dns_boot_nodes(
+                        &mut discv5,
+                        &resolver,
+                        &config.boot_nodes_dns,
+                        DNS_BOOT_NODES_TIMEOUT,
+                        &log,
+                    )
+                    .await
+                }
+                Err(e) => warn!(log, "Could not resolve DNS boot nodes"; "error" => e),
+            }
+        }
+
         // Start the discv5 service and obtain an event stream
         let event_stream = if !config.disable_discovery {
             discv5
@@ -292,10 +411,22 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
             network_globals,
             find_peer_active: false,
             queued_queries: VecDeque::with_capacity(10),
+            subnet_query_cache: HashMap::new(),
+            subnet_query_cache_window: config.subnet_discovery_cache_window,
+            cached_query_results: HashMap::new(),
             active_queries: FuturesUnordered::new(),
+            requested_queries: FuturesUnordered::new(),
             discv5,
             event_stream,
             started: !config.disable_discovery,
+            mode: config.discovery_mode,
+            queries_suspended: config.trusted_peers_only,
+            boot_nodes: config.boot_nodes_enr.clone(),
+            target_peers_per_sync_subnet: config.target_peers_per_sync_subnet,
+            enr_update_interval: config.enr_update_interval.map(tokio::time::interval),
+            pending_enr_updates: HashMap::new(),
+            enr_filter: config.discovery_enr_filter.clone(),
+            allow_private_addresses: config.allow_private_addresses,
             log,
             enr_dir,
         })
@@ -306,6 +437,14 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
         self.discv5.local_enr()
     }
 
+    /// The number of peers we want to be connected to on `subnet`.
+    pub fn target_subnet_peers(&self, subnet: &Subnet) -> usize {
+        match subnet {
+            Subnet::Attestation(_) => TARGET_SUBNET_PEERS,
+            Subnet::SyncCommittee(_) => self.target_peers_per_sync_subnet,
+        }
+    }
+
     /// Return the cached enrs.
     pub fn cached_enrs(&self) -> impl Iterator<Item = (&PeerId, &Enr)> {
         self.cached_enrs.iter()
@@ -316,12 +455,38 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
         self.cached_enrs.pop(peer_id)
     }
 
+    /// Whether we may start queries of our own. This requires the discv5 service to be running in
+    /// full discovery mode, with our queries not suspended.
+    fn queries_enabled(&self) -> bool {
+        self.started && self.mode == DiscoveryMode::Full && !self.queries_suspended
+    }
+
+    /// Suspends or resumes our queries. We keep answering the queries of other nodes while ours
+    /// are suspended. Suspending drops the queued subnet queries.
+    pub fn set_queries_suspended(&mut self, suspended: bool) {
+        self.queries_suspended = suspended;
+        if suspended {
+            self.queued_queries.clear();
+            metrics::set_gauge(&metrics::DISCOVERY_QUEUE, 0);
+        }
+    }
+
+    /// Adds the boot nodes to the routing table again, in case they were evicted, and starts
+    /// searching for peers from them.
+    pub fn reseed_boot_nodes(&mut self) {
+        debug!(self.log, "Reseeding discovery from the boot nodes"; "boot_nodes" => self.boot_nodes.len());
+        for bootnode_enr in self.boot_nodes.clone() {
+            self.add_enr(bootnode_enr);
+        }
+        self.discover_peers(FIND_NODE_QUERY_CLOSEST_PEERS);
+    }
+
     /// This adds a new `FindPeers` query to the queue if one doesn't already exist.
     /// The `target_peers` parameter informs discovery to end the query once the target is found.
     /// The maximum this can be is 16.
     pub fn discover_peers(&mut self, target_peers: usize) {
-        // If the discv5 service isn't running or we are in the process of a query, don't bother queuing a new one.
-        if !self.started || self.find_peer_active {
+        // If we can't query or we are in the process of a query, don't bother queuing a new one.
+        if !self.queries_enabled() || self.find_peer_active {
             return;
         }
         // Immediately start a FindNode query
@@ -331,10 +496,45 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
         self.start_query(QueryType::FindPeers, target_peers, |_| true);
     }
 
+    /// Searches for up to `target_peers` peers on `subnet`, independently of the queries run for
+    /// the peer manager. The matching ENRs are sent through the returned channel once the query
+    /// completes. Queries are bounded by the discv5 query timeout, after which the peers found so
+    /// far are returned.
+    pub fn discover_subnet_enrs(
+        &mut self,
+        subnet: Subnet,
+        target_peers: usize,
+    ) -> oneshot::Receiver<Vec<Enr>> {
+        let (sender, receiver) = oneshot::channel();
+        if !self.queries_enabled() {
+            let _ = sender.send(Vec::new());
+            return receiver;
+        }
+
+        let predicate = subnet_predicate::<TSpec>(vec![subnet], &self.log);
+        if let Some(query) = self.find_node_query(target_peers, predicate) {
+            let log = self.log.clone();
+            self.requested_queries.push(Box::pin(query.map(move |result| {
+                let enrs = match result {
+                    Ok(enrs) => enrs,
+                    Err(e) => {
+                        debug!(log, "Requested subnet query failed"; "subnet" => ?subnet, "error" => %e);
+                        Vec::new()
+                    }
+                };
+                // the requester may no longer be interested in the result
+                let _ = sender.send(enrs);
+            })));
+        } else {
+            let _ = sender.send(Vec::new());
+        }
+        receiver
+    }
+
     /// Processes a request to search for more peers on a subnet.
     pub fn discover_subnet_peers(&mut self, subnets_to_discover: Vec<SubnetDiscovery>) {
-        // If the discv5 service isn't running, ignore queries
-        if !self.started {
+        // If we can't query, ignore the request
+        if !self.queries_enabled() {
             return;
         }
         trace!(
@@ -343,14 +543,16 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
             "subnets" => ?subnets_to_discover.iter().map(|s| s.subnet).collect::<Vec<_>>()
         );
         for subnet in subnets_to_discover {
-            self.add_subnet_query(subnet.subnet, subnet.min_ttl, 0);
+            if !self.answer_from_cache(subnet.subnet, subnet.min_ttl) {
+                self.add_subnet_query(subnet.subnet, subnet.min_ttl, 0);
+            }
         }
     }
 
     /// Add an ENR to the routing table of the discovery mechanism.
     pub fn add_enr(&mut self, enr: Enr) {
         // add the enr to seen caches
-        self.cached_enrs.put(enr.peer_id(), enr.clone());
+        self.cache_enr(enr.clone());
 
         if let Err(e) = self.discv5.add_enr(enr) {
             debug!(
@@ -366,18 +568,58 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
         self.discv5.table_entries_enr()
     }
 
+    /// Returns the number of entries of the routing table, in total and per k-bucket.
+    pub fn table_stats(&self) -> DiscoveryTableStats {
+        let local_key = kbucket::Key::from(self.discv5.local_enr().node_id());
+        let mut stats = DiscoveryTableStats {
+            bucket_entries: vec![0; NUM_BUCKETS],
+            ..Default::default()
+        };
+        for (node_id, _enr, status) in self.discv5.table_entries() {
+            stats.total_entries += 1;
+            if status.is_connected() {
+                stats.connected_entries += 1;
+            } else {
+                stats.disconnected_entries += 1;
+            }
+            if let Some(distance) = kbucket::Key::from(node_id).log2_distance(&local_key) {
+                stats.bucket_entries[distance as usize - 1] += 1;
+            }
+        }
+        stats
+    }
+
     /// Returns the ENR of a known peer if it exists.
+    ///
+    /// If both the local cache and the routing table know the peer, the ENR with the highest
+    /// sequence number is returned, as older ENRs may point at stale addresses.
     pub fn enr_of_peer(&mut self, peer_id: &PeerId) -> Option<Enr> {
         // first search the local cache
-        if let Some(enr) = self.cached_enrs.get(peer_id) {
-            return Some(enr.clone());
+        let cached_enr = self.cached_enrs.get(peer_id).cloned();
+        // then look in the routing table
+        let table_enr = enr_ext::peer_id_to_node_id(peer_id)
+            .ok()
+            .and_then(|node_id| self.discv5.find_enr(&node_id));
+        match (cached_enr, table_enr) {
+            (Some(cached_enr), Some(table_enr)) if table_enr.seq() > cached_enr.seq() => {
+                Some(table_enr)
+            }
+            (Some(cached_enr), _) => Some(cached_enr),
+            (None, table_enr) => table_enr,
         }
-        // not in the local cache, look in the routing table
-        if let Ok(node_id) = enr_ext::peer_id_to_node_id(peer_id) {
-            self.discv5.find_enr(&node_id)
-        } else {
-            None
+    }
+
+    /// Caches a seen ENR, unless an ENR of the same node with a higher sequence number is already
+    /// cached.
+    fn cache_enr(&mut self, enr: Enr) {
+        let peer_id = enr.peer_id();
+        if let Some(cached_enr) = self.cached_enrs.get(&peer_id) {
+            if cached_enr.seq() > enr.seq() {
+                debug!(self.log, "Ignoring outdated ENR"; "peer_id" => %peer_id, "seq" => enr.seq(), "cached_seq" => cached_enr.seq());
+                return;
+            }
         }
+        self.cached_enrs.put(peer_id, enr);
     }
 
     /// Updates the local ENR TCP port.
@@ -397,6 +639,19 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
         Ok(())
     }
 
+    /// Sets an application field of the local ENR, re-signing it.
+    pub fn update_enr_app_field(&mut self, key: &str, value: Vec<u8>) -> Result<(), String> {
+        self.discv5
+            .enr_insert(&enr::app_enr_key(key), &value)
+            .map_err(|e| format!("{:?}", e))?;
+
+        // replace the global version
+        *self.network_globals.local_enr.write() = self.discv5.local_enr();
+        // persist modified enr to disk
+        enr::save_enr_to_disk(Path::new(&self.enr_dir), &self.local_enr(), &self.log);
+        Ok(())
+    }
+
     /// Updates the local ENR UDP socket.
     ///
     /// This is with caution. Discovery should automatically maintain this. This should only be
@@ -428,14 +683,60 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
         Ok(())
     }
 
-    /// Adds/Removes a subnet from the ENR attnets/syncnets Bitfield
-    pub fn update_enr_bitfield(&mut self, subnet: Subnet, value: bool) -> Result<(), String> {
-        let local_enr = self.discv5.local_enr();
+    /// Updates the local ENR IP address and TCP port.
+    ///
+    /// This is used to advertise the external address observed by our peers.
+    pub fn update_enr_tcp_socket(&mut self, socket_addr: SocketAddr) -> Result<(), String> {
+        let (ip_key, tcp_key) = match socket_addr {
+            SocketAddr::V4(_) => ("ip", "tcp"),
+            SocketAddr::V6(_) => ("ip6", "tcp6"),
+        };
+        let ip = match socket_addr.ip() {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        self.discv5
+            .enr_insert(ip_key, &ip)
+            .map_err(|e| format!("{:?}", e))?;
+        self.discv5
+            .enr_insert(tcp_key, &socket_addr.port().to_be_bytes())
+            .map_err(|e| format!("{:?}", e))?;
 
-        match subnet {
+        // replace the global version
+        *self.network_globals.local_enr.write() = self.discv5.local_enr();
+        // persist modified enr to disk
+        enr::save_enr_to_disk(Path::new(&self.enr_dir), &self.local_enr(), &self.log);
+        Ok(())
+    }
+
+    /// Our attestation subnet bitfield, including a change not yet published in the ENR.
+    pub fn attestation_bitfield(&self) -> Result<EnrAttestationBitfield<TSpec>, &'static str> {
+        match self.pending_enr_updates.get(ATTESTATION_BITFIELD_ENR_KEY) {
+            Some(bytes) => EnrAttestationBitfield::<TSpec>::from_ssz_bytes(bytes)
+                .map_err(|_| "Could not decode the pending attestation bitfield"),
+            None => self.discv5.local_enr().attestation_bitfield::<TSpec>(),
+        }
+    }
+
+    /// Our sync committee subnet bitfield, including a change not yet published in the ENR.
+    pub fn sync_committee_bitfield(&self) -> Result<EnrSyncCommitteeBitfield<TSpec>, &'static str> {
+        match self
+            .pending_enr_updates
+            .get(SYNC_COMMITTEE_BITFIELD_ENR_KEY)
+        {
+            Some(bytes) => EnrSyncCommitteeBitfield::<TSpec>::from_ssz_bytes(bytes)
+                .map_err(|_| "Could not decode the pending sync committee bitfield"),
+            None => self.discv5.local_enr().sync_committee_bitfield::<TSpec>(),
+        }
+    }
+
+    /// Adds/Removes a subnet from the ENR attnets/syncnets Bitfield. With an
+    /// `enr_update_interval`, the change is published on the next tick of the interval.
+    pub fn update_enr_bitfield(&mut self, subnet: Subnet, value: bool) -> Result<(), String> {
+        let (key, bitfield_bytes) = match subnet {
             Subnet::Attestation(id) => {
                 let id = *id as usize;
-                let mut current_bitfield = local_enr.attestation_bitfield::<TSpec>()?;
+                let mut current_bitfield = self.attestation_bitfield()?;
                 if id >= current_bitfield.len() {
                     return Err(format!(
                         "Subnet id: {} is outside the ENR bitfield length: {}",
@@ -458,17 +759,14 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
                     String::from("Subnet ID out of bounds, could not set subnet ID")
                 })?;
 
-                // insert the bitfield into the ENR record
-                self.discv5
-                    .enr_insert(
-                        ATTESTATION_BITFIELD_ENR_KEY,
-                        &current_bitfield.as_ssz_bytes(),
-                    )
-                    .map_err(|e| format!("{:?}", e))?;
+                (
+                    ATTESTATION_BITFIELD_ENR_KEY,
+                    current_bitfield.as_ssz_bytes(),
+                )
             }
             Subnet::SyncCommittee(id) => {
                 let id = *id as usize;
-                let mut current_bitfield = local_enr.sync_committee_bitfield::<TSpec>()?;
+                let mut current_bitfield = self.sync_committee_bitfield()?;
 
                 if id >= current_bitfield.len() {
                     return Err(format!(
@@ -492,16 +790,28 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
                     String::from("Subnet ID out of bounds, could not set subnet ID")
                 })?;
 
-                // insert the bitfield into the ENR record
-                self.discv5
-                    .enr_insert(
-                        SYNC_COMMITTEE_BITFIELD_ENR_KEY,
-                        &current_bitfield.as_ssz_bytes(),
-                    )
-                    .map_err(|e| format!("{:?}", e))?;
+                (
+                    SYNC_COMMITTEE_BITFIELD_ENR_KEY,
+                    current_bitfield.as_ssz_bytes(),
+                )
             }
+        };
+
+        if self.enr_update_interval.is_some() {
+            // a change back to the published bitfield leaves nothing to publish
+            if self.discv5.local_enr().get(key) == Some(bitfield_bytes.as_slice()) {
+                self.pending_enr_updates.remove(key);
+            } else {
+                self.pending_enr_updates.insert(key, bitfield_bytes);
+            }
+            return Ok(());
         }
 
+        // insert the bitfield into the ENR record
+        self.discv5
+            .enr_insert(key, &bitfield_bytes)
+            .map_err(|e| format!("{:?}", e))?;
+
         // replace the global version
         *self.network_globals.local_enr.write() = self.discv5.local_enr();
 
@@ -510,6 +820,26 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
         Ok(())
     }
 
+    /// Inserts the pending subnet bitfields into our ENR, which re-advertises it with a new
+    /// sequence number.
+    fn publish_pending_enr_updates(&mut self) {
+        if self.pending_enr_updates.is_empty() {
+            return;
+        }
+        for (key, bitfield_bytes) in self.pending_enr_updates.drain() {
+            if let Err(e) = self.discv5.enr_insert(key, &bitfield_bytes) {
+                crit!(self.log, "Could not update ENR bitfield"; "key" => key, "error" => ?e);
+            }
+        }
+
+        // replace the global version
+        *self.network_globals.local_enr.write() = self.discv5.local_enr();
+
+        // persist modified enr to disk
+        enr::save_enr_to_disk(Path::new(&self.enr_dir), &self.local_enr(), &self.log);
+        debug!(self.log, "Published ENR subnet bitfields"; "seq" => self.discv5.local_enr().seq());
+    }
+
     /// Updates the `eth2` field of our local ENR.
     pub fn update_eth2_enr(&mut self, enr_fork_id: EnrForkId) {
         // to avoid having a reference to the spec constant, for the logging we assume
@@ -584,6 +914,29 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
 
     /* Internal Functions */
 
+    /// Answers a request for peers on `subnet` with the peers found by a recent query for it.
+    /// Returns `false` if no query for the subnet completed within the cache window.
+    fn answer_from_cache(&mut self, subnet: Subnet, min_ttl: Option<Instant>) -> bool {
+        let window = self.subnet_query_cache_window;
+        self.subnet_query_cache
+            .retain(|_, (completed, _)| completed.elapsed() < window);
+
+        let enrs = match self.subnet_query_cache.get(&subnet) {
+            Some((_, enrs)) => enrs.clone(),
+            None => return false,
+        };
+        debug!(self.log, "Subnet discovery answered from cache"; "subnet" => ?subnet, "peers" => enrs.len());
+        for enr in enrs {
+            let peer_id = enr.peer_id();
+            // the cached ENR may have been evicted since the query completed
+            self.cache_enr(enr);
+            // keep the min_ttl furthest in the future, `None` being the shortest
+            let other_min_ttl = self.cached_query_results.entry(peer_id).or_insert(min_ttl);
+            *other_min_ttl = std::cmp::max(*other_min_ttl, min_ttl);
+        }
+        true
+    }
+
     /// Adds a subnet query if one doesn't exist. If a subnet query already exists, this
     /// updates the min_ttl field.
     fn add_subnet_query(&mut self, subnet: Subnet, min_ttl: Option<Instant>, retries: usize) {
@@ -682,17 +1035,18 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
                     .read()
                     .good_peers_on_subnet(subnet_query.subnet)
                     .count();
+                let target_subnet_peers = self.target_subnet_peers(&subnet_query.subnet);
 
-                if peers_on_subnet >= TARGET_SUBNET_PEERS {
+                if peers_on_subnet >= target_subnet_peers {
                     debug!(self.log, "Discovery ignored";
                         "reason" => "Already connected to desired peers",
                         "connected_peers_on_subnet" => peers_on_subnet,
-                        "target_subnet_peers" => TARGET_SUBNET_PEERS,
+                        "target_subnet_peers" => target_subnet_peers,
                     );
                     return false;
                 }
 
-                let target_peers = TARGET_SUBNET_PEERS.saturating_sub(peers_on_subnet);
+                let target_peers = target_subnet_peers.saturating_sub(peers_on_subnet);
                 trace!(self.log, "Discovery query started for subnet";
                     "subnet_query" => ?subnet_query,
                     "connected_peers_on_subnet" => peers_on_subnet,
@@ -747,6 +1101,26 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
             return;
         }
 
+        // Build the future
+        let query_future = match self.find_node_query(target_peers, additional_predicate) {
+            Some(query_future) => query_future.map(|v| QueryResult {
+                query_type: query,
+                result: v,
+            }),
+            None => return,
+        };
+
+        // Add the future to active queries, to be executed.
+        self.active_queries.push(Box::pin(query_future));
+    }
+
+    /// Builds a discv5 query for `target_peers` peers on our fork that match
+    /// `additional_predicate`. Returns `None` if our ENR has no fork id.
+    fn find_node_query(
+        &self,
+        target_peers: usize,
+        additional_predicate: impl Fn(&Enr) -> bool + Send + 'static,
+    ) -> Option<impl Future<Output = Result<Vec<Enr>, discv5::QueryError>> + Send + 'static> {
         // Generate a random target node id.
         let random_node = NodeId::random();
 
@@ -754,7 +1128,7 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
             Ok(v) => v,
             Err(e) => {
                 crit!(self.log, "Local ENR has no fork id"; "error" => e);
-                return;
+                return None;
             }
         };
         // predicate for finding nodes with a matching fork and valid tcp port
@@ -770,24 +1144,36 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
         let predicate: Box<dyn Fn(&Enr) -> bool + Send> =
             Box::new(move |enr: &Enr| eth2_fork_predicate(enr) && additional_predicate(enr));
 
-        // Build the future
-        let query_future = self
-            .discv5
-            .find_node_predicate(random_node, predicate, target_peers)
-            .map(|v| QueryResult {
-                query_type: query,
-                result: v,
-            });
-
-        // Add the future to active queries, to be executed.
-        self.active_queries.push(Box::pin(query_future));
+        Some(
+            self.discv5
+                .find_node_predicate(random_node, predicate, target_peers),
+        )
     }
 
     /// Process the completed QueryResult returned from discv5.
     fn process_completed_queries(
         &mut self,
-        query: QueryResult,
+        mut query: QueryResult,
     ) -> Option<HashMap<PeerId, Option<Instant>>> {
+        // drop the ENRs whose peers must not be dialed
+        if let (Some(filter), Ok(enrs)) = (&self.enr_filter, &mut query.result) {
+            let found = enrs.len();
+            enrs.retain(|enr| filter.accepts(enr));
+            let rejected = found - enrs.len();
+            if rejected > 0 {
+                debug!(self.log, "Discovered ENRs rejected by filter"; "rejected" => rejected);
+                metrics::inc_counter_by(&metrics::DISCOVERY_FILTERED_ENRS, rejected as u64);
+            }
+        }
+        if let (false, Ok(enrs)) = (self.allow_private_addresses, &mut query.result) {
+            let found = enrs.len();
+            enrs.retain(|enr| !enr_has_private_ip(enr));
+            let rejected = found - enrs.len();
+            if rejected > 0 {
+                debug!(self.log, "Discovered ENRs with private addresses ignored"; "ignored" => rejected);
+            }
+        }
+
         match query.query_type {
             QueryType::FindPeers => {
                 self.find_peer_active = false;
@@ -800,7 +1186,7 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
                         let mut results: HashMap<_, Option<Instant>> = HashMap::new();
                         r.iter().for_each(|enr| {
                             // cache the found ENR's
-                            self.cached_enrs.put(enr.peer_id(), enr.clone());
+                            self.cache_enr(enr.clone());
                             results.insert(enr.peer_id(), None);
                         });
                         return Some(results);
@@ -813,6 +1199,24 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
             QueryType::Subnet(queries) => {
                 let subnets_searched_for: Vec<Subnet> =
                     queries.iter().map(|query| query.subnet).collect();
+                if let Ok(r) = &query.result {
+                    if !self.subnet_query_cache_window.is_zero() {
+                        let now = Instant::now();
+                        for subnet in subnets_searched_for.iter() {
+                            let subnet_predicate =
+                                subnet_predicate::<TSpec>(vec![*subnet], &self.log);
+                            let enrs: Vec<Enr> = r
+                                .iter()
+                                .filter(|enr| subnet_predicate(enr))
+                                .cloned()
+                                .collect();
+                            // a subnet without peers is searched again on the next request
+                            if !enrs.is_empty() {
+                                self.subnet_query_cache.insert(*subnet, (now, enrs));
+                            }
+                        }
+                    }
+                }
                 match query.result {
                     Ok(r) if r.is_empty() => {
                         debug!(self.log, "Grouped subnet discovery query yielded no results."; "subnets_searched_for" => ?subnets_searched_for);
@@ -827,7 +1231,7 @@ impl<TSpec: EthSpec> Discovery<TSpec> {
 
                         // cache the found ENR's
                         for enr in r.iter().cloned() {
-                            self.cached_enrs.put(enr.peer_id(), enr);
+                            self.cache_enr(enr);
                         }
 
                         // Map each subnet query's min_ttl to the set of ENR's returned for that subnet.
@@ -968,6 +1372,15 @@ impl<TSpec: EthSpec> NetworkBehaviour for Discovery<TSpec> {
         cx: &mut Context,
         _: &mut impl PollParameters,
     ) -> Poll<NBAction<Self::OutEvent, Self::ConnectionHandler>> {
+        // Our ENR is maintained whether or not discovery is started
+        while self
+            .enr_update_interval
+            .as_mut()
+            .map_or(false, |interval| interval.poll_tick(cx).is_ready())
+        {
+            self.publish_pending_enr_updates();
+        }
+
         if !self.started {
             return Poll::Pending;
         }
@@ -975,6 +1388,17 @@ impl<TSpec: EthSpec> NetworkBehaviour for Discovery<TSpec> {
         // Process the query queue
         self.process_queue();
 
+        // Drive the queries requested through `discover_subnet_enrs`, which report their results
+        // directly
+        while let Poll::Ready(Some(())) = self.requested_queries.poll_next_unpin(cx) {}
+
+        // Return the peers of the subnet requests answered from the cache
+        if !self.cached_query_results.is_empty() {
+            return Poll::Ready(NBAction::GenerateEvent(DiscoveryEvent::QueryResult(
+                std::mem::take(&mut self.cached_query_results),
+            )));
+        }
+
         // Drive the queries and return any results from completed queries
         if let Some(results) = self.poll_queries(cx) {
             // return the result to the peer manager
@@ -1033,6 +1457,10 @@ impl<TSpec: EthSpec> NetworkBehaviour for Discovery<TSpec> {
                                 DiscoveryEvent::SocketUpdated(socket),
                             ));
                         }
+                        // NOTE: discv5 handles session and decryption failures internally and
+                        // reports none of them, per node or otherwise, through this stream. Noisy
+                        // nodes can therefore not be tracked or deprioritized from here, and the
+                        // failures are only visible in the discv5 logs.
                         Discv5Event::EnrAdded { .. }
                         | Discv5Event::TalkRequest(_)
                         | Discv5Event::NodeInserted { .. } => {} // Ignore all other discv5 server events
@@ -1044,13 +1472,56 @@ impl<TSpec: EthSpec> NetworkBehaviour for Discovery<TSpec> {
     }
 }
 
+/// Resolves the ENR trees at `urls` concurrently and adds their ENRs to the routing table. Trees
+/// that can't be resolved within `timeout` are skipped.
+async fn add_dns_boot_nodes(
+    discv5: &mut Discv5,
+    resolver: &dyn TxtResolver,
+    urls: &[String],
+    timeout: Duration,
+    log: &slog::Logger,
+) {
+    let resolutions = urls.iter().map(|url| async move {
+        let result = tokio::time::timeout(timeout, resolve_enr_tree(resolver, url, log))
+            .await
+            .unwrap_or_else(|_| Err("Timed out".into()));
+        (url, result)
+    });
+    for (url, result) in futures::future::join_all(resolutions).await {
+        let enrs = match result {
+            Ok(enrs) => enrs,
+            Err(e) => {
+                warn!(log, "Could not resolve DNS boot nodes"; "url" => url, "error" => e);
+                continue;
+            }
+        };
+        debug!(log, "Resolved DNS boot nodes"; "url" => url, "count" => enrs.len());
+        for enr in enrs {
+            let node_id = enr.node_id();
+            if let Err(e) = discv5.add_enr(enr) {
+                debug!(log, "Could not add DNS boot node to the routing table"; "node_id" => %node_id, "error" => e);
+            }
+        }
+    }
+}
+
+/// Returns true if the ENR advertises a private IP address.
+fn enr_has_private_ip(enr: &Enr) -> bool {
+    enr.ip()
+        .map(IpAddr::V4)
+        .into_iter()
+        .chain(enr.ip6().map(IpAddr::V6))
+        .any(|ip| is_private_ip(&ip))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::rpc::methods::{MetaData, MetaDataV2};
     use enr::EnrBuilder;
     use slog::{o, Drain};
-    use types::{BitVector, MinimalEthSpec, SubnetId};
+    use std::collections::HashSet;
+    use types::{BitVector, MinimalEthSpec, SubnetId, SyncSubnetId};
     use unused_port::unused_udp_port;
 
     type E = MinimalEthSpec;
@@ -1093,6 +1564,27 @@ mod tests {
             .unwrap()
     }
 
+    #[tokio::test]
+    async fn test_passive_mode_starts_no_queries() {
+        let mut discovery = build_discovery().await;
+        discovery.mode = DiscoveryMode::PassiveRespond;
+
+        discovery.discover_peers(16);
+        discovery.discover_subnet_peers(vec![SubnetDiscovery {
+            subnet: Subnet::Attestation(SubnetId::new(1)),
+            min_ttl: None,
+        }]);
+        let enrs = discovery.discover_subnet_enrs(Subnet::Attestation(SubnetId::new(2)), 16);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!discovery.process_queue());
+        assert!(!discovery.find_peer_active);
+        assert!(discovery.active_queries.is_empty());
+        assert!(discovery.requested_queries.is_empty());
+        // requested searches complete immediately without results
+        assert_eq!(enrs.await.unwrap(), vec![]);
+    }
+
     #[tokio::test]
     async fn test_add_subnet_query() {
         let mut discovery = build_discovery().await;
@@ -1132,6 +1624,63 @@ mod tests {
         assert_eq!(discovery.queued_queries.len(), 0);
     }
 
+    #[tokio::test]
+    async fn test_empty_subnet_query_is_repeated() {
+        let mut discovery = build_discovery().await;
+        let subnet = Subnet::Attestation(SubnetId::new(1));
+        let request = || {
+            vec![SubnetDiscovery {
+                subnet,
+                min_ttl: None,
+            }]
+        };
+
+        discovery.discover_subnet_peers(request());
+        assert!(discovery.process_queue());
+        let mut completed = discovery.active_queries.next().await.unwrap();
+        completed.result = Ok(vec![]);
+        discovery.process_completed_queries(completed);
+        discovery.queued_queries.clear();
+
+        // the query found no peer on the subnet, so the next request searches again
+        discovery.discover_subnet_peers(request());
+        assert!(discovery.cached_query_results.is_empty());
+        assert_eq!(discovery.queued_queries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_recent_subnet_query_is_not_repeated() {
+        let mut discovery = build_discovery().await;
+        let subnet = Subnet::Attestation(SubnetId::new(1));
+        let request = || {
+            vec![SubnetDiscovery {
+                subnet,
+                min_ttl: None,
+            }]
+        };
+
+        discovery.discover_subnet_peers(request());
+        assert!(discovery.process_queue());
+        assert_eq!(discovery.active_queries.len(), 1);
+
+        // complete the query with a peer on the subnet
+        let enr = make_enr(vec![1]);
+        let mut completed = discovery.active_queries.next().await.unwrap();
+        completed.result = Ok(vec![enr.clone()]);
+        discovery.process_completed_queries(completed);
+        // drop the retry of the completed query
+        discovery.queued_queries.clear();
+
+        // the second request is answered with the peer found by the first query
+        discovery.discover_subnet_peers(request());
+        assert!(!discovery.process_queue());
+        assert!(discovery.active_queries.is_empty());
+        assert_eq!(
+            discovery.cached_query_results.get(&enr.peer_id()),
+            Some(&None)
+        );
+    }
+
     fn make_enr(subnet_ids: Vec<usize>) -> Enr {
         let mut builder = EnrBuilder::new("v4");
         let keypair = libp2p::identity::Keypair::generate_secp256k1();
@@ -1147,6 +1696,222 @@ mod tests {
         builder.build(&enr_key).unwrap()
     }
 
+    fn make_sync_enr(sync_subnet_ids: Vec<usize>) -> Enr {
+        let mut builder = EnrBuilder::new("v4");
+        let keypair = libp2p::identity::Keypair::generate_secp256k1();
+        let enr_key: CombinedKey = CombinedKey::from_libp2p(&keypair).unwrap();
+
+        // the subnet predicate requires an "attnets" field
+        let attnets = BitVector::<ssz_types::typenum::U64>::new();
+        builder.add_value(ATTESTATION_BITFIELD_ENR_KEY, &attnets.as_ssz_bytes());
+
+        // set the "syncnets" field on our ENR
+        let mut syncnets = BitVector::<ssz_types::typenum::U4>::new();
+        for id in sync_subnet_ids {
+            syncnets.set(id, true).unwrap();
+        }
+        builder.add_value(SYNC_COMMITTEE_BITFIELD_ENR_KEY, &syncnets.as_ssz_bytes());
+        builder.build(&enr_key).unwrap()
+    }
+
+    #[test]
+    fn test_sync_subnet_predicate() {
+        let log = build_log(slog::Level::Debug, false);
+        let predicate =
+            subnet_predicate::<E>(vec![Subnet::SyncCommittee(SyncSubnetId::new(1))], &log);
+
+        assert!(predicate(&make_sync_enr(vec![1])));
+        assert!(predicate(&make_sync_enr(vec![0, 1])));
+        assert!(!predicate(&make_sync_enr(vec![0, 2, 3])));
+        assert!(!predicate(&make_sync_enr(vec![])));
+        // a peer on the attestation subnet of the same index is not on the sync subnet
+        assert!(!predicate(&make_enr(vec![1])));
+    }
+
+    #[tokio::test]
+    async fn test_target_subnet_peers() {
+        let keypair = libp2p::identity::Keypair::generate_secp256k1();
+        let config = NetworkConfig {
+            discovery_port: unused_udp_port().unwrap(),
+            target_peers_per_sync_subnet: 10,
+            ..Default::default()
+        };
+        let log = build_log(slog::Level::Debug, false);
+        let globals = NetworkGlobals::new_test_globals(&log);
+        let discovery: Discovery<E> = Discovery::new(&keypair, &config, Arc::new(globals), &log)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            discovery.target_subnet_peers(&Subnet::SyncCommittee(SyncSubnetId::new(0))),
+            10
+        );
+        assert_eq!(
+            discovery.target_subnet_peers(&Subnet::Attestation(SubnetId::new(0))),
+            TARGET_SUBNET_PEERS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enr_filter_rejects_enrs() {
+        let mut discovery = build_discovery().await;
+        // only accept peers advertising their sync committee subnets
+        discovery.enr_filter = Some(EnrFilter::new(|enr: &Enr| {
+            enr.get(SYNC_COMMITTEE_BITFIELD_ENR_KEY).is_some()
+        }));
+
+        let with_syncnets = make_sync_enr(vec![]);
+        let without_syncnets = make_enr(vec![]);
+        let results = discovery
+            .process_completed_queries(QueryResult {
+                query_type: QueryType::FindPeers,
+                result: Ok(vec![with_syncnets.clone(), without_syncnets.clone()]),
+            })
+            .unwrap();
+
+        // only the accepted peer is handed to the peer manager for dialing
+        assert_eq!(results.len(), 1);
+        assert!(results.contains_key(&with_syncnets.peer_id()));
+        assert!(!results.contains_key(&without_syncnets.peer_id()));
+
+        // a query yielding only rejected ENRs has no peers to dial
+        assert!(discovery
+            .process_completed_queries(QueryResult {
+                query_type: QueryType::FindPeers,
+                result: Ok(vec![without_syncnets]),
+            })
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_private_enrs_are_ignored() {
+        let mut discovery = build_discovery().await;
+        let key = CombinedKey::generate_secp256k1();
+        let private_enr = EnrBuilder::new("v4")
+            .ip("10.0.0.1".parse().unwrap())
+            .build(&key)
+            .unwrap();
+        let query = || QueryResult {
+            query_type: QueryType::FindPeers,
+            result: Ok(vec![private_enr.clone()]),
+        };
+
+        assert!(discovery.process_completed_queries(query()).is_none());
+
+        discovery.allow_private_addresses = true;
+        let results = discovery.process_completed_queries(query()).unwrap();
+        assert!(results.contains_key(&private_enr.peer_id()));
+    }
+
+    #[tokio::test]
+    async fn test_newest_enr_is_used_for_dialing() {
+        let mut discovery = build_discovery().await;
+        let key = CombinedKey::generate_secp256k1();
+        let old_enr = EnrBuilder::new("v4")
+            .ip("8.8.8.8".parse().unwrap())
+            .tcp(9000)
+            .build(&key)
+            .unwrap();
+        let mut new_enr = old_enr.clone();
+        new_enr.set_tcp(9001, &key).unwrap();
+        assert!(new_enr.seq() > old_enr.seq());
+        let peer_id = old_enr.peer_id();
+        let query = |enr: &Enr| QueryResult {
+            query_type: QueryType::FindPeers,
+            result: Ok(vec![enr.clone()]),
+        };
+
+        // the newer ENR replaces the older one
+        discovery.process_completed_queries(query(&old_enr));
+        discovery.process_completed_queries(query(&new_enr));
+        assert_eq!(discovery.enr_of_peer(&peer_id), Some(new_enr.clone()));
+
+        // an older ENR seen later does not replace the newer one
+        let results = discovery
+            .process_completed_queries(query(&old_enr))
+            .unwrap();
+        assert!(results.contains_key(&peer_id));
+        assert_eq!(discovery.enr_of_peer(&peer_id).unwrap().tcp(), Some(9001));
+    }
+
+    #[tokio::test]
+    async fn test_dns_boot_nodes_are_added_to_routing_table() {
+        let mut discovery = build_discovery().await;
+        let enrs = vec![make_enr(vec![]), make_enr(vec![])];
+        let (resolver, url) = enr_tree::tests::build_tree("nodes.example.org", &enrs);
+        let log = build_log(slog::Level::Debug, false);
+
+        // an unresolvable tree is skipped
+        let urls = vec!["enrtree://invalid@nodes.example.org".to_string(), url];
+        add_dns_boot_nodes(
+            &mut discovery.discv5,
+            &resolver,
+            &urls,
+            DNS_BOOT_NODES_TIMEOUT,
+            &log,
+        )
+        .await;
+
+        let table: HashSet<_> = discovery
+            .table_entries_enr()
+            .iter()
+            .map(Enr::node_id)
+            .collect();
+        for enr in enrs {
+            assert!(table.contains(&enr.node_id()));
+        }
+    }
+
+    /// Never answers the lookups of `domain`, serving the other records from memory.
+    struct StallingResolver {
+        inner: enr_tree::tests::StaticResolver,
+        domain: &'static str,
+    }
+
+    impl TxtResolver for StallingResolver {
+        fn lookup_txt<'a>(&'a self, name: &'a str) -> enr_tree::TxtLookup<'a> {
+            if name.ends_with(self.domain) {
+                Box::pin(future::pending::<Result<String, String>>())
+            } else {
+                self.inner.lookup_txt(name)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dns_boot_nodes_resolve_within_timeout() {
+        let mut discovery = build_discovery().await;
+        let slow_enrs = vec![make_enr(vec![])];
+        let enrs = vec![make_enr(vec![])];
+        let (mut resolver, slow_url) = enr_tree::tests::build_tree("slow.example.org", &slow_enrs);
+        let (other_resolver, url) = enr_tree::tests::build_tree("nodes.example.org", &enrs);
+        resolver.0.extend(other_resolver.0);
+        let resolver = StallingResolver {
+            inner: resolver,
+            domain: "slow.example.org",
+        };
+        let log = build_log(slog::Level::Debug, false);
+
+        // the stalled tree does not hold up the other one
+        let urls = vec![slow_url, url];
+        add_dns_boot_nodes(
+            &mut discovery.discv5,
+            &resolver,
+            &urls,
+            Duration::from_millis(100),
+            &log,
+        )
+        .await;
+
+        let table: HashSet<_> = discovery
+            .table_entries_enr()
+            .iter()
+            .map(Enr::node_id)
+            .collect();
+        assert!(table.contains(&enrs[0].node_id()));
+        assert!(!table.contains(&slow_enrs[0].node_id()));
+    }
+
     #[tokio::test]
     async fn test_completed_subnet_queries() {
         let mut discovery = build_discovery().await;
@@ -0,0 +1,131 @@
+//! Rate limits the inbound connections accepted from each IP address.
+//!
+//! The limiter is applied to the raw transport, so that connections exceeding the limit are
+//! dropped before any protocol is negotiated on them.
+use crate::peer_manager::multiaddr_ip;
+use crate::rpc::{Limiter, Quota};
+use libp2p::core::ConnectedPoint;
+use parking_lot::Mutex;
+use serde_derive::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the IPs whose quota has been fully replenished are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The inbound connections accepted from a single IP address.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct InboundRateLimit {
+    /// The sustained rate of accepted connections.
+    pub connections_per_second: u64,
+    /// The number of connections that can be accepted at once, before the rate applies.
+    pub burst: u64,
+}
+
+/// A per-IP limiter of inbound connections. Clones share the same quotas.
+#[derive(Clone)]
+pub(crate) struct InboundRateLimiter {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    limiter: Limiter<IpAddr>,
+    init_time: Instant,
+    last_prune: Duration,
+}
+
+impl InboundRateLimiter {
+    pub fn new(limit: InboundRateLimit) -> Result<Self, &'static str> {
+        if limit.connections_per_second == 0 {
+            return Err("The inbound connection rate must be positive");
+        }
+        // `burst` tokens are replenished at `connections_per_second`
+        let replenish_all_every =
+            Duration::from_secs_f64(limit.burst as f64 / limit.connections_per_second as f64);
+        let limiter = Limiter::from_quota(Quota::n_every(limit.burst, replenish_all_every))?;
+        Ok(InboundRateLimiter {
+            inner: Arc::new(Mutex::new(Inner {
+                limiter,
+                init_time: Instant::now(),
+                last_prune: Duration::ZERO,
+            })),
+        })
+    }
+
+    /// Whether a connection from `ip` is allowed, consuming one token of its quota if so.
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let mut inner = self.inner.lock();
+        let time_since_start = inner.init_time.elapsed();
+        if time_since_start >= inner.last_prune + PRUNE_INTERVAL {
+            inner.limiter.prune(time_since_start);
+            inner.last_prune = time_since_start;
+        }
+        inner.limiter.allows(time_since_start, &ip, 1).is_ok()
+    }
+
+    /// Whether a connection at `endpoint` is allowed. Only inbound connections with an IP
+    /// address are limited.
+    pub fn allows_endpoint(&self, endpoint: &ConnectedPoint) -> bool {
+        match endpoint {
+            ConnectedPoint::Listener { send_back_addr, .. } => {
+                multiaddr_ip(send_back_addr).map_or(true, |ip| self.allows(ip))
+            }
+            ConnectedPoint::Dialer { .. } => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InboundRateLimit, InboundRateLimiter};
+    use libp2p::core::ConnectedPoint;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    fn listener(ip: &str) -> ConnectedPoint {
+        ConnectedPoint::Listener {
+            local_addr: "/ip4/127.0.0.1/tcp/9000".parse().unwrap(),
+            send_back_addr: format!("/ip4/{}/tcp/30000", ip).parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn connections_beyond_the_burst_are_rejected() {
+        let limiter = InboundRateLimiter::new(InboundRateLimit {
+            connections_per_second: 10,
+            burst: 3,
+        })
+        .unwrap();
+
+        // the burst is accepted, later connections from the same IP are not
+        let accepted = (0..10)
+            .filter(|_| limiter.allows_endpoint(&listener("10.0.0.1")))
+            .count();
+        assert_eq!(accepted, 3);
+
+        // other IPs have their own quota
+        assert!(limiter.allows_endpoint(&listener("10.0.0.2")));
+
+        // outbound connections are never limited
+        let dialer = ConnectedPoint::Dialer {
+            address: "/ip4/10.0.0.1/tcp/9000".parse().unwrap(),
+        };
+        assert!(limiter.allows_endpoint(&dialer));
+
+        // a token is replenished every 100ms
+        std::thread::sleep(Duration::from_millis(150));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(limiter.allows(ip));
+        assert!(!limiter.allows(ip));
+    }
+
+    #[test]
+    fn zero_rate_is_invalid() {
+        assert!(InboundRateLimiter::new(InboundRateLimit {
+            connections_per_second: 0,
+            burst: 3,
+        })
+        .is_err());
+    }
+}
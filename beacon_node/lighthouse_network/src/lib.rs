@@ -10,10 +10,13 @@ mod config;
 
 #[allow(clippy::mutable_key_type)] // PeerId in hashmaps are no longer permitted by clippy
 pub mod discovery;
+mod inbound_rate_limiter;
 pub mod metrics;
+mod peer_bandwidth;
 pub mod peer_manager;
 pub mod rpc;
 mod service;
+mod subscription_rate_limiter;
 pub mod types;
 
 pub use config::gossip_max_size;
@@ -63,26 +66,40 @@ impl<'de> Deserialize<'de> for PeerIdSerialized {
 }
 
 pub use crate::types::{
-    error, Enr, EnrSyncCommitteeBitfield, GossipTopic, NetworkGlobals, PubsubMessage, Subnet,
-    SubnetDiscovery,
+    error, ApplicationCodec, ApplicationMessage, DialStats, Enr, EnrSyncCommitteeBitfield,
+    GossipTopic, NetworkGlobals, NetworkHealth, PubsubMessage, Subnet, SubnetDiscovery,
 };
 
 pub use prometheus_client;
 
-pub use behaviour::{BehaviourEvent, Gossipsub, PeerRequestId, Request, Response};
+pub use behaviour::{
+    BehaviourEvent, Gossipsub, PeerRequestId, Request, RequestAction, RequestInterceptor, Response,
+    StatusHook,
+};
 pub use config::Config as NetworkConfig;
-pub use discovery::{CombinedKeyExt, EnrExt, Eth2Enr};
+pub use discovery::{
+    CombinedKeyExt, DiscoveryMode, DiscoveryTableStats, EnrExt, EnrFilter, Eth2Enr,
+};
 pub use discv5;
+pub use inbound_rate_limiter::InboundRateLimit;
+pub use ipnet::IpNet as IpNetwork;
 pub use libp2p;
 pub use libp2p::bandwidth::BandwidthSinks;
 pub use libp2p::gossipsub::{IdentTopic, MessageAcceptance, MessageId, Topic, TopicHash};
 pub use libp2p::{core::ConnectedPoint, PeerId, Swarm};
 pub use libp2p::{multiaddr, Multiaddr};
 pub use metrics::scrape_discovery_metrics;
+pub use peer_bandwidth::{BandwidthStats, PeerBandwidth};
 pub use peer_manager::{
+    config::PeerEvictionPolicy,
     peerdb::client::Client,
     peerdb::score::{PeerAction, ReportSource},
+    peerdb::snapshot::PeerDbSnapshot,
     peerdb::PeerDB,
-    ConnectionDirection, PeerConnectionStatus, PeerInfo, PeerManager, SyncInfo, SyncStatus,
+    ConnectionDirection, DisconnectReason, PeerConnectionStatus, PeerInfo, PeerManager,
+    PeerProtocolInfo, SyncInfo, SyncStatus,
+};
+pub use service::{
+    load_private_key, Context, EventKind, Libp2pEvent, Service, NETWORK_KEY_FILENAME,
 };
-pub use service::{load_private_key, Context, Libp2pEvent, Service, NETWORK_KEY_FILENAME};
+pub use subscription_rate_limiter::SubscriptionChangeLimit;
@@ -22,6 +22,19 @@ lazy_static! {
         "libp2p_peer_disconnect_event_total",
         "Count of libp2p peer disconnect events"
     );
+    pub static ref DIAL_ATTEMPTS: Result<IntCounter> = try_create_int_counter(
+        "libp2p_dial_attempts_total",
+        "Count of outbound dials started"
+    );
+    pub static ref DIAL_SUCCESSES: Result<IntCounter> = try_create_int_counter(
+        "libp2p_dial_successes_total",
+        "Count of outbound dials that established a connection"
+    );
+    pub static ref DIAL_FAILURES_PER_KIND: Result<IntCounterVec> = try_create_int_counter_vec(
+        "libp2p_dial_failures_total",
+        "Count of outbound dials that failed, per kind of failure",
+        &["kind"]
+    );
     pub static ref DISCOVERY_SENT_BYTES: Result<IntGauge> = try_create_int_gauge(
         "discovery_sent_bytes",
         "The number of bytes sent in discovery"
@@ -42,12 +55,21 @@ lazy_static! {
         "discovery_sessions",
         "The number of active discovery sessions with peers"
     );
+    pub static ref DISCOVERY_FILTERED_ENRS: Result<IntCounter> = try_create_int_counter(
+        "discovery_filtered_enrs_total",
+        "Count of discovered ENRs rejected by the configured ENR filter"
+    );
 
     pub static ref PEERS_PER_CLIENT: Result<IntGaugeVec> = try_create_int_gauge_vec(
         "libp2p_peers_per_client",
         "The connected peers via client implementation",
         &["Client"]
     );
+    pub static ref PEERS_PER_SYNC_COMMITTEE_SUBNET: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "libp2p_peers_per_sync_committee_subnet",
+        "The number of good connected peers on each sync committee subnet",
+        &["subnet"]
+    );
     pub static ref FAILED_ATTESTATION_PUBLISHES_PER_SUBNET: Result<IntGaugeVec> =
         try_create_int_gauge_vec(
             "gossipsub_failed_attestation_publishes_per_subnet",
@@ -69,6 +91,20 @@ lazy_static! {
         "RPC requests total",
         &["type"]
     );
+    pub static ref RPC_OUTBOUND_REQUEST_DURATION: Result<HistogramVec> = try_create_histogram_vec(
+        "libp2p_rpc_outbound_request_duration_seconds",
+        "Time between queueing an outbound RPC request and its stream closing or failing",
+        &["protocol"]
+    );
+    pub static ref RPC_INBOUND_REQUEST_DURATION: Result<HistogramVec> = try_create_histogram_vec(
+        "libp2p_rpc_inbound_request_duration_seconds",
+        "Time between receiving an inbound RPC request and its response stream closing or failing",
+        &["protocol"]
+    );
+    pub static ref RPC_RESPONSE_CHUNKS_AHEAD: Result<Histogram> = try_create_histogram(
+        "libp2p_rpc_response_chunks_ahead",
+        "Response chunks read by an RPC handler and not yet taken by the application, observed as each chunk is read"
+    );
     pub static ref PEER_ACTION_EVENTS_PER_CLIENT: Result<IntCounterVec> =
         try_create_int_counter_vec(
             "libp2p_peer_actions_per_client",
@@ -81,6 +117,12 @@ lazy_static! {
             "Gossipsub messages that we did not accept, per client",
             &["client", "validation_result"]
         );
+    pub static ref GOSSIP_VALIDATION_RESULTS_PER_TOPIC_KIND: Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "gossipsub_validation_results_per_topic_kind",
+            "Validation results reported for gossipsub messages, per topic kind",
+            &["topic_kind", "validation_result"]
+        );
     pub static ref GOSSIP_LATE_PUBLISH_PER_TOPIC_KIND: Result<IntCounterVec> =
         try_create_int_counter_vec(
             "gossipsub_late_publish_per_topic_kind",
@@ -0,0 +1,139 @@
+//! Accounts the bytes exchanged with each peer.
+//!
+//! The muxer of every authenticated connection is wrapped so that the bytes read from and written
+//! to its substreams are added to the counters of the remote peer. The counters are stored in the
+//! `PeerDB`, apart from the `PeerInfo` of the peer, and shared by all of its connections.
+use libp2p::core::muxing::{StreamMuxer, StreamMuxerEvent};
+use serde_derive::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// The cumulative number of bytes exchanged with a peer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct BandwidthStats {
+    /// The bytes received from the peer.
+    pub inbound: u64,
+    /// The bytes sent to the peer.
+    pub outbound: u64,
+}
+
+/// The byte counters of a peer.
+#[derive(Debug, Default)]
+pub struct PeerBandwidth {
+    inbound: AtomicU64,
+    outbound: AtomicU64,
+}
+
+impl PeerBandwidth {
+    /// Returns the bytes exchanged with the peer so far.
+    pub fn stats(&self) -> BandwidthStats {
+        BandwidthStats {
+            inbound: self.inbound.load(Ordering::Relaxed),
+            outbound: self.outbound.load(Ordering::Relaxed),
+        }
+    }
+
+    fn add_inbound(&self, bytes: usize) {
+        self.inbound.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn add_outbound(&self, bytes: usize) {
+        self.outbound.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// Wraps the muxer of a connection, counting the bytes of its substreams.
+pub(crate) struct BandwidthCountingMuxer<M> {
+    inner: M,
+    bandwidth: Arc<PeerBandwidth>,
+}
+
+impl<M> BandwidthCountingMuxer<M> {
+    pub fn new(inner: M, bandwidth: Arc<PeerBandwidth>) -> Self {
+        BandwidthCountingMuxer { inner, bandwidth }
+    }
+}
+
+impl<M: StreamMuxer> StreamMuxer for BandwidthCountingMuxer<M> {
+    type Substream = M::Substream;
+    type OutboundSubstream = M::OutboundSubstream;
+    type Error = M::Error;
+
+    fn poll_event(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<StreamMuxerEvent<Self::Substream>, Self::Error>> {
+        self.inner.poll_event(cx)
+    }
+
+    fn open_outbound(&self) -> Self::OutboundSubstream {
+        self.inner.open_outbound()
+    }
+
+    fn poll_outbound(
+        &self,
+        cx: &mut Context<'_>,
+        substream: &mut Self::OutboundSubstream,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        self.inner.poll_outbound(cx, substream)
+    }
+
+    fn destroy_outbound(&self, substream: Self::OutboundSubstream) {
+        self.inner.destroy_outbound(substream)
+    }
+
+    fn read_substream(
+        &self,
+        cx: &mut Context<'_>,
+        substream: &mut Self::Substream,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Self::Error>> {
+        let result = self.inner.read_substream(cx, substream, buf);
+        if let Poll::Ready(Ok(bytes)) = result {
+            self.bandwidth.add_inbound(bytes);
+        }
+        result
+    }
+
+    fn write_substream(
+        &self,
+        cx: &mut Context<'_>,
+        substream: &mut Self::Substream,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Self::Error>> {
+        let result = self.inner.write_substream(cx, substream, buf);
+        if let Poll::Ready(Ok(bytes)) = result {
+            self.bandwidth.add_outbound(bytes);
+        }
+        result
+    }
+
+    fn flush_substream(
+        &self,
+        cx: &mut Context<'_>,
+        substream: &mut Self::Substream,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.flush_substream(cx, substream)
+    }
+
+    fn shutdown_substream(
+        &self,
+        cx: &mut Context<'_>,
+        substream: &mut Self::Substream,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.shutdown_substream(cx, substream)
+    }
+
+    fn destroy_substream(&self, substream: Self::Substream) {
+        self.inner.destroy_substream(substream)
+    }
+
+    fn close(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.close(cx)
+    }
+
+    fn flush_all(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.flush_all(cx)
+    }
+}
@@ -1,3 +1,8 @@
+use crate::behaviour::gossipsub_scoring_parameters::lighthouse_gossip_thresholds;
+use libp2p::gossipsub::PeerScoreThresholds;
+use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+
 /// The time in seconds between re-status's peers.
 pub const DEFAULT_STATUS_INTERVAL: u64 = 300;
 
@@ -10,6 +15,38 @@ pub const DEFAULT_PING_INTERVAL_INBOUND: u64 = 20;
 /// Default number of peers to connect to.
 pub const DEFAULT_TARGET_PEERS: usize = 50;
 
+/// Default number of peers to maintain on each sync committee subnet.
+pub const DEFAULT_TARGET_PEERS_PER_SYNC_SUBNET: usize = crate::behaviour::TARGET_SUBNET_PEERS;
+
+/// Default time in seconds for which an IP subnet stays banned.
+pub const DEFAULT_IP_SUBNET_BAN_DURATION: u64 = 3600;
+
+/// Default delay in seconds before redialing a peer whose dial failed.
+pub const DEFAULT_DIAL_BACKOFF_BASE: u64 = 5;
+
+/// Default maximum delay in seconds between dials to a failing peer.
+pub const DEFAULT_DIAL_BACKOFF_MAX: u64 = 300;
+
+/// Default number of consecutive dial failures after which a peer is dial-banned.
+pub const DEFAULT_DIAL_BACKOFF_MAX_ATTEMPTS: u32 = 5;
+
+/// How the peers to disconnect are chosen among the low-scored candidates when pruning to the
+/// target peer count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PeerEvictionPolicy {
+    /// Disconnect the worst scored candidates first.
+    Score,
+    /// Disconnect candidates from the /24 (IPv4) or /48 (IPv6) subnets we have the most peers on
+    /// first, breaking ties by score.
+    IpDiversity,
+}
+
+impl Default for PeerEvictionPolicy {
+    fn default() -> Self {
+        PeerEvictionPolicy::Score
+    }
+}
+
 /// Configurations for the PeerManager.
 #[derive(Debug)]
 pub struct Config {
@@ -20,6 +57,36 @@ pub struct Config {
     pub metrics_enabled: bool,
     /// Target number of peers to connect to.
     pub target_peer_count: usize,
+    /// Target number of peers on each sync committee subnet we need to maintain.
+    pub target_peers_per_sync_subnet: usize,
+    /// Time in seconds for which an IP subnet banned through `NetworkGlobals::ban_ip_subnet` is
+    /// refused.
+    pub ip_subnet_ban_duration: u64,
+    /// How long a peer may stay idle before it is disconnected, while we are above our target
+    /// peer count. `None` keeps idle peers.
+    pub idle_peer_timeout: Option<Duration>,
+    /// The maximum number of outbound dials in flight at once. Further dials are queued until a
+    /// slot frees. `None` does not limit the dials.
+    pub max_concurrent_dials: Option<usize>,
+    /// The delay before redialing a peer after its first failed dial. The delay doubles with each
+    /// further failure.
+    pub dial_backoff_base: Duration,
+    /// The maximum delay between dials to a failing peer.
+    pub dial_backoff_max: Duration,
+    /// Consecutive failed dials after which a peer is not dialed for `dial_backoff_max`.
+    pub dial_backoff_max_attempts: u32,
+    /// The maximum number of peers kept in the `PeerDB`. Beyond it, disconnected peers are
+    /// pruned. `None` only applies the `PeerDB`'s own limits.
+    pub max_peer_db_entries: Option<usize>,
+    /// The gossipsub score thresholds, which delimit the `GossipScoreBand`s.
+    pub gossip_thresholds: PeerScoreThresholds,
+    /// Whether only trusted peers may connect and be dialed.
+    pub trusted_peers_only: bool,
+    /// The minimum version, such as `v2.0.0`, of the Lighthouse peers we stay connected to. `None`
+    /// accepts any version.
+    pub min_peer_protocol_version: Option<String>,
+    /// How the peers to disconnect are chosen when pruning to the target peer count.
+    pub eviction_policy: PeerEvictionPolicy,
 
     /* RPC related configurations */
     /// Time in seconds between status requests sent to peers.
@@ -38,6 +105,18 @@ impl Default for Config {
             discovery_enabled: true,
             metrics_enabled: false,
             target_peer_count: DEFAULT_TARGET_PEERS,
+            target_peers_per_sync_subnet: DEFAULT_TARGET_PEERS_PER_SYNC_SUBNET,
+            ip_subnet_ban_duration: DEFAULT_IP_SUBNET_BAN_DURATION,
+            idle_peer_timeout: None,
+            max_concurrent_dials: None,
+            dial_backoff_base: Duration::from_secs(DEFAULT_DIAL_BACKOFF_BASE),
+            dial_backoff_max: Duration::from_secs(DEFAULT_DIAL_BACKOFF_MAX),
+            dial_backoff_max_attempts: DEFAULT_DIAL_BACKOFF_MAX_ATTEMPTS,
+            max_peer_db_entries: None,
+            gossip_thresholds: lighthouse_gossip_thresholds(),
+            trusted_peers_only: false,
+            min_peer_protocol_version: None,
+            eviction_policy: PeerEvictionPolicy::default(),
             status_interval: DEFAULT_STATUS_INTERVAL,
             ping_interval_inbound: DEFAULT_PING_INTERVAL_INBOUND,
             ping_interval_outbound: DEFAULT_PING_INTERVAL_OUTBOUND,
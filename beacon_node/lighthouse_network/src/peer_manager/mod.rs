@@ -1,5 +1,6 @@
 //! Implementation of Lighthouse's peer management system.
 
+use crate::behaviour::gossipsub_scoring_parameters::GossipScoreBand;
 use crate::behaviour::TARGET_SUBNET_PEERS;
 use crate::rpc::{GoodbyeReason, MetaData, Protocol, RPCError, RPCResponseErrorCode};
 use crate::{error, metrics, Gossipsub};
@@ -7,6 +8,7 @@ use crate::{NetworkGlobals, PeerId};
 use crate::{Subnet, SubnetDiscovery};
 use discv5::Enr;
 use hashset_delay::HashSetDelay;
+use libp2p::gossipsub::PeerScoreThresholds;
 use libp2p::identify::IdentifyInfo;
 use peerdb::{client::ClientKind, BanOperation, BanResult, ScoreUpdateResult};
 use rand::seq::SliceRandom;
@@ -17,7 +19,7 @@ use std::{
     time::{Duration, Instant},
 };
 use strum::IntoEnumIterator;
-use types::{EthSpec, SyncSubnetId};
+use types::{consts::altair::SYNC_COMMITTEE_SUBNET_COUNT, EthSpec, SyncSubnetId};
 
 pub use libp2p::core::{identity::Keypair, Multiaddr};
 
@@ -25,15 +27,18 @@ pub use libp2p::core::{identity::Keypair, Multiaddr};
 pub mod peerdb;
 
 pub use peerdb::peer_info::{
-    ConnectionDirection, PeerConnectionStatus, PeerConnectionStatus::*, PeerInfo,
+    ConnectionDirection, DisconnectReason, PeerConnectionStatus, PeerConnectionStatus::*, PeerInfo,
+    PeerProtocolInfo,
 };
 use peerdb::score::{PeerAction, ReportSource};
 pub use peerdb::sync_status::{SyncInfo, SyncStatus};
-use std::collections::{hash_map::Entry, HashMap};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
 use std::net::IpAddr;
 pub mod config;
 mod network_behaviour;
 
+pub(crate) use network_behaviour::multiaddr_ip;
+
 /// The heartbeat performs regular updates such as updating reputations and performing discovery
 /// requests. This defines the interval in seconds.
 const HEARTBEAT_INTERVAL: u64 = 30;
@@ -69,8 +74,44 @@ pub struct PeerManager<TSpec: EthSpec> {
     outbound_ping_peers: HashSetDelay<PeerId>,
     /// A collection of peers awaiting to be Status'd.
     status_peers: HashSetDelay<PeerId>,
+    /// The peers we have sent a Ping to and not yet received a Pong from, with when the Ping was
+    /// sent.
+    pings_in_flight: HashMap<PeerId, Instant>,
+    /// Connected peers whose STATUS fork digest has been reported as not matching ours.
+    fork_digest_mismatch_peers: HashSet<PeerId>,
+    /// The thresholds delimiting the gossipsub score bands.
+    gossip_thresholds: PeerScoreThresholds,
+    /// Connected peers whose gossipsub score is not in the healthy band, with their band.
+    gossip_score_bands: HashMap<PeerId, GossipScoreBand>,
     /// The target number of peers we would like to connect to.
     target_peers: usize,
+    /// The target number of peers on each of the `sync_committee_subnets`.
+    target_peers_per_sync_subnet: usize,
+    /// How long IP subnets banned through the `PeerDB` stay banned.
+    ip_subnet_ban_duration: Duration,
+    /// The last time each connected peer sent us gossip or a blocks request or response.
+    last_activity: HashMap<PeerId, Instant>,
+    /// How long a peer may stay idle before it is pruned, if above our target peer count.
+    idle_peer_timeout: Option<Duration>,
+    /// The peers we are dialing whose dial has not yet completed or failed.
+    dials_in_flight: HashSet<PeerId>,
+    /// Peers waiting for a free dial slot, in the order they are to be dialed.
+    dial_queue: VecDeque<PeerId>,
+    /// The number of dials counted as attempts in the `DialStats` whose outcome is pending, by
+    /// the peer dialed. The dials of an address without a peer id are counted under `None`.
+    counted_dials: HashMap<Option<PeerId>, usize>,
+    /// The maximum number of dials in flight at once, if limited.
+    max_concurrent_dials: Option<usize>,
+    /// Peers whose recent dials failed, with when they may next be dialed.
+    dial_backoffs: HashMap<PeerId, DialBackoff>,
+    /// The delay before redialing a peer after its first failed dial.
+    dial_backoff_base: Duration,
+    /// The maximum delay between dials to a failing peer.
+    dial_backoff_max: Duration,
+    /// Consecutive failed dials after which a peer is dial-banned for `dial_backoff_max`.
+    dial_backoff_max_attempts: u32,
+    /// The maximum number of peers kept in the `PeerDB`, if limited.
+    max_peer_db_entries: Option<usize>,
     /// A collection of sync committee subnets that we need to stay subscribed to.
     /// Sync committee subnets are longer term (256 epochs). Hence, we need to re-run
     /// discovery queries for subnet peers if we disconnect from existing sync
@@ -80,12 +121,27 @@ pub struct PeerManager<TSpec: EthSpec> {
     heartbeat: tokio::time::Interval,
     /// Keeps track of whether the discovery service is enabled or not.
     discovery_enabled: bool,
+    /// Whether only trusted peers may connect and be dialed.
+    trusted_peers_only: bool,
+    /// The minimum `(major, minor, patch)` version of the Lighthouse peers we stay connected to.
+    min_peer_version: Option<(u64, u64, u64)>,
+    /// How the peers to disconnect are chosen when pruning to the target peer count.
+    eviction_policy: config::PeerEvictionPolicy,
     /// Keeps track if the current instance is reporting metrics or not.
     metrics_enabled: bool,
     /// The logger associated with the `PeerManager`.
     log: slog::Logger,
 }
 
+/// The redial backoff of a peer whose dials have been failing.
+#[derive(Debug, Clone, Copy)]
+struct DialBackoff {
+    /// The consecutive failed dials since the last connection or dial ban.
+    failures: u32,
+    /// The peer is not dialed before this instant.
+    next_attempt: Instant,
+}
+
 /// The events that the `PeerManager` outputs (requests).
 #[derive(Debug)]
 pub enum PeerManagerEvent {
@@ -101,6 +157,8 @@ pub enum PeerManagerEvent {
     Ping(PeerId),
     /// Request METADATA from a peer.
     MetaData(PeerId),
+    /// Request the behaviour to dial a peer.
+    DialPeer(PeerId),
     /// The peer should be disconnected.
     DisconnectPeer(PeerId, GoodbyeReason),
     /// Inform the behaviour to ban this peer and associated ip addresses.
@@ -111,6 +169,21 @@ pub enum PeerManagerEvent {
     DiscoverPeers(usize),
     /// Request the behaviour to discover peers on subnets.
     DiscoverSubnetPeers(Vec<SubnetDiscovery>),
+    /// A peer sent a STATUS with a fork digest that does not match ours.
+    ForkDigestMismatch {
+        peer_id: PeerId,
+        their_fork_digest: [u8; 4],
+        our_fork_digest: [u8; 4],
+    },
+    /// The gossipsub score of a peer moved to another band.
+    GossipScoreThresholdCrossed {
+        peer_id: PeerId,
+        from: GossipScoreBand,
+        to: GossipScoreBand,
+    },
+    /// Discovery found a peer that is banned, either by its score or by its IP. The peer is not
+    /// dialed.
+    DiscoveredBannedPeer { peer_id: PeerId },
 }
 
 impl<TSpec: EthSpec> PeerManager<TSpec> {
@@ -124,11 +197,30 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
             discovery_enabled,
             metrics_enabled,
             target_peer_count,
+            target_peers_per_sync_subnet,
+            ip_subnet_ban_duration,
+            idle_peer_timeout,
+            max_concurrent_dials,
+            dial_backoff_base,
+            dial_backoff_max,
+            dial_backoff_max_attempts,
+            max_peer_db_entries,
+            gossip_thresholds,
+            trusted_peers_only,
+            min_peer_protocol_version,
+            eviction_policy,
             status_interval,
             ping_interval_inbound,
             ping_interval_outbound,
         } = cfg;
 
+        let min_peer_version = min_peer_protocol_version
+            .map(|version| {
+                peerdb::client::parse_semantic_version(&version)
+                    .ok_or_else(|| format!("Invalid minimum peer protocol version: {}", version))
+            })
+            .transpose()?;
+
         // Set up the peer manager heartbeat interval
         let heartbeat = tokio::time::interval(tokio::time::Duration::from_secs(HEARTBEAT_INTERVAL));
 
@@ -138,10 +230,30 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
             inbound_ping_peers: HashSetDelay::new(Duration::from_secs(ping_interval_inbound)),
             outbound_ping_peers: HashSetDelay::new(Duration::from_secs(ping_interval_outbound)),
             status_peers: HashSetDelay::new(Duration::from_secs(status_interval)),
+            pings_in_flight: HashMap::new(),
+            fork_digest_mismatch_peers: HashSet::new(),
+            gossip_thresholds,
+            gossip_score_bands: HashMap::new(),
             target_peers: target_peer_count,
+            target_peers_per_sync_subnet,
+            ip_subnet_ban_duration: Duration::from_secs(ip_subnet_ban_duration),
+            last_activity: HashMap::new(),
+            idle_peer_timeout,
+            dials_in_flight: HashSet::new(),
+            dial_queue: VecDeque::new(),
+            counted_dials: HashMap::new(),
+            max_concurrent_dials,
+            dial_backoffs: HashMap::new(),
+            dial_backoff_base,
+            dial_backoff_max,
+            dial_backoff_max_attempts,
+            max_peer_db_entries,
             sync_committee_subnets: Default::default(),
             heartbeat,
             discovery_enabled,
+            trusted_peers_only,
+            min_peer_version,
+            eviction_policy,
             metrics_enabled,
             log: log.clone(),
         })
@@ -172,6 +284,37 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
         );
     }
 
+    /// Gracefully disconnects all connected peers, sending each of them a goodbye with the given
+    /// reason. The peers are not banned.
+    pub fn disconnect_all_peers(&mut self, reason: GoodbyeReason) {
+        let connected_peers: Vec<PeerId> = self
+            .network_globals
+            .peers
+            .read()
+            .connected_peer_ids()
+            .cloned()
+            .collect();
+        for peer_id in connected_peers {
+            self.disconnect_peer(peer_id, reason.clone());
+        }
+    }
+
+    /// Gracefully disconnects the given peers, sending each of them a goodbye with the given
+    /// reason. Peers that are not connected are skipped. The peers are not banned.
+    pub fn disconnect_peers(&mut self, peers: &[PeerId], reason: GoodbyeReason) {
+        let peers_to_disconnect: HashSet<PeerId> = {
+            let peer_db = self.network_globals.peers.read();
+            peers
+                .iter()
+                .filter(|peer_id| peer_db.is_connected(peer_id))
+                .cloned()
+                .collect()
+        };
+        for peer_id in peers_to_disconnect {
+            self.disconnect_peer(peer_id, reason.clone());
+        }
+    }
+
     /// Reports a peer for some action.
     ///
     /// If the peer doesn't exist, log a warning and insert defaults.
@@ -213,6 +356,7 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
             ScoreUpdateResult::Disconnect => {
                 // The peer has transitioned to a disconnect state and has been marked as such in
                 // the peer db. We must inform libp2p to disconnect this peer.
+                self.set_disconnect_reason(peer_id, DisconnectReason::BadScore);
                 self.events.push(PeerManagerEvent::DisconnectPeer(
                     *peer_id,
                     GoodbyeReason::BadScore,
@@ -241,10 +385,10 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
                 // The peer was currently connected, so we start a disconnection.
                 // Once the peer has disconnected, its connection state will transition to a
                 // banned state.
-                self.events.push(PeerManagerEvent::DisconnectPeer(
-                    *peer_id,
-                    reason.unwrap_or(GoodbyeReason::BadScore),
-                ));
+                let reason = reason.unwrap_or(GoodbyeReason::BadScore);
+                self.set_disconnect_reason(peer_id, reason.clone().into());
+                self.events
+                    .push(PeerManagerEvent::DisconnectPeer(*peer_id, reason));
             }
             BanOperation::PeerDisconnecting => {
                 // The peer is currently being disconnected and will be banned once the
@@ -272,6 +416,7 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
         let mut to_dial_peers = Vec::new();
 
         let connected_or_dialing = self.network_globals.connected_or_dialing_peers();
+        let now = Instant::now();
         for (peer_id, min_ttl) in results {
             // There are two conditions in deciding whether to dial this peer.
             // 1. If we are less than our max connections. Discovery queries are executed to reach
@@ -285,6 +430,7 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
                 && connected_or_dialing + to_dial_peers.len() < self.max_priority_peers()
                 || connected_or_dialing + to_dial_peers.len() < self.max_peers())
                 && self.network_globals.peers.read().should_dial(&peer_id)
+                && !self.is_dial_backed_off(&peer_id, now)
             {
                 // This should be updated with the peer dialing. In fact created once the peer is
                 // dialed
@@ -304,11 +450,81 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
         to_dial_peers
     }
 
+    /// Returns true if a discovered peer is banned, either by its score or because an IP of its
+    /// ENR is banned or falls in a banned IP subnet. Banned peers are reported with a
+    /// `DiscoveredBannedPeer` event and should not be dialed.
+    pub fn discovered_peer_is_banned(&mut self, peer_id: &PeerId, enr: Option<&Enr>) -> bool {
+        let banned = {
+            let peer_db = self.network_globals.peers.read();
+            let ip_banned = |ip: IpAddr| {
+                peer_db.is_ip_banned(&ip)
+                    || peer_db
+                        .banned_ip_subnet(&ip, self.ip_subnet_ban_duration)
+                        .is_some()
+            };
+            !matches!(peer_db.ban_status(peer_id), BanResult::NotBanned)
+                || enr.map_or(false, |enr| {
+                    enr.ip()
+                        .map(IpAddr::V4)
+                        .into_iter()
+                        .chain(enr.ip6().map(IpAddr::V6))
+                        .any(ip_banned)
+                })
+        };
+
+        if banned {
+            debug!(self.log, "Discovered a banned peer"; "peer_id" => %peer_id);
+            self.events
+                .push(PeerManagerEvent::DiscoveredBannedPeer { peer_id: *peer_id });
+        }
+        banned
+    }
+
+    /// A peer sent us a goodbye. The RPC handler closes the connection, so the peer is marked as
+    /// disconnecting.
+    pub fn goodbye_received(&mut self, peer_id: &PeerId, reason: GoodbyeReason) {
+        let mut peers = self.network_globals.peers.write();
+        if peers.is_connected(peer_id) {
+            peers.notify_disconnecting(peer_id, false);
+            if let Some(info) = peers.peer_info_mut(peer_id) {
+                info.set_disconnect_reason(DisconnectReason::GoodbyeReceived(reason));
+            }
+        }
+    }
+
+    /// A peer sent us gossip or a blocks request or response. This resets its idle timer.
+    pub fn peer_activity(&mut self, peer_id: &PeerId) {
+        if let Some(last_activity) = self.last_activity.get_mut(peer_id) {
+            *last_activity = Instant::now();
+        }
+    }
+
     /// A STATUS message has been received from a peer. This resets the status timer.
     pub fn peer_statusd(&mut self, peer_id: &PeerId) {
         self.status_peers.insert(*peer_id);
     }
 
+    /// Compares the fork digest of a peer's STATUS with ours. A mismatch is reported once per
+    /// connection, subsequent STATUS messages from the same peer are not reported again.
+    pub fn check_fork_digest(
+        &mut self,
+        peer_id: &PeerId,
+        their_fork_digest: [u8; 4],
+        our_fork_digest: [u8; 4],
+    ) {
+        if their_fork_digest != our_fork_digest && self.fork_digest_mismatch_peers.insert(*peer_id)
+        {
+            debug!(self.log, "Peer fork digest does not match ours"; "peer_id" => %peer_id,
+                "their_fork_digest" => hex::encode(their_fork_digest),
+                "our_fork_digest" => hex::encode(our_fork_digest));
+            self.events.push(PeerManagerEvent::ForkDigestMismatch {
+                peer_id: *peer_id,
+                their_fork_digest,
+                our_fork_digest,
+            });
+        }
+    }
+
     /// Insert the sync subnet into list of long lived sync committee subnets that we need to
     /// maintain adequate number of peers for.
     pub fn add_sync_subnet(&mut self, subnet_id: SyncSubnetId, min_ttl: Instant) {
@@ -355,6 +571,204 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
             as usize
     }
 
+    /// Dials a peer, or queues the dial if `max_concurrent_dials` dials are already in flight.
+    ///
+    /// The peer is marked as dialing straight away, so that queued dials count towards our peer
+    /// limits. Queued dials are started as in-flight dials complete or fail.
+    ///
+    /// Peers whose recent dials failed are not dialed until their backoff has elapsed.
+    pub fn dial_peer(&mut self, peer_id: &PeerId, enr: Option<Enr>) {
+        if self.trusted_peers_only && !self.is_trusted(peer_id) {
+            debug!(self.log, "Not dialing untrusted peer"; "peer_id" => %peer_id);
+            return;
+        }
+        if self.is_dial_backed_off(peer_id, Instant::now()) {
+            debug!(self.log, "Not dialing peer in dial backoff"; "peer_id" => %peer_id);
+            return;
+        }
+        self.inject_dialing(peer_id, enr);
+        if self.dials_in_flight.contains(peer_id) || self.dial_queue.contains(peer_id) {
+            return;
+        }
+        if self.has_free_dial_slot() {
+            self.start_dial(*peer_id);
+        } else {
+            debug!(self.log, "Dial limit reached, queueing dial"; "peer_id" => %peer_id,
+                "queued" => self.dial_queue.len() + 1);
+            self.dial_queue.push_back(*peer_id);
+        }
+    }
+
+    /// Drops the queued dials. The queued peers are no longer considered dialing.
+    pub fn clear_dial_queue(&mut self) {
+        debug!(self.log, "Clearing the dial queue"; "queued" => self.dial_queue.len());
+        for peer_id in std::mem::take(&mut self.dial_queue) {
+            if !self.network_globals.peers.read().is_connected(&peer_id) {
+                self.inject_disconnect(&peer_id);
+            }
+        }
+    }
+
+    /// Changes the number of peers we aim to be connected to. More peers are discovered if the
+    /// target was raised and the excess peers are pruned if it was lowered, without waiting for
+    /// the next heartbeat.
+    pub fn set_target_peers(&mut self, target_peers: usize) {
+        debug!(self.log, "Updating the target peer count"; "old" => self.target_peers,
+            "new" => target_peers);
+        self.target_peers = target_peers;
+        self.network_globals
+            .target_peers
+            .store(target_peers, std::sync::atomic::Ordering::Relaxed);
+        self.maintain_peer_count(0);
+        self.prune_excess_peers();
+    }
+
+    /// Enables or disables the trusted peers only mode. While enabled, inbound connections and
+    /// dials of untrusted peers are refused. The peers already connected are kept.
+    pub fn set_trusted_peers_only(&mut self, enabled: bool) {
+        debug!(self.log, "Updating the trusted peers only mode"; "enabled" => enabled);
+        self.trusted_peers_only = enabled;
+    }
+
+    /// Whether only trusted peers may connect and be dialed.
+    pub fn trusted_peers_only(&self) -> bool {
+        self.trusted_peers_only
+    }
+
+    /// Returns true if the peer is one of our trusted peers.
+    fn is_trusted(&self, peer_id: &PeerId) -> bool {
+        self.network_globals
+            .peers
+            .read()
+            .peer_info(peer_id)
+            .map_or(false, |info| info.is_trusted())
+    }
+
+    /// The number of dials currently in flight.
+    pub fn dials_in_flight(&self) -> usize {
+        self.dials_in_flight.len()
+    }
+
+    /// Returns true if the dials to a peer have been failing and it should not be dialed before
+    /// its backoff elapses.
+    pub fn is_dial_backed_off(&self, peer_id: &PeerId, now: Instant) -> bool {
+        self.dial_backoffs
+            .get(peer_id)
+            .map_or(false, |backoff| now < backoff.next_attempt)
+    }
+
+    /// Records a failed dial to a peer, doubling the delay before it is redialed. After
+    /// `dial_backoff_max_attempts` consecutive failures the peer is dial-banned for
+    /// `dial_backoff_max`.
+    fn register_dial_failure(&mut self, peer_id: &PeerId, now: Instant) {
+        let backoff = self.dial_backoffs.entry(*peer_id).or_insert(DialBackoff {
+            failures: 0,
+            next_attempt: now,
+        });
+        backoff.failures += 1;
+        if backoff.failures >= self.dial_backoff_max_attempts {
+            debug!(self.log, "Dial-banning peer after repeated dial failures"; "peer_id" => %peer_id,
+                "failures" => backoff.failures, "duration" => ?self.dial_backoff_max);
+            backoff.failures = 0;
+            backoff.next_attempt = now + self.dial_backoff_max;
+        } else {
+            let delay = self
+                .dial_backoff_base
+                .saturating_mul(2u32.saturating_pow(backoff.failures - 1))
+                .min(self.dial_backoff_max);
+            trace!(self.log, "Backing off dials to peer"; "peer_id" => %peer_id,
+                "failures" => backoff.failures, "delay" => ?delay);
+            backoff.next_attempt = now + delay;
+        }
+    }
+
+    /// Forgets the backoffs that elapsed long enough ago to no longer be relevant.
+    fn prune_dial_backoffs(&mut self, now: Instant) {
+        let dial_backoff_max = self.dial_backoff_max;
+        self.dial_backoffs
+            .retain(|_, backoff| backoff.next_attempt + dial_backoff_max > now);
+    }
+
+    fn has_free_dial_slot(&self) -> bool {
+        self.max_concurrent_dials
+            .map_or(true, |max| self.dials_in_flight.len() < max)
+    }
+
+    fn start_dial(&mut self, peer_id: PeerId) {
+        self.dials_in_flight.insert(peer_id);
+        self.count_dial_attempt(Some(peer_id));
+        self.events.push(PeerManagerEvent::DialPeer(peer_id));
+    }
+
+    /// Counts a dial of `peer_id` in the `DialStats` of the network globals, or of an address
+    /// without a known peer id if `None`. Only the outcomes of the counted dials are counted.
+    pub(crate) fn count_dial_attempt(&mut self, peer_id: Option<PeerId>) {
+        *self.counted_dials.entry(peer_id).or_default() += 1;
+        self.network_globals.dial_stats.write().attempts += 1;
+        metrics::inc_counter(&metrics::DIAL_ATTEMPTS);
+    }
+
+    /// Takes one of the counted dials of `peer_id` on the outcome of a dial, returning false if
+    /// the dial was not counted as an attempt, as for the dials of gossipsub peer exchange. A
+    /// connection to a peer that was not dialed by its peer id takes a dial of an address instead.
+    fn take_counted_dial(&mut self, peer_id: Option<PeerId>, connected: bool) -> bool {
+        let key = if self.counted_dials.contains_key(&peer_id) {
+            peer_id
+        } else if connected {
+            None
+        } else {
+            return false;
+        };
+        match self.counted_dials.entry(key) {
+            Entry::Occupied(mut entry) => {
+                *entry.get_mut() -= 1;
+                if *entry.get() == 0 {
+                    entry.remove();
+                }
+                true
+            }
+            Entry::Vacant(_) => false,
+        }
+    }
+
+    /// Counts a dial that established a connection.
+    fn count_dial_success(&self) {
+        self.network_globals.dial_stats.write().successes += 1;
+        metrics::inc_counter(&metrics::DIAL_SUCCESSES);
+    }
+
+    /// Counts a failed dial by the kind of its failure.
+    fn count_dial_failure(&self, kind: &'static str) {
+        let mut dial_stats = self.network_globals.dial_stats.write();
+        dial_stats.failures += 1;
+        *dial_stats.failures_by_kind.entry(kind).or_default() += 1;
+        metrics::inc_counter_vec(&metrics::DIAL_FAILURES_PER_KIND, &[kind]);
+    }
+
+    /// Frees the dial slot of a peer whose dial completed or failed, and starts the queued
+    /// dials that now fit.
+    fn dial_finished(&mut self, peer_id: &PeerId) {
+        if !self.dials_in_flight.remove(peer_id) {
+            return;
+        }
+        while self.has_free_dial_slot() {
+            let peer_id = match self.dial_queue.pop_front() {
+                Some(peer_id) => peer_id,
+                None => break,
+            };
+            // The dial may have timed out or the peer have connected while queued.
+            if matches!(
+                self.network_globals
+                    .peers
+                    .read()
+                    .connection_status(&peer_id),
+                Some(PeerConnectionStatus::Dialing { .. })
+            ) {
+                self.start_dial(peer_id);
+            }
+        }
+    }
+
     /* Notifications from the Swarm */
 
     // A peer is being dialed.
@@ -386,12 +800,44 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
     }
 
     /// Updates `PeerInfo` with `identify` information.
+    ///
+    /// Lighthouse peers older than our minimum peer version are disconnected. The versions of
+    /// other clients are not comparable to ours and are not checked.
     pub fn identify(&mut self, peer_id: &PeerId, info: &IdentifyInfo) {
+        self.update_identify_info(peer_id, info);
+
+        if let Some(min_version) = self.min_peer_version {
+            let outdated_version = self
+                .network_globals
+                .peers
+                .read()
+                .peer_info(peer_id)
+                .filter(|peer_info| {
+                    !peer_info.is_trusted()
+                        && peer_info.is_connected()
+                        && peer_info.client().kind == ClientKind::Lighthouse
+                })
+                .and_then(|peer_info| peer_info.client().semantic_version())
+                .filter(|version| *version < min_version);
+            if let Some(version) = outdated_version {
+                debug!(self.log, "Disconnecting peer with an outdated client"; "peer_id" => %peer_id,
+                    "agent_version" => &info.agent_version, "version" => ?version);
+                self.disconnect_peer_for(
+                    *peer_id,
+                    GoodbyeReason::IrrelevantNetwork,
+                    DisconnectReason::OutdatedClient,
+                );
+            }
+        }
+    }
+
+    fn update_identify_info(&mut self, peer_id: &PeerId, info: &IdentifyInfo) {
         if let Some(peer_info) = self.network_globals.peers.write().peer_info_mut(peer_id) {
             let previous_kind = peer_info.client().kind.clone();
             let previous_listening_addresses =
                 peer_info.set_listening_addresses(info.listen_addrs.clone());
             peer_info.set_client(peerdb::client::Client::from_identify_info(info));
+            peer_info.set_protocol_info(PeerProtocolInfo::from_identify_info(info));
 
             if previous_kind != peer_info.client().kind
                 || *peer_info.listening_addresses() != previous_listening_addresses
@@ -473,6 +919,10 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
             RPCError::ErrorResponse(code, _) => match code {
                 RPCResponseErrorCode::Unknown => PeerAction::HighToleranceError,
                 RPCResponseErrorCode::ResourceUnavailable => {
+                    // Peers are not expected to serve a bootstrap for every block root.
+                    if protocol == Protocol::LightClientBootstrap {
+                        return;
+                    }
                     // NOTE: This error only makes sense for the `BlocksByRange` and `BlocksByRoot`
                     // protocols.
                     //
@@ -493,6 +943,8 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
                     Protocol::Ping => PeerAction::MidToleranceError,
                     Protocol::BlocksByRange => PeerAction::MidToleranceError,
                     Protocol::BlocksByRoot => PeerAction::MidToleranceError,
+                    Protocol::BlobsByRange => PeerAction::MidToleranceError,
+                    Protocol::LightClientBootstrap => PeerAction::LowToleranceError,
                     Protocol::Goodbye => PeerAction::LowToleranceError,
                     Protocol::MetaData => PeerAction::LowToleranceError,
                     Protocol::Status => PeerAction::LowToleranceError,
@@ -508,6 +960,8 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
                     Protocol::Ping => PeerAction::Fatal,
                     Protocol::BlocksByRange => return,
                     Protocol::BlocksByRoot => return,
+                    Protocol::BlobsByRange => return,
+                    Protocol::LightClientBootstrap => return,
                     Protocol::Goodbye => return,
                     Protocol::MetaData => PeerAction::LowToleranceError,
                     Protocol::Status => PeerAction::LowToleranceError,
@@ -523,6 +977,8 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
                     Protocol::Ping => PeerAction::LowToleranceError,
                     Protocol::BlocksByRange => PeerAction::MidToleranceError,
                     Protocol::BlocksByRoot => PeerAction::MidToleranceError,
+                    Protocol::BlobsByRange => PeerAction::MidToleranceError,
+                    Protocol::LightClientBootstrap => PeerAction::LowToleranceError,
                     Protocol::Goodbye => return,
                     Protocol::MetaData => return,
                     Protocol::Status => return,
@@ -530,6 +986,10 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
             },
             RPCError::NegotiationTimeout => PeerAction::LowToleranceError,
             RPCError::Disconnected => return, // No penalty for a graceful disconnection
+            RPCError::OutboundQueueFull => {
+                // We are sending requests faster than we allow ourselves to. Our fault.
+                return;
+            }
         };
 
         self.report_peer(
@@ -581,8 +1041,11 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
 
     /// A PONG has been returned from a peer.
     pub fn pong_response(&mut self, peer_id: &PeerId, seq: u64) {
-        if let Some(peer_info) = self.network_globals.peers.read().peer_info(peer_id) {
-            // received a pong
+        if let Some(peer_info) = self.network_globals.peers.write().peer_info_mut(peer_id) {
+            // received a pong, time it against our ping
+            if let Some(sent) = self.pings_in_flight.remove(peer_id) {
+                peer_info.record_rtt(sent.elapsed());
+            }
 
             // if the sequence number is unknown send update the meta data of the peer.
             if let Some(meta_data) = &peer_info.meta_data() {
@@ -638,6 +1101,35 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
         for (peer_id, score_action) in actions {
             self.handle_score_action(&peer_id, score_action, None);
         }
+
+        let scores: Vec<_> = self
+            .network_globals
+            .peers
+            .read()
+            .connected_peer_ids()
+            .filter_map(|peer_id| gossipsub.peer_score(peer_id).map(|score| (*peer_id, score)))
+            .collect();
+        for (peer_id, score) in scores {
+            self.update_gossip_score_band(peer_id, score);
+        }
+    }
+
+    /// Records the gossipsub score band of a peer, reporting when it changes.
+    fn update_gossip_score_band(&mut self, peer_id: PeerId, score: f64) {
+        let to = GossipScoreBand::from_score(score, &self.gossip_thresholds);
+        let from = if to == GossipScoreBand::Healthy {
+            self.gossip_score_bands.remove(&peer_id)
+        } else {
+            self.gossip_score_bands.insert(peer_id, to)
+        }
+        .unwrap_or(GossipScoreBand::Healthy);
+
+        if from != to {
+            debug!(self.log, "Peer gossipsub score crossed a threshold"; "peer_id" => %peer_id,
+                "from" => ?from, "to" => ?to, "score" => score);
+            self.events
+                .push(PeerManagerEvent::GossipScoreThresholdCrossed { peer_id, from, to });
+        }
     }
 
     // This function updates metrics for all connected peers.
@@ -727,6 +1219,11 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
         self.inbound_ping_peers.remove(peer_id);
         self.outbound_ping_peers.remove(peer_id);
         self.status_peers.remove(peer_id);
+        self.pings_in_flight.remove(peer_id);
+        self.fork_digest_mismatch_peers.remove(peer_id);
+        self.gossip_score_bands.remove(peer_id);
+        self.last_activity.remove(peer_id);
+        self.network_globals.update_connected_peers();
         self.events.extend(
             purged_peers
                 .into_iter()
@@ -773,6 +1270,8 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
 
         // start a ping and status timer for the peer
         self.status_peers.insert(*peer_id);
+        self.last_activity.insert(*peer_id, Instant::now());
+        self.network_globals.update_connected_peers();
 
         let connected_peers = self.network_globals.connected_peers() as i64;
 
@@ -785,20 +1284,67 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
 
     // Gracefully disconnects a peer without banning them.
     fn disconnect_peer(&mut self, peer_id: PeerId, reason: GoodbyeReason) {
+        self.disconnect_peer_for(peer_id, reason.clone(), reason.into());
+    }
+
+    // Gracefully disconnects a peer without banning them, recording `disconnect_reason` as the
+    // cause.
+    fn disconnect_peer_for(
+        &mut self,
+        peer_id: PeerId,
+        reason: GoodbyeReason,
+        disconnect_reason: DisconnectReason,
+    ) {
         self.events
             .push(PeerManagerEvent::DisconnectPeer(peer_id, reason));
-        self.network_globals
-            .peers
-            .write()
-            .notify_disconnecting(&peer_id, false);
+        let mut peers = self.network_globals.peers.write();
+        peers.notify_disconnecting(&peer_id, false);
+        if let Some(info) = peers.peer_info_mut(&peer_id) {
+            info.set_disconnect_reason(disconnect_reason);
+        }
+    }
+
+    /// Records why a peer is being disconnected. It is logged once the peer has disconnected.
+    fn set_disconnect_reason(&self, peer_id: &PeerId, reason: DisconnectReason) {
+        if let Some(info) = self.network_globals.peers.write().peer_info_mut(peer_id) {
+            info.set_disconnect_reason(reason);
+        }
     }
 
-    /// Run discovery query for additional sync committee peers if we fall below `TARGET_PEERS`.
+    /// Disconnects the connected peers with an address in an IP subnet banned since the last
+    /// call.
+    fn disconnect_banned_ip_subnet_peers(&mut self) {
+        let peers_to_disconnect: Vec<PeerId> = {
+            let mut peer_db = self.network_globals.peers.write();
+            let new_bans = peer_db.take_new_ip_subnet_bans();
+            if new_bans.is_empty() {
+                return;
+            }
+            peer_db
+                .connected_peers()
+                .filter(|(_, info)| {
+                    info.seen_ip_addresses()
+                        .any(|ip| new_bans.iter().any(|subnet| subnet.contains(&ip)))
+                })
+                .map(|(peer_id, _)| *peer_id)
+                .collect()
+        };
+
+        for peer_id in peers_to_disconnect {
+            debug!(self.log, "Disconnecting peer in a banned IP subnet"; "peer_id" => %peer_id);
+            self.disconnect_peer(peer_id, GoodbyeReason::BannedIP);
+        }
+    }
+
+    /// Run discovery query for additional sync committee peers if we fall below
+    /// `target_peers_per_sync_subnet`.
     fn maintain_sync_committee_peers(&mut self) {
         // Remove expired entries
         self.sync_committee_subnets
             .retain(|_, v| *v > Instant::now());
 
+        self.update_sync_subnet_metrics();
+
         let subnets_to_discover: Vec<SubnetDiscovery> = self
             .sync_committee_subnets
             .iter()
@@ -809,7 +1355,7 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
                     .read()
                     .good_peers_on_subnet(Subnet::SyncCommittee(*k))
                     .count()
-                    < TARGET_SUBNET_PEERS
+                    < self.target_peers_per_sync_subnet
                 {
                     Some(SubnetDiscovery {
                         subnet: Subnet::SyncCommittee(*k),
@@ -884,16 +1430,17 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
     ///     long-lived subnets.
     /// - When pruning peers based on subnet count. If multiple peers can be chosen, choose a peer
     ///     that is not subscribed to a long-lived sync committee subnet.
-    /// - When pruning peers based on subnet count, do not prune a peer that would lower us below the
-    ///     MIN_SYNC_COMMITTEE_PEERS peer count. To keep it simple, we favour a minimum number of sync-committee-peers over
-    ///     uniformity subnet peers. NOTE: We could apply more sophisticated logic, but the code is
-    ///     simpler and easier to maintain if we take this approach. If we are pruning subnet peers
-    ///     below the MIN_SYNC_COMMITTEE_PEERS and maintaining the sync committee peers, this should be
-    ///     fine as subnet peers are more likely to be found than sync-committee-peers. Also, we're
-    ///     in a bit of trouble anyway if we have so few peers on subnets. The
-    ///     MIN_SYNC_COMMITTEE_PEERS
-    ///     number should be set low as an absolute lower bound to maintain peers on the sync
-    ///     committees.
+    /// - When pruning peers based on subnet count, do not prune a peer that would lower us below
+    ///     the `target_peers_per_sync_subnet` peer count on a sync committee subnet we are
+    ///     subscribed to, or the MIN_SYNC_COMMITTEE_PEERS peer count on the others. To keep it
+    ///     simple, we favour a minimum number of sync-committee-peers over uniformity subnet
+    ///     peers. NOTE: We could apply more sophisticated logic, but the code is simpler and
+    ///     easier to maintain if we take this approach. If we are pruning subnet peers below the
+    ///     MIN_SYNC_COMMITTEE_PEERS and maintaining the sync committee peers, this should be fine
+    ///     as subnet peers are more likely to be found than sync-committee-peers. Also, we're in
+    ///     a bit of trouble anyway if we have so few peers on subnets. The
+    ///     MIN_SYNC_COMMITTEE_PEERS number should be set low as an absolute lower bound to
+    ///     maintain peers on the sync committees.
     ///
     /// Prune peers in the following order:
     /// 1. Remove worst scoring peers
@@ -901,6 +1448,8 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
     /// 3. Remove peers that we have many on any particular subnet
     /// 4. Randomly remove peers if all the above are satisfied
     ///
+    /// With the `IpDiversity` eviction policy, the candidates of 1. and 2. are removed from the
+    /// most represented IP subnets first.
     fn prune_excess_peers(&mut self) {
         // The current number of connected peers.
         let connected_peer_count = self.network_globals.connected_peers();
@@ -916,16 +1465,31 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
         // Keep track of the number of outbound peers we are pruning.
         let mut outbound_peers_pruned = 0;
 
+        // The number of connected peers on each IP subnet that are not being pruned, used by the
+        // `IpDiversity` eviction policy.
+        let mut ip_subnet_peer_count: HashMap<IpAddr, usize> = HashMap::new();
+        if self.eviction_policy == config::PeerEvictionPolicy::IpDiversity {
+            for (_, info) in self.network_globals.peers.read().connected_peers() {
+                if let Some(subnet) = diversity_subnet(info) {
+                    *ip_subnet_peer_count.entry(subnet).or_default() += 1;
+                }
+            }
+        }
+
         macro_rules! prune_peers {
-            ($filter: expr) => {
-                for (peer_id, info) in self
-                    .network_globals
-                    .peers
-                    .read()
+            ($filter: expr) => {{
+                let peers = self.network_globals.peers.read();
+                let mut candidates = peers
                     .worst_connected_peers()
-                    .iter()
-                    .filter(|(_, info)| !info.has_future_duty() && $filter(*info))
-                {
+                    .into_iter()
+                    .filter(|(_, info)| {
+                        !info.has_future_duty() && !info.is_protected() && $filter(*info)
+                    })
+                    .collect::<Vec<_>>();
+                if self.eviction_policy == config::PeerEvictionPolicy::IpDiversity {
+                    candidates = order_by_ip_diversity(candidates, &ip_subnet_peer_count);
+                }
+                for (peer_id, info) in candidates.iter() {
                     if peers_to_prune.len()
                         >= connected_peer_count.saturating_sub(self.target_peers)
                     {
@@ -945,9 +1509,14 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
                             continue;
                         }
                     }
+                    if let Some(count) = diversity_subnet(info)
+                        .and_then(|subnet| ip_subnet_peer_count.get_mut(&subnet))
+                    {
+                        *count = count.saturating_sub(1);
+                    }
                     peers_to_prune.insert(**peer_id);
                 }
-            };
+            }};
         }
 
         // 1. Look through peers that have the worst score (ignoring non-penalized scored peers).
@@ -974,8 +1543,8 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
             > = HashMap::new();
 
             for (peer_id, info) in self.network_globals.peers.read().connected_peers() {
-                // Ignore peers we are already pruning
-                if peers_to_prune.contains(peer_id) {
+                // Ignore peers we are already pruning and protected peers
+                if peers_to_prune.contains(peer_id) || info.is_protected() {
                     continue;
                 }
 
@@ -1041,21 +1610,16 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
 
                             // Check the sync committee
                             if let Some(subnets) = peer_to_sync_committee.get(candidate_peer) {
-                                // The peer is subscribed to some long-lived sync-committees
-                                // Of all the subnets this peer is subscribed too, the minimum
-                                // peer count of all of them is min_subnet_count
-                                if let Some(min_subnet_count) = subnets
-                                    .iter()
-                                    .filter_map(|v| sync_committee_peer_count.get(v).copied())
-                                    .min()
-                                {
-                                    // If the minimum count is our target or lower, we
-                                    // shouldn't remove this peer, because it drops us lower
-                                    // than our target
-                                    if min_subnet_count <= MIN_SYNC_COMMITTEE_PEERS {
-                                        // Do not drop this peer in this pruning interval
-                                        continue;
-                                    }
+                                // The peer is subscribed to some long-lived sync-committees. If
+                                // any of them is at its retention target or lower, we shouldn't
+                                // remove this peer, because it drops us lower than our target
+                                if subnets.iter().any(|id| {
+                                    sync_committee_peer_count.get(id).map_or(false, |count| {
+                                        *count <= self.sync_committee_retain_target(id)
+                                    })
+                                }) {
+                                    // Do not drop this peer in this pruning interval
+                                    continue;
                                 }
                             }
 
@@ -1105,6 +1669,66 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
         }
     }
 
+    /// Disconnects the peers that have been idle for longer than the idle peer timeout at `now`,
+    /// the longest idle first, until we are back to our target peer count.
+    ///
+    /// Protected peers, peers with a future duty or on a long-lived subnet that is not above its
+    /// target peer count are kept.
+    fn prune_idle_peers(&mut self, now: Instant) {
+        let timeout = match self.idle_peer_timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        let excess_peer_count = self
+            .network_globals
+            .connected_peers()
+            .saturating_sub(self.target_peers);
+        if excess_peer_count == 0 {
+            return;
+        }
+
+        let idle_peers: Vec<PeerId> = {
+            let peers = self.network_globals.peers.read();
+            let subnet_lacks_peers = |subnet: Subnet| {
+                let target = match subnet {
+                    Subnet::Attestation(_) => TARGET_SUBNET_PEERS,
+                    Subnet::SyncCommittee(_) => self.target_peers_per_sync_subnet,
+                };
+                peers.good_peers_on_subnet(subnet).count() <= target
+            };
+
+            let mut idle_peers: Vec<_> = self
+                .last_activity
+                .iter()
+                .filter(|(_, last_activity)| {
+                    now.saturating_duration_since(**last_activity) >= timeout
+                })
+                .filter(|(peer_id, _)| {
+                    peers.peer_info(peer_id).map_or(false, |info| {
+                        info.is_connected()
+                            && !info.has_future_duty()
+                            && !info.is_protected()
+                            && !info
+                                .long_lived_subnets()
+                                .into_iter()
+                                .any(subnet_lacks_peers)
+                    })
+                })
+                .collect();
+            idle_peers.sort_by_key(|(_, last_activity)| **last_activity);
+            idle_peers
+                .into_iter()
+                .take(excess_peer_count)
+                .map(|(peer_id, _)| *peer_id)
+                .collect()
+        };
+
+        for peer_id in idle_peers {
+            debug!(self.log, "Disconnecting idle peer"; "peer_id" => %peer_id);
+            self.disconnect_peer_for(peer_id, GoodbyeReason::TooManyPeers, DisconnectReason::Idle);
+        }
+    }
+
     /// The Peer manager's heartbeat maintains the peer count and maintains peer reputations.
     ///
     /// It will request discovery queries if the peer count has not reached the desired number of
@@ -1122,6 +1746,23 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
         // we count the number of dialing peers in our inbound connections.
         self.network_globals.peers.write().cleanup_dialing_peers();
 
+        // Forget the counters of the connections of peers that are not in the `PeerDB`.
+        self.network_globals.peers.write().prune_peer_counters();
+
+        // Forget disconnected peers beyond the size limit of the `PeerDB`.
+        if let Some(max_peer_db_entries) = self.max_peer_db_entries {
+            self.network_globals
+                .peers
+                .write()
+                .prune_disconnected_peers(max_peer_db_entries);
+        }
+
+        // Lift the IP subnet bans that have expired.
+        self.network_globals
+            .peers
+            .write()
+            .prune_ip_subnet_bans(self.ip_subnet_ban_duration);
+
         // Updates peer's scores and unban any peers if required.
         let actions = self.network_globals.peers.write().update_scores();
         for (peer_id, action) in actions {
@@ -1134,11 +1775,45 @@ impl<TSpec: EthSpec> PeerManager<TSpec> {
         // Maintain minimum count for sync committee peers.
         self.maintain_sync_committee_peers();
 
+        // Prune idle peers first, as they are the least useful of the excess peers.
+        self.prune_idle_peers(Instant::now());
+
+        self.prune_dial_backoffs(Instant::now());
+
         // Prune any excess peers back to our target in such a way that incentivises good scores and
         // a uniform distribution of subnets.
         self.prune_excess_peers();
     }
 
+    /// The number of peers on the sync committee subnet `id` that pruning keeps if it can. This is
+    /// `target_peers_per_sync_subnet` for the subnets we are subscribed to, and
+    /// `MIN_SYNC_COMMITTEE_PEERS` for the others.
+    fn sync_committee_retain_target(&self, id: &SyncSubnetId) -> u64 {
+        if self.sync_committee_subnets.contains_key(id) {
+            (self.target_peers_per_sync_subnet as u64).max(MIN_SYNC_COMMITTEE_PEERS)
+        } else {
+            MIN_SYNC_COMMITTEE_PEERS
+        }
+    }
+
+    // Update the number of peers on each sync committee subnet.
+    fn update_sync_subnet_metrics(&self) {
+        if !self.metrics_enabled {
+            return;
+        }
+        let peers = self.network_globals.peers.read();
+        for subnet_id in 0..SYNC_COMMITTEE_SUBNET_COUNT {
+            let peer_count = peers
+                .good_peers_on_subnet(Subnet::SyncCommittee(SyncSubnetId::new(subnet_id)))
+                .count();
+            metrics::set_gauge_vec(
+                &metrics::PEERS_PER_SYNC_COMMITTEE_SUBNET,
+                &[&subnet_id.to_string()],
+                peer_count as i64,
+            );
+        }
+    }
+
     // Update metrics related to peer scoring.
     fn update_peer_score_metrics(&self) {
         if !self.metrics_enabled {
@@ -1224,6 +1899,65 @@ enum ConnectingType {
     },
 }
 
+/// The /24 (IPv4) or /48 (IPv6) subnet of the first IP address we have seen the peer on, by which
+/// the IP diversity of our peers is measured.
+pub(crate) fn diversity_subnet<TSpec: EthSpec>(info: &PeerInfo<TSpec>) -> Option<IpAddr> {
+    info.seen_ip_addresses().next().map(|ip| match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::V4(std::net::Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            IpAddr::V6(std::net::Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                0,
+                0,
+                0,
+                0,
+                0,
+            ))
+        }
+    })
+}
+
+/// Orders the prune `candidates`, sorted from worst to best score, such that each next candidate
+/// is the worst scored one of the IP subnet with the most remaining peers in
+/// `ip_subnet_peer_count`.
+fn order_by_ip_diversity<'a, TSpec: EthSpec>(
+    mut candidates: Vec<(&'a PeerId, &'a PeerInfo<TSpec>)>,
+    ip_subnet_peer_count: &HashMap<IpAddr, usize>,
+) -> Vec<(&'a PeerId, &'a PeerInfo<TSpec>)> {
+    let mut ip_subnet_peer_count = ip_subnet_peer_count.clone();
+    let mut ordered = Vec::with_capacity(candidates.len());
+    while !candidates.is_empty() {
+        let subnet_peers = |info: &PeerInfo<TSpec>| {
+            diversity_subnet(info)
+                .and_then(|subnet| ip_subnet_peer_count.get(&subnet).copied())
+                .unwrap_or(0)
+        };
+        // `max_by_key` returns the last of equal elements, iterating in reverse keeps the worst
+        // scored candidate of the subnet.
+        let index = candidates
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, (_, info))| subnet_peers(info))
+            .map(|(index, _)| index)
+            .unwrap_or(0);
+        let candidate = candidates.remove(index);
+        if let Some(count) =
+            diversity_subnet(candidate.1).and_then(|subnet| ip_subnet_peer_count.get_mut(&subnet))
+        {
+            *count = count.saturating_sub(1);
+        }
+        ordered.push(candidate);
+    }
+    ordered
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1324,6 +2058,42 @@ mod tests {
         assert_eq!(peer_manager.network_globals.connected_or_dialing_peers(), 3);
     }
 
+    #[tokio::test]
+    async fn test_set_target_peers_prunes_excess_peers() {
+        let mut peer_manager = build_peer_manager(5).await;
+
+        let peers: Vec<PeerId> = (0..5).map(|_| PeerId::random()).collect();
+        for peer in &peers {
+            peer_manager.inject_connect_ingoing(peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        }
+        // Give the first two peers the lowest scores.
+        for (peer, score) in peers.iter().zip([-2.0, -1.0]) {
+            peer_manager
+                .network_globals
+                .peers
+                .write()
+                .peer_info_mut(peer)
+                .unwrap()
+                .add_to_score(score);
+        }
+
+        peer_manager.heartbeat();
+        assert_eq!(peer_manager.network_globals.connected_or_dialing_peers(), 5);
+
+        peer_manager.set_target_peers(3);
+        assert_eq!(
+            peer_manager
+                .network_globals
+                .target_peers
+                .load(std::sync::atomic::Ordering::Relaxed),
+            3
+        );
+        assert_eq!(peer_manager.network_globals.connected_or_dialing_peers(), 3);
+        let peer_db = peer_manager.network_globals.peers.read();
+        assert!(!peer_db.is_connected(&peers[0]));
+        assert!(!peer_db.is_connected(&peers[1]));
+    }
+
     #[tokio::test]
     async fn test_peer_manager_not_enough_outbound_peers_no_panic_during_heartbeat() {
         let mut peer_manager = build_peer_manager(20).await;
@@ -1893,4 +2663,844 @@ mod tests {
         assert!(!connected_peers.contains(&peers[1]));
         assert!(!connected_peers.contains(&peers[2]));
     }
+
+    /// Test that pruning keeps `target_peers_per_sync_subnet` peers on a sync committee subnet
+    /// we are subscribed to, rather than only `MIN_SYNC_COMMITTEE_PEERS`.
+    ///
+    /// Create 4 peers.
+    /// Peer0 : Subnet 1,  Sync-committee-1
+    /// Peer1 : Subnet 1,  Sync-committee-1
+    /// Peer2 : Subnet 1,  Sync-committee-1
+    /// Peer3 : Subnet 1
+    ///
+    /// Prune 2 peers: Only Peer3 should be pruned, as the target on sync-committee-1 is 3.
+    #[tokio::test]
+    async fn test_peer_manager_prune_retains_sync_subnet_target() {
+        let config = config::Config {
+            target_peer_count: 2,
+            target_peers_per_sync_subnet: 3,
+            discovery_enabled: false,
+            ..Default::default()
+        };
+        let log = build_log(slog::Level::Debug, false);
+        let globals = NetworkGlobals::new_test_globals(&log);
+        let mut peer_manager = PeerManager::new(config, Arc::new(globals), &log)
+            .await
+            .unwrap();
+        peer_manager.add_sync_subnet(
+            SyncSubnetId::new(1),
+            Instant::now() + Duration::from_secs(3600),
+        );
+
+        let mut peers = Vec::new();
+        for x in 0..4 {
+            let peer = PeerId::random();
+            peer_manager.inject_connect_ingoing(&peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+
+            let mut attnets = crate::types::EnrAttestationBitfield::<E>::new();
+            let mut syncnets = crate::types::EnrSyncCommitteeBitfield::<E>::new();
+            attnets.set(1, true).unwrap();
+            if x < 3 {
+                syncnets.set(1, true).unwrap();
+            }
+
+            let metadata = crate::rpc::MetaDataV2 {
+                seq_number: 0,
+                attnets,
+                syncnets,
+            };
+            peer_manager
+                .network_globals
+                .peers
+                .write()
+                .peer_info_mut(&peer)
+                .unwrap()
+                .set_meta_data(MetaData::V2(metadata));
+            let long_lived_subnets = peer_manager
+                .network_globals
+                .peers
+                .read()
+                .peer_info(&peer)
+                .unwrap()
+                .long_lived_subnets();
+            for subnet in long_lived_subnets {
+                peer_manager
+                    .network_globals
+                    .peers
+                    .write()
+                    .add_subscription(&peer, subnet);
+            }
+            peers.push(peer);
+        }
+
+        // Perform the heartbeat.
+        peer_manager.heartbeat();
+
+        let connected_peers: std::collections::HashSet<_> = peer_manager
+            .network_globals
+            .peers
+            .read()
+            .connected_or_dialing_peers()
+            .cloned()
+            .collect();
+
+        assert_eq!(connected_peers.len(), 3);
+        assert!(!connected_peers.contains(&peers[3]));
+    }
+
+    #[tokio::test]
+    async fn test_fork_digest_mismatch_reported_once_per_connection() {
+        let mut peer_manager = build_peer_manager(3).await;
+        let peer = PeerId::random();
+        peer_manager.inject_connect_ingoing(&peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+
+        let mismatches = |peer_manager: &PeerManager<E>| {
+            peer_manager
+                .events
+                .iter()
+                .filter(|event| matches!(event, PeerManagerEvent::ForkDigestMismatch { .. }))
+                .count()
+        };
+
+        // A matching digest is not reported, a mismatching one only the first time.
+        peer_manager.check_fork_digest(&peer, [0; 4], [0; 4]);
+        assert_eq!(mismatches(&peer_manager), 0);
+        peer_manager.check_fork_digest(&peer, [1; 4], [0; 4]);
+        peer_manager.check_fork_digest(&peer, [1; 4], [0; 4]);
+        assert_eq!(mismatches(&peer_manager), 1);
+
+        // The peer is reported again after reconnecting.
+        peer_manager.inject_disconnect(&peer);
+        peer_manager.inject_connect_ingoing(&peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        peer_manager.check_fork_digest(&peer, [1; 4], [0; 4]);
+        assert_eq!(mismatches(&peer_manager), 2);
+    }
+
+    #[tokio::test]
+    async fn test_gossip_score_threshold_crossing_reported_once() {
+        let mut peer_manager = build_peer_manager(3).await;
+        let peer = PeerId::random();
+        peer_manager.inject_connect_ingoing(&peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        let thresholds = peer_manager.gossip_thresholds.clone();
+
+        let crossings = |peer_manager: &PeerManager<E>| {
+            peer_manager
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    PeerManagerEvent::GossipScoreThresholdCrossed { peer_id, from, to } => {
+                        Some((*peer_id, *from, *to))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Scores within the healthy band are not reported.
+        peer_manager.update_gossip_score_band(peer, 0.0);
+        peer_manager.update_gossip_score_band(peer, thresholds.publish_threshold + 1.0);
+        assert!(crossings(&peer_manager).is_empty());
+
+        // Dropping below the publish threshold is reported only once.
+        peer_manager.update_gossip_score_band(peer, thresholds.publish_threshold - 1.0);
+        peer_manager.update_gossip_score_band(peer, thresholds.publish_threshold - 2.0);
+        assert_eq!(
+            crossings(&peer_manager),
+            vec![(peer, GossipScoreBand::Healthy, GossipScoreBand::NoPublish)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_peers() {
+        let mut peer_manager = build_peer_manager(3).await;
+        let globals = peer_manager.network_globals.clone();
+
+        // the count is already met
+        tokio::time::timeout(Duration::from_secs(1), globals.wait_for_peers(0))
+            .await
+            .expect("Should resolve immediately");
+
+        let mut waiting = tokio::spawn(globals.wait_for_peers(2));
+        peer_manager.inject_connect_ingoing(
+            &PeerId::random(),
+            "/ip4/0.0.0.0".parse().unwrap(),
+            None,
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), &mut waiting)
+                .await
+                .is_err(),
+            "Should wait for the second peer"
+        );
+
+        peer_manager.inject_connect_ingoing(
+            &PeerId::random(),
+            "/ip4/0.0.0.0".parse().unwrap(),
+            None,
+        );
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .expect("Should resolve once the peers are connected")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_idle_peers_are_pruned_above_target() {
+        let config = config::Config {
+            target_peer_count: 2,
+            discovery_enabled: false,
+            idle_peer_timeout: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let log = build_log(slog::Level::Debug, false);
+        let globals = NetworkGlobals::new_test_globals(&log);
+        let mut peer_manager = PeerManager::<E>::new(config, Arc::new(globals), &log)
+            .await
+            .unwrap();
+
+        let idle_peer = PeerId::random();
+        let subnet_peer = PeerId::random();
+        let active_peer = PeerId::random();
+        for peer in [&idle_peer, &subnet_peer, &active_peer] {
+            peer_manager.inject_connect_ingoing(peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        }
+
+        // the only peer on a long-lived subnet is essential
+        let mut attnets = crate::types::EnrAttestationBitfield::<E>::new();
+        attnets.set(1, true).unwrap();
+        let metadata = crate::rpc::MetaDataV2 {
+            seq_number: 0,
+            attnets,
+            syncnets: Default::default(),
+        };
+        peer_manager
+            .network_globals
+            .peers
+            .write()
+            .peer_info_mut(&subnet_peer)
+            .unwrap()
+            .set_meta_data(MetaData::V2(metadata));
+
+        // nobody is idle before the timeout
+        peer_manager.prune_idle_peers(Instant::now() + Duration::from_secs(30));
+        assert_eq!(peer_manager.network_globals.connected_peers(), 3);
+
+        let now = Instant::now() + Duration::from_secs(90);
+        *peer_manager.last_activity.get_mut(&active_peer).unwrap() = now;
+        peer_manager.prune_idle_peers(now);
+
+        let disconnected: Vec<_> = peer_manager
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                PeerManagerEvent::DisconnectPeer(peer_id, _) => Some(*peer_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(disconnected, vec![idle_peer]);
+    }
+
+    #[tokio::test]
+    async fn test_prune_peers_for_ip_diversity() {
+        let config = config::Config {
+            target_peer_count: 3,
+            discovery_enabled: false,
+            eviction_policy: config::PeerEvictionPolicy::IpDiversity,
+            ..Default::default()
+        };
+        let log = build_log(slog::Level::Debug, false);
+        let globals = NetworkGlobals::new_test_globals(&log);
+        let mut peer_manager = PeerManager::new(config, Arc::new(globals), &log)
+            .await
+            .unwrap();
+
+        // Four peers clustered on one /24 and two peers on their own subnets, none of them
+        // subscribed to a long-lived subnet.
+        let clustered_peers: Vec<PeerId> = (1..=4)
+            .map(|host| {
+                let peer = PeerId::random();
+                peer_manager.inject_connect_ingoing(
+                    &peer,
+                    format!("/ip4/10.0.0.{}/tcp/9000", host).parse().unwrap(),
+                    None,
+                );
+                peer
+            })
+            .collect();
+        let diverse_peers: Vec<PeerId> = (1..=2)
+            .map(|network| {
+                let peer = PeerId::random();
+                peer_manager.inject_connect_ingoing(
+                    &peer,
+                    format!("/ip4/10.{}.0.1/tcp/9000", network).parse().unwrap(),
+                    None,
+                );
+                peer
+            })
+            .collect();
+
+        peer_manager.heartbeat();
+
+        let disconnected: HashSet<PeerId> = peer_manager
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                PeerManagerEvent::DisconnectPeer(peer_id, _) => Some(*peer_id),
+                _ => None,
+            })
+            .collect();
+        // Pruning only reduces the cluster, down to a single peer.
+        assert_eq!(disconnected.len(), 3);
+        assert!(disconnected
+            .iter()
+            .all(|peer| clustered_peers.contains(peer)));
+        assert!(diverse_peers
+            .iter()
+            .all(|peer| !disconnected.contains(peer)));
+    }
+
+    #[tokio::test]
+    async fn test_ip_subnet_ban() {
+        use libp2p::core::{connection::ConnectionId, ConnectedPoint};
+        use libp2p::swarm::NetworkBehaviour;
+
+        let mut peer_manager = build_peer_manager(3).await;
+        let is_disconnected = |peer_manager: &PeerManager<E>, peer: &PeerId| {
+            peer_manager.events.iter().any(|event| {
+                matches!(
+                    event,
+                    PeerManagerEvent::DisconnectPeer(peer_id, GoodbyeReason::BannedIP) if peer_id == peer
+                )
+            })
+        };
+
+        // A connected peer in the subnet is disconnected once the subnet is banned.
+        let connected_peer = PeerId::random();
+        peer_manager.inject_connect_ingoing(
+            &connected_peer,
+            "/ip4/10.0.0.1/tcp/9000".parse().unwrap(),
+            None,
+        );
+        peer_manager
+            .network_globals
+            .ban_ip_subnet("10.0.0.1/32".parse().unwrap());
+        peer_manager.disconnect_banned_ip_subnet_peers();
+        assert!(is_disconnected(&peer_manager, &connected_peer));
+
+        // A dial from the subnet is rejected.
+        let dialing_peer = PeerId::random();
+        let endpoint = ConnectedPoint::Listener {
+            local_addr: "/ip4/127.0.0.1/tcp/9000".parse().unwrap(),
+            send_back_addr: "/ip4/10.0.0.1/tcp/9001".parse().unwrap(),
+        };
+        peer_manager.inject_connection_established(
+            &dialing_peer,
+            &ConnectionId::new(0),
+            &endpoint,
+            None,
+            0,
+        );
+        assert!(is_disconnected(&peer_manager, &dialing_peer));
+        assert!(!peer_manager.is_connected(&dialing_peer));
+
+        // A dial from outside the subnet is accepted.
+        let other_peer = PeerId::random();
+        let endpoint = ConnectedPoint::Listener {
+            local_addr: "/ip4/127.0.0.1/tcp/9000".parse().unwrap(),
+            send_back_addr: "/ip4/10.0.0.2/tcp/9001".parse().unwrap(),
+        };
+        peer_manager.inject_connection_established(
+            &other_peer,
+            &ConnectionId::new(1),
+            &endpoint,
+            None,
+            0,
+        );
+        assert!(!is_disconnected(&peer_manager, &other_peer));
+        assert!(peer_manager.is_connected(&other_peer));
+
+        // The ban expires.
+        peer_manager.ip_subnet_ban_duration = Duration::from_secs(0);
+        peer_manager.heartbeat();
+        assert!(peer_manager
+            .network_globals
+            .peers
+            .read()
+            .banned_ip_subnet(&"10.0.0.1".parse().unwrap(), Duration::from_secs(3600))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_peer_banned_on_heartbeat_after_score_set() {
+        let mut peer_manager = build_peer_manager(3).await;
+        let peer = PeerId::random();
+        peer_manager.inject_connect_ingoing(&peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+
+        peer_manager
+            .network_globals
+            .peers
+            .write()
+            .set_score_for_test(&peer, -60.0);
+        assert!(!peer_manager.ban_status(&peer).is_banned());
+
+        // The connected peer is disconnected first.
+        peer_manager.heartbeat();
+        assert!(peer_manager.events.iter().any(|event| matches!(
+            event,
+            PeerManagerEvent::DisconnectPeer(peer_id, GoodbyeReason::BadScore) if *peer_id == peer
+        )));
+
+        // The ban completes once the peer has disconnected.
+        peer_manager.inject_disconnect(&peer);
+        assert!(peer_manager.ban_status(&peer).is_banned());
+        assert!(peer_manager.events.iter().any(
+            |event| matches!(event, PeerManagerEvent::Banned(peer_id, _) if *peer_id == peer)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_low_score_disconnect_reason_is_recorded() {
+        let mut peer_manager = build_peer_manager(3).await;
+        let peer = PeerId::random();
+        peer_manager.inject_connect_ingoing(&peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        assert_eq!(
+            peer_manager.network_globals.last_disconnect_reason(&peer),
+            None
+        );
+
+        peer_manager
+            .network_globals
+            .peers
+            .write()
+            .set_score_for_test(&peer, -30.0);
+        peer_manager.heartbeat();
+        peer_manager.inject_disconnect(&peer);
+
+        assert_eq!(
+            peer_manager.network_globals.last_disconnect_reason(&peer),
+            Some(DisconnectReason::BadScore)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_discovered_banned_peer_is_not_dialed() {
+        use crate::EnrExt;
+        use discv5::enr::{CombinedKey, EnrBuilder};
+
+        let mut peer_manager = build_peer_manager(3).await;
+        let build_enr = |ip: &str| {
+            EnrBuilder::new("v4")
+                .ip(ip.parse().unwrap())
+                .build(&CombinedKey::generate_secp256k1())
+                .unwrap()
+        };
+        let reported = |peer_manager: &PeerManager<E>, peer: &PeerId| {
+            peer_manager.events.iter().any(|event| {
+                matches!(
+                    event,
+                    PeerManagerEvent::DiscoveredBannedPeer { peer_id } if peer_id == peer
+                )
+            })
+        };
+
+        // A peer whose ENR advertises an IP in a banned subnet.
+        peer_manager
+            .network_globals
+            .ban_ip_subnet("10.0.0.0/24".parse().unwrap());
+        let subnet_banned_enr = build_enr("10.0.0.1");
+        let subnet_banned_peer = subnet_banned_enr.peer_id();
+
+        // A peer banned by its score.
+        let score_banned_peer = PeerId::random();
+        peer_manager.inject_connect_ingoing(
+            &score_banned_peer,
+            "/ip4/0.0.0.0".parse().unwrap(),
+            None,
+        );
+        peer_manager.goodbye_peer(
+            &score_banned_peer,
+            GoodbyeReason::Fault,
+            ReportSource::PeerManager,
+        );
+        peer_manager.inject_disconnect(&score_banned_peer);
+
+        let good_enr = build_enr("10.0.1.1");
+        let good_peer = good_enr.peer_id();
+
+        let mut results = HashMap::new();
+        let enrs = [
+            (subnet_banned_peer, Some(subnet_banned_enr)),
+            (score_banned_peer, None),
+            (good_peer, Some(good_enr)),
+        ];
+        for (peer_id, enr) in &enrs {
+            if !peer_manager.discovered_peer_is_banned(peer_id, enr.as_ref()) {
+                results.insert(*peer_id, None);
+            }
+        }
+
+        assert!(reported(&peer_manager, &subnet_banned_peer));
+        assert!(reported(&peer_manager, &score_banned_peer));
+        assert!(!reported(&peer_manager, &good_peer));
+        assert_eq!(peer_manager.peers_discovered(results), vec![good_peer]);
+    }
+
+    #[tokio::test]
+    async fn test_connected_peers_sorted_is_deterministic() {
+        let mut peer_manager = build_peer_manager(20).await;
+        let mut peers: Vec<_> = (0..10).map(|_| PeerId::random()).collect();
+        for peer in &peers {
+            peer_manager.inject_connect_ingoing(peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        }
+
+        let sorted = peer_manager.network_globals.connected_peers_sorted();
+        peers.sort_by_key(|peer| peer.to_bytes());
+        assert_eq!(sorted, peers);
+        for _ in 0..5 {
+            assert_eq!(
+                peer_manager.network_globals.connected_peers_sorted(),
+                sorted
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_dials_are_limited() {
+        use libp2p::swarm::{handler::DummyConnectionHandler, DialError, NetworkBehaviour};
+
+        let config = config::Config {
+            target_peer_count: 20,
+            discovery_enabled: false,
+            max_concurrent_dials: Some(2),
+            ..Default::default()
+        };
+        let log = build_log(slog::Level::Debug, false);
+        let globals = NetworkGlobals::new_test_globals(&log);
+        let mut peer_manager = PeerManager::<E>::new(config, Arc::new(globals), &log)
+            .await
+            .unwrap();
+
+        let peers: Vec<_> = (0..5).map(|_| PeerId::random()).collect();
+        for peer in &peers {
+            peer_manager.dial_peer(peer, None);
+            assert!(peer_manager.dials_in_flight() <= 2);
+        }
+        assert_eq!(peer_manager.dials_in_flight(), 2);
+
+        let mut dialed = Vec::new();
+        loop {
+            let started: Vec<_> = peer_manager
+                .events
+                .drain(..)
+                .filter_map(|event| match event {
+                    PeerManagerEvent::DialPeer(peer_id) => Some(peer_id),
+                    _ => None,
+                })
+                .collect();
+            if started.is_empty() {
+                break;
+            }
+            assert!(started.len() <= 2);
+            dialed.extend(started.iter().copied());
+            // Fail the dials, freeing their slots for the queued peers.
+            for peer_id in started {
+                peer_manager.inject_dial_failure(
+                    Some(peer_id),
+                    DummyConnectionHandler::default(),
+                    &DialError::NoAddresses,
+                );
+                assert!(peer_manager.dials_in_flight() <= 2);
+            }
+        }
+
+        assert_eq!(dialed, peers);
+        assert_eq!(peer_manager.dials_in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dial_stats_only_count_outcomes_of_counted_dials() {
+        use libp2p::core::{connection::ConnectionId, ConnectedPoint, Endpoint};
+        use libp2p::swarm::{handler::DummyConnectionHandler, DialError, NetworkBehaviour};
+
+        let mut peer_manager = build_peer_manager(3).await;
+        let dialer = ConnectedPoint::Dialer {
+            address: "/ip4/127.0.0.1/tcp/9000".parse().unwrap(),
+            role_override: Endpoint::Dialer,
+        };
+
+        // A dial of the peer manager fails.
+        let dialed_peer = PeerId::random();
+        peer_manager.dial_peer(&dialed_peer, None);
+        peer_manager.inject_dial_failure(
+            Some(dialed_peer),
+            DummyConnectionHandler::default(),
+            &DialError::NoAddresses,
+        );
+
+        // The dials we did not count, as the ones of gossipsub peer exchange, are ignored.
+        peer_manager.inject_dial_failure(
+            Some(PeerId::random()),
+            DummyConnectionHandler::default(),
+            &DialError::NoAddresses,
+        );
+        peer_manager.inject_connection_established(
+            &PeerId::random(),
+            &ConnectionId::new(0),
+            &dialer,
+            None,
+            0,
+        );
+
+        // A dial of an address connects to a peer whose id we did not know.
+        peer_manager.count_dial_attempt(None);
+        peer_manager.inject_connection_established(
+            &PeerId::random(),
+            &ConnectionId::new(1),
+            &dialer,
+            None,
+            0,
+        );
+
+        let dial_stats = peer_manager.network_globals.dial_stats();
+        assert_eq!(dial_stats.attempts, 2);
+        assert_eq!(dial_stats.successes, 1);
+        assert_eq!(dial_stats.failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_network_health_subnet_coverage() {
+        let mut peer_manager = build_peer_manager(3).await;
+        let globals = peer_manager.network_globals.clone();
+        globals
+            .target_peers
+            .store(3, std::sync::atomic::Ordering::Relaxed);
+
+        let health = globals.network_health();
+        assert_eq!(health.connected_peers, 0);
+        assert_eq!(health.attestation_subnets_covered, 0);
+        assert!(!health.target_peers_met);
+
+        // Two peers on subnet 1, one of them also on subnet 2, and a peer on no subnet.
+        let subnets = [vec![1], vec![1, 2], vec![]];
+        for peer_subnets in &subnets {
+            let peer = PeerId::random();
+            peer_manager.inject_connect_ingoing(&peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+            let mut attnets = crate::types::EnrAttestationBitfield::<E>::new();
+            for subnet in peer_subnets {
+                attnets.set(*subnet, true).unwrap();
+            }
+            let metadata = crate::rpc::MetaDataV2 {
+                seq_number: 0,
+                attnets,
+                syncnets: Default::default(),
+            };
+            globals
+                .peers
+                .write()
+                .peer_info_mut(&peer)
+                .unwrap()
+                .set_meta_data(MetaData::V2(metadata));
+        }
+
+        let health = globals.network_health();
+        assert_eq!(health.connected_peers, 3);
+        assert_eq!(health.attestation_subnets_covered, 2);
+        assert!(health.target_peers_met);
+    }
+
+    #[tokio::test]
+    async fn test_dial_backoff_grows_and_bans() {
+        let config = config::Config {
+            target_peer_count: 20,
+            discovery_enabled: false,
+            dial_backoff_base: Duration::from_secs(1),
+            dial_backoff_max: Duration::from_secs(10),
+            dial_backoff_max_attempts: 4,
+            ..Default::default()
+        };
+        let log = build_log(slog::Level::Debug, false);
+        let globals = NetworkGlobals::new_test_globals(&log);
+        let mut peer_manager = PeerManager::<E>::new(config, Arc::new(globals), &log)
+            .await
+            .unwrap();
+
+        let peer = PeerId::random();
+        let mut now = Instant::now();
+        assert!(!peer_manager.is_dial_backed_off(&peer, now));
+
+        // Each failure doubles the delay before the next dial, until the peer is dial-banned for
+        // the maximum delay after its fourth failure.
+        for expected_delay in [1, 2, 4, 10] {
+            peer_manager.register_dial_failure(&peer, now);
+            let delay = Duration::from_secs(expected_delay);
+            assert!(peer_manager.is_dial_backed_off(&peer, now));
+            assert!(peer_manager.is_dial_backed_off(&peer, now + delay - Duration::from_millis(1)));
+            assert!(!peer_manager.is_dial_backed_off(&peer, now + delay));
+            now += delay;
+        }
+
+        // A dial-banned peer is not dialed, even when rediscovered.
+        peer_manager.register_dial_failure(&peer, Instant::now());
+        peer_manager.events.clear();
+        peer_manager.dial_peer(&peer, None);
+        assert_eq!(peer_manager.dials_in_flight(), 0);
+        assert!(peer_manager.events.is_empty());
+        let discovered = peer_manager.peers_discovered(HashMap::from([(peer, None)]));
+        assert!(discovered.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_protected_peer_is_not_disconnected_or_pruned() {
+        let mut peer_manager = build_peer_manager(1).await;
+        let protected_peer = PeerId::random();
+        let peer0 = PeerId::random();
+        let peer1 = PeerId::random();
+
+        for peer in [&protected_peer, &peer0, &peer1] {
+            peer_manager.inject_connect_ingoing(peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        }
+        peer_manager.network_globals.protect_peer(&protected_peer);
+        peer_manager.events.clear();
+
+        // Reports that would ban an unprotected peer leave the protected peer connected.
+        for _ in 0..3 {
+            peer_manager.report_peer(
+                &protected_peer,
+                PeerAction::Fatal,
+                ReportSource::PeerManager,
+                None,
+                "",
+            );
+        }
+        assert!(peer_manager.events.is_empty());
+        assert!(!peer_manager
+            .network_globals
+            .peers
+            .read()
+            .peer_info(&protected_peer)
+            .unwrap()
+            .score_is_banned());
+
+        // The protected peer counts towards the target, but the other peers are pruned instead.
+        peer_manager.heartbeat();
+        assert_eq!(peer_manager.network_globals.connected_or_dialing_peers(), 1);
+        assert!(peer_manager
+            .network_globals
+            .peers
+            .read()
+            .is_connected(&protected_peer));
+
+        // Once unprotected, the peer can be banned again.
+        peer_manager.network_globals.unprotect_peer(&protected_peer);
+        peer_manager.report_peer(
+            &protected_peer,
+            PeerAction::Fatal,
+            ReportSource::PeerManager,
+            None,
+            "",
+        );
+        assert!(peer_manager
+            .network_globals
+            .peers
+            .read()
+            .peer_info(&protected_peer)
+            .unwrap()
+            .score_is_banned());
+    }
+
+    #[tokio::test]
+    async fn test_trusted_peers_only() {
+        use libp2p::core::{connection::ConnectionId, ConnectedPoint};
+        use libp2p::swarm::NetworkBehaviour;
+
+        let mut peer_manager = build_peer_manager(3).await;
+        let log = build_log(slog::Level::Debug, false);
+        let trusted_peer = PeerId::random();
+        *peer_manager.network_globals.peers.write() = peerdb::PeerDB::new(vec![trusted_peer], &log);
+        peer_manager.set_trusted_peers_only(true);
+
+        let endpoint = ConnectedPoint::Listener {
+            local_addr: "/ip4/127.0.0.1/tcp/9000".parse().unwrap(),
+            send_back_addr: "/ip4/10.0.0.1/tcp/9001".parse().unwrap(),
+        };
+        let is_disconnected = |peer_manager: &PeerManager<E>, peer: &PeerId| {
+            peer_manager.events.iter().any(|event| {
+                matches!(event, PeerManagerEvent::DisconnectPeer(peer_id, _) if peer_id == peer)
+            })
+        };
+
+        // An untrusted peer connecting to us is rejected.
+        let untrusted_peer = PeerId::random();
+        peer_manager.inject_connection_established(
+            &untrusted_peer,
+            &ConnectionId::new(0),
+            &endpoint,
+            None,
+            0,
+        );
+        assert!(is_disconnected(&peer_manager, &untrusted_peer));
+        assert!(!peer_manager.is_connected(&untrusted_peer));
+
+        // A trusted peer connecting to us is accepted.
+        peer_manager.inject_connection_established(
+            &trusted_peer,
+            &ConnectionId::new(1),
+            &endpoint,
+            None,
+            0,
+        );
+        assert!(!is_disconnected(&peer_manager, &trusted_peer));
+        assert!(peer_manager.is_connected(&trusted_peer));
+
+        // Untrusted peers are not dialed.
+        peer_manager.dial_peer(&untrusted_peer, None);
+        assert_eq!(peer_manager.dials_in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_min_peer_version_applies_to_lighthouse_only() {
+        let config = config::Config {
+            min_peer_protocol_version: Some("v2.0.0".into()),
+            discovery_enabled: false,
+            ..Default::default()
+        };
+        let log = build_log(slog::Level::Debug, false);
+        let globals = NetworkGlobals::new_test_globals(&log);
+        let mut peer_manager = PeerManager::new(config, Arc::new(globals), &log)
+            .await
+            .unwrap();
+
+        let identify_info = |agent_version: &str| IdentifyInfo {
+            public_key: libp2p::identity::Keypair::generate_ed25519().public(),
+            protocol_version: "eth2/1.0.0".into(),
+            agent_version: agent_version.into(),
+            listen_addrs: vec![],
+            protocols: vec![],
+            observed_addr: "/ip4/0.0.0.0".parse().unwrap(),
+        };
+        let old_lighthouse = PeerId::random();
+        let new_lighthouse = PeerId::random();
+        let old_teku = PeerId::random();
+        for (peer, agent_version) in [
+            (&old_lighthouse, "Lighthouse/v1.5.0-1234abcd/x86_64-linux"),
+            (&new_lighthouse, "Lighthouse/v2.3.0-1234abcd/x86_64-linux"),
+            (
+                &old_teku,
+                "teku/teku/v1.0.0/linux-x86_64/-eclipseadoptium-openjdk64bitservervm-java-17",
+            ),
+        ] {
+            peer_manager.inject_connect_ingoing(peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+            peer_manager.identify(peer, &identify_info(agent_version));
+        }
+
+        let disconnected: Vec<_> = peer_manager
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                PeerManagerEvent::DisconnectPeer(peer_id, _) => Some(*peer_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(disconnected, vec![old_lighthouse]);
+    }
 }
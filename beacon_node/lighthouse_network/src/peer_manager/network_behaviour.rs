@@ -1,8 +1,11 @@
+use std::net::IpAddr;
 use std::task::{Context, Poll};
+use std::time::Instant;
 
 use futures::StreamExt;
 use libp2p::core::connection::ConnectionId;
 use libp2p::core::ConnectedPoint;
+use libp2p::multiaddr::Protocol;
 use libp2p::swarm::handler::DummyConnectionHandler;
 use libp2p::swarm::{
     ConnectionHandler, DialError, NetworkBehaviour, NetworkBehaviourAction, PollParameters,
@@ -16,7 +19,7 @@ use crate::rpc::GoodbyeReason;
 use crate::types::SyncState;
 
 use super::peerdb::BanResult;
-use super::{PeerManager, PeerManagerEvent, ReportSource};
+use super::{DisconnectReason, PeerManager, PeerManagerEvent, ReportSource};
 
 impl<TSpec: EthSpec> NetworkBehaviour for PeerManager<TSpec> {
     type ConnectionHandler = DummyConnectionHandler;
@@ -48,11 +51,15 @@ impl<TSpec: EthSpec> NetworkBehaviour for PeerManager<TSpec> {
             self.heartbeat();
         }
 
+        // disconnect the peers in newly banned IP subnets
+        self.disconnect_banned_ip_subnet_peers();
+
         // poll the timeouts for pings and status'
         loop {
             match self.inbound_ping_peers.poll_next_unpin(cx) {
                 Poll::Ready(Some(Ok(peer_id))) => {
                     self.inbound_ping_peers.insert(peer_id);
+                    self.pings_in_flight.insert(peer_id, Instant::now());
                     self.events.push(PeerManagerEvent::Ping(peer_id));
                 }
                 Poll::Ready(Some(Err(e))) => {
@@ -66,6 +73,7 @@ impl<TSpec: EthSpec> NetworkBehaviour for PeerManager<TSpec> {
             match self.outbound_ping_peers.poll_next_unpin(cx) {
                 Poll::Ready(Some(Ok(peer_id))) => {
                     self.outbound_ping_peers.insert(peer_id);
+                    self.pings_in_flight.insert(peer_id, Instant::now());
                     self.events.push(PeerManagerEvent::Ping(peer_id));
                 }
                 Poll::Ready(Some(Err(e))) => {
@@ -113,6 +121,11 @@ impl<TSpec: EthSpec> NetworkBehaviour for PeerManager<TSpec> {
         other_established: usize,
     ) {
         debug!(self.log, "Connection established"; "peer_id" => %peer_id, "connection" => ?endpoint.to_endpoint());
+        if endpoint.is_dialer() && self.take_counted_dial(Some(*peer_id), true) {
+            self.count_dial_success();
+        }
+        self.dial_finished(peer_id);
+        self.dial_backoffs.remove(peer_id);
         if other_established == 0 {
             self.events.push(PeerManagerEvent::MetaData(*peer_id));
         }
@@ -122,6 +135,26 @@ impl<TSpec: EthSpec> NetworkBehaviour for PeerManager<TSpec> {
             metrics::check_nat();
         }
 
+        // Only trusted peers may connect to us in the trusted peers only mode
+        if self.trusted_peers_only && endpoint.is_listener() && !self.is_trusted(peer_id) {
+            debug!(self.log, "Untrusted peer connected in trusted peers only mode. Disconnecting"; "peer_id" => %peer_id);
+            self.disconnect_peer(*peer_id, GoodbyeReason::TooManyPeers);
+            return;
+        }
+
+        // Refuse connections from banned IP subnets
+        let banned_ip_subnet = multiaddr_ip(endpoint.get_remote_address()).and_then(|ip| {
+            self.network_globals
+                .peers
+                .read()
+                .banned_ip_subnet(&ip, self.ip_subnet_ban_duration)
+        });
+        if let Some(subnet) = banned_ip_subnet {
+            debug!(self.log, "Peer connected via a banned IP subnet. Disconnecting"; "peer_id" => %peer_id, "banned_subnet" => %subnet);
+            self.disconnect_peer(*peer_id, GoodbyeReason::BannedIP);
+            return;
+        }
+
         // Check to make sure the peer is not supposed to be banned
         match self.ban_status(peer_id) {
             // TODO: directly emit the ban event?
@@ -190,19 +223,33 @@ impl<TSpec: EthSpec> NetworkBehaviour for PeerManager<TSpec> {
         }
 
         // There are no more connections
-        if self
-            .network_globals
-            .peers
-            .read()
-            .is_connected_or_disconnecting(peer_id)
-        {
+        let disconnect_reason = {
+            let mut peers = self.network_globals.peers.write();
+            if !peers.is_connected_or_disconnecting(peer_id) {
+                None
+            } else {
+                // A peer still connected closed the connection without a goodbye
+                if peers.is_connected(peer_id) {
+                    if let Some(info) = peers.peer_info_mut(peer_id) {
+                        info.set_disconnect_reason(DisconnectReason::ConnectionClosed);
+                    }
+                }
+                Some(
+                    peers
+                        .peer_info(peer_id)
+                        .and_then(|info| info.disconnect_reason().cloned())
+                        .unwrap_or(DisconnectReason::ConnectionClosed),
+                )
+            }
+        };
+        if let Some(reason) = disconnect_reason {
             // We are disconnecting the peer or the peer has already been connected.
             // Both these cases, the peer has been previously registered by the peer manager and
             // potentially the application layer.
             // Inform the application.
             self.events
                 .push(PeerManagerEvent::PeerDisconnected(*peer_id));
-            debug!(self.log, "Peer disconnected"; "peer_id" => %peer_id);
+            debug!(self.log, "Peer disconnected"; "peer_id" => %peer_id, "reason" => ?reason);
         }
 
         // NOTE: It may be the case that a rejected node, due to too many peers is disconnected
@@ -248,12 +295,52 @@ impl<TSpec: EthSpec> NetworkBehaviour for PeerManager<TSpec> {
         &mut self,
         peer_id: Option<PeerId>,
         _handler: DummyConnectionHandler,
-        _error: &DialError,
+        error: &DialError,
     ) {
+        if self.take_counted_dial(peer_id, false) {
+            self.count_dial_failure(dial_error_kind(error));
+        }
         if let Some(peer_id) = peer_id {
+            self.dial_finished(&peer_id);
+            // Only failures to reach the peer count towards its backoff, not dials we gave up on
+            // locally.
+            if !matches!(
+                error,
+                DialError::DialPeerConditionFalse(_)
+                    | DialError::Aborted
+                    | DialError::ConnectionLimit(_)
+                    | DialError::Banned
+            ) {
+                self.register_dial_failure(&peer_id, Instant::now());
+            }
             if !self.network_globals.peers.read().is_connected(&peer_id) {
                 self.inject_disconnect(&peer_id);
             }
         }
     }
 }
+
+/// Returns the kind of a dial failure, as counted in the `DialStats`.
+fn dial_error_kind(error: &DialError) -> &'static str {
+    match error {
+        DialError::Banned => "banned",
+        DialError::ConnectionLimit(_) => "connection_limit",
+        DialError::LocalPeerId => "local_peer_id",
+        DialError::NoAddresses => "no_addresses",
+        DialError::DialPeerConditionFalse(_) => "dial_peer_condition_false",
+        DialError::Aborted => "aborted",
+        DialError::InvalidPeerId(_) => "invalid_peer_id",
+        DialError::WrongPeerId { .. } => "wrong_peer_id",
+        DialError::ConnectionIo(_) => "connection_io",
+        DialError::Transport(_) => "transport",
+    }
+}
+
+/// Returns the IP address of a multiaddr, if it has one.
+pub(crate) fn multiaddr_ip(addr: &Multiaddr) -> Option<IpAddr> {
+    addr.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(ip.into()),
+        Protocol::Ip6(ip) => Some(ip.into()),
+        _ => None,
+    })
+}
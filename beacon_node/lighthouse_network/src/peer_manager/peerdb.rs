@@ -1,23 +1,28 @@
 use crate::{
     metrics,
     multiaddr::{Multiaddr, Protocol},
+    peer_bandwidth::{BandwidthStats, PeerBandwidth},
     types::Subnet,
-    Enr, Gossipsub, PeerId,
+    Enr, Gossipsub, IpNetwork, PeerId,
 };
 use peer_info::{ConnectionDirection, PeerConnectionStatus, PeerInfo};
 use rand::seq::SliceRandom;
 use score::{PeerAction, ReportSource, Score, ScoreState};
 use slog::{crit, debug, error, trace, warn};
+use snapshot::{PeerDbSnapshot, PeerSnapshot};
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, SocketAddr};
-use std::time::Instant;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use sync_status::SyncStatus;
 use types::EthSpec;
 
 pub mod client;
 pub mod peer_info;
 pub mod score;
+pub mod snapshot;
 pub mod sync_status;
 
 /// Max number of disconnected nodes to remember.
@@ -33,6 +38,23 @@ const ALLOWED_NEGATIVE_GOSSIPSUB_FACTOR: f32 = 0.1;
 /// disconnected state.
 const DIAL_TIMEOUT: u64 = 15;
 
+/// The counters of a peer shared with its connections.
+#[derive(Default)]
+struct PeerCounters {
+    /// The bytes exchanged with the peer, counted by the transport.
+    bandwidth: Arc<PeerBandwidth>,
+    /// The number of our RPC requests to the peer that are queued or in flight, counted by the
+    /// RPC handlers of its connections.
+    outbound_requests: Arc<AtomicUsize>,
+}
+
+impl PeerCounters {
+    /// Whether a connection of the peer still holds the counters.
+    fn in_use(&self) -> bool {
+        Arc::strong_count(&self.bandwidth) > 1 || Arc::strong_count(&self.outbound_requests) > 1
+    }
+}
+
 /// Storage of known peers, their reputation and information
 pub struct PeerDB<TSpec: EthSpec> {
     /// The collection of known connected peers, their status and reputation
@@ -41,6 +63,16 @@ pub struct PeerDB<TSpec: EthSpec> {
     disconnected_peers: usize,
     /// Counts banned peers in total and per ip
     banned_peers_count: BannedPeersCount,
+    /// Banned IP subnets and the time at which they were banned.
+    banned_ip_subnets: HashMap<IpNetwork, Instant>,
+    /// IP subnets banned since the peer manager last disconnected the peers in banned subnets.
+    new_ip_subnet_bans: Vec<IpNetwork>,
+    /// The counters of the peers, including those of connections the peer manager has not
+    /// registered. They are kept apart from the `PeerInfo`s so that connections which are refused
+    /// do not add peers to the database.
+    peer_counters: HashMap<PeerId, PeerCounters>,
+    /// The peers protected by the application, which need not be known yet.
+    protected_peers: HashSet<PeerId>,
     /// PeerDB's logger
     log: slog::Logger,
 }
@@ -56,6 +88,10 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
             log: log.clone(),
             disconnected_peers: 0,
             banned_peers_count: BannedPeersCount::default(),
+            banned_ip_subnets: HashMap::new(),
+            new_ip_subnet_bans: Vec::new(),
+            peer_counters: HashMap::new(),
+            protected_peers: HashSet::new(),
             peers,
         }
     }
@@ -75,6 +111,17 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
         self.peers.iter()
     }
 
+    /// Returns an owned copy of the state of every peer in the db.
+    pub fn snapshot(&self) -> PeerDbSnapshot {
+        PeerDbSnapshot {
+            peers: self
+                .peers
+                .iter()
+                .map(|(peer_id, info)| PeerSnapshot::new(peer_id, info))
+                .collect(),
+        }
+    }
+
     /// Gives the ids of all known peers.
     pub fn peer_ids(&self) -> impl Iterator<Item = &PeerId> {
         self.peers.keys()
@@ -166,11 +213,30 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
             .find(|ip| self.banned_peers_count.ip_is_banned(ip))
     }
 
+    /// Sets the lighthouse component of a peer's score, bypassing the limits on the score. The
+    /// peer is banned or disconnected as the score requires on the next score update, which the
+    /// peer manager runs on its heartbeat.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn set_score_for_test(&mut self, peer_id: &PeerId, score: f64) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.set_lighthouse_score(score);
+        }
+    }
+
     /// Returns true if the IP is banned.
     pub fn is_ip_banned(&self, ip: &IpAddr) -> bool {
         self.banned_peers_count.ip_is_banned(ip)
     }
 
+    /// Returns the banned subnet containing `ip`, ignoring bans placed more than `ban_duration`
+    /// ago.
+    pub fn banned_ip_subnet(&self, ip: &IpAddr, ban_duration: Duration) -> Option<IpNetwork> {
+        self.banned_ip_subnets
+            .iter()
+            .find(|(subnet, banned_at)| banned_at.elapsed() < ban_duration && subnet.contains(ip))
+            .map(|(subnet, _)| *subnet)
+    }
+
     /// Returns true if the Peer is either banned or in the disconnected state.
     fn score_state_banned_or_disconnected(&self, peer_id: &PeerId) -> bool {
         if let Some(peer) = self.peers.get(peer_id) {
@@ -252,6 +318,22 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
             .map(|(peer_id, _)| peer_id)
     }
 
+    /// Gives the ids of the connected peers advertising `subnet` in their metadata or ENR.
+    pub fn peers_advertising_subnet(&self, subnet: Subnet) -> impl Iterator<Item = &PeerId> {
+        self.peers
+            .iter()
+            .filter(move |(_, info)| info.is_connected() && info.advertises_subnet(&subnet))
+            .map(|(peer_id, _)| peer_id)
+    }
+
+    /// Gives the ids of the known peers labelled with `label`.
+    pub fn peers_with_label<'a>(&'a self, label: &'a str) -> impl Iterator<Item = &'a PeerId> {
+        self.peers
+            .iter()
+            .filter(move |(_, info)| info.has_label(label))
+            .map(|(peer_id, _)| peer_id)
+    }
+
     /// Gives the ids of all known disconnected peers.
     pub fn disconnected_peers(&self) -> impl Iterator<Item = &PeerId> {
         self.peers
@@ -268,6 +350,16 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
             .map(|(peer_id, _)| peer_id)
     }
 
+    /// Returns all known banned peers with the estimated time at which they will be unbanned, or
+    /// `None` if they are not expected to be unbanned.
+    pub fn banned_peers_with_unban_time(&self) -> Vec<(PeerId, Option<Instant>)> {
+        self.peers
+            .iter()
+            .filter(|(_, info)| info.is_banned())
+            .map(|(peer_id, info)| (*peer_id, info.score().unban_time()))
+            .collect()
+    }
+
     /// Gives the ids of all known banned peers.
     pub fn banned_peers_by_score(&self) -> impl Iterator<Item = &PeerId> {
         self.peers
@@ -362,16 +454,99 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
         Some(info.update_sync_status(sync_status))
     }
 
+    /// Sets whether a peer is protected. A protected peer's score is frozen and it is never
+    /// pruned, until it is unprotected. A peer that is not known yet is protected once it is
+    /// added to the database.
+    pub fn set_peer_protected(&mut self, peer_id: &PeerId, protected: bool) {
+        debug!(self.log, "Setting peer protection"; "peer_id" => %peer_id, "protected" => protected);
+        if protected {
+            self.protected_peers.insert(*peer_id);
+        } else {
+            self.protected_peers.remove(peer_id);
+        }
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.set_protected(protected);
+        }
+    }
+
+    /// Labels a known peer with an application-defined `label`. The label is dropped with the
+    /// peer's entry once it is pruned from the database. Returns false if the peer is unknown.
+    pub fn set_peer_label(&mut self, peer_id: &PeerId, label: String) -> bool {
+        match self.peers.get_mut(peer_id) {
+            Some(info) => {
+                info.add_label(label);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes an application-defined label from a peer.
+    pub fn remove_peer_label(&mut self, peer_id: &PeerId, label: &str) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.remove_label(label);
+        }
+    }
+
+    /// Returns the ids of the trusted peers.
+    pub fn trusted_peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.peers
+            .iter()
+            .filter(|(_, info)| info.is_trusted())
+            .map(|(peer_id, _)| peer_id)
+    }
+
+    /// Replaces the set of trusted peers. Unknown peers in `trusted_peers` are added to the
+    /// database, and the peers that are no longer trusted are scored as any other peer from now on.
+    pub fn set_trusted_peers(&mut self, trusted_peers: Vec<PeerId>) {
+        let trusted_peers: HashSet<PeerId> = trusted_peers.into_iter().collect();
+        for (peer_id, info) in self.peers.iter_mut() {
+            if info.is_trusted() && !trusted_peers.contains(peer_id) {
+                debug!(self.log, "Removing trusted peer"; "peer_id" => %peer_id);
+                info.set_trusted(false);
+            }
+        }
+        for peer_id in trusted_peers {
+            let info = self.peers.entry(peer_id).or_default();
+            if !info.is_trusted() {
+                debug!(self.log, "Adding trusted peer"; "peer_id" => %peer_id);
+                info.set_trusted(true);
+            }
+        }
+    }
+
+    /// Bans all addresses in `subnet`, starting now. Banning an already banned subnet extends its
+    /// ban.
+    pub fn ban_ip_subnet(&mut self, subnet: IpNetwork) {
+        debug!(self.log, "Banning IP subnet"; "subnet" => %subnet);
+        self.banned_ip_subnets.insert(subnet, Instant::now());
+        self.new_ip_subnet_bans.push(subnet);
+    }
+
+    /// Returns the IP subnets banned since the last call.
+    pub(super) fn take_new_ip_subnet_bans(&mut self) -> Vec<IpNetwork> {
+        std::mem::take(&mut self.new_ip_subnet_bans)
+    }
+
+    /// Removes the IP subnet bans placed more than `ban_duration` ago.
+    pub(super) fn prune_ip_subnet_bans(&mut self, ban_duration: Duration) {
+        self.banned_ip_subnets
+            .retain(|_, banned_at| banned_at.elapsed() < ban_duration);
+    }
+
     /// Updates the scores of known peers according to their connection status and the time that
-    /// has passed. This function returns a list of peers that have been unbanned.
-    /// NOTE: Peer scores cannot be penalized during the update, they can only increase. Therefore
-    /// it not possible to ban peers when updating scores.
+    /// has passed. This function returns a list of peers that have been unbanned, or banned and
+    /// disconnected if their score was lowered without a score transition.
+    /// NOTE: Peer scores cannot be penalized during the update, they can only increase. A peer is
+    /// thus only banned or disconnected here if its score was changed without updating its state.
     #[must_use = "The unbanned peers must be sent to libp2p"]
     pub(super) fn update_scores(&mut self) -> Vec<(PeerId, ScoreUpdateResult)> {
         // Peer can be unbanned in this process.
         // We return the result, such that the peer manager can inform the swarm to lift the libp2p
         // ban on these peers.
         let mut peers_to_unban = Vec::new();
+        let mut peers_to_ban = Vec::new();
+        let mut peers_to_disconnect = Vec::new();
         let mut result = Vec::new();
 
         for (peer_id, info) in self.peers.iter_mut() {
@@ -380,15 +555,8 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
             info.score_update();
 
             match Self::handle_score_transition(previous_state, peer_id, info, &self.log) {
-                // A peer should not be able to be banned from a score update.
-                ScoreTransitionResult::Banned => {
-                    error!(self.log, "Peer has been banned in an update"; "peer_id" => %peer_id)
-                }
-                // A peer should not be able to transition to a disconnected state from a healthy
-                // state in a score update.
-                ScoreTransitionResult::Disconnected => {
-                    error!(self.log, "Peer has been disconnected in an update"; "peer_id" => %peer_id)
-                }
+                ScoreTransitionResult::Banned => peers_to_ban.push(*peer_id),
+                ScoreTransitionResult::Disconnected => peers_to_disconnect.push(*peer_id),
                 ScoreTransitionResult::Unbanned => {
                     peers_to_unban.push(*peer_id);
                 }
@@ -397,6 +565,18 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
         }
 
         // Update the state in the peerdb
+        for banned_peer in peers_to_ban {
+            let ban_operation =
+                self.update_connection_state(&banned_peer, NewConnectionState::Banned);
+            result.push((banned_peer, ban_operation.into()));
+        }
+        for disconnected_peer in peers_to_disconnect {
+            self.update_connection_state(
+                &disconnected_peer,
+                NewConnectionState::Disconnecting { to_ban: false },
+            );
+            result.push((disconnected_peer, ScoreUpdateResult::Disconnect));
+        }
         for unbanned_peer in peers_to_unban {
             self.update_connection_state(&unbanned_peer, NewConnectionState::Unbanned);
             let seen_ip_addresses = self
@@ -582,6 +762,48 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
         }
     }
 
+    /// Returns the bandwidth counters of a peer, creating them if needed. The transport counts
+    /// the bytes of a connection before the peer manager learns about it.
+    pub(crate) fn bandwidth_counters(&mut self, peer_id: &PeerId) -> Arc<PeerBandwidth> {
+        self.peer_counters
+            .entry(*peer_id)
+            .or_default()
+            .bandwidth
+            .clone()
+    }
+
+    /// Returns the outbound RPC request counter of a peer, creating it if needed.
+    pub(crate) fn outbound_request_counter(&mut self, peer_id: &PeerId) -> Arc<AtomicUsize> {
+        self.peer_counters
+            .entry(*peer_id)
+            .or_default()
+            .outbound_requests
+            .clone()
+    }
+
+    /// The bytes exchanged with a peer so far, if any of its connections were counted.
+    pub fn peer_bandwidth(&self, peer_id: &PeerId) -> Option<BandwidthStats> {
+        self.peer_counters
+            .get(peer_id)
+            .map(|counters| counters.bandwidth.stats())
+    }
+
+    /// The number of our RPC requests to a peer that are queued, negotiating a substream or
+    /// awaiting a response.
+    pub fn outbound_requests(&self, peer_id: &PeerId) -> usize {
+        self.peer_counters.get(peer_id).map_or(0, |counters| {
+            counters.outbound_requests.load(atomic::Ordering::Relaxed)
+        })
+    }
+
+    /// Forgets the counters of the peers that are not in the database and have no connection
+    /// left.
+    pub(super) fn prune_peer_counters(&mut self) {
+        let peers = &self.peers;
+        self.peer_counters
+            .retain(|peer_id, counters| peers.contains_key(peer_id) || counters.in_use());
+    }
+
     /// Update min ttl of a peer.
     // VISIBILITY: Only the peer manager can update the min_ttl
     pub(super) fn update_min_ttl(&mut self, peer_id: &PeerId, min_ttl: Instant) {
@@ -690,6 +912,7 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
         new_state: NewConnectionState,
     ) -> Option<BanOperation> {
         let log_ref = &self.log;
+        let protected = self.protected_peers.contains(peer_id);
         let info = self.peers.entry(*peer_id).or_insert_with(|| {
             // If we are not creating a new connection (or dropping a current inbound connection) log a warning indicating we are updating a
             // connection state for an unknown peer.
@@ -706,6 +929,9 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
             }
             PeerInfo::default()
         });
+        if protected && !info.is_protected() {
+            info.set_protected(true);
+        }
 
         // Ban the peer if the score is not already low enough.
         if matches!(new_state, NewConnectionState::Banned) {
@@ -1066,6 +1292,47 @@ impl<TSpec: EthSpec> PeerDB<TSpec> {
         unbanned_peers
     }
 
+    /// Removes disconnected peers until the database holds at most `max_entries` peers. The
+    /// lowest scoring peers go first and, among equal scores, those disconnected the longest.
+    /// Connected, dialing, banned and trusted peers are never removed, so the database may stay
+    /// above `max_entries`. Returns the number of removed peers.
+    pub(super) fn prune_disconnected_peers(&mut self, max_entries: usize) -> usize {
+        let excess_peers = self.peers.len().saturating_sub(max_entries);
+        if excess_peers == 0 {
+            return 0;
+        }
+
+        let mut candidates = self
+            .peers
+            .iter()
+            .filter(|(_, info)| !info.is_trusted())
+            .filter_map(|(id, info)| match info.connection_status() {
+                PeerConnectionStatus::Disconnected { since } => {
+                    Some((*id, info.score().score(), *since))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|(_, score_a, since_a), (_, score_b, since_b)| {
+            score_a
+                .partial_cmp(score_b)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| since_a.cmp(since_b))
+        });
+
+        let mut removed = 0;
+        for (peer_id, _, _) in candidates.into_iter().take(excess_peers) {
+            self.peers.remove(&peer_id);
+            self.disconnected_peers = self.disconnected_peers.saturating_sub(1);
+            removed += 1;
+        }
+        if removed > 0 {
+            debug!(self.log, "Pruned disconnected peers from the peer db"; "removed" => removed,
+                "db_size" => self.peers.len(), "max_entries" => max_entries);
+        }
+        removed
+    }
+
     /// This handles score transitions between states. It transitions peers states from
     /// disconnected/banned/connected.
     fn handle_score_transition(
@@ -1258,6 +1525,7 @@ mod tests {
     use super::*;
     use libp2p::core::Multiaddr;
     use slog::{o, Drain};
+    use snapshot::{ConnectionStatusSnapshot, ScoreSnapshot};
     use std::net::{Ipv4Addr, Ipv6Addr};
     use types::MinimalEthSpec;
 
@@ -1292,6 +1560,59 @@ mod tests {
         PeerDB::new(vec![], &log)
     }
 
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_snapshot_round_trip() {
+        let trusted_peer = PeerId::random();
+        let log = build_log(slog::Level::Debug, false);
+        let mut pdb: PeerDB<M> = PeerDB::new(vec![trusted_peer], &log);
+
+        let connected_peer = PeerId::random();
+        let disconnected_peer = PeerId::random();
+        let listening_address: Multiaddr = "/ip4/1.2.3.4/tcp/9000".parse().unwrap();
+        pdb.connect_ingoing(&connected_peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        pdb.connect_outgoing(&disconnected_peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        pdb.inject_disconnect(&disconnected_peer);
+        {
+            let info = pdb.peer_info_mut(&connected_peer).unwrap();
+            info.set_listening_addresses(vec![listening_address.clone()]);
+            info.update_gossipsub_score(1.5, false);
+        }
+
+        let snapshot = pdb.snapshot();
+        assert_eq!(snapshot.peers.len(), 3);
+        let peer_snapshot = |peer_id: &PeerId| {
+            snapshot
+                .peers
+                .iter()
+                .find(|peer| peer.peer_id == peer_id.to_string())
+                .unwrap()
+        };
+
+        let connected = peer_snapshot(&connected_peer);
+        assert_eq!(
+            connected.connection_status,
+            ConnectionStatusSnapshot::Connected { n_in: 1, n_out: 0 }
+        );
+        assert_eq!(
+            connected.listening_addresses,
+            vec![listening_address.to_string()]
+        );
+        assert!(matches!(
+            connected.score,
+            ScoreSnapshot::Real { gossipsub_score, .. } if gossipsub_score == 1.5
+        ));
+        assert!(matches!(
+            peer_snapshot(&disconnected_peer).connection_status,
+            ConnectionStatusSnapshot::Disconnected { .. }
+        ));
+        assert_eq!(peer_snapshot(&trusted_peer).score, ScoreSnapshot::Max);
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let decoded: PeerDbSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, snapshot);
+    }
+
     #[test]
     #[allow(clippy::float_cmp)]
     fn test_peer_connected_successfully() {
@@ -1456,6 +1777,22 @@ mod tests {
         assert_eq!(pdb.banned_peers_count.banned_peers(), MAX_BANNED_PEERS);
     }
 
+    #[test]
+    fn test_banned_peers_with_unban_time() {
+        let mut pdb = get_db();
+        let p = PeerId::random();
+        pdb.connect_ingoing(&p, "/ip4/0.0.0.0".parse().unwrap(), None);
+        assert!(pdb.banned_peers_with_unban_time().is_empty());
+
+        let _ = pdb.report_peer(&p, PeerAction::Fatal, ReportSource::PeerManager, "");
+        pdb.inject_disconnect(&p);
+
+        let banned = pdb.banned_peers_with_unban_time();
+        assert_eq!(banned.len(), 1);
+        assert_eq!(banned[0].0, p);
+        assert!(banned[0].1.unwrap() > Instant::now());
+    }
+
     #[test]
     fn test_best_peers() {
         let mut pdb = get_db();
@@ -2007,4 +2344,172 @@ mod tests {
             Score::max_score().score()
         );
     }
+
+    #[test]
+    fn test_prune_disconnected_peers_to_max_entries() {
+        let mut pdb = get_db();
+        let connected_peers: Vec<_> = (0..3).map(|_| PeerId::random()).collect();
+        for peer in &connected_peers {
+            pdb.connect_ingoing(peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        }
+        let banned_peer = PeerId::random();
+        pdb.connect_ingoing(&banned_peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        let _ = pdb.report_peer(
+            &banned_peer,
+            PeerAction::Fatal,
+            ReportSource::PeerManager,
+            "test",
+        );
+        pdb.inject_disconnect(&banned_peer);
+        assert!(pdb.ban_status(&banned_peer).is_banned());
+
+        let disconnected_peers: Vec<_> = (0..10).map(|_| PeerId::random()).collect();
+        for peer in &disconnected_peers {
+            pdb.connect_outgoing(peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+            pdb.inject_disconnect(peer);
+        }
+        // the last disconnected peer has the lowest score, so it goes first
+        let lowest_scoring_peer = disconnected_peers[9];
+        pdb.peer_info_mut(&lowest_scoring_peer)
+            .unwrap()
+            .add_to_score(-10.0);
+        assert_eq!(pdb.peers.len(), 14);
+
+        assert_eq!(pdb.prune_disconnected_peers(8), 6);
+        assert_eq!(pdb.peers.len(), 8);
+        assert_eq!(pdb.disconnected_peers, 4);
+        assert!(pdb.peer_info(&lowest_scoring_peer).is_none());
+        for peer in &connected_peers {
+            assert!(pdb.is_connected(peer));
+        }
+        assert!(pdb.ban_status(&banned_peer).is_banned());
+
+        // connected and banned peers are kept even if the db stays above the cap
+        assert_eq!(pdb.prune_disconnected_peers(2), 4);
+        assert_eq!(pdb.peers.len(), 4);
+        assert_eq!(pdb.disconnected_peers, 0);
+        assert_eq!(pdb.prune_disconnected_peers(2), 0);
+    }
+
+    #[test]
+    fn test_peers_advertising_subnet() {
+        use crate::discovery::enr::ATTESTATION_BITFIELD_ENR_KEY;
+        use crate::rpc::{MetaData, MetaDataV2};
+        use crate::types::{EnrAttestationBitfield, EnrSyncCommitteeBitfield};
+        use crate::CombinedKeyExt;
+        use ssz::Encode;
+        use types::{SubnetId, SyncSubnetId};
+
+        let mut pdb = get_db();
+
+        // a peer advertising its subnets in its metadata
+        let metadata_peer = PeerId::random();
+        pdb.connect_ingoing(&metadata_peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        let mut attnets = EnrAttestationBitfield::<M>::default();
+        attnets.set(3, true).unwrap();
+        let mut syncnets = EnrSyncCommitteeBitfield::<M>::default();
+        syncnets.set(1, true).unwrap();
+        pdb.peer_info_mut(&metadata_peer)
+            .unwrap()
+            .set_meta_data(MetaData::V2(MetaDataV2 {
+                seq_number: 1,
+                attnets,
+                syncnets,
+            }));
+
+        // a peer only known by its ENR
+        let mut attnets = EnrAttestationBitfield::<M>::default();
+        attnets.set(5, true).unwrap();
+        let keypair = libp2p::identity::Keypair::generate_secp256k1();
+        let enr_key = discv5::enr::CombinedKey::from_libp2p(&keypair).unwrap();
+        let enr = discv5::enr::EnrBuilder::new("v4")
+            .add_value(ATTESTATION_BITFIELD_ENR_KEY, &attnets.as_ssz_bytes())
+            .build(&enr_key)
+            .unwrap();
+        let enr_peer = PeerId::from(keypair.public());
+        pdb.connect_outgoing(&enr_peer, "/ip4/0.0.0.0".parse().unwrap(), Some(enr));
+
+        // a peer advertising nothing
+        pdb.connect_ingoing(&PeerId::random(), "/ip4/0.0.0.0".parse().unwrap(), None);
+
+        let on_subnet = |subnet: Subnet| {
+            pdb.peers_advertising_subnet(subnet)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            on_subnet(Subnet::Attestation(SubnetId::new(3))),
+            vec![metadata_peer]
+        );
+        assert_eq!(
+            on_subnet(Subnet::Attestation(SubnetId::new(5))),
+            vec![enr_peer]
+        );
+        assert!(on_subnet(Subnet::Attestation(SubnetId::new(4))).is_empty());
+        assert_eq!(
+            on_subnet(Subnet::SyncCommittee(SyncSubnetId::new(1))),
+            vec![metadata_peer]
+        );
+        assert!(on_subnet(Subnet::SyncCommittee(SyncSubnetId::new(0))).is_empty());
+    }
+
+    #[test]
+    fn test_peer_labels() {
+        let mut pdb = get_db();
+        let sentry = PeerId::random();
+        let archive = PeerId::random();
+        pdb.connect_ingoing(&sentry, "/ip4/0.0.0.0".parse().unwrap(), None);
+        pdb.connect_ingoing(&archive, "/ip4/0.0.0.0".parse().unwrap(), None);
+
+        assert!(pdb.set_peer_label(&sentry, "sentry".into()));
+        assert!(pdb.set_peer_label(&archive, "archive".into()));
+        assert!(pdb.set_peer_label(&archive, "sentry".into()));
+        // unknown peers are not labelled
+        assert!(!pdb.set_peer_label(&PeerId::random(), "sentry".into()));
+
+        assert_eq!(
+            pdb.peers_with_label("sentry").collect::<HashSet<_>>(),
+            HashSet::from([&sentry, &archive])
+        );
+        assert_eq!(
+            pdb.peers_with_label("archive").collect::<Vec<_>>(),
+            vec![&archive]
+        );
+
+        pdb.remove_peer_label(&archive, "sentry");
+        assert_eq!(
+            pdb.peers_with_label("sentry").collect::<Vec<_>>(),
+            vec![&sentry]
+        );
+
+        // the labels go with the pruned peer entries
+        pdb.inject_disconnect(&sentry);
+        pdb.prune_disconnected_peers(0);
+        assert_eq!(pdb.peers_with_label("sentry").count(), 0);
+    }
+
+    #[test]
+    fn test_counters_and_protection_of_unknown_peers() {
+        let mut pdb = get_db();
+        let peer = PeerId::random();
+
+        // neither the counters nor the protection of an unknown peer add it to the database
+        let counter = pdb.outbound_request_counter(&peer);
+        counter.fetch_add(2, atomic::Ordering::Relaxed);
+        pdb.set_peer_protected(&peer, true);
+        assert!(pdb.peer_info(&peer).is_none());
+        assert_eq!(pdb.outbound_requests(&peer), 2);
+
+        // the counters are kept while a connection holds them
+        pdb.prune_peer_counters();
+        assert_eq!(pdb.outbound_requests(&peer), 2);
+        drop(counter);
+        pdb.prune_peer_counters();
+        assert_eq!(pdb.outbound_requests(&peer), 0);
+        assert!(pdb.peer_bandwidth(&peer).is_none());
+
+        // the protection applies once the peer connects
+        pdb.connect_ingoing(&peer, "/ip4/0.0.0.0".parse().unwrap(), None);
+        assert!(pdb.peer_info(&peer).unwrap().is_protected());
+    }
 }
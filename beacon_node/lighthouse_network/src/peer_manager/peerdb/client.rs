@@ -3,11 +3,11 @@
 //! Currently using identify to fingerprint.
 
 use libp2p::identify::IdentifyInfo;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use strum::{AsRefStr, AsStaticStr, EnumIter};
 
 /// Various client and protocol information related to a node.
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Client {
     /// The client's name (Ex: lighthouse, prism, nimbus, etc)
     pub kind: ClientKind,
@@ -21,7 +21,7 @@ pub struct Client {
     pub agent_string: Option<String>,
 }
 
-#[derive(Clone, Debug, Serialize, PartialEq, AsRefStr, AsStaticStr, EnumIter)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, AsRefStr, AsStaticStr, EnumIter)]
 pub enum ClientKind {
     /// A lighthouse node (the best kind).
     Lighthouse,
@@ -62,6 +62,23 @@ impl Client {
             agent_string: Some(info.agent_version.clone()),
         }
     }
+
+    /// The `(major, minor, patch)` version of the client, if its version is known.
+    pub fn semantic_version(&self) -> Option<(u64, u64, u64)> {
+        parse_semantic_version(&self.version)
+    }
+}
+
+/// Parses a `major.minor.patch` version, such as `v2.3.0-4e8ad4e`. The leading `v`, the pre-release
+/// or build suffix and any missing minor or patch number are ignored.
+pub fn parse_semantic_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.strip_prefix('v').unwrap_or(version);
+    let version = version.split(|c| c == '-' || c == '+').next()?;
+    let mut numbers = version.split('.').map(|number| number.parse::<u64>().ok());
+    let major = numbers.next()??;
+    let minor = numbers.next().unwrap_or(Some(0))?;
+    let patch = numbers.next().unwrap_or(Some(0))?;
+    Some((major, minor, patch))
 }
 
 impl std::fmt::Display for Client {
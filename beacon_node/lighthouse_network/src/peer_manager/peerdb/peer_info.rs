@@ -3,19 +3,23 @@ use super::score::{PeerAction, Score, ScoreState};
 use super::sync_status::SyncStatus;
 use crate::discovery::Eth2Enr;
 use crate::Multiaddr;
-use crate::{rpc::MetaData, types::Subnet};
+use crate::{rpc::GoodbyeReason, rpc::MetaData, rpc::PROTOCOL_PREFIX, types::Subnet};
 use discv5::Enr;
+use libp2p::identify::IdentifyInfo;
 use serde::{
     ser::{SerializeStruct, Serializer},
     Serialize,
 };
 use std::collections::HashSet;
 use std::net::{IpAddr, SocketAddr};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use strum::AsRefStr;
 use types::EthSpec;
 use PeerConnectionStatus::*;
 
+/// The weight of a new round-trip time sample in the latency average of a peer.
+const LATENCY_EWMA_WEIGHT: f64 = 0.2;
+
 /// Information about a given connected peer.
 #[derive(Clone, Debug, Serialize)]
 #[serde(bound = "T: EthSpec")]
@@ -46,11 +50,50 @@ pub struct PeerInfo<T: EthSpec> {
     min_ttl: Option<Instant>,
     /// Is the peer a trusted peer.
     is_trusted: bool,
+    /// Is the peer protected from score changes and pruning at runtime.
+    is_protected: bool,
     /// Direction of the first connection of the last (or current) connected session with this peer.
     /// None if this peer was never connected.
     connection_direction: Option<ConnectionDirection>,
     /// The enr of the peer, if known.
     enr: Option<Enr>,
+    /// The protocols and agent advertised by the peer through identify, if known.
+    protocol_info: Option<PeerProtocolInfo>,
+    /// The exponentially weighted moving average of the round-trip times of our pings, if the
+    /// peer has answered one.
+    #[serde(skip)]
+    latency: Option<Duration>,
+    /// Why the peer was last disconnected, if it ever was.
+    #[serde(skip)]
+    disconnect_reason: Option<DisconnectReason>,
+    /// The application-defined labels of the peer.
+    labels: HashSet<String>,
+}
+
+/// The protocols and agent a peer advertised through identify.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PeerProtocolInfo {
+    /// The identify agent version, naming the client of the peer.
+    pub agent_version: String,
+    /// The identify protocol version.
+    pub protocol_version: String,
+    /// The ids of the RPC protocols supported by the peer.
+    pub rpc_protocols: Vec<String>,
+}
+
+impl PeerProtocolInfo {
+    pub fn from_identify_info(info: &IdentifyInfo) -> Self {
+        PeerProtocolInfo {
+            agent_version: info.agent_version.clone(),
+            protocol_version: info.protocol_version.clone(),
+            rpc_protocols: info
+                .protocols
+                .iter()
+                .filter(|protocol| protocol.starts_with(PROTOCOL_PREFIX))
+                .cloned()
+                .collect(),
+        }
+    }
 }
 
 impl<TSpec: EthSpec> Default for PeerInfo<TSpec> {
@@ -66,8 +109,13 @@ impl<TSpec: EthSpec> Default for PeerInfo<TSpec> {
             meta_data: None,
             min_ttl: None,
             is_trusted: false,
+            is_protected: false,
             connection_direction: None,
             enr: None,
+            protocol_info: None,
+            latency: None,
+            disconnect_reason: None,
+            labels: HashSet::new(),
         }
     }
 }
@@ -99,6 +147,31 @@ impl<T: EthSpec> PeerInfo<T> {
         false
     }
 
+    /// Returns if the peer advertises a given `Subnet`, from its metadata if known or else from
+    /// its ENR.
+    pub fn advertises_subnet(&self, subnet: &Subnet) -> bool {
+        if self.meta_data.is_some() {
+            return self.on_subnet_metadata(subnet);
+        }
+        if let Some(enr) = &self.enr {
+            match subnet {
+                Subnet::Attestation(id) => {
+                    return enr
+                        .attestation_bitfield::<T>()
+                        .map_or(false, |attnets| attnets.get(**id as usize).unwrap_or(false))
+                }
+                Subnet::SyncCommittee(id) => {
+                    return enr
+                        .sync_committee_bitfield::<T>()
+                        .map_or(false, |syncnets| {
+                            syncnets.get(**id as usize).unwrap_or(false)
+                        })
+                }
+            }
+        }
+        false
+    }
+
     /// Obtains the client of the peer.
     pub fn client(&self) -> &Client {
         &self.client
@@ -129,6 +202,21 @@ impl<T: EthSpec> PeerInfo<T> {
         self.is_trusted
     }
 
+    /// Returns whether the peer is protected from score-based disconnection, banning and pruning.
+    pub fn is_protected(&self) -> bool {
+        self.is_protected
+    }
+
+    /// Returns the application-defined labels of the peer.
+    pub fn labels(&self) -> &HashSet<String> {
+        &self.labels
+    }
+
+    /// Returns whether the peer has the application-defined `label`.
+    pub fn has_label(&self, label: &str) -> bool {
+        self.labels.contains(label)
+    }
+
     /// The time a peer is expected to be useful until for an attached validator. If this is set to
     /// None, the peer is not required for any upcoming duty.
     pub fn min_ttl(&self) -> Option<&Instant> {
@@ -140,6 +228,22 @@ impl<T: EthSpec> PeerInfo<T> {
         self.enr.as_ref()
     }
 
+    /// The protocols and agent advertised by the peer through identify, if known.
+    pub fn protocol_info(&self) -> Option<&PeerProtocolInfo> {
+        self.protocol_info.as_ref()
+    }
+
+    /// The average round-trip time of our pings to the peer, if it has answered one.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// Why the peer was last disconnected. This is recorded as the disconnection starts, so it is
+    /// also set while the peer is disconnecting.
+    pub fn disconnect_reason(&self) -> Option<&DisconnectReason> {
+        self.disconnect_reason.as_ref()
+    }
+
     /// An iterator over all the subnets this peer is subscribed to.
     pub fn subnets(&self) -> impl Iterator<Item = &Subnet> {
         self.subnets.iter()
@@ -320,6 +424,12 @@ impl<T: EthSpec> PeerInfo<T> {
         self.sync_status.update(sync_status)
     }
 
+    /// Sets the protocols and agent advertised by the peer.
+    // VISIBILITY: The peer manager is able to set the protocol info
+    pub(in crate::peer_manager) fn set_protocol_info(&mut self, protocol_info: PeerProtocolInfo) {
+        self.protocol_info = Some(protocol_info)
+    }
+
     /// Sets the client of the peer.
     // VISIBILITY: The peer manager is able to set the client
     pub(in crate::peer_manager) fn set_client(&mut self, client: Client) {
@@ -342,6 +452,23 @@ impl<T: EthSpec> PeerInfo<T> {
         self.meta_data = Some(meta_data)
     }
 
+    /// Records why the peer is being disconnected.
+    // VISIBILITY: The peer manager is able to set the disconnect reason
+    pub(in crate::peer_manager) fn set_disconnect_reason(&mut self, reason: DisconnectReason) {
+        self.disconnect_reason = Some(reason)
+    }
+
+    /// Adds the round-trip time of a ping to the latency average of the peer.
+    // VISIBILITY: The peer manager times the pings
+    pub(in crate::peer_manager) fn record_rtt(&mut self, rtt: Duration) {
+        self.latency = Some(match self.latency {
+            Some(latency) => {
+                latency.mul_f64(1.0 - LATENCY_EWMA_WEIGHT) + rtt.mul_f64(LATENCY_EWMA_WEIGHT)
+            }
+            None => rtt,
+        });
+    }
+
     /// Sets the connection status of the peer.
     pub(super) fn set_connection_status(&mut self, connection_status: PeerConnectionStatus) {
         self.connection_status = connection_status
@@ -372,24 +499,55 @@ impl<T: EthSpec> PeerInfo<T> {
         self.subnets.clear()
     }
 
-    /// Applies decay rates to a non-trusted peer's score.
+    /// Adds an application-defined label to the peer.
+    pub(super) fn add_label(&mut self, label: String) {
+        self.labels.insert(label);
+    }
+
+    /// Removes an application-defined label from the peer.
+    pub(super) fn remove_label(&mut self, label: &str) {
+        self.labels.remove(label);
+    }
+
+    /// Sets whether the peer is protected. The score of a protected peer is frozen.
+    pub(super) fn set_protected(&mut self, protected: bool) {
+        self.is_protected = protected;
+    }
+
+    /// Sets whether the peer is trusted. A peer becoming trusted gets the maximum score, a peer that
+    /// is no longer trusted starts over from the default score.
+    pub(super) fn set_trusted(&mut self, trusted: bool) {
+        if self.is_trusted != trusted {
+            self.is_trusted = trusted;
+            self.score = if trusted {
+                Score::max_score()
+            } else {
+                Score::default()
+            };
+        }
+    }
+
+    /// Applies decay rates to a non-trusted, non-protected peer's score.
     pub(super) fn score_update(&mut self) {
-        if !self.is_trusted {
+        if !self.is_trusted && !self.is_protected {
             self.score.update()
         }
     }
 
-    /// Apply peer action to a non-trusted peer's score.
+    /// Apply peer action to a non-trusted, non-protected peer's score.
     // VISIBILITY: The peer manager is able to modify the score of a peer.
     pub(in crate::peer_manager) fn apply_peer_action_to_score(&mut self, peer_action: PeerAction) {
-        if !self.is_trusted {
+        if !self.is_trusted && !self.is_protected {
             self.score.apply_peer_action(peer_action)
         }
     }
 
-    /// Updates the gossipsub score with a new score. Optionally ignore the gossipsub score.
+    /// Updates the gossipsub score of a non-protected peer with a new score. Optionally ignore the
+    /// gossipsub score.
     pub(super) fn update_gossipsub_score(&mut self, new_score: f64, ignore: bool) {
-        self.score.update_gossipsub_score(new_score, ignore);
+        if !self.is_protected {
+            self.score.update_gossipsub_score(new_score, ignore);
+        }
     }
 
     #[cfg(test)]
@@ -464,6 +622,11 @@ impl<T: EthSpec> PeerInfo<T> {
     pub fn set_gossipsub_score(&mut self, score: f64) {
         self.score.set_gossipsub_score(score);
     }
+
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn set_lighthouse_score(&mut self, score: f64) {
+        self.score.test_set(score);
+    }
 }
 
 /// Connection Direction of connection.
@@ -476,6 +639,36 @@ pub enum ConnectionDirection {
     Outgoing,
 }
 
+/// Why a peer was disconnected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisconnectReason {
+    /// The score of the peer fell below the disconnection or ban threshold.
+    BadScore,
+    /// The peer is on another network or fork.
+    ForkMismatch,
+    /// The peer was idle while we were above our target peer count.
+    Idle,
+    /// The peer runs a client older than our minimum peer version.
+    OutdatedClient,
+    /// The peer sent us a goodbye with the given reason.
+    GoodbyeReceived(GoodbyeReason),
+    /// We sent the peer a goodbye with the given reason, for a cause not listed above.
+    GoodbyeSent(GoodbyeReason),
+    /// The peer closed the connection without any goodbye.
+    ConnectionClosed,
+}
+
+impl From<GoodbyeReason> for DisconnectReason {
+    /// The reason of a disconnection started by sending a goodbye.
+    fn from(reason: GoodbyeReason) -> Self {
+        match reason {
+            GoodbyeReason::BadScore => DisconnectReason::BadScore,
+            GoodbyeReason::IrrelevantNetwork => DisconnectReason::ForkMismatch,
+            reason => DisconnectReason::GoodbyeSent(reason),
+        }
+    }
+}
+
 /// Connection Status of the peer.
 #[derive(Debug, Clone)]
 pub enum PeerConnectionStatus {
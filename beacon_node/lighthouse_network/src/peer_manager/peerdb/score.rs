@@ -169,6 +169,16 @@ impl RealScore {
         self.score
     }
 
+    /// The component of the score assigned by lighthouse.
+    pub fn lighthouse_score(&self) -> f64 {
+        self.lighthouse_score
+    }
+
+    /// The component of the score given by gossipsub.
+    pub fn gossipsub_score(&self) -> f64 {
+        self.gossipsub_score
+    }
+
     /// Modifies the score based on a peer's action.
     pub fn apply_peer_action(&mut self, peer_action: PeerAction) {
         match peer_action {
@@ -218,6 +228,13 @@ impl RealScore {
         self.set_lighthouse_score(0f64);
     }
 
+    // Set the lighthouse_score to a specific f64, ignoring the limits.
+    // The score state is only updated on the next score update.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn test_set(&mut self, score: f64) {
+        self.lighthouse_score = score;
+    }
+
     // Set the gossipsub_score to a specific f64.
     // Used in testing to induce score status changes during a heartbeat.
     #[cfg(test)]
@@ -232,8 +249,8 @@ impl RealScore {
     }
 
     /// Applies time-based logic such as decay rates to the score with the given now value.
-    /// This private sub function is mainly used for testing.
-    fn update_at(&mut self, now: Instant) {
+    /// This allows tests to drive the decay of the score without waiting in real time.
+    pub fn update_at(&mut self, now: Instant) {
         // Decay the current score
         // Using exponential decay based on a constant half life.
 
@@ -265,6 +282,23 @@ impl RealScore {
     pub fn is_good_gossipsub_peer(&self) -> bool {
         self.gossipsub_score >= 0.0
     }
+
+    /// Estimates when the decay of the lighthouse score will lift the score out of the banned
+    /// range, assuming the other components of the score stay the same. Returns `None` if the
+    /// decay alone can never lift it.
+    fn unban_time(&self) -> Option<Instant> {
+        // the lighthouse score the peer needs to decay to
+        let target = MIN_SCORE_BEFORE_BAN - (self.score - self.lighthouse_score);
+        if target >= 0.0 {
+            return None;
+        }
+        if self.lighthouse_score >= target {
+            return Some(self.last_updated);
+        }
+        // decay is only applied from `last_updated`, which is in the future for banned peers
+        let secs = (target / self.lighthouse_score).ln() / *HALFLIFE_DECAY;
+        Some(self.last_updated + Duration::from_secs_f64(secs))
+    }
 }
 
 #[derive(PartialEq, Clone, Debug, Serialize)]
@@ -295,11 +329,14 @@ macro_rules! apply {
 
 apply!(apply_peer_action, peer_action: PeerAction);
 apply!(update);
+apply!(update_at, now: Instant);
 apply!(update_gossipsub_score, new_score: f64, ignore: bool);
 #[cfg(test)]
 apply!(test_add, score: f64);
 #[cfg(test)]
 apply!(test_reset);
+#[cfg(any(test, feature = "test-utils"))]
+apply!(test_set, score: f64);
 #[cfg(test)]
 apply!(set_gossipsub_score, score: f64);
 
@@ -330,6 +367,15 @@ impl Score {
             Self::Real(score) => score.is_good_gossipsub_peer(),
         }
     }
+
+    /// Estimates when the score of a banned peer will have decayed enough for it to be unbanned.
+    /// Returns `None` if it is not expected to be unbanned.
+    pub fn unban_time(&self) -> Option<Instant> {
+        match self {
+            Self::Max => None,
+            Self::Real(score) => score.unban_time(),
+        }
+    }
 }
 
 impl Eq for Score {}
@@ -403,6 +449,38 @@ mod tests {
         assert!(score.score() > MIN_SCORE_BEFORE_BAN);
     }
 
+    #[test]
+    fn test_unban_time() {
+        let mut score = RealScore::default();
+        let now = Instant::now();
+
+        score.test_add(MIN_SCORE);
+        let unban_time = score.unban_time().unwrap();
+        assert!(unban_time > now + BANNED_BEFORE_DECAY);
+
+        score.update_at(unban_time - Duration::from_secs(1));
+        assert!(score.score() <= MIN_SCORE_BEFORE_BAN);
+        score.update_at(unban_time + Duration::from_secs(1));
+        assert!(score.score() > MIN_SCORE_BEFORE_BAN);
+    }
+
+    #[test]
+    fn test_score_decay() {
+        let mut score = Score::default();
+        let now = Instant::now();
+        let halflife = Duration::from_secs(SCORE_HALFLIFE as u64);
+
+        score.apply_peer_action(PeerAction::LowToleranceError);
+        assert!((score.score() + 10.0).abs() < f64::EPSILON);
+
+        // The penalty halves once per halflife.
+        score.update_at(now + halflife);
+        assert!((score.score() + 5.0).abs() < 1e-9);
+
+        score.update_at(now + 2 * halflife);
+        assert!((score.score() + 2.5).abs() < 1e-9);
+    }
+
     #[test]
     fn test_very_negative_gossipsub_score() {
         let mut score = Score::default();
@@ -0,0 +1,113 @@
+//! Owned, serializable copies of the `PeerDB` state.
+//!
+//! A snapshot can be held and serialized without keeping the `PeerDB` locked.
+
+use super::client::Client;
+use super::peer_info::{PeerConnectionStatus, PeerInfo};
+use super::score::Score;
+use crate::PeerId;
+use serde::{Deserialize, Serialize};
+use types::EthSpec;
+
+/// The state of every peer known to the `PeerDB` at the time of the snapshot.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerDbSnapshot {
+    pub peers: Vec<PeerSnapshot>,
+}
+
+/// The state of a single peer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeerSnapshot {
+    /// The peer's id.
+    pub peer_id: String,
+    /// Connection status of the peer.
+    pub connection_status: ConnectionStatusSnapshot,
+    /// The peer's score, along with its components.
+    pub score: ScoreSnapshot,
+    /// Client managing the peer.
+    pub client: Client,
+    /// The listening addresses known for the peer.
+    pub listening_addresses: Vec<String>,
+}
+
+impl PeerSnapshot {
+    pub fn new<TSpec: EthSpec>(peer_id: &PeerId, info: &PeerInfo<TSpec>) -> Self {
+        PeerSnapshot {
+            peer_id: peer_id.to_string(),
+            connection_status: info.connection_status().into(),
+            score: info.score().into(),
+            client: info.client().clone(),
+            listening_addresses: info
+                .listening_addresses()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        }
+    }
+}
+
+/// Connection status of a peer. Instants are given as the number of seconds elapsed since them
+/// at the time of the snapshot.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ConnectionStatusSnapshot {
+    Connected { n_in: u8, n_out: u8 },
+    Disconnecting { to_ban: bool },
+    Disconnected { seconds_since: u64 },
+    Banned { seconds_since: u64 },
+    Dialing { seconds_since: u64 },
+    Unknown,
+}
+
+impl From<&PeerConnectionStatus> for ConnectionStatusSnapshot {
+    fn from(status: &PeerConnectionStatus) -> Self {
+        match status {
+            PeerConnectionStatus::Connected { n_in, n_out } => Self::Connected {
+                n_in: *n_in,
+                n_out: *n_out,
+            },
+            PeerConnectionStatus::Disconnecting { to_ban } => {
+                Self::Disconnecting { to_ban: *to_ban }
+            }
+            PeerConnectionStatus::Disconnected { since } => Self::Disconnected {
+                seconds_since: since.elapsed().as_secs(),
+            },
+            PeerConnectionStatus::Banned { since } => Self::Banned {
+                seconds_since: since.elapsed().as_secs(),
+            },
+            PeerConnectionStatus::Dialing { since } => Self::Dialing {
+                seconds_since: since.elapsed().as_secs(),
+            },
+            PeerConnectionStatus::Unknown => Self::Unknown,
+        }
+    }
+}
+
+/// The score of a peer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScoreSnapshot {
+    /// The peer is trusted and has the maximum score.
+    Max,
+    Real {
+        /// The aggregate score.
+        score: f64,
+        /// The score assigned by lighthouse.
+        lighthouse_score: f64,
+        /// The score given by gossipsub.
+        gossipsub_score: f64,
+    },
+}
+
+impl From<&Score> for ScoreSnapshot {
+    fn from(score: &Score) -> Self {
+        match score {
+            Score::Max => Self::Max,
+            Score::Real(real_score) => Self::Real {
+                score: score.score(),
+                lighthouse_score: real_score.lighthouse_score(),
+                gossipsub_score: real_score.gossipsub_score(),
+            },
+        }
+    }
+}
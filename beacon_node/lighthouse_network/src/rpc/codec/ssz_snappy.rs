@@ -23,6 +23,9 @@ use unsigned_varint::codec::Uvi;
 
 const CONTEXT_BYTES_LEN: usize = 4;
 
+/// The largest amount of uncompressed data in a single frame of the snappy framing format.
+pub const MAX_SNAPPY_FRAME_SIZE: usize = 65536;
+
 /* Inbound Codec */
 
 pub struct SSZSnappyInboundCodec<TSpec: EthSpec> {
@@ -31,6 +34,8 @@ pub struct SSZSnappyInboundCodec<TSpec: EthSpec> {
     len: Option<usize>,
     /// Maximum bytes that can be sent in one req/resp chunked responses.
     max_packet_size: usize,
+    /// Maximum uncompressed bytes in each snappy frame of the encoded responses.
+    snappy_frame_size: usize,
     fork_context: Arc<ForkContext>,
     phantom: PhantomData<TSpec>,
 }
@@ -52,8 +57,17 @@ impl<T: EthSpec> SSZSnappyInboundCodec<T> {
             phantom: PhantomData,
             fork_context,
             max_packet_size,
+            snappy_frame_size: MAX_SNAPPY_FRAME_SIZE,
         }
     }
+
+    /// Splits the encoded responses into snappy frames of at most `snappy_frame_size`
+    /// uncompressed bytes, rather than the largest frames allowed.
+    pub fn with_snappy_frame_size(mut self, snappy_frame_size: usize) -> Self {
+        debug_assert!(snappy_frame_size > 0 && snappy_frame_size <= MAX_SNAPPY_FRAME_SIZE);
+        self.snappy_frame_size = snappy_frame_size;
+        self
+    }
 }
 
 // Encoder for inbound streams: Encodes RPC Responses sent to peers.
@@ -70,6 +84,8 @@ impl<TSpec: EthSpec> Encoder<RPCCodedResponse<TSpec>> for SSZSnappyInboundCodec<
                 RPCResponse::Status(res) => res.as_ssz_bytes(),
                 RPCResponse::BlocksByRange(res) => res.as_ssz_bytes(),
                 RPCResponse::BlocksByRoot(res) => res.as_ssz_bytes(),
+                RPCResponse::BlobsByRange(res) => res.as_ssz_bytes(),
+                RPCResponse::LightClientBootstrap(res) => res.as_ssz_bytes(),
                 RPCResponse::Pong(res) => res.data.as_ssz_bytes(),
                 RPCResponse::MetaData(res) =>
                 // Encode the correct version of the MetaData response based on the negotiated version.
@@ -122,7 +138,15 @@ impl<TSpec: EthSpec> Encoder<RPCCodedResponse<TSpec>> for SSZSnappyInboundCodec<
             .map_err(RPCError::from)?;
 
         let mut writer = FrameEncoder::new(Vec::new());
-        writer.write_all(&bytes).map_err(RPCError::from)?;
+        if bytes.len() <= self.snappy_frame_size {
+            writer.write_all(&bytes).map_err(RPCError::from)?;
+        } else {
+            // the encoder emits the buffered bytes as a frame on each flush
+            for frame in bytes.chunks(self.snappy_frame_size) {
+                writer.write_all(frame).map_err(RPCError::from)?;
+                writer.flush().map_err(RPCError::from)?;
+            }
+        }
         writer.flush().map_err(RPCError::from)?;
 
         // Write compressed bytes to `dst`
@@ -222,6 +246,8 @@ impl<TSpec: EthSpec> Encoder<OutboundRequest<TSpec>> for SSZSnappyOutboundCodec<
             OutboundRequest::Goodbye(req) => req.as_ssz_bytes(),
             OutboundRequest::BlocksByRange(req) => req.as_ssz_bytes(),
             OutboundRequest::BlocksByRoot(req) => req.block_roots.as_ssz_bytes(),
+            OutboundRequest::BlobsByRange(req) => req.as_ssz_bytes(),
+            OutboundRequest::LightClientBootstrap(req) => req.as_ssz_bytes(),
             OutboundRequest::Ping(req) => req.as_ssz_bytes(),
             OutboundRequest::MetaData(_) => return Ok(()), // no metadata to encode
         };
@@ -279,7 +305,9 @@ impl<TSpec: EthSpec> Decoder for SSZSnappyOutboundCodec<TSpec> {
 
         // Should not attempt to decode rpc chunks with `length > max_packet_size` or not within bounds of
         // packet size for ssz container corresponding to `self.protocol`.
-        let ssz_limits = self.protocol.rpc_response_limits::<TSpec>();
+        let ssz_limits = self
+            .protocol
+            .rpc_response_limits::<TSpec>(&self.fork_context);
         if ssz_limits.is_out_of_bounds(length, self.max_packet_size) {
             return Err(RPCError::InvalidData);
         }
@@ -299,7 +327,11 @@ impl<TSpec: EthSpec> Decoder for SSZSnappyOutboundCodec<TSpec> {
                 let _read_bytes = src.split_to(n as usize);
 
                 match self.protocol.version {
-                    Version::V1 => handle_v1_response(self.protocol.message_name, &decoded_buffer),
+                    Version::V1 => handle_v1_response(
+                        self.protocol.message_name,
+                        &decoded_buffer,
+                        &mut self.fork_name,
+                    ),
                     Version::V2 => handle_v2_response(
                         self.protocol.message_name,
                         &decoded_buffer,
@@ -402,6 +434,10 @@ fn context_bytes<T: EthSpec>(
                     SignedBeaconBlock::Base { .. } => Some(fork_context.genesis_context_bytes()),
                 };
             }
+            if let RPCResponse::LightClientBootstrap(_) = rpc_variant {
+                // The bootstrap container is defined from altair and unchanged since.
+                return fork_context.to_context_bytes(ForkName::Altair);
+            }
         }
     }
     None
@@ -432,6 +468,19 @@ fn handle_length(
     }
 }
 
+/// Rejects `BlocksByRange` requests with a zero step, or whose range end
+/// `start_slot + count * step` overflows.
+fn validate_blocks_by_range(req: BlocksByRangeRequest) -> Result<BlocksByRangeRequest, RPCError> {
+    if req.step == 0 {
+        return Err(RPCError::InvalidData);
+    }
+    req.count
+        .checked_mul(req.step)
+        .and_then(|range_len| req.start_slot.checked_add(range_len))
+        .ok_or(RPCError::InvalidData)?;
+    Ok(req)
+}
+
 /// Decodes a `Version::V1` `InboundRequest` from the byte stream.
 /// `decoded_buffer` should be an ssz-encoded bytestream with
 // length = length-prefix received in the beginning of the stream.
@@ -447,11 +496,17 @@ fn handle_v1_request<T: EthSpec>(
             GoodbyeReason::from_ssz_bytes(decoded_buffer)?,
         ))),
         Protocol::BlocksByRange => Ok(Some(InboundRequest::BlocksByRange(
-            BlocksByRangeRequest::from_ssz_bytes(decoded_buffer)?,
+            validate_blocks_by_range(BlocksByRangeRequest::from_ssz_bytes(decoded_buffer)?)?,
         ))),
         Protocol::BlocksByRoot => Ok(Some(InboundRequest::BlocksByRoot(BlocksByRootRequest {
             block_roots: VariableList::from_ssz_bytes(decoded_buffer)?,
         }))),
+        Protocol::BlobsByRange => Ok(Some(InboundRequest::BlobsByRange(
+            BlobsByRangeRequest::from_ssz_bytes(decoded_buffer)?,
+        ))),
+        Protocol::LightClientBootstrap => Ok(Some(InboundRequest::LightClientBootstrap(
+            LightClientBootstrapRequest::from_ssz_bytes(decoded_buffer)?,
+        ))),
         Protocol::Ping => Ok(Some(InboundRequest::Ping(Ping {
             data: u64::from_ssz_bytes(decoded_buffer)?,
         }))),
@@ -477,7 +532,7 @@ fn handle_v2_request<T: EthSpec>(
 ) -> Result<Option<InboundRequest<T>>, RPCError> {
     match protocol {
         Protocol::BlocksByRange => Ok(Some(InboundRequest::BlocksByRange(
-            BlocksByRangeRequest::from_ssz_bytes(decoded_buffer)?,
+            validate_blocks_by_range(BlocksByRangeRequest::from_ssz_bytes(decoded_buffer)?)?,
         ))),
         Protocol::BlocksByRoot => Ok(Some(InboundRequest::BlocksByRoot(BlocksByRootRequest {
             block_roots: VariableList::from_ssz_bytes(decoded_buffer)?,
@@ -501,9 +556,12 @@ fn handle_v2_request<T: EthSpec>(
 /// Decodes a `Version::V1` `RPCResponse` from the byte stream.
 /// `decoded_buffer` should be an ssz-encoded bytestream with
 // length = length-prefix received in the beginning of the stream.
+///
+/// For LightClientBootstrap responses, checks that the received `ForkName` has light clients.
 fn handle_v1_response<T: EthSpec>(
     protocol: Protocol,
     decoded_buffer: &[u8],
+    fork_name: &mut Option<ForkName>,
 ) -> Result<Option<RPCResponse<T>>, RPCError> {
     match protocol {
         Protocol::Status => Ok(Some(RPCResponse::Status(StatusMessage::from_ssz_bytes(
@@ -517,6 +575,24 @@ fn handle_v1_response<T: EthSpec>(
         Protocol::BlocksByRoot => Ok(Some(RPCResponse::BlocksByRoot(Box::new(
             SignedBeaconBlock::Base(SignedBeaconBlockBase::from_ssz_bytes(decoded_buffer)?),
         )))),
+        Protocol::BlobsByRange => Ok(Some(RPCResponse::BlobsByRange(Box::new(
+            BlobsSidecar::from_ssz_bytes(decoded_buffer)?,
+        )))),
+        Protocol::LightClientBootstrap => match fork_name.take() {
+            Some(ForkName::Altair | ForkName::Merge) => {
+                Ok(Some(RPCResponse::LightClientBootstrap(Box::new(
+                    LightClientBootstrap::from_ssz_bytes(decoded_buffer)?,
+                ))))
+            }
+            Some(ForkName::Base) => Err(RPCError::ErrorResponse(
+                RPCResponseErrorCode::InvalidRequest,
+                format!("No {} response before altair", protocol),
+            )),
+            None => Err(RPCError::ErrorResponse(
+                RPCResponseErrorCode::InvalidRequest,
+                format!("No context bytes provided for {} response", protocol),
+            )),
+        },
         Protocol::Ping => Ok(Some(RPCResponse::Pong(Ping {
             data: u64::from_ssz_bytes(decoded_buffer)?,
         }))),
@@ -615,12 +691,13 @@ mod tests {
     };
     use std::sync::Arc;
     use types::{
-        BeaconBlock, BeaconBlockAltair, BeaconBlockBase, Epoch, ForkContext, Hash256, Signature,
-        SignedBeaconBlock, Slot,
+        BeaconBlock, BeaconBlockAltair, BeaconBlockBase, BeaconBlockHeader, Epoch, ForkContext,
+        Hash256, Signature, SignedBeaconBlock, Slot, SyncCommittee,
     };
 
     use snap::write::FrameEncoder;
     use ssz::Encode;
+    use ssz_types::FixedVector;
     use std::io::Write;
 
     type Spec = types::MainnetEthSpec;
@@ -654,6 +731,29 @@ mod tests {
         }
     }
 
+    fn blobs_sidecar() -> BlobsSidecar {
+        BlobsSidecar {
+            beacon_block_root: Hash256::zero(),
+            beacon_block_slot: Slot::new(1),
+            blobs: VariableList::from(vec![FixedVector::from(vec![42; 131072])]),
+            kzg_aggregated_proof: FixedVector::default(),
+        }
+    }
+
+    fn light_client_bootstrap() -> LightClientBootstrap<Spec> {
+        LightClientBootstrap {
+            header: BeaconBlockHeader {
+                slot: Slot::new(1),
+                proposer_index: 0,
+                parent_root: Hash256::zero(),
+                state_root: Hash256::zero(),
+                body_root: Hash256::zero(),
+            },
+            current_sync_committee: SyncCommittee::temporary().unwrap(),
+            current_sync_committee_branch: FixedVector::default(),
+        }
+    }
+
     fn ping_message() -> Ping {
         Ping { data: 1 }
     }
@@ -781,6 +881,28 @@ mod tests {
             "altair block cannot be decoded with blocks by range V1 version"
         );
 
+        assert_eq!(
+            encode_then_decode(
+                Protocol::BlobsByRange,
+                Version::V1,
+                RPCCodedResponse::Success(RPCResponse::BlobsByRange(Box::new(blobs_sidecar())))
+            ),
+            Ok(Some(RPCResponse::BlobsByRange(Box::new(blobs_sidecar()))))
+        );
+
+        assert_eq!(
+            encode_then_decode(
+                Protocol::LightClientBootstrap,
+                Version::V1,
+                RPCCodedResponse::Success(RPCResponse::LightClientBootstrap(Box::new(
+                    light_client_bootstrap()
+                )))
+            ),
+            Ok(Some(RPCResponse::LightClientBootstrap(Box::new(
+                light_client_bootstrap()
+            ))))
+        );
+
         assert_eq!(
             encode_then_decode(
                 Protocol::MetaData,
@@ -1011,6 +1133,58 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_context_bytes_light_client_bootstrap() {
+        let fork_context = fork_context();
+        let encoded_bytes = encode(
+            Protocol::LightClientBootstrap,
+            Version::V1,
+            RPCCodedResponse::Success(RPCResponse::LightClientBootstrap(Box::new(
+                light_client_bootstrap(),
+            ))),
+        )
+        .unwrap();
+
+        // The bootstrap is sent with the altair context bytes
+        assert_eq!(
+            encoded_bytes[..CONTEXT_BYTES_LEN],
+            fork_context.to_context_bytes(ForkName::Altair).unwrap()
+        );
+        assert_eq!(
+            decode(
+                Protocol::LightClientBootstrap,
+                Version::V1,
+                &mut encoded_bytes.clone()
+            ),
+            Ok(Some(RPCResponse::LightClientBootstrap(Box::new(
+                light_client_bootstrap()
+            ))))
+        );
+
+        // Removing the context bytes should error
+        let mut no_context_bytes = encoded_bytes.clone().split_off(CONTEXT_BYTES_LEN);
+        assert!(decode(
+            Protocol::LightClientBootstrap,
+            Version::V1,
+            &mut no_context_bytes
+        )
+        .is_err());
+
+        // A bootstrap with base context bytes should error
+        let mut base_fork_bytes = BytesMut::new();
+        base_fork_bytes.extend_from_slice(&fork_context.genesis_context_bytes());
+        base_fork_bytes.extend_from_slice(&encoded_bytes[CONTEXT_BYTES_LEN..]);
+        assert!(matches!(
+            decode(
+                Protocol::LightClientBootstrap,
+                Version::V1,
+                &mut base_fork_bytes
+            )
+            .unwrap_err(),
+            RPCError::ErrorResponse(RPCResponseErrorCode::InvalidRequest, _),
+        ));
+    }
+
     /// Test a malicious snappy encoding for a V1 `Status` message where the attacker
     /// sends a valid message filled with a stream of useless padding before the actual message.
     #[test]
@@ -1124,6 +1298,19 @@ mod tests {
         );
     }
 
+    /// Test that the blobs sidecar limit is bounded by max_rpc_size.
+    #[test]
+    fn test_blobs_sidecar_limit() {
+        let fork_context = fork_context();
+        let protocol_id = ProtocolId::new(Protocol::BlobsByRange, Version::V1, Encoding::SSZSnappy);
+
+        assert!(*BLOBS_SIDECAR_MAX > max_rpc_size(&fork_context));
+        assert_eq!(
+            protocol_id.rpc_response_limits::<Spec>(&fork_context).max,
+            max_rpc_size(&fork_context)
+        );
+    }
+
     /// Test sending a message with encoded length prefix > max_rpc_size.
     #[test]
     fn test_decode_invalid_length() {
@@ -1162,4 +1349,127 @@ mod tests {
             RPCError::InvalidData
         );
     }
+
+    /// Test that a response split into small snappy frames decodes like the default encoding.
+    #[test]
+    fn test_encode_with_small_snappy_frames() {
+        let snappy_frame_size = 4096;
+        let snappy_protocol_id =
+            ProtocolId::new(Protocol::BlocksByRange, Version::V1, Encoding::SSZSnappy);
+        let fork_context = Arc::new(fork_context());
+        let mut snappy_inbound_codec = SSZSnappyInboundCodec::<Spec>::new(
+            snappy_protocol_id,
+            max_rpc_size(&fork_context),
+            fork_context,
+        )
+        .with_snappy_frame_size(snappy_frame_size);
+
+        let message = RPCCodedResponse::Success(RPCResponse::BlocksByRange(Box::new(base_block())));
+        let mut encoded = BytesMut::new();
+        snappy_inbound_codec
+            .encode(message.clone(), &mut encoded)
+            .unwrap();
+        assert_ne!(
+            encoded,
+            encode(Protocol::BlocksByRange, Version::V1, message).unwrap()
+        );
+
+        // skip the length prefix and count the data frames, each made of a type byte, a 3 byte
+        // length and the frame itself
+        let mut frames = encoded.clone();
+        Uvi::<usize>::default().decode(&mut frames).unwrap();
+        let mut data_frames = 0;
+        let mut rest = &frames[..];
+        while rest.len() >= 4 {
+            let frame_len = u32::from_le_bytes([rest[1], rest[2], rest[3], 0]) as usize;
+            // compressed and uncompressed data frames
+            if rest[0] <= 1 {
+                data_frames += 1;
+            }
+            rest = &rest[4 + frame_len..];
+        }
+        let block_len = base_block().as_ssz_bytes().len();
+        assert!(block_len > snappy_frame_size);
+        assert_eq!(
+            data_frames,
+            (block_len + snappy_frame_size - 1) / snappy_frame_size
+        );
+
+        assert_eq!(
+            decode(Protocol::BlocksByRange, Version::V1, &mut encoded),
+            Ok(Some(RPCResponse::BlocksByRange(Box::new(base_block()))))
+        );
+    }
+
+    /// Encodes the given request and decodes it as an inbound request.
+    fn encode_then_decode_request(
+        version: Version,
+        request: OutboundRequest<Spec>,
+    ) -> Result<Option<InboundRequest<Spec>>, RPCError> {
+        let protocol_id = ProtocolId::new(request.protocol(), version, Encoding::SSZSnappy);
+        let fork_context = Arc::new(fork_context());
+        let max_packet_size = max_rpc_size(&fork_context);
+
+        let mut buf = BytesMut::new();
+        SSZSnappyOutboundCodec::<Spec>::new(
+            protocol_id.clone(),
+            max_packet_size,
+            fork_context.clone(),
+        )
+        .encode(request, &mut buf)?;
+        SSZSnappyInboundCodec::<Spec>::new(protocol_id, max_packet_size, fork_context)
+            .decode(&mut buf)
+    }
+
+    #[test]
+    fn test_decode_malformed_blocks_by_range_request() {
+        for version in [Version::V1, Version::V2] {
+            // zero step
+            let request = OutboundRequest::BlocksByRange(BlocksByRangeRequest {
+                start_slot: 0,
+                count: 10,
+                step: 0,
+            });
+            assert_eq!(
+                encode_then_decode_request(version.clone(), request),
+                Err(RPCError::InvalidData)
+            );
+
+            // `count * step` overflows
+            let request = OutboundRequest::BlocksByRange(BlocksByRangeRequest {
+                start_slot: 0,
+                count: u64::MAX,
+                step: 2,
+            });
+            assert_eq!(
+                encode_then_decode_request(version.clone(), request),
+                Err(RPCError::InvalidData)
+            );
+
+            // `start_slot + count * step` overflows
+            let request = OutboundRequest::BlocksByRange(BlocksByRangeRequest {
+                start_slot: u64::MAX,
+                count: 1,
+                step: 1,
+            });
+            assert_eq!(
+                encode_then_decode_request(version.clone(), request),
+                Err(RPCError::InvalidData)
+            );
+
+            // a request for no blocks is valid
+            let request = BlocksByRangeRequest {
+                start_slot: 0,
+                count: 0,
+                step: 1,
+            };
+            assert_eq!(
+                encode_then_decode_request(
+                    version,
+                    OutboundRequest::BlocksByRange(request.clone())
+                ),
+                Ok(Some(InboundRequest::BlocksByRange(request)))
+            );
+        }
+    }
 }
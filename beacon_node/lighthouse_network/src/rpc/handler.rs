@@ -1,10 +1,14 @@
 #![allow(clippy::type_complexity)]
 #![allow(clippy::cognitive_complexity)]
 
-use super::methods::{GoodbyeReason, RPCCodedResponse, RPCResponseErrorCode, ResponseTermination};
+use super::methods::{
+    GoodbyeReason, RPCCodedResponse, RPCResponse, RPCResponseErrorCode, ResponseTermination,
+};
 use super::outbound::OutboundRequestContainer;
 use super::protocol::{max_rpc_size, InboundRequest, Protocol, RPCError, RPCProtocol};
+use super::self_rate_limiter::SelfRateLimiter;
 use super::{RPCReceived, RPCSend, ReqId};
+use crate::metrics;
 use crate::rpc::outbound::{OutboundFramed, OutboundRequest};
 use crate::rpc::protocol::InboundFramed;
 use fnv::FnvHashMap;
@@ -20,9 +24,11 @@ use libp2p::swarm::handler::{
 use libp2p::swarm::NegotiatedSubstream;
 use slog::{crit, debug, trace, warn};
 use smallvec::SmallVec;
+use ssz::Encode;
 use std::{
-    collections::{hash_map::Entry, VecDeque},
+    collections::{hash_map::Entry, HashMap, VecDeque},
     pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
     sync::Arc,
     task::{Context, Poll},
     time::{Duration, Instant},
@@ -40,6 +46,11 @@ const IO_ERROR_RETRIES: u8 = 3;
 /// Maximum time given to the handler to perform shutdown operations.
 const SHUTDOWN_TIMEOUT_SECS: u8 = 15;
 
+/// The maximum number of response chunks read from our outbound substreams that the application
+/// has not taken yet. Once reached, no more chunks are read from the sockets until the behaviour
+/// reports a chunk as consumed, which applies back-pressure to peers sending large responses.
+const RESPONSE_CHUNK_WINDOW: usize = 2;
+
 /// Identifier of inbound and outbound substreams from the handler's perspective.
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub struct SubstreamId(usize);
@@ -86,8 +97,8 @@ where
     /// Queue of events to produce in `poll()`.
     events_out: SmallVec<[HandlerEvent<Id, TSpec>; 4]>,
 
-    /// Queue of outbound substreams to open.
-    dial_queue: SmallVec<[(Id, OutboundRequest<TSpec>); 4]>,
+    /// Queue of outbound substreams to open, with the time their request was queued.
+    dial_queue: SmallVec<[(Id, OutboundRequest<TSpec>, Instant); 4]>,
 
     /// Current number of concurrent outbound substreams being opened.
     dial_negotiated: u32,
@@ -123,6 +134,31 @@ where
     /// Fork specific info.
     fork_context: Arc<ForkContext>,
 
+    /// Rate limiter for our outbound requests, if enabled.
+    self_limiter: Option<SelfRateLimiter<Id, TSpec>>,
+
+    /// Response chunks read from the outbound substreams that the application has not taken yet.
+    unconsumed_chunks: usize,
+
+    /// The counter of the outbound requests to the peer, shared with the peer's other
+    /// connections. Set by the behaviour once the connection is established.
+    outbound_request_counter: Option<Arc<AtomicUsize>>,
+
+    /// The number of outbound requests of this handler accounted in `outbound_request_counter`.
+    reported_outbound_requests: usize,
+
+    /// The maximum number of block bytes sent in a single `BlocksByRange` or `BlocksByRoot`
+    /// response, if limited.
+    response_byte_budget: Option<usize>,
+
+    /// How long the responses to our requests of each protocol may take, overriding
+    /// `RESPONSE_TIMEOUT`.
+    request_timeouts: Arc<HashMap<Protocol, Duration>>,
+
+    /// The maximum number of inbound substreams of this connection awaiting a response, of any
+    /// protocol, if limited.
+    max_inbound_substreams: Option<usize>,
+
     /// Waker, to be sure the handler gets polled when needed.
     waker: Option<std::task::Waker>,
 
@@ -153,6 +189,10 @@ struct InboundInfo<TSpec: EthSpec> {
     protocol: Protocol,
     /// Responses that the peer is still expecting from us.
     remaining_chunks: u64,
+    /// The block bytes queued for sending, counted against the response byte budget.
+    response_bytes: usize,
+    /// Whether the response was terminated early for exceeding the response byte budget.
+    response_truncated: bool,
     /// Useful to timing how long each request took to process. Currently only used by
     /// BlocksByRange.
     request_start_time: Instant,
@@ -172,6 +212,8 @@ struct OutboundInfo<Id, TSpec: EthSpec> {
     remaining_chunks: Option<u64>,
     /// `Id` as given by the application that sent the request.
     req_id: Id,
+    /// When the request was queued in the handler, to time the request.
+    request_start_time: Instant,
 }
 
 /// State of an inbound substream connection.
@@ -209,6 +251,10 @@ where
     pub fn new(
         listen_protocol: SubstreamProtocol<RPCProtocol<TSpec>, ()>,
         fork_context: Arc<ForkContext>,
+        self_limiter: Option<SelfRateLimiter<Id, TSpec>>,
+        response_byte_budget: Option<usize>,
+        request_timeouts: Arc<HashMap<Protocol, Duration>>,
+        max_inbound_substreams: Option<usize>,
         log: &slog::Logger,
     ) -> Self {
         RPCHandler {
@@ -226,20 +272,58 @@ where
             max_dial_negotiated: 8,
             outbound_io_error_retries: 0,
             fork_context,
+            self_limiter,
+            unconsumed_chunks: 0,
+            outbound_request_counter: None,
+            reported_outbound_requests: 0,
+            response_byte_budget,
+            request_timeouts,
+            max_inbound_substreams,
             waker: None,
             log: log.clone(),
         }
     }
 
+    /// Accounts the requests queued, negotiating a substream or awaiting a response in the
+    /// peer's outbound request counter.
+    fn update_outbound_request_counter(&mut self) {
+        let outbound_requests =
+            self.dial_queue.len() + self.dial_negotiated as usize + self.outbound_substreams.len();
+        if let Some(counter) = &self.outbound_request_counter {
+            if outbound_requests > self.reported_outbound_requests {
+                counter.fetch_add(
+                    outbound_requests - self.reported_outbound_requests,
+                    Ordering::Relaxed,
+                );
+            } else {
+                counter.fetch_sub(
+                    self.reported_outbound_requests - outbound_requests,
+                    Ordering::Relaxed,
+                );
+            }
+            self.reported_outbound_requests = outbound_requests;
+        }
+    }
+
     /// Initiates the handler's shutdown process, sending an optional Goodbye message to the
     /// peer.
     fn shutdown(&mut self, goodbye_reason: Option<(Id, GoodbyeReason)>) {
         if matches!(self.state, HandlerState::Active) {
+            // Requests held back by the rate limiter are not sent either
+            if let Some(self_limiter) = self.self_limiter.as_mut() {
+                let now = Instant::now();
+                self.dial_queue.extend(
+                    self_limiter
+                        .drain()
+                        .into_iter()
+                        .map(|(id, req)| (id, req, now)),
+                );
+            }
             if !self.dial_queue.is_empty() {
                 debug!(self.log, "Starting handler shutdown"; "unsent_queued_requests" => self.dial_queue.len());
             }
             // We now drive to completion communications already dialed/established
-            while let Some((id, req)) = self.dial_queue.pop() {
+            while let Some((id, req, _)) = self.dial_queue.pop() {
                 self.events_out.push(Err(HandlerErr::Outbound {
                     error: RPCError::HandlerRejected,
                     proto: req.protocol(),
@@ -249,33 +333,44 @@ where
 
             // Queue our goodbye message.
             if let Some((id, reason)) = goodbye_reason {
-                self.dial_queue.push((id, OutboundRequest::Goodbye(reason)));
+                self.dial_queue
+                    .push((id, OutboundRequest::Goodbye(reason), Instant::now()));
             }
 
             self.state = HandlerState::ShuttingDown(Box::new(sleep_until(
                 TInstant::now() + Duration::from_secs(SHUTDOWN_TIMEOUT_SECS as u64),
             )));
+            self.update_outbound_request_counter();
         }
     }
 
     /// Opens an outbound substream with a request.
+    ///
+    /// Requests are timed from the moment they are queued here, or released by the rate limiter.
     fn send_request(&mut self, id: Id, req: OutboundRequest<TSpec>) {
         match self.state {
-            HandlerState::Active => {
-                self.dial_queue.push((id, req));
-            }
+            HandlerState::Active => match self.self_limiter.as_mut() {
+                Some(self_limiter) => match self_limiter.allows(id, req) {
+                    Ok(Some((id, req))) => self.dial_queue.push((id, req, Instant::now())),
+                    // The request is sent once the rate limiter releases it
+                    Ok(None) => {}
+                    Err(e) => self.events_out.push(Err(e)),
+                },
+                None => self.dial_queue.push((id, req, Instant::now())),
+            },
             _ => self.events_out.push(Err(HandlerErr::Outbound {
                 error: RPCError::HandlerRejected,
                 proto: req.protocol(),
                 id,
             })),
         }
+        self.update_outbound_request_counter();
     }
 
     /// Sends a response to a peer's request.
     // NOTE: If the substream has closed due to inactivity, or the substream is in the
     // wrong state a response will fail silently.
-    fn send_response(&mut self, inbound_id: SubstreamId, response: RPCCodedResponse<TSpec>) {
+    fn send_response(&mut self, inbound_id: SubstreamId, mut response: RPCCodedResponse<TSpec>) {
         // check if the stream matching the response still exists
         let inbound_info = if let Some(info) = self.inbound_substreams.get_mut(&inbound_id) {
             info
@@ -303,10 +398,40 @@ where
                 "response" => %response, "id" => inbound_id);
             return;
         }
+
+        if inbound_info.response_truncated {
+            // the stream is already terminated, the remaining chunks are not sent
+            return;
+        }
+        if let (Some(budget), Some((chunk_bytes, termination))) =
+            (self.response_byte_budget, budgeted_chunk(&response))
+        {
+            if inbound_info.response_bytes + chunk_bytes > budget {
+                debug!(self.log, "Response byte budget reached, terminating the stream";
+                    "protocol" => %inbound_info.protocol, "id" => inbound_id,
+                    "sent_bytes" => inbound_info.response_bytes);
+                inbound_info.response_truncated = true;
+                response = RPCCodedResponse::StreamTermination(termination);
+            } else {
+                inbound_info.response_bytes += chunk_bytes;
+            }
+        }
         inbound_info.pending_items.push_back(response);
     }
 }
 
+impl<Id, TSpec> Drop for RPCHandler<Id, TSpec>
+where
+    TSpec: EthSpec,
+{
+    fn drop(&mut self) {
+        // the requests of a closed connection are no longer pending
+        if let Some(counter) = &self.outbound_request_counter {
+            counter.fetch_sub(self.reported_outbound_requests, Ordering::Relaxed);
+        }
+    }
+}
+
 impl<Id, TSpec> ConnectionHandler for RPCHandler<Id, TSpec>
 where
     TSpec: EthSpec,
@@ -317,7 +442,7 @@ where
     type Error = RPCError;
     type InboundProtocol = RPCProtocol<TSpec>;
     type OutboundProtocol = OutboundRequestContainer<TSpec>;
-    type OutboundOpenInfo = (Id, OutboundRequest<TSpec>); // Keep track of the id and the request
+    type OutboundOpenInfo = (Id, OutboundRequest<TSpec>, Instant); // Keep track of the id, the request and when it was queued
     type InboundOpenInfo = ();
 
     fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, ()> {
@@ -335,7 +460,20 @@ where
         }
 
         let (req, substream) = substream;
-        let expected_responses = req.expected_responses();
+        let expected_responses = match req {
+            // a request for no blocks is answered with the stream termination alone
+            InboundRequest::BlocksByRange(ref blocks_req) if blocks_req.count == 0 => 1,
+            _ => req.expected_responses(),
+        };
+
+        // Drop, and thereby reset, the substreams awaiting a response beyond the limit.
+        if let Some(max_inbound_substreams) = self.max_inbound_substreams {
+            if expected_responses > 0 && self.inbound_substreams.len() >= max_inbound_substreams {
+                debug!(self.log, "Too many inbound substreams, resetting the substream";
+                    "protocol" => %req.protocol(), "limit" => max_inbound_substreams);
+                return;
+            }
+        }
 
         // store requests that expect responses
         if expected_responses > 0 {
@@ -354,6 +492,8 @@ where
                     protocol: req.protocol(),
                     request_start_time: Instant::now(),
                     remaining_chunks: expected_responses,
+                    response_bytes: 0,
+                    response_truncated: false,
                 },
             );
         }
@@ -376,7 +516,7 @@ where
         request_info: Self::OutboundOpenInfo,
     ) {
         self.dial_negotiated -= 1;
-        let (id, request) = request_info;
+        let (id, request, request_start_time) = request_info;
         let proto = request.protocol();
 
         // accept outbound connections only if the handler is not deactivated
@@ -394,7 +534,7 @@ where
             // new outbound request. Store the stream and tag the output.
             let delay_key = self.outbound_substreams_delay.insert(
                 self.current_outbound_substream_id,
-                Duration::from_secs(RESPONSE_TIMEOUT),
+                response_timeout(&self.request_timeouts, proto),
             );
             let awaiting_stream = OutboundSubstreamState::RequestPendingResponse {
                 substream: Box::new(out),
@@ -416,6 +556,7 @@ where
                         proto,
                         remaining_chunks: expected_responses,
                         req_id: id,
+                        request_start_time,
                     },
                 )
                 .is_some()
@@ -424,6 +565,7 @@ where
             }
             self.current_outbound_substream_id.0 += 1;
         }
+        self.update_outbound_request_counter();
     }
 
     fn inject_event(&mut self, rpc_event: Self::InEvent) {
@@ -431,6 +573,13 @@ where
             RPCSend::Request(id, req) => self.send_request(id, req),
             RPCSend::Response(inbound_id, response) => self.send_response(inbound_id, response),
             RPCSend::Shutdown(id, reason) => self.shutdown(Some((id, reason))),
+            RPCSend::ChunkConsumed => {
+                self.unconsumed_chunks = self.unconsumed_chunks.saturating_sub(1)
+            }
+            RPCSend::OutboundRequestCounter(counter) => {
+                self.outbound_request_counter = Some(counter);
+                self.update_outbound_request_counter();
+            }
         }
         // In any case, we need the handler to process the event.
         if let Some(waker) = &self.waker {
@@ -445,7 +594,7 @@ where
             <Self::OutboundProtocol as OutboundUpgrade<NegotiatedSubstream>>::Error,
         >,
     ) {
-        let (id, req) = request_info;
+        let (id, req, _) = request_info;
         if let ConnectionHandlerUpgrErr::Upgrade(UpgradeError::Apply(RPCError::IoError(_))) = error
         {
             self.outbound_io_error_retries += 1;
@@ -457,6 +606,7 @@ where
 
         // This dialing is now considered failed
         self.dial_negotiated -= 1;
+        self.update_outbound_request_counter();
 
         self.outbound_io_error_retries = 0;
         // map the error
@@ -531,6 +681,18 @@ where
         } else {
             self.waker = Some(cx.waker().clone());
         }
+
+        // queue the requests released by the rate limiter
+        if let Some(self_limiter) = self.self_limiter.as_mut() {
+            while let Poll::Ready(released) = self_limiter.poll_ready(cx) {
+                match released {
+                    Ok((id, req)) => self.dial_queue.push((id, req, Instant::now())),
+                    Err(e) => self.events_out.push(Err(e)),
+                }
+            }
+            self.update_outbound_request_counter();
+        }
+
         // return any events that need to be reported
         if !self.events_out.is_empty() {
             return Poll::Ready(ConnectionHandlerEvent::Custom(self.events_out.remove(0)));
@@ -587,9 +749,36 @@ where
         loop {
             match self.outbound_substreams_delay.poll_expired(cx) {
                 Poll::Ready(Some(Ok(outbound_id))) => {
-                    if let Some(OutboundInfo { proto, req_id, .. }) =
-                        self.outbound_substreams.remove(outbound_id.get_ref())
+                    // while the response window is full, streams wait on the application rather
+                    // than on the peer
+                    if self.unconsumed_chunks >= RESPONSE_CHUNK_WINDOW {
+                        if let Some(info) = self.outbound_substreams.get_mut(outbound_id.get_ref())
+                        {
+                            if matches!(
+                                info.state,
+                                OutboundSubstreamState::RequestPendingResponse { .. }
+                            ) {
+                                info.delay_key = self.outbound_substreams_delay.insert(
+                                    *outbound_id.get_ref(),
+                                    response_timeout(&self.request_timeouts, info.proto),
+                                );
+                                continue;
+                            }
+                        }
+                    }
+                    if let Some(OutboundInfo {
+                        proto,
+                        req_id,
+                        request_start_time,
+                        ..
+                    }) = self.outbound_substreams.remove(outbound_id.get_ref())
                     {
+                        self.update_outbound_request_counter();
+                        observe_request_duration(
+                            &metrics::RPC_OUTBOUND_REQUEST_DURATION,
+                            proto,
+                            request_start_time,
+                        );
                         let outbound_err = HandlerErr::Outbound {
                             id: req_id,
                             proto,
@@ -731,6 +920,11 @@ where
                                 if let Some(ref delay_key) = info.delay_key {
                                     self.inbound_substreams_delay.remove(delay_key);
                                 }
+                                observe_request_duration(
+                                    &metrics::RPC_INBOUND_REQUEST_DURATION,
+                                    info.protocol,
+                                    info.request_start_time,
+                                );
 
                                 // BlocksByRange is the one that typically consumes the most time.
                                 // Its useful to log when the request was completed.
@@ -750,6 +944,11 @@ where
                                 if let Some(ref delay_key) = info.delay_key {
                                     self.inbound_substreams_delay.remove(delay_key);
                                 }
+                                observe_request_duration(
+                                    &metrics::RPC_INBOUND_REQUEST_DURATION,
+                                    info.protocol,
+                                    info.request_start_time,
+                                );
                                 // Report the error that occurred during the send process
                                 self.events_out.push(Err(HandlerErr::Inbound {
                                     error,
@@ -807,6 +1006,13 @@ where
                         id: entry.get().req_id,
                     }))
                 }
+                OutboundSubstreamState::RequestPendingResponse { substream, request }
+                    if self.unconsumed_chunks >= RESPONSE_CHUNK_WINDOW =>
+                {
+                    // wait for the application to take the chunks already read
+                    entry.get_mut().state =
+                        OutboundSubstreamState::RequestPendingResponse { substream, request }
+                }
                 OutboundSubstreamState::RequestPendingResponse {
                     mut substream,
                     request,
@@ -831,8 +1037,10 @@ where
                                         request,
                                     };
                                 substream_entry.remaining_chunks = Some(remaining_chunks);
-                                self.outbound_substreams_delay
-                                    .reset(delay_key, Duration::from_secs(RESPONSE_TIMEOUT));
+                                self.outbound_substreams_delay.reset(
+                                    delay_key,
+                                    response_timeout(&self.request_timeouts, substream_entry.proto),
+                                );
                             }
                         } else {
                             // either this is a single response request or this response closes the
@@ -840,6 +1048,18 @@ where
                             entry.get_mut().state = OutboundSubstreamState::Closing(substream);
                         }
 
+                        // Chunks of multiple-response protocols count against the response
+                        // window until the application takes them
+                        if matches!(response, RPCCodedResponse::Success(_))
+                            && response.multiple_responses()
+                        {
+                            self.unconsumed_chunks += 1;
+                            metrics::observe(
+                                &metrics::RPC_RESPONSE_CHUNKS_AHEAD,
+                                self.unconsumed_chunks as f64,
+                            );
+                        }
+
                         // Check what type of response we got and report it accordingly
                         let id = entry.get().req_id;
                         let proto = entry.get().proto;
@@ -867,7 +1087,13 @@ where
                         let delay_key = &entry.get().delay_key;
                         let request_id = entry.get().req_id;
                         self.outbound_substreams_delay.remove(delay_key);
-                        entry.remove_entry();
+                        let (_, info) = entry.remove_entry();
+                        self.update_outbound_request_counter();
+                        observe_request_duration(
+                            &metrics::RPC_OUTBOUND_REQUEST_DURATION,
+                            info.proto,
+                            info.request_start_time,
+                        );
                         // notify the application error
                         if request.expected_responses() > 1 {
                             // return an end of stream result
@@ -897,7 +1123,13 @@ where
                             proto: entry.get().proto,
                             error: e,
                         };
-                        entry.remove_entry();
+                        let (_, info) = entry.remove_entry();
+                        self.update_outbound_request_counter();
+                        observe_request_duration(
+                            &metrics::RPC_OUTBOUND_REQUEST_DURATION,
+                            info.proto,
+                            info.request_start_time,
+                        );
                         return Poll::Ready(ConnectionHandlerEvent::Custom(Err(outbound_err)));
                     }
                 },
@@ -909,7 +1141,13 @@ where
                             let protocol = entry.get().proto;
                             let request_id = entry.get().req_id;
                             self.outbound_substreams_delay.remove(delay_key);
-                            entry.remove_entry();
+                            let (_, info) = entry.remove_entry();
+                            self.update_outbound_request_counter();
+                            observe_request_duration(
+                                &metrics::RPC_OUTBOUND_REQUEST_DURATION,
+                                protocol,
+                                info.request_start_time,
+                            );
 
                             // report the stream termination to the user
                             //
@@ -920,6 +1158,7 @@ where
                             let termination = match protocol {
                                 Protocol::BlocksByRange => Some(ResponseTermination::BlocksByRange),
                                 Protocol::BlocksByRoot => Some(ResponseTermination::BlocksByRoot),
+                                Protocol::BlobsByRange => Some(ResponseTermination::BlobsByRange),
                                 _ => None, // all other protocols are do not have multiple responses and we do not inform the user, we simply drop the stream.
                             };
 
@@ -944,8 +1183,9 @@ where
         // establish outbound substreams
         if !self.dial_queue.is_empty() && self.dial_negotiated < self.max_dial_negotiated {
             self.dial_negotiated += 1;
-            let (id, req) = self.dial_queue.remove(0);
+            let (id, req, request_start_time) = self.dial_queue.remove(0);
             self.dial_queue.shrink_to_fit();
+            self.update_outbound_request_counter();
             return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest {
                 protocol: SubstreamProtocol::new(
                     OutboundRequestContainer {
@@ -955,7 +1195,7 @@ where
                     },
                     (),
                 )
-                .map_info(|()| (id, req)),
+                .map_info(|()| (id, req, request_start_time)),
             });
         }
 
@@ -975,6 +1215,26 @@ where
     }
 }
 
+/// How long we wait for each response chunk to a request of `protocol`.
+fn response_timeout(
+    request_timeouts: &HashMap<Protocol, Duration>,
+    protocol: Protocol,
+) -> Duration {
+    request_timeouts
+        .get(&protocol)
+        .copied()
+        .unwrap_or_else(|| Duration::from_secs(RESPONSE_TIMEOUT))
+}
+
+/// Records the time elapsed since `start` in the `histogram` of `protocol`.
+fn observe_request_duration(
+    histogram: &metrics::Result<metrics::HistogramVec>,
+    protocol: Protocol,
+    start: Instant,
+) {
+    metrics::observe_timer_vec(histogram, &[&protocol.to_string()], start.elapsed());
+}
+
 impl slog::Value for SubstreamId {
     fn serialize(
         &self,
@@ -986,6 +1246,22 @@ impl slog::Value for SubstreamId {
     }
 }
 
+/// Returns the size of a block response chunk, which counts against the response byte budget, and
+/// the termination of its response.
+fn budgeted_chunk<TSpec: EthSpec>(
+    response: &RPCCodedResponse<TSpec>,
+) -> Option<(usize, ResponseTermination)> {
+    match response {
+        RPCCodedResponse::Success(RPCResponse::BlocksByRange(block)) => {
+            Some((block.ssz_bytes_len(), ResponseTermination::BlocksByRange))
+        }
+        RPCCodedResponse::Success(RPCResponse::BlocksByRoot(block)) => {
+            Some((block.ssz_bytes_len(), ResponseTermination::BlocksByRoot))
+        }
+        _ => None,
+    }
+}
+
 /// Creates a future that can be polled that will send any queued message to the peer.
 ///
 /// This function returns the given substream, along with whether it has been closed or not. Any
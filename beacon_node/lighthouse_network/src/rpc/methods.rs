@@ -5,13 +5,13 @@ use regex::bytes::Regex;
 use serde::Serialize;
 use ssz_derive::{Decode, Encode};
 use ssz_types::{
-    typenum::{U1024, U256},
-    VariableList,
+    typenum::{U1024, U131072, U16, U256, U48, U5},
+    FixedVector, VariableList,
 };
 use std::ops::Deref;
 use strum::AsStaticStr;
 use superstruct::superstruct;
-use types::{Epoch, EthSpec, Hash256, SignedBeaconBlock, Slot};
+use types::{BeaconBlockHeader, Epoch, EthSpec, Hash256, SignedBeaconBlock, Slot, SyncCommittee};
 
 /// Maximum number of blocks in a single request.
 pub type MaxRequestBlocks = U1024;
@@ -21,6 +21,18 @@ pub const MAX_REQUEST_BLOCKS: u64 = 1024;
 pub type MaxErrorLen = U256;
 pub const MAX_ERROR_LEN: u64 = 256;
 
+/// Maximum number of blobs in a single `BlobsSidecar`.
+pub type MaxBlobsPerBlock = U16;
+
+/// Number of bytes in a blob, 4096 field elements of 32 bytes each.
+pub type BytesPerBlob = U131072;
+
+/// Number of bytes in a KZG proof.
+pub type BytesPerKzgProof = U48;
+
+/// Length of the merkle branch proving the current sync committee of a beacon state.
+pub type CurrentSyncCommitteeProofLen = U5;
+
 /// Wrapper over SSZ List to represent error message in rpc responses.
 #[derive(Debug, Clone)]
 pub struct ErrorType(pub VariableList<u8, MaxErrorLen>);
@@ -217,6 +229,48 @@ pub struct BlocksByRootRequest {
     pub block_roots: VariableList<Hash256, MaxRequestBlocks>,
 }
 
+/// Request the blobs sidecars of a range of slots from a peer.
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct BlobsByRangeRequest {
+    /// The starting slot to request blobs sidecars.
+    pub start_slot: u64,
+
+    /// The number of slots from the start slot.
+    pub count: u64,
+}
+
+/// The blobs of a beacon block, as sent in response to a BLOBS_BY_RANGE request.
+///
+/// The sidecar is not verified against its block, this is left to the application.
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct BlobsSidecar {
+    pub beacon_block_root: Hash256,
+    pub beacon_block_slot: Slot,
+    pub blobs: VariableList<FixedVector<u8, BytesPerBlob>, MaxBlobsPerBlock>,
+    pub kzg_aggregated_proof: FixedVector<u8, BytesPerKzgProof>,
+}
+
+/// Request the light client bootstrap of a trusted block root from a peer.
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct LightClientBootstrapRequest {
+    /// The root of the block to bootstrap from.
+    pub root: Hash256,
+}
+
+/// The data a light client needs to start syncing from a trusted block, as sent in response to a
+/// LIGHT_CLIENT_BOOTSTRAP request.
+///
+/// The bootstrap is not verified against the block root, this is left to the application.
+#[derive(Encode, Decode, Clone, Debug, PartialEq)]
+pub struct LightClientBootstrap<T: EthSpec> {
+    /// The header of the trusted block.
+    pub header: BeaconBlockHeader,
+    /// The current sync committee of the block's post-state.
+    pub current_sync_committee: SyncCommittee<T>,
+    /// The merkle branch of the current sync committee in the block's post-state.
+    pub current_sync_committee_branch: FixedVector<Hash256, CurrentSyncCommitteeProofLen>,
+}
+
 /* RPC Handling and Grouping */
 // Collection of enums and structs used by the Codecs to encode/decode RPC messages
 
@@ -232,6 +286,12 @@ pub enum RPCResponse<T: EthSpec> {
     /// A response to a get BLOCKS_BY_ROOT request.
     BlocksByRoot(Box<SignedBeaconBlock<T>>),
 
+    /// A response to a get BLOBS_BY_RANGE request.
+    BlobsByRange(Box<BlobsSidecar>),
+
+    /// A response to a LIGHT_CLIENT_BOOTSTRAP request.
+    LightClientBootstrap(Box<LightClientBootstrap<T>>),
+
     /// A PONG response to a PING request.
     Pong(Ping),
 
@@ -247,6 +307,9 @@ pub enum ResponseTermination {
 
     /// Blocks by root stream termination.
     BlocksByRoot,
+
+    /// Blobs by range stream termination.
+    BlobsByRange,
 }
 
 /// The structured response containing a result/code indicating success or failure
@@ -308,6 +371,8 @@ impl<T: EthSpec> RPCCodedResponse<T> {
                 RPCResponse::Status(_) => false,
                 RPCResponse::BlocksByRange(_) => true,
                 RPCResponse::BlocksByRoot(_) => true,
+                RPCResponse::BlobsByRange(_) => true,
+                RPCResponse::LightClientBootstrap(_) => false,
                 RPCResponse::Pong(_) => false,
                 RPCResponse::MetaData(_) => false,
             },
@@ -364,6 +429,21 @@ impl<T: EthSpec> std::fmt::Display for RPCResponse<T> {
             RPCResponse::BlocksByRoot(block) => {
                 write!(f, "BlocksByRoot: Block slot: {}", block.slot())
             }
+            RPCResponse::BlobsByRange(sidecar) => {
+                write!(
+                    f,
+                    "BlobsByRange: Block slot: {}, Blobs: {}",
+                    sidecar.beacon_block_slot,
+                    sidecar.blobs.len()
+                )
+            }
+            RPCResponse::LightClientBootstrap(bootstrap) => {
+                write!(
+                    f,
+                    "LightClientBootstrap: Header slot: {}",
+                    bootstrap.header.slot
+                )
+            }
             RPCResponse::Pong(ping) => write!(f, "Pong: {}", ping.data),
             RPCResponse::MetaData(metadata) => write!(f, "Metadata: {}", metadata.seq_number()),
         }
@@ -406,6 +486,18 @@ impl std::fmt::Display for BlocksByRangeRequest {
     }
 }
 
+impl std::fmt::Display for BlobsByRangeRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Start Slot: {}, Count: {}", self.start_slot, self.count)
+    }
+}
+
+impl std::fmt::Display for LightClientBootstrapRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Root: {}", self.root)
+    }
+}
+
 impl slog::KV for StatusMessage {
     fn serialize(
         &self,
@@ -4,17 +4,22 @@
 //! direct peer-to-peer communication primarily for sending/receiving chain information for
 //! syncing.
 
+use crate::NetworkGlobals;
 use futures::future::FutureExt;
 use handler::{HandlerEvent, RPCHandler};
 use libp2p::core::connection::ConnectionId;
+use libp2p::core::ConnectedPoint;
 use libp2p::swarm::{
     handler::ConnectionHandler, NetworkBehaviour, NetworkBehaviourAction, NotifyHandler,
     PollParameters, SubstreamProtocol,
 };
-use libp2p::PeerId;
-use rate_limiter::{RPCRateLimiter as RateLimiter, RPCRateLimiterBuilder, RateLimitedErr};
+use libp2p::{Multiaddr, PeerId};
+use rate_limiter::{RPCRateLimiter as RateLimiter, RPCRateLimiterBuilder};
+use self_rate_limiter::{SelfRateLimiter, MAX_QUEUED_REQUESTS_PER_PEER};
 use slog::{crit, debug, o};
+use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
@@ -26,18 +31,24 @@ pub(crate) use protocol::{InboundRequest, RPCProtocol};
 
 pub use handler::SubstreamId;
 pub use methods::{
-    BlocksByRangeRequest, BlocksByRootRequest, GoodbyeReason, MaxRequestBlocks,
-    RPCResponseErrorCode, ResponseTermination, StatusMessage, MAX_REQUEST_BLOCKS,
+    BlobsByRangeRequest, BlobsSidecar, BlocksByRangeRequest, BlocksByRootRequest, GoodbyeReason,
+    LightClientBootstrap, LightClientBootstrapRequest, MaxRequestBlocks, RPCResponseErrorCode,
+    ResponseTermination, StatusMessage, MAX_REQUEST_BLOCKS,
 };
 pub(crate) use outbound::OutboundRequest;
+pub(crate) use protocol::PROTOCOL_PREFIX;
 pub use protocol::{max_rpc_size, Protocol, RPCError};
+pub use rate_limiter::RateLimiterConfig;
+pub(crate) use rate_limiter::{Limiter, Quota, RateLimitedErr};
 
+pub use codec::ssz_snappy::MAX_SNAPPY_FRAME_SIZE;
 pub(crate) mod codec;
 mod handler;
 pub mod methods;
 mod outbound;
 mod protocol;
 mod rate_limiter;
+mod self_rate_limiter;
 
 /// Composite trait for a request id.
 pub trait ReqId: Send + 'static + std::fmt::Debug + Copy + Clone {}
@@ -59,6 +70,11 @@ pub enum RPCSend<Id, TSpec: EthSpec> {
     Response(SubstreamId, RPCCodedResponse<TSpec>),
     /// Lighthouse has requested to terminate the connection with a goodbye message.
     Shutdown(Id, GoodbyeReason),
+    /// The application has taken a response chunk received by the handler, allowing it to read
+    /// the next one.
+    ChunkConsumed,
+    /// The counter in which the handler accounts its outbound requests to the peer.
+    OutboundRequestCounter(Arc<AtomicUsize>),
 }
 
 /// RPC events received from outside Lighthouse.
@@ -85,6 +101,8 @@ impl<T: EthSpec, Id: std::fmt::Debug> std::fmt::Display for RPCSend<Id, T> {
             RPCSend::Request(id, req) => write!(f, "RPC Request(id: {:?}, {})", id, req),
             RPCSend::Response(id, res) => write!(f, "RPC Response(id: {:?}, {})", id, res),
             RPCSend::Shutdown(_id, reason) => write!(f, "Sending Goodbye: {}", reason),
+            RPCSend::ChunkConsumed => write!(f, "Response chunk consumed"),
+            RPCSend::OutboundRequestCounter(_) => write!(f, "Outbound request counter"),
         }
     }
 }
@@ -104,35 +122,70 @@ pub struct RPCMessage<Id, TSpec: EthSpec> {
 pub struct RPC<Id: ReqId, TSpec: EthSpec> {
     /// Rate limiter
     limiter: RateLimiter,
+    /// Quotas for our outbound requests. If set, each handler rate limits the requests it sends.
+    outbound_rate_limits: Option<RateLimiterConfig>,
     /// Queue of events to be processed.
     events: Vec<NetworkBehaviourAction<RPCMessage<Id, TSpec>, RPCHandler<Id, TSpec>>>,
     fork_context: Arc<ForkContext>,
+    /// The maximum number of block bytes sent in a single `BlocksByRange` or `BlocksByRoot`
+    /// response, if limited.
+    response_byte_budget: Option<usize>,
+    /// Maximum uncompressed bytes in each snappy frame of our responses.
+    snappy_frame_size: usize,
+    /// How long the responses to our requests of each protocol may take, if not the default.
+    request_timeouts: Arc<HashMap<Protocol, Duration>>,
+    /// The maximum number of inbound substreams of each connection awaiting a response, if
+    /// limited. As a peer has a single connection this bounds the substreams of each peer.
+    max_inbound_substreams: Option<usize>,
+    /// Holds the outbound request counters of the peers.
+    network_globals: Arc<NetworkGlobals<TSpec>>,
     /// Slog logger for RPC behaviour.
     log: slog::Logger,
 }
 
 impl<Id: ReqId, TSpec: EthSpec> RPC<Id, TSpec> {
-    pub fn new(fork_context: Arc<ForkContext>, log: slog::Logger) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        fork_context: Arc<ForkContext>,
+        rate_limits: Option<&RateLimiterConfig>,
+        outbound_rate_limits: Option<RateLimiterConfig>,
+        response_byte_budget: Option<usize>,
+        snappy_frame_size: usize,
+        request_timeouts: HashMap<Protocol, Duration>,
+        max_inbound_substreams: Option<usize>,
+        network_globals: Arc<NetworkGlobals<TSpec>>,
+        log: slog::Logger,
+    ) -> Result<Self, &'static str> {
         let log = log.new(o!("service" => "libp2p_rpc"));
-        let limiter = RPCRateLimiterBuilder::new()
-            .n_every(Protocol::MetaData, 2, Duration::from_secs(5))
-            .n_every(Protocol::Ping, 2, Duration::from_secs(10))
-            .n_every(Protocol::Status, 5, Duration::from_secs(15))
-            .one_every(Protocol::Goodbye, Duration::from_secs(10))
-            .n_every(
-                Protocol::BlocksByRange,
-                methods::MAX_REQUEST_BLOCKS,
-                Duration::from_secs(10),
-            )
-            .n_every(Protocol::BlocksByRoot, 128, Duration::from_secs(10))
-            .build()
-            .expect("Configuration parameters are valid");
-        RPC {
+        if snappy_frame_size == 0 || snappy_frame_size > MAX_SNAPPY_FRAME_SIZE {
+            return Err("The snappy frame size must be between 1 and 65536 bytes");
+        }
+        // check the outbound quotas now, since handlers can't report an invalid configuration
+        if let Some(outbound_rate_limits) = outbound_rate_limits.as_ref() {
+            SelfRateLimiter::<Id, TSpec>::new(
+                outbound_rate_limits,
+                MAX_QUEUED_REQUESTS_PER_PEER,
+                &log,
+            )?;
+        }
+        let mut limiter_builder = RPCRateLimiterBuilder::with_default_quotas();
+        // user supplied quotas take precedence over the defaults
+        if let Some(rate_limits) = rate_limits {
+            limiter_builder = limiter_builder.with_config(rate_limits);
+        }
+        let limiter = limiter_builder.build()?;
+        Ok(RPC {
             limiter,
+            outbound_rate_limits,
             events: Vec::new(),
             fork_context,
+            response_byte_budget,
+            snappy_frame_size,
+            request_timeouts: Arc::new(request_timeouts),
+            max_inbound_substreams,
+            network_globals,
             log,
-        }
+        })
     }
 
     /// Sends an RPC response.
@@ -151,6 +204,16 @@ impl<Id: ReqId, TSpec: EthSpec> RPC<Id, TSpec> {
         });
     }
 
+    /// Reports a response chunk read by the handler of `conn_id` as taken by the application,
+    /// which lets the handler read the next one.
+    pub fn chunk_consumed(&mut self, peer_id: PeerId, conn_id: ConnectionId) {
+        self.events.push(NetworkBehaviourAction::NotifyHandler {
+            peer_id,
+            handler: NotifyHandler::One(conn_id),
+            event: RPCSend::ChunkConsumed,
+        });
+    }
+
     /// Submits an RPC request.
     ///
     /// The peer must be connected for this to succeed.
@@ -182,20 +245,50 @@ where
     type OutEvent = RPCMessage<Id, TSpec>;
 
     fn new_handler(&mut self) -> Self::ConnectionHandler {
+        let self_limiter = self.outbound_rate_limits.as_ref().map(|config| {
+            SelfRateLimiter::new(config, MAX_QUEUED_REQUESTS_PER_PEER, &self.log)
+                .expect("Outbound rate limits are checked on construction")
+        });
         RPCHandler::new(
             SubstreamProtocol::new(
                 RPCProtocol {
                     fork_context: self.fork_context.clone(),
                     max_rpc_size: max_rpc_size(&self.fork_context),
+                    snappy_frame_size: self.snappy_frame_size,
                     phantom: PhantomData,
                 },
                 (),
             ),
             self.fork_context.clone(),
+            self_limiter,
+            self.response_byte_budget,
+            self.request_timeouts.clone(),
+            self.max_inbound_substreams,
             &self.log,
         )
     }
 
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        _endpoint: &ConnectedPoint,
+        _failed_addresses: Option<&Vec<Multiaddr>>,
+        _other_established: usize,
+    ) {
+        // let the handler account its outbound requests in the peer's counter
+        let counter = self
+            .network_globals
+            .peers
+            .write()
+            .outbound_request_counter(peer_id);
+        self.events.push(NetworkBehaviourAction::NotifyHandler {
+            peer_id: *peer_id,
+            handler: NotifyHandler::One(*connection_id),
+            event: RPCSend::OutboundRequestCounter(counter),
+        });
+    }
+
     fn inject_event(
         &mut self,
         peer_id: PeerId,
@@ -206,13 +299,24 @@ where
             // check if the request is conformant to the quota
             match self.limiter.allows(&peer_id, req) {
                 Ok(()) => {
-                    // send the event to the user
-                    self.events
-                        .push(NetworkBehaviourAction::GenerateEvent(RPCMessage {
+                    if matches!(req, InboundRequest::BlocksByRange(blocks_req) if blocks_req.count == 0)
+                    {
+                        // a request for no blocks is answered without reaching the application,
+                        // once it has been charged to the quota of the peer
+                        self.send_response(
                             peer_id,
-                            conn_id,
-                            event,
-                        }))
+                            (conn_id, *id),
+                            RPCCodedResponse::StreamTermination(ResponseTermination::BlocksByRange),
+                        );
+                    } else {
+                        // send the event to the user
+                        self.events
+                            .push(NetworkBehaviourAction::GenerateEvent(RPCMessage {
+                                peer_id,
+                                conn_id,
+                                event,
+                            }))
+                    }
                 }
                 Err(RateLimitedErr::TooLarge) => {
                     // we set the batch sizes, so this is a coding/config err for most protocols
@@ -8,8 +8,9 @@ use futures::future::BoxFuture;
 use futures::prelude::{AsyncRead, AsyncWrite};
 use futures::{FutureExt, StreamExt};
 use libp2p::core::{InboundUpgrade, ProtocolName, UpgradeInfo};
+use serde_derive::{Deserialize, Serialize};
 use ssz::Encode;
-use ssz_types::VariableList;
+use ssz_types::{typenum::Unsigned, FixedVector, VariableList};
 use std::io;
 use std::marker::PhantomData;
 use std::sync::Arc;
@@ -22,7 +23,7 @@ use tokio_util::{
 };
 use types::{
     BeaconBlock, BeaconBlockAltair, BeaconBlockBase, BeaconBlockMerge, EthSpec, ForkContext,
-    ForkName, Hash256, MainnetEthSpec, Signature, SignedBeaconBlock,
+    ForkName, Hash256, MainnetEthSpec, Signature, SignedBeaconBlock, Slot,
 };
 
 lazy_static! {
@@ -77,6 +78,22 @@ lazy_static! {
         ])
     .as_ssz_bytes()
     .len();
+    pub static ref BLOBS_SIDECAR_MIN: usize = BlobsSidecar {
+        beacon_block_root: Hash256::zero(),
+        beacon_block_slot: Slot::new(0),
+        blobs: VariableList::empty(),
+        kzg_aggregated_proof: FixedVector::default(),
+    }
+    .as_ssz_bytes()
+    .len();
+    pub static ref BLOBS_SIDECAR_MAX: usize = BlobsSidecar {
+        beacon_block_root: Hash256::zero(),
+        beacon_block_slot: Slot::new(0),
+        blobs: VariableList::from(vec![FixedVector::default(); MaxBlobsPerBlock::to_usize()]),
+        kzg_aggregated_proof: FixedVector::default(),
+    }
+    .as_ssz_bytes()
+    .len();
     pub static ref ERROR_TYPE_MIN: usize =
         VariableList::<u8, MaxErrorLen>::from(Vec::<u8>::new())
     .as_ssz_bytes()
@@ -97,7 +114,7 @@ pub(crate) const MAX_RPC_SIZE: usize = 1_048_576; // 1M
 /// The maximum bytes that can be sent across the RPC post-merge.
 pub(crate) const MAX_RPC_SIZE_POST_MERGE: usize = 10 * 1_048_576; // 10M
 /// The protocol prefix the RPC protocol id.
-const PROTOCOL_PREFIX: &str = "/eth2/beacon_chain/req";
+pub(crate) const PROTOCOL_PREFIX: &str = "/eth2/beacon_chain/req";
 /// Time allowed for the first byte of a request to arrive before we time out (Time To First Byte).
 const TTFB_TIMEOUT: u64 = 5;
 /// The number of seconds to wait for the first bytes of a request once a protocol has been
@@ -114,7 +131,7 @@ pub fn max_rpc_size(fork_context: &ForkContext) -> usize {
 }
 
 /// Protocol names to be used.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Protocol {
     /// The Status protocol name.
     Status,
@@ -124,6 +141,10 @@ pub enum Protocol {
     BlocksByRange,
     /// The `BlocksByRoot` protocol name.
     BlocksByRoot,
+    /// The `BlobsByRange` protocol name.
+    BlobsByRange,
+    /// The `LightClientBootstrap` protocol name.
+    LightClientBootstrap,
     /// The `Ping` protocol name.
     Ping,
     /// The `MetaData` protocol name.
@@ -152,6 +173,8 @@ impl std::fmt::Display for Protocol {
             Protocol::Goodbye => "goodbye",
             Protocol::BlocksByRange => "beacon_blocks_by_range",
             Protocol::BlocksByRoot => "beacon_blocks_by_root",
+            Protocol::BlobsByRange => "blobs_sidecars_by_range",
+            Protocol::LightClientBootstrap => "light_client_bootstrap",
             Protocol::Ping => "ping",
             Protocol::MetaData => "metadata",
         };
@@ -182,6 +205,8 @@ impl std::fmt::Display for Version {
 pub struct RPCProtocol<TSpec: EthSpec> {
     pub fork_context: Arc<ForkContext>,
     pub max_rpc_size: usize,
+    /// Maximum uncompressed bytes in each snappy frame of our responses.
+    pub snappy_frame_size: usize,
     pub phantom: PhantomData<TSpec>,
 }
 
@@ -199,6 +224,12 @@ impl<TSpec: EthSpec> UpgradeInfo for RPCProtocol<TSpec> {
             ProtocolId::new(Protocol::BlocksByRange, Version::V1, Encoding::SSZSnappy),
             ProtocolId::new(Protocol::BlocksByRoot, Version::V2, Encoding::SSZSnappy),
             ProtocolId::new(Protocol::BlocksByRoot, Version::V1, Encoding::SSZSnappy),
+            ProtocolId::new(Protocol::BlobsByRange, Version::V1, Encoding::SSZSnappy),
+            ProtocolId::new(
+                Protocol::LightClientBootstrap,
+                Version::V1,
+                Encoding::SSZSnappy,
+            ),
             ProtocolId::new(Protocol::Ping, Version::V1, Encoding::SSZSnappy),
             ProtocolId::new(Protocol::MetaData, Version::V2, Encoding::SSZSnappy),
             ProtocolId::new(Protocol::MetaData, Version::V1, Encoding::SSZSnappy),
@@ -260,6 +291,14 @@ impl ProtocolId {
             Protocol::BlocksByRoot => {
                 RpcLimits::new(*BLOCKS_BY_ROOT_REQUEST_MIN, *BLOCKS_BY_ROOT_REQUEST_MAX)
             }
+            Protocol::BlobsByRange => RpcLimits::new(
+                <BlobsByRangeRequest as Encode>::ssz_fixed_len(),
+                <BlobsByRangeRequest as Encode>::ssz_fixed_len(),
+            ),
+            Protocol::LightClientBootstrap => RpcLimits::new(
+                <LightClientBootstrapRequest as Encode>::ssz_fixed_len(),
+                <LightClientBootstrapRequest as Encode>::ssz_fixed_len(),
+            ),
             Protocol::Ping => RpcLimits::new(
                 <Ping as Encode>::ssz_fixed_len(),
                 <Ping as Encode>::ssz_fixed_len(),
@@ -269,7 +308,7 @@ impl ProtocolId {
     }
 
     /// Returns min and max size for messages of given protocol id responses.
-    pub fn rpc_response_limits<T: EthSpec>(&self) -> RpcLimits {
+    pub fn rpc_response_limits<T: EthSpec>(&self, fork_context: &ForkContext) -> RpcLimits {
         match self.message_name {
             Protocol::Status => RpcLimits::new(
                 <StatusMessage as Encode>::ssz_fixed_len(),
@@ -302,6 +341,15 @@ impl ProtocolId {
                     *SIGNED_BEACON_BLOCK_BASE_MAX,
                 ),
             ),
+            // a sidecar of the most blobs does not fit in an RPC chunk before the merge
+            Protocol::BlobsByRange => RpcLimits::new(
+                *BLOBS_SIDECAR_MIN,
+                std::cmp::min(*BLOBS_SIDECAR_MAX, max_rpc_size(fork_context)),
+            ),
+            Protocol::LightClientBootstrap => RpcLimits::new(
+                <LightClientBootstrap<T> as Encode>::ssz_fixed_len(),
+                <LightClientBootstrap<T> as Encode>::ssz_fixed_len(),
+            ),
 
             Protocol::Ping => RpcLimits::new(
                 <Ping as Encode>::ssz_fixed_len(),
@@ -323,7 +371,8 @@ impl ProtocolId {
                 _ => return false,
             }
         }
-        false
+        // Light client bootstraps carry context bytes from their first version
+        self.message_name == Protocol::LightClientBootstrap
     }
 }
 
@@ -375,11 +424,14 @@ where
             let socket = socket.compat();
             let codec = match protocol.encoding {
                 Encoding::SSZSnappy => {
-                    let ssz_snappy_codec = BaseInboundCodec::new(SSZSnappyInboundCodec::new(
-                        protocol,
-                        self.max_rpc_size,
-                        self.fork_context.clone(),
-                    ));
+                    let ssz_snappy_codec = BaseInboundCodec::new(
+                        SSZSnappyInboundCodec::new(
+                            protocol,
+                            self.max_rpc_size,
+                            self.fork_context.clone(),
+                        )
+                        .with_snappy_frame_size(self.snappy_frame_size),
+                    );
                     InboundCodec::SSZSnappy(ssz_snappy_codec)
                 }
             };
@@ -416,6 +468,8 @@ pub enum InboundRequest<TSpec: EthSpec> {
     Goodbye(GoodbyeReason),
     BlocksByRange(BlocksByRangeRequest),
     BlocksByRoot(BlocksByRootRequest),
+    BlobsByRange(BlobsByRangeRequest),
+    LightClientBootstrap(LightClientBootstrapRequest),
     Ping(Ping),
     MetaData(PhantomData<TSpec>),
 }
@@ -455,6 +509,16 @@ impl<TSpec: EthSpec> InboundRequest<TSpec> {
                 ProtocolId::new(Protocol::BlocksByRoot, Version::V2, Encoding::SSZSnappy),
                 ProtocolId::new(Protocol::BlocksByRoot, Version::V1, Encoding::SSZSnappy),
             ],
+            InboundRequest::BlobsByRange(_) => vec![ProtocolId::new(
+                Protocol::BlobsByRange,
+                Version::V1,
+                Encoding::SSZSnappy,
+            )],
+            InboundRequest::LightClientBootstrap(_) => vec![ProtocolId::new(
+                Protocol::LightClientBootstrap,
+                Version::V1,
+                Encoding::SSZSnappy,
+            )],
             InboundRequest::Ping(_) => vec![ProtocolId::new(
                 Protocol::Ping,
                 Version::V1,
@@ -476,6 +540,8 @@ impl<TSpec: EthSpec> InboundRequest<TSpec> {
             InboundRequest::Goodbye(_) => 0,
             InboundRequest::BlocksByRange(req) => req.count,
             InboundRequest::BlocksByRoot(req) => req.block_roots.len() as u64,
+            InboundRequest::BlobsByRange(req) => req.count,
+            InboundRequest::LightClientBootstrap(_) => 1,
             InboundRequest::Ping(_) => 1,
             InboundRequest::MetaData(_) => 1,
         }
@@ -488,6 +554,8 @@ impl<TSpec: EthSpec> InboundRequest<TSpec> {
             InboundRequest::Goodbye(_) => Protocol::Goodbye,
             InboundRequest::BlocksByRange(_) => Protocol::BlocksByRange,
             InboundRequest::BlocksByRoot(_) => Protocol::BlocksByRoot,
+            InboundRequest::BlobsByRange(_) => Protocol::BlobsByRange,
+            InboundRequest::LightClientBootstrap(_) => Protocol::LightClientBootstrap,
             InboundRequest::Ping(_) => Protocol::Ping,
             InboundRequest::MetaData(_) => Protocol::MetaData,
         }
@@ -501,8 +569,10 @@ impl<TSpec: EthSpec> InboundRequest<TSpec> {
             // variants that have `multiple_responses()` can have values.
             InboundRequest::BlocksByRange(_) => ResponseTermination::BlocksByRange,
             InboundRequest::BlocksByRoot(_) => ResponseTermination::BlocksByRoot,
+            InboundRequest::BlobsByRange(_) => ResponseTermination::BlobsByRange,
             InboundRequest::Status(_) => unreachable!(),
             InboundRequest::Goodbye(_) => unreachable!(),
+            InboundRequest::LightClientBootstrap(_) => unreachable!(),
             InboundRequest::Ping(_) => unreachable!(),
             InboundRequest::MetaData(_) => unreachable!(),
         }
@@ -537,6 +607,8 @@ pub enum RPCError {
     HandlerRejected,
     /// We have intentionally disconnected.
     Disconnected,
+    /// Too many of our requests to this peer are waiting for the outbound rate limit.
+    OutboundQueueFull,
 }
 
 impl From<ssz::DecodeError> for RPCError {
@@ -576,6 +648,7 @@ impl std::fmt::Display for RPCError {
             RPCError::NegotiationTimeout => write!(f, "Negotiation timeout"),
             RPCError::HandlerRejected => write!(f, "Handler rejected the request"),
             RPCError::Disconnected => write!(f, "Gracefully Disconnected"),
+            RPCError::OutboundQueueFull => write!(f, "Outbound request queue is full"),
         }
     }
 }
@@ -595,6 +668,7 @@ impl std::error::Error for RPCError {
             RPCError::NegotiationTimeout => None,
             RPCError::HandlerRejected => None,
             RPCError::Disconnected => None,
+            RPCError::OutboundQueueFull => None,
         }
     }
 }
@@ -606,6 +680,10 @@ impl<TSpec: EthSpec> std::fmt::Display for InboundRequest<TSpec> {
             InboundRequest::Goodbye(reason) => write!(f, "Goodbye: {}", reason),
             InboundRequest::BlocksByRange(req) => write!(f, "Blocks by range: {}", req),
             InboundRequest::BlocksByRoot(req) => write!(f, "Blocks by root: {:?}", req),
+            InboundRequest::BlobsByRange(req) => write!(f, "Blobs by range: {}", req),
+            InboundRequest::LightClientBootstrap(req) => {
+                write!(f, "Light client bootstrap: {}", req)
+            }
             InboundRequest::Ping(ping) => write!(f, "Ping: {}", ping.data),
             InboundRequest::MetaData(_) => write!(f, "MetaData request"),
         }
@@ -1,6 +1,9 @@
-use crate::rpc::{InboundRequest, Protocol};
+use super::outbound::OutboundRequest;
+use crate::rpc::{InboundRequest, Protocol, MAX_REQUEST_BLOCKS};
 use fnv::FnvHashMap;
 use libp2p::PeerId;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::future::Future;
 use std::hash::Hash;
@@ -55,24 +58,94 @@ pub struct Quota {
     max_tokens: u64,
 }
 
-/// Manages rate limiting of requests per peer, with differentiated rates per protocol.
-pub struct RPCRateLimiter {
+impl Quota {
+    /// A quota of `max_tokens` tokens replenished every `replenish_all_every`.
+    pub(crate) fn n_every(max_tokens: u64, replenish_all_every: Duration) -> Self {
+        Quota {
+            replenish_all_every,
+            max_tokens,
+        }
+    }
+}
+
+/// User supplied quotas for inbound RPC requests.
+///
+/// Each entry maps a protocol to the `(max_tokens, replenish_interval)` of its `Quota`. Protocols
+/// without an entry keep the built-in defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimiterConfig {
+    pub quotas: HashMap<Protocol, (u64, Duration)>,
+}
+
+/// Manages rate limiting of requests per key, with differentiated rates per protocol.
+///
+/// Inbound requests are keyed by the `PeerId` that sent them.
+pub struct RPCRateLimiter<Key: Hash + Eq + Clone = PeerId> {
     /// Interval to prune peers for which their timer ran out.
     prune_interval: Interval,
     /// Creation time of the rate limiter.
     init_time: Instant,
     /// Goodbye rate limiter.
-    goodbye_rl: Limiter<PeerId>,
+    goodbye_rl: Limiter<Key>,
     /// Ping rate limiter.
-    ping_rl: Limiter<PeerId>,
+    ping_rl: Limiter<Key>,
     /// MetaData rate limiter.
-    metadata_rl: Limiter<PeerId>,
+    metadata_rl: Limiter<Key>,
     /// Status rate limiter.
-    status_rl: Limiter<PeerId>,
+    status_rl: Limiter<Key>,
     /// BlocksByRange rate limiter.
-    bbrange_rl: Limiter<PeerId>,
+    bbrange_rl: Limiter<Key>,
     /// BlocksByRoot rate limiter.
-    bbroots_rl: Limiter<PeerId>,
+    bbroots_rl: Limiter<Key>,
+    /// BlobsByRange rate limiter.
+    blbrange_rl: Limiter<Key>,
+    /// LightClientBootstrap rate limiter.
+    lcbootstrap_rl: Limiter<Key>,
+}
+
+/// A request that can be rate limited.
+pub trait RateLimiterItem {
+    /// The protocol of the request.
+    fn protocol(&self) -> Protocol;
+    /// The number of responses the request asks for.
+    fn expected_responses(&self) -> u64;
+    /// The step of the request, if it is a `BlocksByRange` request.
+    fn blocks_by_range_step(&self) -> Option<u64>;
+}
+
+impl<T: EthSpec> RateLimiterItem for InboundRequest<T> {
+    fn protocol(&self) -> Protocol {
+        self.protocol()
+    }
+
+    fn expected_responses(&self) -> u64 {
+        self.expected_responses()
+    }
+
+    fn blocks_by_range_step(&self) -> Option<u64> {
+        match self {
+            InboundRequest::BlocksByRange(bbr_req) => Some(bbr_req.step),
+            _ => None,
+        }
+    }
+}
+
+impl<T: EthSpec> RateLimiterItem for OutboundRequest<T> {
+    fn protocol(&self) -> Protocol {
+        self.protocol()
+    }
+
+    fn expected_responses(&self) -> u64 {
+        self.expected_responses()
+    }
+
+    fn blocks_by_range_step(&self) -> Option<u64> {
+        match self {
+            OutboundRequest::BlocksByRange(bbr_req) => Some(bbr_req.step),
+            _ => None,
+        }
+    }
 }
 
 /// Error type for non conformant requests
@@ -98,6 +171,10 @@ pub struct RPCRateLimiterBuilder {
     bbrange_quota: Option<Quota>,
     /// Quota for the BlocksByRoot protocol.
     bbroots_quota: Option<Quota>,
+    /// Quota for the BlobsByRange protocol.
+    blbrange_quota: Option<Quota>,
+    /// Quota for the LightClientBootstrap protocol.
+    lcbootstrap_quota: Option<Quota>,
 }
 
 impl RPCRateLimiterBuilder {
@@ -106,6 +183,23 @@ impl RPCRateLimiterBuilder {
         Default::default()
     }
 
+    /// Get a `RPCRateLimiterBuilder` with Lighthouse's default quota set for every protocol.
+    pub fn with_default_quotas() -> Self {
+        Self::new()
+            .n_every(Protocol::MetaData, 2, Duration::from_secs(5))
+            .n_every(Protocol::Ping, 2, Duration::from_secs(10))
+            .n_every(Protocol::Status, 5, Duration::from_secs(15))
+            .one_every(Protocol::Goodbye, Duration::from_secs(10))
+            .n_every(
+                Protocol::BlocksByRange,
+                MAX_REQUEST_BLOCKS,
+                Duration::from_secs(10),
+            )
+            .n_every(Protocol::BlocksByRoot, 128, Duration::from_secs(10))
+            .n_every(Protocol::BlobsByRange, 128, Duration::from_secs(10))
+            .one_every(Protocol::LightClientBootstrap, Duration::from_secs(10))
+    }
+
     /// Set a quota for a protocol.
     fn set_quota(mut self, protocol: Protocol, quota: Quota) -> Self {
         let q = Some(quota);
@@ -116,6 +210,8 @@ impl RPCRateLimiterBuilder {
             Protocol::Goodbye => self.goodbye_quota = q,
             Protocol::BlocksByRange => self.bbrange_quota = q,
             Protocol::BlocksByRoot => self.bbroots_quota = q,
+            Protocol::BlobsByRange => self.blbrange_quota = q,
+            Protocol::LightClientBootstrap => self.lcbootstrap_quota = q,
         }
         self
     }
@@ -143,7 +239,16 @@ impl RPCRateLimiterBuilder {
         )
     }
 
-    pub fn build(self) -> Result<RPCRateLimiter, &'static str> {
+    /// Override the quotas of the protocols present in `config`. Quotas of other protocols are
+    /// left as they were.
+    pub fn with_config(mut self, config: &RateLimiterConfig) -> Self {
+        for (protocol, (max_tokens, replenish_interval)) in config.quotas.iter() {
+            self = self.n_every(*protocol, *max_tokens, *replenish_interval);
+        }
+        self
+    }
+
+    pub fn build<Key: Hash + Eq + Clone>(self) -> Result<RPCRateLimiter<Key>, &'static str> {
         // get our quotas
         let ping_quota = self.ping_quota.ok_or("Ping quota not specified")?;
         let metadata_quota = self.metadata_quota.ok_or("MetaData quota not specified")?;
@@ -155,6 +260,12 @@ impl RPCRateLimiterBuilder {
         let bbrange_quota = self
             .bbrange_quota
             .ok_or("BlocksByRange quota not specified")?;
+        let blbrange_quota = self
+            .blbrange_quota
+            .ok_or("BlobsByRange quota not specified")?;
+        let lcbootstrap_quota = self
+            .lcbootstrap_quota
+            .ok_or("LightClientBootstrap quota not specified")?;
 
         // create the rate limiters
         let ping_rl = Limiter::from_quota(ping_quota)?;
@@ -163,6 +274,8 @@ impl RPCRateLimiterBuilder {
         let goodbye_rl = Limiter::from_quota(goodbye_quota)?;
         let bbroots_rl = Limiter::from_quota(bbroots_quota)?;
         let bbrange_rl = Limiter::from_quota(bbrange_quota)?;
+        let blbrange_rl = Limiter::from_quota(blbrange_quota)?;
+        let lcbootstrap_rl = Limiter::from_quota(lcbootstrap_quota)?;
 
         // check for peers to prune every 30 seconds, starting in 30 seconds
         let prune_every = tokio::time::Duration::from_secs(30);
@@ -176,16 +289,18 @@ impl RPCRateLimiterBuilder {
             goodbye_rl,
             bbroots_rl,
             bbrange_rl,
+            blbrange_rl,
+            lcbootstrap_rl,
             init_time: Instant::now(),
         })
     }
 }
 
-impl RPCRateLimiter {
-    pub fn allows<T: EthSpec>(
+impl<Key: Hash + Eq + Clone> RPCRateLimiter<Key> {
+    pub fn allows<Item: RateLimiterItem>(
         &mut self,
-        peer_id: &PeerId,
-        request: &InboundRequest<T>,
+        key: &Key,
+        request: &Item,
     ) -> Result<(), RateLimitedErr> {
         let time_since_start = self.init_time.elapsed();
         let mut tokens = request.expected_responses().max(1);
@@ -207,13 +322,12 @@ impl RPCRateLimiter {
         //     9     |   4
         //     10    |   5
 
-        if let InboundRequest::BlocksByRange(bbr_req) = request {
-            let penalty_factor = (bbr_req.step as f64 / 5.0).powi(2) as u64 + 1;
-            tokens *= penalty_factor;
+        if let Some(step) = request.blocks_by_range_step() {
+            let penalty_factor = ((step as f64 / 5.0).powi(2) as u64).saturating_add(1);
+            tokens = tokens.saturating_mul(penalty_factor);
         }
 
-        let check =
-            |limiter: &mut Limiter<PeerId>| limiter.allows(time_since_start, peer_id, tokens);
+        let check = |limiter: &mut Limiter<Key>| limiter.allows(time_since_start, key, tokens);
         let limiter = match request.protocol() {
             Protocol::Ping => &mut self.ping_rl,
             Protocol::Status => &mut self.status_rl,
@@ -221,6 +335,8 @@ impl RPCRateLimiter {
             Protocol::Goodbye => &mut self.goodbye_rl,
             Protocol::BlocksByRange => &mut self.bbrange_rl,
             Protocol::BlocksByRoot => &mut self.bbroots_rl,
+            Protocol::BlobsByRange => &mut self.blbrange_rl,
+            Protocol::LightClientBootstrap => &mut self.lcbootstrap_rl,
         };
         check(limiter)
     }
@@ -233,10 +349,12 @@ impl RPCRateLimiter {
         self.goodbye_rl.prune(time_since_start);
         self.bbrange_rl.prune(time_since_start);
         self.bbroots_rl.prune(time_since_start);
+        self.blbrange_rl.prune(time_since_start);
+        self.lcbootstrap_rl.prune(time_since_start);
     }
 }
 
-impl Future for RPCRateLimiter {
+impl<Key: Hash + Eq + Clone + Unpin> Future for RPCRateLimiter<Key> {
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
@@ -291,7 +409,7 @@ impl<Key: Hash + Eq + Clone> Limiter<Key> {
         let tau = self.tau;
         let t = self.t;
         // how long does it take to replenish these tokens
-        let additional_time = t * tokens;
+        let additional_time = t.saturating_mul(tokens);
         if additional_time > tau {
             // the time required to process this amount of tokens is longer than the time that
             // makes the bucket full. So, this batch can _never_ be processed
@@ -328,8 +446,13 @@ impl<Key: Hash + Eq + Clone> Limiter<Key> {
 
 #[cfg(test)]
 mod tests {
-    use crate::rpc::rate_limiter::{Limiter, Quota};
+    use crate::rpc::rate_limiter::{
+        Limiter, Quota, RPCRateLimiterBuilder, RateLimitedErr, RateLimiterConfig,
+    };
+    use crate::rpc::{BlocksByRangeRequest, InboundRequest, Protocol, StatusMessage};
+    use libp2p::PeerId;
     use std::time::Duration;
+    use types::{Epoch, Hash256, MainnetEthSpec, Slot};
 
     #[test]
     fn it_works_a() {
@@ -396,4 +519,62 @@ mod tests {
             .allows(Duration::from_secs_f32(0.4), &key, 1)
             .is_err());
     }
+
+    #[tokio::test]
+    async fn configured_quotas_override_defaults() {
+        let mut config = RateLimiterConfig::default();
+        config
+            .quotas
+            .insert(Protocol::BlocksByRange, (2, Duration::from_secs(10)));
+
+        let mut limiter = RPCRateLimiterBuilder::with_default_quotas()
+            .with_config(&config)
+            .build()
+            .unwrap();
+        let peer_id = PeerId::random();
+
+        let bbrange: InboundRequest<MainnetEthSpec> =
+            InboundRequest::BlocksByRange(BlocksByRangeRequest {
+                start_slot: 0,
+                count: 1,
+                step: 1,
+            });
+        assert!(limiter.allows(&peer_id, &bbrange).is_ok());
+        assert!(limiter.allows(&peer_id, &bbrange).is_ok());
+        // the tiny configured quota is exhausted by the burst
+        assert!(matches!(
+            limiter.allows(&peer_id, &bbrange),
+            Err(RateLimitedErr::TooSoon(_))
+        ));
+
+        // protocols absent from the config keep their default quota
+        let status: InboundRequest<MainnetEthSpec> = InboundRequest::Status(StatusMessage {
+            fork_digest: [0; 4],
+            finalized_root: Hash256::zero(),
+            finalized_epoch: Epoch::new(0),
+            head_root: Hash256::zero(),
+            head_slot: Slot::new(0),
+        });
+        for _ in 0..5 {
+            assert!(limiter.allows(&peer_id, &status).is_ok());
+        }
+        assert!(limiter.allows(&peer_id, &status).is_err());
+    }
+
+    #[tokio::test]
+    async fn huge_blocks_by_range_step_is_too_large() {
+        let mut limiter = RPCRateLimiterBuilder::with_default_quotas()
+            .build()
+            .unwrap();
+        let bbrange: InboundRequest<MainnetEthSpec> =
+            InboundRequest::BlocksByRange(BlocksByRangeRequest {
+                start_slot: 0,
+                count: 1,
+                step: u64::MAX,
+            });
+        assert!(matches!(
+            limiter.allows(&PeerId::random(), &bbrange),
+            Err(RateLimitedErr::TooLarge)
+        ));
+    }
 }
@@ -0,0 +1,245 @@
+use super::handler::HandlerErr;
+use super::outbound::OutboundRequest;
+use super::protocol::{Protocol, RPCError};
+use super::rate_limiter::{
+    RPCRateLimiter as RateLimiter, RPCRateLimiterBuilder, RateLimitedErr, RateLimiterConfig,
+};
+use fnv::FnvHashMap;
+use futures::FutureExt;
+use slog::{crit, debug};
+use std::collections::{hash_map::Entry, VecDeque};
+use std::task::{Context, Poll};
+use tokio_util::time::DelayQueue;
+use types::EthSpec;
+
+/// The maximum number of outbound requests that can be queued for a single peer.
+pub const MAX_QUEUED_REQUESTS_PER_PEER: usize = 32;
+
+/// Rate limits the requests we send to a peer, so that we do not exceed the quotas the peer
+/// applies to its inbound requests.
+///
+/// Requests that do not fit in the quota of their protocol are queued and released once enough
+/// tokens have been replenished. Requests of the same protocol are released in the order they
+/// were made.
+pub(crate) struct SelfRateLimiter<Id, TSpec: EthSpec> {
+    /// Requests waiting for their protocol's quota, per protocol.
+    delayed_requests: FnvHashMap<Protocol, VecDeque<(Id, OutboundRequest<TSpec>)>>,
+    /// When to try releasing the queued requests of each protocol.
+    next_release: DelayQueue<Protocol>,
+    /// Requests released from the queue, ready to be sent.
+    ready_requests: VecDeque<Result<(Id, OutboundRequest<TSpec>), HandlerErr<Id>>>,
+    /// Rate limiter for our requests. Since only one peer is handled, there is a single key.
+    limiter: RateLimiter<()>,
+    /// Maximum number of queued requests across all protocols.
+    max_queued_requests: usize,
+    /// Logger for the outbound rate limiter.
+    log: slog::Logger,
+}
+
+impl<Id, TSpec: EthSpec> SelfRateLimiter<Id, TSpec> {
+    /// Creates a new `SelfRateLimiter`. Protocols that are not present in `config` are limited
+    /// with the default quotas.
+    pub fn new(
+        config: &RateLimiterConfig,
+        max_queued_requests: usize,
+        log: &slog::Logger,
+    ) -> Result<Self, &'static str> {
+        let limiter = RPCRateLimiterBuilder::with_default_quotas()
+            .with_config(config)
+            .build()?;
+        Ok(SelfRateLimiter {
+            delayed_requests: FnvHashMap::default(),
+            next_release: DelayQueue::new(),
+            ready_requests: VecDeque::new(),
+            limiter,
+            max_queued_requests,
+            log: log.clone(),
+        })
+    }
+
+    /// Checks if a request can be sent right away, in which case it is returned. Otherwise the
+    /// request is queued until its protocol's quota allows it.
+    pub fn allows(
+        &mut self,
+        id: Id,
+        req: OutboundRequest<TSpec>,
+    ) -> Result<Option<(Id, OutboundRequest<TSpec>)>, HandlerErr<Id>> {
+        let protocol = req.protocol();
+        // keep the requests ordered: if others are already waiting, this one waits as well
+        if self
+            .delayed_requests
+            .get(&protocol)
+            .map_or(false, |queued| !queued.is_empty())
+        {
+            return self.queue_request(id, req).map(|()| None);
+        }
+
+        match self.limiter.allows(&(), &req) {
+            Ok(()) => Ok(Some((id, req))),
+            Err(RateLimitedErr::TooSoon(wait_time)) => {
+                debug!(self.log, "Delaying outbound request"; "protocol" => %protocol, "wait_time_ms" => wait_time.as_millis());
+                self.queue_request(id, req)?;
+                self.next_release.insert(protocol, wait_time);
+                Ok(None)
+            }
+            Err(RateLimitedErr::TooLarge) => Err(Self::too_large(id, protocol, &self.log)),
+        }
+    }
+
+    /// The number of requests waiting to be released.
+    pub fn queued_requests(&self) -> usize {
+        self.delayed_requests.values().map(VecDeque::len).sum()
+    }
+
+    /// Removes and returns all requests that have not been sent yet.
+    pub fn drain(&mut self) -> Vec<(Id, OutboundRequest<TSpec>)> {
+        self.next_release.clear();
+        // errors are kept so that they are still reported
+        let (ready, failed): (VecDeque<_>, VecDeque<_>) =
+            self.ready_requests.drain(..).partition(Result::is_ok);
+        self.ready_requests = failed;
+        let mut pending: Vec<_> = ready.into_iter().filter_map(Result::ok).collect();
+        for (_protocol, queued) in self.delayed_requests.drain() {
+            pending.extend(queued);
+        }
+        pending
+    }
+
+    /// Returns the next request that can be sent, or an error for a queued request that can
+    /// never be sent.
+    pub fn poll_ready(
+        &mut self,
+        cx: &mut Context,
+    ) -> Poll<Result<(Id, OutboundRequest<TSpec>), HandlerErr<Id>>> {
+        while let Poll::Ready(Some(Ok(expired))) = self.next_release.poll_expired(cx) {
+            self.release_requests(expired.into_inner());
+        }
+
+        // let the rate limiter prune
+        let _ = self.limiter.poll_unpin(cx);
+
+        match self.ready_requests.pop_front() {
+            Some(released) => Poll::Ready(released),
+            None => Poll::Pending,
+        }
+    }
+
+    fn queue_request(&mut self, id: Id, req: OutboundRequest<TSpec>) -> Result<(), HandlerErr<Id>> {
+        if self.queued_requests() >= self.max_queued_requests {
+            return Err(HandlerErr::Outbound {
+                id,
+                proto: req.protocol(),
+                error: RPCError::OutboundQueueFull,
+            });
+        }
+        self.delayed_requests
+            .entry(req.protocol())
+            .or_default()
+            .push_back((id, req));
+        Ok(())
+    }
+
+    /// Releases as many queued requests of `protocol` as the quota allows, scheduling the next
+    /// release if any remain.
+    fn release_requests(&mut self, protocol: Protocol) {
+        if let Entry::Occupied(mut entry) = self.delayed_requests.entry(protocol) {
+            let queued = entry.get_mut();
+            while let Some((id, req)) = queued.pop_front() {
+                match self.limiter.allows(&(), &req) {
+                    Ok(()) => self.ready_requests.push_back(Ok((id, req))),
+                    Err(RateLimitedErr::TooSoon(wait_time)) => {
+                        queued.push_front((id, req));
+                        self.next_release.insert(protocol, wait_time);
+                        break;
+                    }
+                    Err(RateLimitedErr::TooLarge) => self
+                        .ready_requests
+                        .push_back(Err(Self::too_large(id, protocol, &self.log))),
+                }
+            }
+            if queued.is_empty() {
+                entry.remove();
+            }
+        }
+    }
+
+    fn too_large(id: Id, protocol: Protocol, log: &slog::Logger) -> HandlerErr<Id> {
+        // we set the batch sizes, so this is a coding/config err
+        crit!(log, "Outbound request too large to ever be sent"; "protocol" => %protocol);
+        HandlerErr::Outbound {
+            id,
+            proto: protocol,
+            error: RPCError::InternalError("Request exceeds the outbound rate limit"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SelfRateLimiter;
+    use crate::rpc::handler::HandlerErr;
+    use crate::rpc::rate_limiter::RateLimiterConfig;
+    use crate::rpc::{OutboundRequest, Ping, Protocol, RPCError};
+    use std::time::{Duration, Instant};
+    use types::MainnetEthSpec;
+
+    fn ping() -> OutboundRequest<MainnetEthSpec> {
+        OutboundRequest::Ping(Ping { data: 0 })
+    }
+
+    fn build_limiter(max_queued_requests: usize) -> SelfRateLimiter<usize, MainnetEthSpec> {
+        let mut config = RateLimiterConfig::default();
+        config
+            .quotas
+            .insert(Protocol::Ping, (1, Duration::from_millis(100)));
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        SelfRateLimiter::new(&config, max_queued_requests, &log).unwrap()
+    }
+
+    #[tokio::test]
+    async fn queued_requests_drain_over_time() {
+        let mut limiter = build_limiter(10);
+        let start = Instant::now();
+
+        // The first request fits the quota, the rest are queued.
+        assert!(matches!(limiter.allows(0, ping()), Ok(Some((0, _)))));
+        for id in 1..4 {
+            assert!(matches!(limiter.allows(id, ping()), Ok(None)));
+        }
+        assert_eq!(limiter.queued_requests(), 3);
+
+        // Queued requests are released in order, once the quota allows it.
+        for expected_id in 1..4 {
+            let released = tokio::time::timeout(
+                Duration::from_secs(2),
+                futures::future::poll_fn(|cx| limiter.poll_ready(cx)),
+            )
+            .await
+            .expect("Queued request should be released");
+            assert!(matches!(released, Ok((id, _)) if id == expected_id));
+        }
+        assert_eq!(limiter.queued_requests(), 0);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn full_queue_rejects_requests() {
+        let mut limiter = build_limiter(2);
+
+        assert!(matches!(limiter.allows(0, ping()), Ok(Some(_))));
+        assert!(matches!(limiter.allows(1, ping()), Ok(None)));
+        assert!(matches!(limiter.allows(2, ping()), Ok(None)));
+        assert!(matches!(
+            limiter.allows(3, ping()),
+            Err(HandlerErr::Outbound {
+                id: 3,
+                error: RPCError::OutboundQueueFull,
+                ..
+            })
+        ));
+
+        // Draining hands back every request that was not sent.
+        assert_eq!(limiter.drain().len(), 2);
+        assert_eq!(limiter.queued_requests(), 0);
+    }
+}
@@ -1,40 +1,73 @@
 use crate::behaviour::{
     save_metadata_to_disk, Behaviour, BehaviourEvent, PeerRequestId, Request, Response,
 };
-use crate::config::NetworkLoad;
-use crate::discovery::enr;
+use crate::config::{is_private_ip, NetworkLoad};
+use crate::discovery::{enr, DiscoveryTableStats};
+use crate::inbound_rate_limiter::InboundRateLimiter;
 use crate::multiaddr::Protocol;
+use crate::peer_bandwidth::BandwidthCountingMuxer;
 use crate::rpc::{GoodbyeReason, MetaData, MetaDataV1, MetaDataV2, RPCResponseErrorCode, ReqId};
-use crate::types::{error, EnrAttestationBitfield, EnrSyncCommitteeBitfield, GossipKind};
-use crate::EnrExt;
-use crate::{NetworkConfig, NetworkGlobals, PeerAction, ReportSource};
+use crate::subscription_rate_limiter::{ChangeOrigin, DeferredChange, SubscriptionRateLimiter};
+use crate::types::{
+    error, ApplicationCodec, EnrAttestationBitfield, EnrSyncCommitteeBitfield, GossipEncoding,
+    GossipKind, GossipTopic, Subnet, SubnetDiscovery, CORE_TOPICS,
+};
+use crate::{Enr, EnrExt};
+use crate::{
+    NetworkConfig, NetworkGlobals, PeerAction, PeerProtocolInfo, ReportSource, RequestInterceptor,
+    StatusHook,
+};
 use futures::prelude::*;
 use libp2p::core::{
-    identity::Keypair, multiaddr::Multiaddr, muxing::StreamMuxerBox, transport::Boxed,
+    identity::Keypair,
+    multiaddr::Multiaddr,
+    muxing::StreamMuxerBox,
+    transport::{Boxed, TransportError},
+    ConnectedPoint,
 };
+use libp2p::gossipsub::{error::PublishError, IdentTopic, MessageAcceptance, MessageId};
 use libp2p::{
     bandwidth::{BandwidthLogging, BandwidthSinks},
     core, noise,
-    swarm::{ConnectionLimits, SwarmBuilder, SwarmEvent},
+    swarm::{
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionLimits, DialError, SwarmBuilder, SwarmEvent,
+    },
     PeerId, Swarm, Transport,
 };
 use prometheus_client::registry::Registry;
+use rand::Rng;
 use slog::{crit, debug, info, o, trace, warn, Logger};
 use ssz::Decode;
+use ssz_types::typenum::Unsigned;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
-use types::{ChainSpec, EnrForkId, EthSpec, ForkContext};
+use tokio::sync::oneshot;
+use tokio::time::Sleep;
+use types::{ChainSpec, EnrForkId, EthSpec, ForkContext, SubnetId};
 
-use crate::peer_manager::{MIN_OUTBOUND_ONLY_FACTOR, PEER_EXCESS_FACTOR, PRIORITY_PEER_EXCESS};
+use crate::peer_manager::{
+    multiaddr_ip, MIN_OUTBOUND_ONLY_FACTOR, PEER_EXCESS_FACTOR, PRIORITY_PEER_EXCESS,
+};
 
 pub const NETWORK_KEY_FILENAME: &str = "key";
 /// The maximum simultaneous libp2p connections per peer.
 const MAX_CONNECTIONS_PER_PEER: u32 = 1;
 /// The filename to store our local metadata.
 pub const METADATA_FILENAME: &str = "metadata";
+/// How long `reset_network` waits for all the peers to disconnect before reconnecting to the
+/// boot nodes anyway. The RPC handlers close their connection at most 15 seconds after the
+/// goodbye, so peers left past this are new connections made during the reset.
+const RESET_RECONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+/// How long we wait for a boot node to connect before falling back to the next one, as long as we
+/// have no peers.
+const BOOT_NODE_DIAL_INTERVAL: Duration = Duration::from_secs(2);
 
 /// The types of events than can be obtained from polling the libp2p service.
 ///
@@ -47,6 +80,52 @@ pub enum Libp2pEvent<AppReqId: ReqId, TSpec: EthSpec> {
     NewListenAddr(Multiaddr),
     /// We reached zero listening addresses.
     ZeroListeners,
+    /// Enough peers have observed us at a new external address.
+    NewExternalAddr(Multiaddr),
+}
+
+/// The kinds of `Libp2pEvent`, by which `Service::set_event_filter` selects the events to return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// Peer connections, disconnections, bans and goodbyes, and gossipsub score changes.
+    Peer,
+    /// RPC requests and responses, and failed requests.
+    Rpc,
+    /// Gossipsub messages.
+    Gossip,
+    /// Requests to send a Status to a peer, and fork digest mismatches reported in one.
+    Status,
+    /// Changes to our listening and external addresses.
+    Listen,
+}
+
+impl<AppReqId: ReqId, TSpec: EthSpec> Libp2pEvent<AppReqId, TSpec> {
+    /// The kind of the event.
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Libp2pEvent::Behaviour(event) => match event {
+                BehaviourEvent::PeerConnectedOutgoing(_)
+                | BehaviourEvent::PeerConnectedIncoming(_)
+                | BehaviourEvent::PeerDisconnected(_)
+                | BehaviourEvent::PeerBanned(_)
+                | BehaviourEvent::PeerUnbanned(_)
+                | BehaviourEvent::GossipScoreThresholdCrossed { .. }
+                | BehaviourEvent::GoodbyeReceived { .. }
+                | BehaviourEvent::DiscoveredBannedPeer { .. } => EventKind::Peer,
+                BehaviourEvent::RPCFailed { .. }
+                | BehaviourEvent::RequestReceived { .. }
+                | BehaviourEvent::ResponseReceived { .. } => EventKind::Rpc,
+                BehaviourEvent::PubsubMessage { .. } => EventKind::Gossip,
+                BehaviourEvent::StatusPeer(_) | BehaviourEvent::ForkDigestMismatch { .. } => {
+                    EventKind::Status
+                }
+                BehaviourEvent::ExternalAddrConfirmed(_) => EventKind::Listen,
+            },
+            Libp2pEvent::NewListenAddr(_)
+            | Libp2pEvent::ZeroListeners
+            | Libp2pEvent::NewExternalAddr(_) => EventKind::Listen,
+        }
+    }
 }
 
 /// The configuration and state of the libp2p components for the beacon node.
@@ -59,6 +138,33 @@ pub struct Service<AppReqId: ReqId, TSpec: EthSpec> {
     pub local_peer_id: PeerId,
     /// The libp2p logger handle.
     pub log: Logger,
+    /// The highest target peer count `set_target_peers` accepts.
+    max_target_peers: usize,
+    /// How long `shutdown` waits for the goodbyes to connected peers to be sent.
+    shutdown_grace_period: Duration,
+    /// Whether gossip messages are held back rather than returned by `next_event`.
+    gossip_paused: bool,
+    /// Gossip messages received while gossip is paused, oldest first.
+    paused_gossip: VecDeque<BehaviourEvent<AppReqId, TSpec>>,
+    /// Maximum number of messages in `paused_gossip`.
+    paused_gossip_buffer_size: usize,
+    /// The kinds of events returned by `next_event`, if restricted.
+    event_filter: Option<HashSet<EventKind>>,
+    /// Limits the subscription changes of each topic, if configured.
+    subscription_limiter: Option<SubscriptionRateLimiter>,
+    /// The trusted peers given in the config, which stay trusted across reloads.
+    configured_trusted_peers: Vec<PeerId>,
+    /// The file the remaining trusted peers are read from, if any.
+    trusted_peers_file: Option<PathBuf>,
+    /// The addresses of the boot nodes with their dial weight, dialed again by `reset_network`.
+    boot_node_multiaddrs: Vec<(Vec<Multiaddr>, u32)>,
+    /// The addresses of the boot nodes not dialed yet, in dial order.
+    boot_node_queue: VecDeque<Vec<Multiaddr>>,
+    /// When to fall back to the next boot node of the queue, if we still have no peers.
+    boot_node_fallback: Option<Pin<Box<Sleep>>>,
+    /// Set while `reset_network` is waiting for all the peers to disconnect before reconnecting
+    /// to the boot nodes. It reconnects when the deadline expires even if peers are left.
+    reset_deadline: Option<Pin<Box<Sleep>>>,
 }
 
 pub struct Context<'a> {
@@ -75,6 +181,51 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
         ctx: Context<'_>,
         log: &Logger,
     ) -> error::Result<(Arc<NetworkGlobals<TSpec>>, Self)> {
+        let config = ctx.config;
+        // TCP is the only transport, nothing can be built without it
+        if config.disable_tcp {
+            return Err("No libp2p transport is enabled: TCP is disabled".into());
+        }
+        let tcp = libp2p::tcp::TokioTcpConfig::new().nodelay(true);
+        let transport = libp2p::dns::TokioDnsConfig::system(tcp)
+            .map_err(|e| format!("Failed to build transport: {:?}", e))?;
+        #[cfg(feature = "libp2p-websocket")]
+        let transport = {
+            let trans_clone = transport.clone();
+            transport.or_transport(libp2p::websocket::WsConfig::new(trans_clone))
+        };
+
+        let listen_multiaddrs = config
+            .listen_addresses()
+            .map(|listen_address| {
+                let mut m = Multiaddr::from(listen_address);
+                m.push(Protocol::Tcp(config.libp2p_port));
+                m
+            })
+            .collect();
+
+        Self::new_with_transport(executor, ctx, transport, listen_multiaddrs, log).await
+    }
+
+    /// Builds the service over `transport` instead of TCP, listening on `listen_multiaddrs`.
+    ///
+    /// The connections of the transport are still authenticated with noise and multiplexed, so
+    /// tests can run the service over a `MemoryTransport` without binding any socket.
+    pub async fn new_with_transport<T>(
+        executor: task_executor::TaskExecutor,
+        ctx: Context<'_>,
+        transport: T,
+        listen_multiaddrs: Vec<Multiaddr>,
+        log: &Logger,
+    ) -> error::Result<(Arc<NetworkGlobals<TSpec>>, Self)>
+    where
+        T: Transport + Send + Unpin + 'static,
+        T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        T::Error: std::error::Error + Send + Sync + 'static,
+        T::Listener: Send + 'static,
+        T::ListenerUpgrade: Send + 'static,
+        T::Dial: Send + 'static,
+    {
         let log = log.new(o!("service"=> "libp2p"));
         trace!(log, "Libp2p Service starting");
 
@@ -91,19 +242,31 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
         // Construct the metadata
         let meta_data = load_or_build_metadata(&config.network_dir, &log);
 
+        let configured_trusted_peers: Vec<PeerId> = config
+            .trusted_peers
+            .iter()
+            .map(|x| PeerId::from(x.clone()))
+            .collect();
+        let mut trusted_peers = configured_trusted_peers.clone();
+        if let Some(path) = &config.trusted_peers_file {
+            trusted_peers.extend(load_trusted_peers_file(path)?);
+        }
+
         // set up a collection of variables accessible outside of the network crate
         let network_globals = Arc::new(NetworkGlobals::new(
             enr.clone(),
             config.libp2p_port,
             config.discovery_port,
             meta_data,
-            config
-                .trusted_peers
-                .iter()
-                .map(|x| PeerId::from(x.clone()))
-                .collect(),
+            trusted_peers,
             &log,
         ));
+        network_globals
+            .target_peers
+            .store(config.target_peers, Ordering::Relaxed);
+        let max_target_peers = config
+            .max_target_peers
+            .map_or(config.target_peers, |max| max.max(config.target_peers));
 
         info!(log, "Libp2p Starting"; "peer_id" => %enr.peer_id(), "bandwidth_config" => format!("{}-{}", config.network_load, NetworkLoad::from(config.network_load).name));
         let discovery_string = if config.disable_discovery {
@@ -111,12 +274,16 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
         } else {
             config.discovery_port.to_string()
         };
-        debug!(log, "Attempting to open listening ports"; "address" => ?config.listen_address, "tcp_port" => config.libp2p_port, "udp_port" => discovery_string);
+        debug!(log, "Attempting to open listening ports"; "addresses" => ?listen_multiaddrs, "udp_port" => discovery_string);
 
         let (mut swarm, bandwidth) = {
-            // Set up the transport - tcp/ws with noise and mplex
-            let (transport, bandwidth) = build_transport(local_keypair.clone())
-                .map_err(|e| format!("Failed to build transport: {:?}", e))?;
+            // Set up the transport - noise and mplex over the given transport
+            let (transport, bandwidth) = build_transport(
+                transport,
+                local_keypair.clone(),
+                config,
+                network_globals.clone(),
+            )?;
 
             // Lighthouse network behaviour
             let behaviour =
@@ -130,20 +297,21 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
                 }
             }
 
-            // sets up the libp2p connection limits
+            // sets up the libp2p connection limits. They are sized for the highest target the
+            // peer count may be raised to, the peer manager enforcing the current target.
             let limits = ConnectionLimits::default()
                 .with_max_pending_incoming(Some(5))
                 .with_max_pending_outgoing(Some(16))
                 .with_max_established_incoming(Some(
-                    (config.target_peers as f32
+                    (max_target_peers as f32
                         * (1.0 + PEER_EXCESS_FACTOR - MIN_OUTBOUND_ONLY_FACTOR))
                         .ceil() as u32,
                 ))
                 .with_max_established_outgoing(Some(
-                    (config.target_peers as f32 * (1.0 + PEER_EXCESS_FACTOR)).ceil() as u32,
+                    (max_target_peers as f32 * (1.0 + PEER_EXCESS_FACTOR)).ceil() as u32,
                 ))
                 .with_max_established(Some(
-                    (config.target_peers as f32 * (1.0 + PEER_EXCESS_FACTOR + PRIORITY_PEER_EXCESS))
+                    (max_target_peers as f32 * (1.0 + PEER_EXCESS_FACTOR + PRIORITY_PEER_EXCESS))
                         .ceil() as u32,
                 ))
                 .with_max_established_per_peer(Some(MAX_CONNECTIONS_PER_PEER));
@@ -159,68 +327,63 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
             )
         };
 
-        // listen on the specified address
-        let listen_multiaddr = {
-            let mut m = Multiaddr::from(config.listen_address);
-            m.push(Protocol::Tcp(config.libp2p_port));
-            m
-        };
-
-        match Swarm::listen_on(&mut swarm, listen_multiaddr.clone()) {
-            Ok(_) => {
-                let mut log_address = listen_multiaddr;
-                log_address.push(Protocol::P2p(local_peer_id.into()));
-                info!(log, "Listening established"; "address" => %log_address);
-            }
-            Err(err) => {
-                crit!(
-                    log,
-                    "Unable to listen on libp2p address";
-                    "error" => ?err,
-                    "listen_multiaddr" => %listen_multiaddr,
-                );
-                return Err("Libp2p was unable to listen on the given listen address.".into());
-            }
-        };
-
-        // helper closure for dialing peers
-        let mut dial = |mut multiaddr: Multiaddr| {
-            // strip the p2p protocol if it exists
-            strip_peer_id(&mut multiaddr);
-            match Swarm::dial(&mut swarm, multiaddr.clone()) {
-                Ok(()) => debug!(log, "Dialing libp2p peer"; "address" => %multiaddr),
-                Err(err) => debug!(
-                    log,
-                    "Could not connect to peer"; "address" => %multiaddr, "error" => ?err
-                ),
+        // listen on the specified addresses
+        for listen_multiaddr in listen_multiaddrs {
+            match Swarm::listen_on(&mut swarm, listen_multiaddr.clone()) {
+                Ok(_) => {
+                    let mut log_address = listen_multiaddr;
+                    log_address.push(Protocol::P2p(local_peer_id.into()));
+                    info!(log, "Listening established"; "address" => %log_address);
+                }
+                Err(err) => {
+                    crit!(
+                        log,
+                        "Unable to listen on libp2p address";
+                        "error" => ?err,
+                        "listen_multiaddr" => %listen_multiaddr,
+                    );
+                    return Err("Libp2p was unable to listen on the given listen address.".into());
+                }
             };
-        };
+        }
 
         // attempt to connect to user-input libp2p nodes
         for multiaddr in &config.libp2p_nodes {
-            dial(multiaddr.clone());
+            dial_address(&mut swarm, multiaddr.clone(), &log);
         }
 
-        // attempt to connect to any specified boot-nodes
+        // connect to the specified boot-nodes one at a time, in a weighted random order so that the
+        // first boot nodes listed are not always dialed first
+        let boot_node_weight = |peer_id: Option<PeerId>| {
+            peer_id
+                .and_then(|peer_id| {
+                    config
+                        .boot_node_weights
+                        .iter()
+                        .find(|(id, _)| PeerId::from(id.clone()) == peer_id)
+                        .map(|(_, weight)| *weight)
+                })
+                .unwrap_or(DEFAULT_BOOT_NODE_WEIGHT)
+        };
         let mut boot_nodes = config.boot_nodes_enr.clone();
         boot_nodes.dedup();
 
+        let mut boot_node_multiaddrs = Vec::new();
         for bootnode_enr in boot_nodes {
-            for multiaddr in &bootnode_enr.multiaddr() {
-                // ignore udp multiaddr if it exists
-                let components = multiaddr.iter().collect::<Vec<_>>();
-                if let Protocol::Udp(_) = components[1] {
-                    continue;
-                }
-
-                if !network_globals
-                    .peers
-                    .read()
-                    .is_connected_or_dialing(&bootnode_enr.peer_id())
-                {
-                    dial(multiaddr.clone());
-                }
+            if network_globals
+                .peers
+                .read()
+                .is_connected_or_dialing(&bootnode_enr.peer_id())
+            {
+                continue;
             }
+            let multiaddrs = bootnode_enr
+                .multiaddr()
+                .into_iter()
+                // ignore udp multiaddr if it exists
+                .filter(|multiaddr| !matches!(multiaddr.iter().nth(1), Some(Protocol::Udp(_))))
+                .collect::<Vec<_>>();
+            boot_node_multiaddrs.push((multiaddrs, boot_node_weight(Some(bootnode_enr.peer_id()))));
         }
 
         for multiaddr in &config.boot_nodes_multiaddr {
@@ -229,10 +392,17 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
                 .iter()
                 .any(|proto| matches!(proto, Protocol::Tcp(_)))
             {
-                dial(multiaddr.clone());
+                let peer_id = multiaddr.iter().find_map(|proto| match proto {
+                    Protocol::P2p(multihash) => PeerId::from_multihash(multihash).ok(),
+                    _ => None,
+                });
+                boot_node_multiaddrs.push((vec![multiaddr.clone()], boot_node_weight(peer_id)));
             }
         }
 
+        let boot_node_queue =
+            weighted_dial_order(boot_node_multiaddrs.clone(), &mut rand::thread_rng()).into();
+
         let mut subscribed_topics: Vec<GossipKind> = vec![];
 
         for topic_kind in &config.topics {
@@ -247,12 +417,35 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
             info!(log, "Subscribed to topics"; "topics" => ?subscribed_topics);
         }
 
-        let service = Service {
+        // drop any subscription the topic filter does not permit
+        swarm.behaviour_mut().unsubscribe_filtered_topics();
+
+        let subscription_limiter = config
+            .subscription_change_limit
+            .map(SubscriptionRateLimiter::new)
+            .transpose()
+            .map_err(|e| format!("Invalid subscription change limit: {}", e))?;
+
+        let mut service = Service {
             swarm,
             bandwidth,
             local_peer_id,
             log,
+            max_target_peers,
+            shutdown_grace_period: config.shutdown_grace_period,
+            gossip_paused: false,
+            paused_gossip: VecDeque::new(),
+            paused_gossip_buffer_size: config.paused_gossip_buffer_size,
+            event_filter: None,
+            subscription_limiter,
+            configured_trusted_peers,
+            trusted_peers_file: config.trusted_peers_file.clone(),
+            boot_node_multiaddrs,
+            boot_node_queue,
+            boot_node_fallback: None,
+            reset_deadline: None,
         };
+        service.dial_next_boot_node();
 
         Ok((network_globals, service))
     }
@@ -277,6 +470,26 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
             .send_error_reponse(peer_id, id, error, reason);
     }
 
+    /// Sets whether inbound `BlocksByRange` and `BlocksByRoot` requests are served. While unset,
+    /// they are answered with a `ServerError`.
+    pub fn set_serving_requests(&mut self, serving: bool) {
+        self.swarm.behaviour_mut().set_serving_requests(serving);
+    }
+
+    /// Sets the interceptor inspecting the inbound requests before they reach the application.
+    /// Requests it rejects are answered with an error and not returned by `next_event`.
+    pub fn set_request_interceptor(&mut self, interceptor: Option<RequestInterceptor>) {
+        self.swarm
+            .behaviour_mut()
+            .set_request_interceptor(interceptor);
+    }
+
+    /// Sets the hook through which the application can inspect and override the Status messages
+    /// sent to peers, for instance on a custom network extending the handshake. `None` removes it.
+    pub fn set_status_hook(&mut self, hook: Option<StatusHook>) {
+        self.swarm.behaviour_mut().set_status_hook(hook);
+    }
+
     /// Report a peer's action.
     pub fn report_peer(
         &mut self,
@@ -298,6 +511,167 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
             .goodbye_peer(peer_id, reason, source);
     }
 
+    /// Changes the number of peers the node aims to be connected to. Peers in excess of the new
+    /// target are pruned immediately, starting with the lowest scored ones. Fails if the target
+    /// is above the configured `max_target_peers`, which the connection limits are sized for.
+    pub fn set_target_peers(&mut self, target_peers: usize) -> Result<(), String> {
+        if target_peers > self.max_target_peers {
+            return Err(format!(
+                "Target peers {} exceeds the maximum of {}",
+                target_peers, self.max_target_peers
+            ));
+        }
+        self.swarm
+            .behaviour_mut()
+            .peer_manager_mut()
+            .set_target_peers(target_peers);
+        Ok(())
+    }
+
+    /// Enables or disables the trusted peers only mode. While enabled, only the trusted peers may
+    /// connect to us or be dialed and our discovery queries are suspended. The peers already
+    /// connected are kept.
+    pub fn set_trusted_peers_only(&mut self, enabled: bool) {
+        let behaviour = self.swarm.behaviour_mut();
+        behaviour.peer_manager_mut().set_trusted_peers_only(enabled);
+        behaviour.discovery_mut().set_queries_suspended(enabled);
+    }
+
+    /// Drops all our connections to start over from the boot nodes, keeping the local ENR, key
+    /// and gossipsub subscriptions.
+    ///
+    /// All connected peers are sent a goodbye and the queued dials are dropped. Once every peer
+    /// has disconnected, or at the latest after `RESET_RECONNECT_TIMEOUT`, discovery is reseeded
+    /// from the boot nodes and they are dialed again. The gossipsub mesh is rebuilt as peers
+    /// reconnect.
+    pub fn reset_network(&mut self) {
+        let behaviour = self.swarm.behaviour_mut();
+        let peers: Vec<PeerId> = behaviour
+            .network_globals()
+            .peers
+            .read()
+            .connected_peer_ids()
+            .cloned()
+            .collect();
+        info!(self.log, "Resetting the network"; "connected_peers" => peers.len());
+        let peer_manager = behaviour.peer_manager_mut();
+        peer_manager.clear_dial_queue();
+        peer_manager.disconnect_peers(&peers, GoodbyeReason::Unknown);
+        self.reset_deadline = Some(Box::pin(tokio::time::sleep(RESET_RECONNECT_TIMEOUT)));
+        self.reconnect_after_reset();
+    }
+
+    /// Reconnects to the boot nodes if a reset is pending and all the connections have closed.
+    fn reconnect_after_reset(&mut self) {
+        if self.reset_deadline.is_none() || self.swarm.network_info().num_peers() > 0 {
+            return;
+        }
+        self.reconnect_to_boot_nodes();
+    }
+
+    /// Ends a pending reset by reseeding discovery and dialing the boot nodes.
+    fn reconnect_to_boot_nodes(&mut self) {
+        self.reset_deadline = None;
+        debug!(self.log, "Reconnecting to the boot nodes after a reset";
+            "connected_peers" => self.swarm.network_info().num_peers());
+        self.swarm
+            .behaviour_mut()
+            .discovery_mut()
+            .reseed_boot_nodes();
+        self.boot_node_queue =
+            weighted_dial_order(self.boot_node_multiaddrs.clone(), &mut rand::thread_rng()).into();
+        self.dial_next_boot_node();
+    }
+
+    /// Dials the next boot node of the queue. The one after it is dialed if we still have no
+    /// peers `BOOT_NODE_DIAL_INTERVAL` later, in case this one fails to connect.
+    fn dial_next_boot_node(&mut self) {
+        self.boot_node_fallback = None;
+        // boot nodes without a TCP address can't be dialed
+        while let Some(multiaddrs) = self.boot_node_queue.pop_front() {
+            if multiaddrs.is_empty() {
+                continue;
+            }
+            for multiaddr in multiaddrs {
+                dial_address(&mut self.swarm, multiaddr, &self.log);
+            }
+            if !self.boot_node_queue.is_empty() {
+                self.boot_node_fallback =
+                    Some(Box::pin(tokio::time::sleep(BOOT_NODE_DIAL_INTERVAL)));
+            }
+            return;
+        }
+    }
+
+    /// Dials the next boot node if none of the boot nodes dialed so far has connected, and drops
+    /// the remaining ones otherwise. A boot node counts as connected once we have any outbound
+    /// connection, the peers that dialed us not telling that the network can be reached.
+    fn fall_back_to_next_boot_node(&mut self) {
+        let has_outbound_peer = self
+            .swarm
+            .behaviour()
+            .network_globals()
+            .peers
+            .read()
+            .connected_peers()
+            .any(|(_, info)| info.connections().1 > 0);
+        if has_outbound_peer {
+            self.boot_node_fallback = None;
+            self.boot_node_queue.clear();
+        } else {
+            self.dial_next_boot_node();
+        }
+    }
+
+    /// Reads the trusted peers file again and replaces the trusted peers with the ones it lists
+    /// and the ones given in the config. Peers that are no longer trusted are scored from now on.
+    ///
+    /// If the file can't be read the trusted peers are left unchanged.
+    pub fn reload_trusted_peers(&mut self) -> error::Result<()> {
+        let mut trusted_peers = self.configured_trusted_peers.clone();
+        if let Some(path) = &self.trusted_peers_file {
+            trusted_peers.extend(load_trusted_peers_file(path)?);
+        }
+        info!(self.log, "Reloaded trusted peers"; "count" => trusted_peers.len());
+        self.swarm
+            .behaviour()
+            .network_globals()
+            .peers
+            .write()
+            .set_trusted_peers(trusted_peers);
+        Ok(())
+    }
+
+    /// Sends a goodbye with the given reason to each of the connected `peers` and disconnects
+    /// them. Unlike `goodbye_peer`, the peers are not penalized.
+    pub fn goodbye_peers(&mut self, peers: &[PeerId], reason: GoodbyeReason) {
+        self.swarm
+            .behaviour_mut()
+            .peer_manager_mut()
+            .disconnect_peers(peers, reason);
+    }
+
+    /// Sends a `ClientShutdown` goodbye to all connected peers and drives the swarm until they
+    /// have disconnected, for at most the configured shutdown grace period.
+    pub async fn shutdown(&mut self) {
+        self.swarm
+            .behaviour_mut()
+            .peer_manager_mut()
+            .disconnect_all_peers(GoodbyeReason::ClientShutdown);
+
+        let grace_period = tokio::time::sleep(self.shutdown_grace_period);
+        tokio::pin!(grace_period);
+        while self.swarm.network_info().num_peers() > 0 {
+            tokio::select! {
+                _ = &mut grace_period => {
+                    debug!(self.log, "Shutdown grace period expired"; "connected_peers" => self.swarm.network_info().num_peers());
+                    break;
+                }
+                _ = self.next_event() => {}
+            }
+        }
+    }
+
     /// Sends a response to a peer's request.
     pub fn send_response(&mut self, peer_id: PeerId, id: PeerRequestId, response: Response<TSpec>) {
         self.swarm
@@ -305,10 +679,459 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
             .send_successful_response(peer_id, id, response);
     }
 
+    /// Dials a peer by its multiaddr.
+    ///
+    /// The multiaddr must end with a `/p2p/` component holding the peer id of the node to dial.
+    pub fn dial_multiaddr(&mut self, mut addr: Multiaddr) -> Result<(), DialError> {
+        let peer_id = match addr.iter().last() {
+            Some(Protocol::P2p(multihash)) => {
+                PeerId::from_multihash(multihash).map_err(DialError::InvalidPeerId)?
+            }
+            _ => {
+                debug!(self.log, "Not dialing multiaddr without a peer id"; "address" => %addr);
+                return Err(DialError::Transport(vec![(
+                    addr.clone(),
+                    TransportError::MultiaddrNotSupported(addr),
+                )]));
+            }
+        };
+        strip_peer_id(&mut addr);
+
+        debug!(self.log, "Dialing libp2p peer"; "peer_id" => %peer_id, "address" => %addr);
+        self.swarm
+            .behaviour_mut()
+            .peer_manager_mut()
+            .count_dial_attempt(Some(peer_id));
+        self.swarm.dial(
+            DialOpts::peer_id(peer_id)
+                .condition(PeerCondition::Disconnected)
+                .addresses(vec![addr])
+                .build(),
+        )
+    }
+
+    /// Subscribes to the gossipsub topic of an attestation subnet, advertises the subnet in the
+    /// `attnets` bitfield of our ENR and starts searching for peers on it.
+    ///
+    /// A change over the `subscription_change_limit` of the topic is applied once the limit
+    /// allows.
+    pub fn subscribe_to_subnet(&mut self, subnet_id: SubnetId) -> error::Result<()> {
+        Self::check_attestation_subnet(subnet_id)?;
+        let topic = self
+            .swarm
+            .behaviour()
+            .kind_topic(GossipKind::Attestation(subnet_id));
+        self.change_subnet_subscription(topic, subnet_id, true);
+        Ok(())
+    }
+
+    /// Unsubscribes from the gossipsub topic of an attestation subnet and removes the subnet from
+    /// the `attnets` bitfield of our ENR.
+    ///
+    /// A change over the `subscription_change_limit` of the topic is applied once the limit
+    /// allows.
+    pub fn unsubscribe_from_subnet(&mut self, subnet_id: SubnetId) -> error::Result<()> {
+        Self::check_attestation_subnet(subnet_id)?;
+        let topic = self
+            .swarm
+            .behaviour()
+            .kind_topic(GossipKind::Attestation(subnet_id));
+        self.change_subnet_subscription(topic, subnet_id, false);
+        Ok(())
+    }
+
+    /// Subscribes to or unsubscribes from `topic` of the attestation subnet `subnet_id`, updating
+    /// the `attnets` bitfield of our ENR accordingly. Subscribing also starts searching for peers
+    /// on the subnet.
+    fn change_subnet_subscription(
+        &mut self,
+        topic: GossipTopic,
+        subnet_id: SubnetId,
+        subscribe: bool,
+    ) {
+        if self.defer_subscription_change(&topic, subscribe, ChangeOrigin::Subnet(subnet_id)) {
+            return;
+        }
+        let subnet = Subnet::Attestation(subnet_id);
+        let behaviour = self.swarm.behaviour_mut();
+        if subscribe {
+            behaviour.subscribe(topic);
+            behaviour.update_enr_subnet(subnet, true);
+            behaviour.discover_subnet_peers(vec![SubnetDiscovery {
+                subnet,
+                min_ttl: None,
+            }]);
+        } else {
+            behaviour.unsubscribe(topic);
+            behaviour.update_enr_subnet(subnet, false);
+        }
+    }
+
+    /// Subscribes to a gossipsub topic. Returns `false` if the subscription failed or exceeded
+    /// the `subscription_change_limit` of the topic, in which case it is applied once the limit
+    /// allows.
+    pub fn subscribe(&mut self, topic: GossipTopic) -> bool {
+        if self.defer_subscription_change(&topic, true, ChangeOrigin::Topic) {
+            return false;
+        }
+        self.swarm.behaviour_mut().subscribe(topic)
+    }
+
+    /// Unsubscribes from a gossipsub topic. Returns `false` if we were not subscribed, or if the
+    /// change exceeded the `subscription_change_limit` of the topic, in which case it is applied
+    /// once the limit allows.
+    pub fn unsubscribe(&mut self, topic: GossipTopic) -> bool {
+        if self.defer_subscription_change(&topic, false, ChangeOrigin::Topic) {
+            return false;
+        }
+        self.swarm.behaviour_mut().unsubscribe(topic)
+    }
+
+    /// Updates the `eth2` field of the local ENR to the new `EnrForkId`, which increments its
+    /// sequence number so the change is propagated by discovery.
+    ///
+    /// If `resubscribe_topics` is set, the current gossipsub topics are replaced by their
+    /// equivalents for the new fork digest.
+    pub fn update_fork_version(&mut self, new_enr_fork_id: EnrForkId, resubscribe_topics: bool) {
+        let new_fork_digest = new_enr_fork_id.fork_digest;
+        let behaviour = self.swarm.behaviour_mut();
+        behaviour.update_fork_version(new_enr_fork_id);
+        if resubscribe_topics {
+            behaviour.subscribe_new_fork_topics(new_fork_digest);
+            behaviour.unsubscribe_from_fork_topics_except(new_fork_digest);
+        }
+    }
+
+    /// Subscribes to the core gossipsub topics under `fork_digest`, whether or not the fork is
+    /// active yet. Subscribing ahead of a fork lets the meshes of its topics form before the
+    /// transition. Topics already subscribed to are left as they are.
+    ///
+    /// Returns the kinds of the topics that could not be subscribed to.
+    pub fn subscribe_new_fork_topics(&mut self, fork_digest: [u8; 4]) -> Vec<GossipKind> {
+        let mut failed_topics = vec![];
+        for kind in CORE_TOPICS.iter() {
+            let topic = GossipTopic::new(kind.clone(), GossipEncoding::default(), fork_digest);
+            if !self.swarm.behaviour_mut().subscribe(topic) {
+                warn!(self.log, "Could not subscribe to fork topic"; "topic" => %kind, "fork_digest" => ?fork_digest);
+                failed_topics.push(kind.clone());
+            }
+        }
+        failed_topics
+    }
+
+    /// Publishes pre-encoded `data` on `topic`, bypassing the `PubsubMessage` encoding. Oversized
+    /// messages are rejected.
+    pub fn publish_raw(
+        &mut self,
+        topic: GossipTopic,
+        data: Vec<u8>,
+    ) -> Result<MessageId, PublishError> {
+        self.swarm.behaviour_mut().publish_raw(topic, data)
+    }
+
+    /// Publishes pre-encoded `message` on `topic` directly to `peers`, regardless of whether they
+    /// are in our mesh of the topic. The message is published as usual in addition, so with flood
+    /// publishing every other subscribed peer receives it as well. Peers that are not connected are
+    /// skipped with a warning.
+    pub fn publish_to_peers(
+        &mut self,
+        topic: GossipTopic,
+        message: Vec<u8>,
+        peers: &[PeerId],
+    ) -> Result<MessageId, PublishError> {
+        self.swarm
+            .behaviour_mut()
+            .publish_to_peers(topic, message, peers)
+    }
+
+    /// Reports the validation result of a gossip message to gossipsub, which propagates accepted
+    /// messages. The result is counted per topic kind of the message.
+    pub fn report_message_validation_result(
+        &mut self,
+        propagation_source: &PeerId,
+        message_id: MessageId,
+        validation_result: MessageAcceptance,
+    ) {
+        self.swarm.behaviour_mut().report_message_validation_result(
+            propagation_source,
+            message_id,
+            validation_result,
+        );
+    }
+
+    /// Lists the ids and topics of the messages currently in the gossipsub message cache. These
+    /// are the messages we still gossip about and serve to peers requesting them with IWANT.
+    /// Empty unless `track_gossip_message_cache` is set.
+    pub fn gossip_cache_snapshot(&mut self) -> Vec<(MessageId, GossipTopic)> {
+        self.swarm.behaviour_mut().gossip_cache_snapshot()
+    }
+
+    /// Returns the protocols and agent advertised by a peer through identify, if known.
+    pub fn peer_protocols(&self, peer_id: &PeerId) -> Option<PeerProtocolInfo> {
+        self.swarm
+            .behaviour()
+            .network_globals()
+            .peer_protocols(peer_id)
+    }
+
+    /// Returns whether gossipsub is currently subscribed to `topic`.
+    pub fn is_subscribed(&self, topic: &GossipTopic) -> bool {
+        let topic_hash = IdentTopic::from(topic.clone()).hash();
+        self.swarm
+            .behaviour()
+            .gs()
+            .topics()
+            .any(|subscribed| *subscribed == topic_hash)
+    }
+
+    /// Returns the topics gossipsub is currently subscribed to.
+    pub fn subscribed_topics(&self) -> Vec<GossipTopic> {
+        self.swarm
+            .behaviour()
+            .gs()
+            .topics()
+            .filter_map(|topic_hash| GossipTopic::decode(topic_hash.as_str()).ok())
+            .collect()
+    }
+
+    /// Returns the number of gossipsub mesh peers of each subscribed topic.
+    pub fn mesh_peers_per_topic(&self) -> HashMap<GossipTopic, usize> {
+        self.swarm.behaviour().mesh_peers_per_topic()
+    }
+
+    /// Returns the number of entries of the discovery routing table, in total and per k-bucket.
+    pub fn discovery_table_stats(&self) -> DiscoveryTableStats {
+        self.swarm.behaviour().discovery().table_stats()
+    }
+
+    /// Returns the ENRs of the discovery routing table, to be persisted and imported on restart.
+    pub fn export_enrs(&mut self) -> Vec<Enr> {
+        self.swarm
+            .behaviour_mut()
+            .discovery_mut()
+            .table_entries_enr()
+    }
+
+    /// Adds previously exported ENRs to the discovery routing table, as is done for boot nodes.
+    /// ENRs with an invalid signature are skipped.
+    pub fn import_enrs(&mut self, enrs: Vec<Enr>) {
+        for enr in enrs {
+            if !enr.verify() {
+                debug!(self.log, "Skipping imported ENR with an invalid signature"; "node_id" => %enr.node_id());
+                continue;
+            }
+            self.swarm.behaviour_mut().discovery_mut().add_enr(enr);
+        }
+    }
+
+    /// Sets the application field `key` of the local ENR. Peers read it with
+    /// `EnrExt::app_field`.
+    pub fn set_app_enr_field(&mut self, key: &str, value: Vec<u8>) -> Result<(), String> {
+        self.swarm
+            .behaviour_mut()
+            .discovery_mut()
+            .update_enr_app_field(key, value)
+    }
+
+    /// Registers the codec of the payloads published on the application topic `topic`. The topic
+    /// must be one of the configured `application_topics` to be subscribed to.
+    pub fn register_application_topic(&mut self, topic: String, codec: ApplicationCodec) {
+        self.swarm
+            .behaviour_mut()
+            .register_application_codec(topic, codec);
+    }
+
+    /// Searches for up to `num` peers advertising the attestation subnet `subnet` in their ENR,
+    /// without dialing them. The search is bounded by the discovery query timeout and the
+    /// returned channel resolves with whatever was found by then, which may be nothing.
+    pub fn discover_peers(&mut self, subnet: SubnetId, num: usize) -> oneshot::Receiver<Vec<Enr>> {
+        self.swarm
+            .behaviour_mut()
+            .discovery_mut()
+            .discover_subnet_enrs(Subnet::Attestation(subnet), num)
+    }
+
+    /// Whether subscribing to (or unsubscribing from) `topic` exceeds the subscription change
+    /// limit of the topic and must be deferred. Changes within the limit are counted. Requests
+    /// that leave the subscription as it is are not changes and cancel any deferred change.
+    fn defer_subscription_change(
+        &mut self,
+        topic: &GossipTopic,
+        subscribe: bool,
+        origin: ChangeOrigin,
+    ) -> bool {
+        let is_subscribed = self.is_subscribed(topic);
+        let limiter = match self.subscription_limiter.as_mut() {
+            Some(limiter) => limiter,
+            None => return false,
+        };
+        if is_subscribed == subscribe {
+            limiter.cancel_deferred(topic);
+            return false;
+        }
+        let allowed = limiter.allows(topic, subscribe, origin);
+        if !allowed {
+            warn!(self.log, "Deferring subscription change over the rate limit";
+                "topic" => %topic, "subscribe" => subscribe);
+        }
+        !allowed
+    }
+
+    /// Applies the subscription changes deferred by the subscription change limit to their exact
+    /// topics, through the entry point they were requested with. The changes still over the limit
+    /// are deferred again.
+    fn apply_deferred_subscription_changes(&mut self, changes: Vec<DeferredChange>) {
+        for DeferredChange {
+            topic,
+            subscribe,
+            origin,
+        } in changes
+        {
+            match origin {
+                ChangeOrigin::Topic if subscribe => {
+                    self.subscribe(topic);
+                }
+                ChangeOrigin::Topic => {
+                    self.unsubscribe(topic);
+                }
+                ChangeOrigin::Subnet(subnet_id) => {
+                    self.change_subnet_subscription(topic, subnet_id, subscribe)
+                }
+            }
+        }
+    }
+
+    /// Errors if `subnet_id` is not a valid attestation subnet.
+    fn check_attestation_subnet(subnet_id: SubnetId) -> error::Result<()> {
+        let subnet_count = TSpec::SubnetBitfieldLength::to_u64();
+        if *subnet_id >= subnet_count {
+            return Err(format!(
+                "Attestation subnet {} is out of range, there are {} subnets",
+                *subnet_id, subnet_count
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Stops returning gossip messages from `next_event`, buffering them until `resume_gossip` is
+    /// called. The gossipsub subscriptions and mesh are left intact.
+    pub fn pause_gossip(&mut self) {
+        self.gossip_paused = true;
+    }
+
+    /// Resumes returning gossip messages from `next_event`, starting with the ones buffered while
+    /// gossip was paused, in the order they were received.
+    pub fn resume_gossip(&mut self) {
+        self.gossip_paused = false;
+    }
+
+    /// The number of gossip messages buffered while gossip is paused.
+    pub fn paused_gossip_messages(&self) -> usize {
+        self.paused_gossip.len()
+    }
+
+    /// Restricts the events returned by `next_event` to the given kinds. Other events are still
+    /// processed but dropped instead of being returned, except for `ZeroListeners`.
+    pub fn set_event_filter(&mut self, kinds: impl IntoIterator<Item = EventKind>) {
+        self.event_filter = Some(kinds.into_iter().collect());
+    }
+
+    /// Returns events of all kinds from `next_event` again.
+    pub fn clear_event_filter(&mut self) {
+        self.event_filter = None;
+    }
+
+    /// Buffers a gossip message received while gossip is paused, discarding the oldest buffered
+    /// message if the buffer is full. With a buffer size of 0 the message itself is discarded.
+    fn buffer_paused_gossip(&mut self, message: BehaviourEvent<AppReqId, TSpec>) {
+        let discarded = if self.paused_gossip_buffer_size == 0 {
+            Some(message)
+        } else {
+            let discarded = if self.paused_gossip.len() >= self.paused_gossip_buffer_size {
+                self.paused_gossip.pop_front()
+            } else {
+                None
+            };
+            self.paused_gossip.push_back(message);
+            discarded
+        };
+        if let Some(BehaviourEvent::PubsubMessage { id, source, .. }) = discarded {
+            debug!(self.log, "Discarding gossip message received while paused"; "message_id" => %id);
+            // let gossipsub know the message won't be validated
+            self.swarm.behaviour_mut().report_message_validation_result(
+                &source,
+                id,
+                MessageAcceptance::Ignore,
+            );
+        }
+    }
+
+    /// Returns the next event, of the kinds selected by `set_event_filter` if one is set.
+    /// `ZeroListeners` is always returned, as the node can no longer accept connections.
     pub async fn next_event(&mut self) -> Libp2pEvent<AppReqId, TSpec> {
         loop {
-            match self.swarm.select_next_some().await {
+            let event = self.next_unfiltered_event().await;
+            if matches!(event, Libp2pEvent::ZeroListeners)
+                || self
+                    .event_filter
+                    .as_ref()
+                    .map_or(true, |kinds| kinds.contains(&event.kind()))
+            {
+                return event;
+            }
+        }
+    }
+
+    /// Waits for the subscription changes deferred by the subscription change limit, if any.
+    async fn deferred_subscription_changes(
+        subscription_limiter: &mut Option<SubscriptionRateLimiter>,
+    ) -> Vec<DeferredChange> {
+        match subscription_limiter {
+            Some(limiter) => limiter.deferred_changes().await,
+            None => futures::future::pending().await,
+        }
+    }
+
+    /// Waits for `deadline` to expire, if it is set.
+    async fn deadline(deadline: &mut Option<Pin<Box<Sleep>>>) {
+        match deadline {
+            Some(deadline) => deadline.await,
+            None => futures::future::pending().await,
+        }
+    }
+
+    async fn next_unfiltered_event(&mut self) -> Libp2pEvent<AppReqId, TSpec> {
+        if !self.gossip_paused {
+            if let Some(message) = self.paused_gossip.pop_front() {
+                return Libp2pEvent::Behaviour(message);
+            }
+        }
+
+        loop {
+            let event = tokio::select! {
+                event = self.swarm.select_next_some() => event,
+                changes = Self::deferred_subscription_changes(&mut self.subscription_limiter) => {
+                    self.apply_deferred_subscription_changes(changes);
+                    continue;
+                }
+                _ = Self::deadline(&mut self.reset_deadline) => {
+                    self.reconnect_to_boot_nodes();
+                    continue;
+                }
+                _ = Self::deadline(&mut self.boot_node_fallback) => {
+                    self.fall_back_to_next_boot_node();
+                    continue;
+                }
+            };
+            match event {
                 SwarmEvent::Behaviour(behaviour) => {
+                    if self.gossip_paused
+                        && matches!(behaviour, BehaviourEvent::PubsubMessage { .. })
+                    {
+                        self.buffer_paused_gossip(behaviour);
+                        continue;
+                    }
                     // Handle banning here
                     match &behaviour {
                         BehaviourEvent::PeerBanned(peer_id) => {
@@ -319,6 +1142,9 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
                         }
                         _ => {}
                     }
+                    if let BehaviourEvent::ExternalAddrConfirmed(address) = behaviour {
+                        return Libp2pEvent::NewExternalAddr(address);
+                    }
                     return Libp2pEvent::Behaviour(behaviour);
                 }
                 SwarmEvent::ConnectionEstablished {
@@ -332,9 +1158,19 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
                     cause: _,
                     endpoint: _,
                     num_established: _,
-                } => {}
+                } => self.reconnect_after_reset(),
                 SwarmEvent::NewListenAddr { address, .. } => {
-                    return Libp2pEvent::NewListenAddr(address)
+                    let mut listen_multiaddrs = self
+                        .swarm
+                        .behaviour()
+                        .network_globals()
+                        .listen_multiaddrs
+                        .write();
+                    if !listen_multiaddrs.contains(&address) {
+                        listen_multiaddrs.push(address.clone());
+                    }
+                    drop(listen_multiaddrs);
+                    return Libp2pEvent::NewListenAddr(address);
                 }
                 SwarmEvent::IncomingConnection {
                     local_addr,
@@ -356,12 +1192,24 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
                     debug!(self.log, "Failed to dial address"; "peer_id" => ?peer_id,  "error" => %error);
                 }
                 SwarmEvent::ExpiredListenAddr { address, .. } => {
-                    debug!(self.log, "Listen address expired"; "address" => %address)
+                    debug!(self.log, "Listen address expired"; "address" => %address);
+                    self.swarm
+                        .behaviour()
+                        .network_globals()
+                        .listen_multiaddrs
+                        .write()
+                        .retain(|listen_address| *listen_address != address);
                 }
                 SwarmEvent::ListenerClosed {
                     addresses, reason, ..
                 } => {
                     crit!(self.log, "Listener closed"; "addresses" => ?addresses, "reason" => ?reason);
+                    self.swarm
+                        .behaviour()
+                        .network_globals()
+                        .listen_multiaddrs
+                        .write()
+                        .retain(|listen_address| !addresses.contains(listen_address));
                     if Swarm::listeners(&self.swarm).count() == 0 {
                         return Libp2pEvent::ZeroListeners;
                     }
@@ -381,18 +1229,93 @@ impl<AppReqId: ReqId, TSpec: EthSpec> Service<AppReqId, TSpec> {
 
 type BoxedTransport = Boxed<(PeerId, StreamMuxerBox)>;
 
-/// The implementation supports TCP/IP, WebSockets over TCP/IP, noise as the encryption layer, and
-/// mplex as the multiplexing layer.
-fn build_transport(
-    local_private_key: Keypair,
-) -> std::io::Result<(BoxedTransport, Arc<BandwidthSinks>)> {
-    let tcp = libp2p::tcp::TokioTcpConfig::new().nodelay(true);
-    let transport = libp2p::dns::TokioDnsConfig::system(tcp)?;
-    #[cfg(feature = "libp2p-websocket")]
-    let transport = {
-        let trans_clone = transport.clone();
-        transport.or_transport(libp2p::websocket::WsConfig::new(trans_clone))
+/// The weight of boot nodes without a configured weight.
+const DEFAULT_BOOT_NODE_WEIGHT: u32 = 1;
+
+/// Dials a peer by its address, ignoring the peer id in the address if any.
+fn dial_address<AppReqId: ReqId, TSpec: EthSpec>(
+    swarm: &mut Swarm<Behaviour<AppReqId, TSpec>>,
+    mut multiaddr: Multiaddr,
+    log: &Logger,
+) {
+    // strip the p2p protocol if it exists
+    strip_peer_id(&mut multiaddr);
+    swarm
+        .behaviour_mut()
+        .peer_manager_mut()
+        .count_dial_attempt(None);
+    match Swarm::dial(swarm, multiaddr.clone()) {
+        Ok(()) => debug!(log, "Dialing libp2p peer"; "address" => %multiaddr),
+        Err(err) => debug!(
+            log,
+            "Could not connect to peer"; "address" => %multiaddr, "error" => ?err
+        ),
     };
+}
+
+/// Returns the nodes in a random order, in which nodes of a larger weight tend to come first.
+/// Nodes of weight 0 come last.
+fn weighted_dial_order<T>(nodes: Vec<(T, u32)>, rng: &mut impl Rng) -> Vec<T> {
+    // each node is keyed by u^(1/weight) for a uniform u in [0, 1), the largest keys first
+    let mut keyed = nodes
+        .into_iter()
+        .map(|(node, weight)| {
+            let key = if weight == 0 {
+                -1.0
+            } else {
+                rng.gen::<f64>().powf(1.0 / weight as f64)
+            };
+            (key, node)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    keyed.into_iter().map(|(_, node)| node).collect()
+}
+
+/// Upgrades the base transport, TCP/IP and WebSockets over TCP/IP unless replaced, with noise as
+/// the encryption layer and yamux or mplex as the multiplexing layer.
+fn build_transport<TSpec: EthSpec, T>(
+    transport: T,
+    local_private_key: Keypair,
+    config: &NetworkConfig,
+    network_globals: Arc<NetworkGlobals<TSpec>>,
+) -> error::Result<(BoxedTransport, Arc<BandwidthSinks>)>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: std::error::Error + Send + Sync + 'static,
+    T::Listener: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+    T::Dial: Send + 'static,
+{
+    // drop inbound connections over the per-IP rate limit before negotiating any protocol
+    let inbound_limiter = config
+        .inbound_rate_limit_per_ip
+        .map(InboundRateLimiter::new)
+        .transpose()
+        .map_err(|e| format!("Invalid inbound connection rate limit: {}", e))?;
+    let allow_private_addresses = config.allow_private_addresses;
+    let transport = transport.and_then(move |stream, endpoint| {
+        let address_allowed = inbound_address_allowed(&endpoint, allow_private_addresses);
+        let allowed = inbound_limiter
+            .as_ref()
+            .map_or(true, |limiter| limiter.allows_endpoint(&endpoint));
+        async move {
+            if !address_allowed {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionRefused,
+                    "Inbound connection from a private address",
+                ))
+            } else if allowed {
+                Ok(stream)
+            } else {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionRefused,
+                    "Inbound connection rate limit exceeded",
+                ))
+            }
+        }
+    });
 
     let (transport, bandwidth) = BandwidthLogging::new(transport);
 
@@ -415,11 +1338,30 @@ fn build_transport(
                 mplex_config,
             ))
             .timeout(Duration::from_secs(10))
+            // count the bytes exchanged with each peer
+            .map(move |(peer_id, muxer), _| {
+                let counters = network_globals.peers.write().bandwidth_counters(&peer_id);
+                let muxer = BandwidthCountingMuxer::new(muxer, counters);
+                (peer_id, StreamMuxerBox::new(muxer))
+            })
             .boxed(),
         bandwidth,
     ))
 }
 
+/// Whether an inbound connection at `endpoint` may be accepted. Connections from private IP
+/// addresses are only accepted if `allow_private_addresses` is set. Outbound connections are
+/// always allowed.
+fn inbound_address_allowed(endpoint: &ConnectedPoint, allow_private_addresses: bool) -> bool {
+    match endpoint {
+        ConnectedPoint::Listener { send_back_addr, .. } => {
+            allow_private_addresses
+                || multiaddr_ip(send_back_addr).map_or(true, |ip| !is_private_ip(&ip))
+        }
+        ConnectedPoint::Dialer { .. } => true,
+    }
+}
+
 // Useful helper functions for debugging. Currently not used in the client.
 #[allow(dead_code)]
 fn keypair_from_hex(hex_bytes: &str) -> error::Result<Keypair> {
@@ -444,13 +1386,37 @@ fn keypair_from_bytes(mut bytes: Vec<u8>) -> error::Result<Keypair> {
         .map_err(|e| format!("Unable to parse p2p secret key: {:?}", e).into())
 }
 
+/// Reads the peer ids listed in a trusted peers file, one base58 encoded peer id per line. Blank
+/// lines and lines starting with `#` are ignored.
+fn load_trusted_peers_file(path: &Path) -> error::Result<Vec<PeerId>> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        format!(
+            "Unable to read trusted peers file {}: {:?}",
+            path.display(),
+            e
+        )
+    })?;
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.parse::<PeerId>()
+                .map_err(|e| format!("Invalid trusted peer id {}: {:?}", line, e).into())
+        })
+        .collect()
+}
+
 /// Loads a private key from disk. If this fails, a new key is
 /// generated and is then saved to disk.
 ///
 /// Currently only secp256k1 keys are allowed, as these are the only keys supported by discv5.
 pub fn load_private_key(config: &NetworkConfig, log: &slog::Logger) -> Keypair {
     // check for key from disk
-    let network_key_f = config.network_dir.join(NETWORK_KEY_FILENAME);
+    let network_key_f = config
+        .network_key_file
+        .clone()
+        .unwrap_or_else(|| config.network_dir.join(NETWORK_KEY_FILENAME));
     if let Ok(mut network_key_file) = File::open(network_key_f.clone()) {
         let mut key_bytes: Vec<u8> = Vec::with_capacity(36);
         match network_key_file.read_to_end(&mut key_bytes) {
@@ -473,7 +1439,9 @@ pub fn load_private_key(config: &NetworkConfig, log: &slog::Logger) -> Keypair {
     // if a key could not be loaded from disk, generate a new one and save it
     let local_private_key = Keypair::generate_secp256k1();
     if let Keypair::Secp256k1(key) = local_private_key.clone() {
-        let _ = std::fs::create_dir_all(&config.network_dir);
+        if let Some(parent) = network_key_f.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
         match File::create(network_key_f.clone())
             .and_then(|mut f| f.write_all(&key.secret().to_bytes()))
         {
@@ -571,3 +1539,56 @@ fn load_or_build_metadata<E: EthSpec>(
     save_metadata_to_disk(network_dir, meta_data.clone(), log);
     meta_data
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn listener(ip: &str) -> ConnectedPoint {
+        ConnectedPoint::Listener {
+            local_addr: "/ip4/127.0.0.1/tcp/9000".parse().unwrap(),
+            send_back_addr: format!("/ip4/{}/tcp/30000", ip).parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_weighted_dial_order() {
+        let mut rng = StdRng::seed_from_u64(7);
+
+        // with equal weights, every node is dialed first some of the time
+        let mut first_dialed = HashSet::new();
+        for _ in 0..100 {
+            let order = weighted_dial_order((0..5).map(|node| (node, 1)).collect(), &mut rng);
+            let mut sorted = order.clone();
+            sorted.sort_unstable();
+            assert_eq!(sorted, vec![0, 1, 2, 3, 4]);
+            first_dialed.insert(order[0]);
+        }
+        assert_eq!(first_dialed.len(), 5);
+
+        // heavier nodes tend to be dialed first, nodes of weight 0 are dialed last
+        let mut heavy_first = 0;
+        for _ in 0..100 {
+            let order = weighted_dial_order(vec![(0, 1), (1, 0), (2, 20), (3, 1)], &mut rng);
+            assert_eq!(order[3], 1);
+            if order[0] == 2 {
+                heavy_first += 1;
+            }
+        }
+        assert!(heavy_first > 70);
+    }
+
+    #[test]
+    fn test_inbound_private_addresses() {
+        assert!(!inbound_address_allowed(&listener("10.0.0.1"), false));
+        assert!(inbound_address_allowed(&listener("10.0.0.1"), true));
+
+        // public addresses and outbound connections are always allowed
+        assert!(inbound_address_allowed(&listener("8.8.8.8"), false));
+        let dialer = ConnectedPoint::Dialer {
+            address: "/ip4/10.0.0.1/tcp/9000".parse().unwrap(),
+        };
+        assert!(inbound_address_allowed(&dialer, false));
+    }
+}
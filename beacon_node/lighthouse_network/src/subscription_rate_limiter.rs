@@ -0,0 +1,121 @@
+//! Rate limits the changes to the gossipsub subscription of each topic.
+//!
+//! Subscribing to and unsubscribing from a topic in quick succession makes gossipsub join and
+//! leave its mesh each time, which destabilizes the mesh for our peers. A change over the limit is
+//! deferred rather than dropped: the latest subscription requested for the topic is applied once
+//! its quota allows, so the rapid flips coalesce into the state last asked for.
+use crate::rpc::{Limiter, Quota, RateLimitedErr};
+use crate::GossipTopic;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use types::SubnetId;
+
+/// The subscription changes allowed for a single topic.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SubscriptionChangeLimit {
+    /// The number of subscribes and unsubscribes of a topic allowed per `window`.
+    pub max_changes: u64,
+    /// The window over which the changes of a topic are counted.
+    pub window: Duration,
+}
+
+/// The entry point through which a subscription change was requested, which is used again when a
+/// deferred change is applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ChangeOrigin {
+    /// `Service::subscribe` or `Service::unsubscribe`.
+    Topic,
+    /// `Service::subscribe_to_subnet` or `Service::unsubscribe_from_subnet`, which also update our
+    /// ENR.
+    Subnet(SubnetId),
+}
+
+/// A subscription change deferred by the limit.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DeferredChange {
+    /// The exact topic the change was requested for.
+    pub topic: GossipTopic,
+    /// Whether to subscribe to or unsubscribe from the topic.
+    pub subscribe: bool,
+    pub origin: ChangeOrigin,
+}
+
+/// A per-topic limiter of subscription changes.
+pub(crate) struct SubscriptionRateLimiter {
+    limiter: Limiter<GossipTopic>,
+    /// How often the topics whose quota has been fully replenished are forgotten.
+    prune_interval: Duration,
+    init_time: Instant,
+    last_prune: Duration,
+    /// The latest subscription state requested for the topics whose change was deferred, with
+    /// the entry point it was requested through.
+    deferred: HashMap<GossipTopic, (bool, ChangeOrigin)>,
+    /// When the earliest deferred change is allowed.
+    retry_at: Option<Instant>,
+}
+
+impl SubscriptionRateLimiter {
+    pub fn new(limit: SubscriptionChangeLimit) -> Result<Self, &'static str> {
+        let limiter = Limiter::from_quota(Quota::n_every(limit.max_changes, limit.window))?;
+        Ok(SubscriptionRateLimiter {
+            limiter,
+            prune_interval: limit.window,
+            init_time: Instant::now(),
+            last_prune: Duration::ZERO,
+            deferred: HashMap::new(),
+            retry_at: None,
+        })
+    }
+
+    /// Whether a change to the subscription of `topic` is allowed, consuming one token of its
+    /// quota if so. Otherwise the change is deferred, replacing any change deferred before for
+    /// the topic.
+    pub fn allows(&mut self, topic: &GossipTopic, subscribe: bool, origin: ChangeOrigin) -> bool {
+        let time_since_start = self.init_time.elapsed();
+        if time_since_start >= self.last_prune + self.prune_interval {
+            self.limiter.prune(time_since_start);
+            self.last_prune = time_since_start;
+        }
+        match self.limiter.allows(time_since_start, topic, 1) {
+            Ok(()) => {
+                self.deferred.remove(topic);
+                true
+            }
+            Err(e) => {
+                // a single token always fits in a valid quota
+                let wait = match e {
+                    RateLimitedErr::TooSoon(wait) => wait,
+                    RateLimitedErr::TooLarge => self.prune_interval,
+                };
+                let retry_at = Instant::now() + wait;
+                self.retry_at = Some(self.retry_at.map_or(retry_at, |at| at.min(retry_at)));
+                self.deferred.insert(topic.clone(), (subscribe, origin));
+                false
+            }
+        }
+    }
+
+    /// Forgets the change deferred for `topic`, if any.
+    pub fn cancel_deferred(&mut self, topic: &GossipTopic) {
+        self.deferred.remove(topic);
+    }
+
+    /// Waits until the earliest deferred change is allowed, then returns all the deferred
+    /// changes. The changes still over the limit are deferred again when they are retried.
+    pub async fn deferred_changes(&mut self) -> Vec<DeferredChange> {
+        match self.retry_at {
+            Some(retry_at) => tokio::time::sleep_until(retry_at.into()).await,
+            None => futures::future::pending().await,
+        }
+        self.retry_at = None;
+        self.deferred
+            .drain()
+            .map(|(topic, (subscribe, origin))| DeferredChange {
+                topic,
+                subscribe,
+                origin,
+            })
+            .collect()
+    }
+}
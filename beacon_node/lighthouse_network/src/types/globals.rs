@@ -1,14 +1,45 @@
 //! A collection of variables that are accessible outside of the network thread itself.
-use crate::peer_manager::peerdb::PeerDB;
+use crate::peer_manager::config::DEFAULT_TARGET_PEERS;
+use crate::peer_manager::peerdb::{snapshot::PeerDbSnapshot, PeerDB};
 use crate::rpc::{MetaData, MetaDataV2};
 use crate::types::{BackFillState, SyncState};
-use crate::Client;
-use crate::EnrExt;
-use crate::{Enr, GossipTopic, Multiaddr, PeerId};
+use crate::{BandwidthStats, Client, DisconnectReason, PeerProtocolInfo};
+use crate::{Enr, GossipTopic, IpNetwork, Multiaddr, PeerId, Subnet};
+use crate::{EnrExt, Eth2Enr};
 use parking_lot::RwLock;
-use std::collections::HashSet;
-use std::sync::atomic::{AtomicU16, Ordering};
-use types::EthSpec;
+use serde::Serialize;
+use ssz_types::typenum::Unsigned;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use types::{EnrForkId, EthSpec, SubnetId, SyncSubnetId};
+
+/// A summary of the connectivity of the node, as returned by `NetworkGlobals::network_health`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NetworkHealth {
+    /// The number of connected peers.
+    pub connected_peers: usize,
+    /// The number of attestation subnets on which at least one connected peer is subscribed.
+    pub attestation_subnets_covered: usize,
+    /// Whether at least the target number of peers are connected.
+    pub target_peers_met: bool,
+}
+
+/// Cumulative counters of the outbound dials, as returned by `NetworkGlobals::dial_stats`. The
+/// dials libp2p starts by itself, as those of gossipsub peer exchange, are not counted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DialStats {
+    /// The number of dials started.
+    pub attempts: u64,
+    /// The number of dials that established a connection.
+    pub successes: u64,
+    /// The number of dials that failed.
+    pub failures: u64,
+    /// The number of failed dials, by kind of failure.
+    pub failures_by_kind: HashMap<&'static str, u64>,
+}
 
 pub struct NetworkGlobals<TSpec: EthSpec> {
     /// The current local ENR.
@@ -31,6 +62,14 @@ pub struct NetworkGlobals<TSpec: EthSpec> {
     pub sync_state: RwLock<SyncState>,
     /// The current state of the backfill sync.
     pub backfill_state: RwLock<BackFillState>,
+    /// The number of peers the node aims to be connected to.
+    pub target_peers: AtomicUsize,
+    /// The outcomes of our dials, maintained by the peer manager.
+    pub(crate) dial_stats: RwLock<DialStats>,
+    /// Publishes the number of connected peers whenever it changes.
+    connected_peers_tx: watch::Sender<usize>,
+    /// Kept so that the number of connected peers can always be published.
+    connected_peers_rx: watch::Receiver<usize>,
 }
 
 impl<TSpec: EthSpec> NetworkGlobals<TSpec> {
@@ -42,6 +81,7 @@ impl<TSpec: EthSpec> NetworkGlobals<TSpec> {
         trusted_peers: Vec<PeerId>,
         log: &slog::Logger,
     ) -> Self {
+        let (connected_peers_tx, connected_peers_rx) = watch::channel(0);
         NetworkGlobals {
             local_enr: RwLock::new(enr.clone()),
             peer_id: RwLock::new(enr.peer_id()),
@@ -53,6 +93,10 @@ impl<TSpec: EthSpec> NetworkGlobals<TSpec> {
             gossipsub_subscriptions: RwLock::new(HashSet::new()),
             sync_state: RwLock::new(SyncState::Stalled),
             backfill_state: RwLock::new(BackFillState::NotRequired),
+            target_peers: AtomicUsize::new(DEFAULT_TARGET_PEERS),
+            dial_stats: RwLock::new(DialStats::default()),
+            connected_peers_tx,
+            connected_peers_rx,
         }
     }
 
@@ -62,12 +106,33 @@ impl<TSpec: EthSpec> NetworkGlobals<TSpec> {
         self.local_enr.read().clone()
     }
 
+    /// Returns the `EnrForkId` currently advertised in the `eth2` field of the local ENR. It
+    /// reflects the updates made through `Service::update_fork_version`.
+    pub fn enr_fork_id(&self) -> EnrForkId {
+        self.local_enr
+            .read()
+            .eth2()
+            .expect("Local ENR must have a fork id")
+    }
+
+    /// Returns the base64 encoding of the current local ENR, as used in `enr:` strings.
+    pub fn local_enr_base64(&self) -> String {
+        self.local_enr.read().to_base64()
+    }
+
+    /// Returns the RLP encoding of the current local ENR.
+    pub fn local_enr_rlp(&self) -> Vec<u8> {
+        rlp::encode(&*self.local_enr.read()).to_vec()
+    }
+
     /// Returns the local libp2p PeerID.
     pub fn local_peer_id(&self) -> PeerId {
         *self.peer_id.read()
     }
 
-    /// Returns the list of `Multiaddr` that the underlying libp2p instance is listening on.
+    /// Returns the list of `Multiaddr` that the underlying libp2p instance is listening on. It is
+    /// updated as listeners are added and removed, so it holds the ports chosen by the OS when
+    /// listening on port 0.
     pub fn listen_multiaddrs(&self) -> Vec<Multiaddr> {
         self.listen_multiaddrs.read().clone()
     }
@@ -87,6 +152,98 @@ impl<TSpec: EthSpec> NetworkGlobals<TSpec> {
         self.peers.read().connected_peer_ids().count()
     }
 
+    /// Returns whether the node is connected to enough peers, and how many attestation subnets
+    /// they cover. A peer covers a subnet if it is subscribed to it, according to its metadata or
+    /// its gossipsub subscriptions.
+    pub fn network_health(&self) -> NetworkHealth {
+        let peers = self.peers.read();
+        let connected_peers = peers.connected_peer_ids().count();
+        let attestation_subnets_covered = (0..TSpec::SubnetBitfieldLength::to_u64())
+            .map(|id| Subnet::Attestation(SubnetId::new(id)))
+            .filter(|subnet| {
+                peers.connected_peers().any(|(_, info)| {
+                    info.on_subnet_metadata(subnet) || info.on_subnet_gossipsub(subnet)
+                })
+            })
+            .count();
+        NetworkHealth {
+            connected_peers,
+            attestation_subnets_covered,
+            target_peers_met: connected_peers >= self.target_peers.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the number of dials started since the node started, and how many of them
+    /// succeeded and failed.
+    pub fn dial_stats(&self) -> DialStats {
+        self.dial_stats.read().clone()
+    }
+
+    /// Returns the ids of the libp2p connected peers, sorted by their byte representation.
+    pub fn connected_peers_sorted(&self) -> Vec<PeerId> {
+        let mut peers: Vec<_> = self.peers.read().connected_peer_ids().cloned().collect();
+        peers.sort_by_key(|peer| peer.to_bytes());
+        peers
+    }
+
+    /// Returns a future that resolves once at least `min` peers are connected, immediately if they
+    /// already are.
+    pub fn wait_for_peers(&self, min: usize) -> impl Future<Output = ()> + Send + 'static {
+        let mut connected_peers = self.connected_peers_rx.clone();
+        async move {
+            while *connected_peers.borrow() < min {
+                // the globals are gone, no more peers will connect
+                if connected_peers.changed().await.is_err() {
+                    futures::future::pending::<()>().await;
+                }
+            }
+        }
+    }
+
+    /// Publishes the current number of connected peers to the `wait_for_peers` futures.
+    pub(crate) fn update_connected_peers(&self) {
+        let connected_peers = self.connected_peers();
+        if *self.connected_peers_rx.borrow() != connected_peers {
+            // a receiver is held by `self`, this can't fail
+            let _ = self.connected_peers_tx.send(connected_peers);
+        }
+    }
+
+    /// Returns the connected peers advertising the attestation subnet `subnet` in their `attnets`.
+    pub fn peers_on_subnet(&self, subnet: SubnetId) -> Vec<PeerId> {
+        self.peers
+            .read()
+            .peers_advertising_subnet(Subnet::Attestation(subnet))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the connected peers advertising the sync committee subnet `subnet` in their
+    /// `syncnets`.
+    pub fn peers_on_sync_subnet(&self, subnet: SyncSubnetId) -> Vec<PeerId> {
+        self.peers
+            .read()
+            .peers_advertising_subnet(Subnet::SyncCommittee(subnet))
+            .cloned()
+            .collect()
+    }
+
+    /// Labels a known peer with an application-defined `label`, such as "sentry". A peer may have
+    /// several labels. Unknown peers are ignored.
+    pub fn set_peer_label(&self, peer_id: &PeerId, label: String) {
+        self.peers.write().set_peer_label(peer_id, label);
+    }
+
+    /// Removes a label set by `set_peer_label` from a peer.
+    pub fn remove_peer_label(&self, peer_id: &PeerId, label: &str) {
+        self.peers.write().remove_peer_label(peer_id, label);
+    }
+
+    /// Returns the ids of the known peers labelled with `label`.
+    pub fn peers_with_label(&self, label: &str) -> Vec<PeerId> {
+        self.peers.read().peers_with_label(label).cloned().collect()
+    }
+
     /// Returns the number of libp2p connected peers with outbound-only connections.
     pub fn connected_outbound_only_peers(&self) -> usize {
         self.peers.read().connected_outbound_only_peers().count()
@@ -121,6 +278,71 @@ impl<TSpec: EthSpec> NetworkGlobals<TSpec> {
             .unwrap_or_default()
     }
 
+    /// Returns the protocols and agent advertised by a peer through identify, if known.
+    pub fn peer_protocols(&self, peer_id: &PeerId) -> Option<PeerProtocolInfo> {
+        self.peers
+            .read()
+            .peer_info(peer_id)
+            .and_then(|info| info.protocol_info().cloned())
+    }
+
+    /// Returns why a peer was last disconnected, if it is known and has been disconnected.
+    pub fn last_disconnect_reason(&self, peer_id: &PeerId) -> Option<DisconnectReason> {
+        self.peers
+            .read()
+            .peer_info(peer_id)
+            .and_then(|info| info.disconnect_reason().cloned())
+    }
+
+    /// Returns the bytes exchanged with a peer, if any of its connections were counted.
+    pub fn peer_bandwidth(&self, peer_id: &PeerId) -> Option<BandwidthStats> {
+        self.peers.read().peer_bandwidth(peer_id)
+    }
+
+    /// Returns the number of our RPC requests to a peer that are queued, negotiating a substream
+    /// or awaiting a response. Unknown peers have none.
+    pub fn outbound_requests(&self, peer_id: &PeerId) -> usize {
+        self.peers.read().outbound_requests(peer_id)
+    }
+
+    /// Returns the average round-trip time of our pings to a peer, if it has answered one.
+    pub fn peer_latency(&self, peer_id: &PeerId) -> Option<Duration> {
+        self.peers
+            .read()
+            .peer_info(peer_id)
+            .and_then(|info| info.latency())
+    }
+
+    /// Returns an owned copy of the state of all known peers. The `PeerDB` is only locked while
+    /// the copy is taken.
+    pub fn peer_db_snapshot(&self) -> PeerDbSnapshot {
+        self.peers.read().snapshot()
+    }
+
+    /// Bans all addresses in `subnet` for the configured `ip_subnet_ban_duration`. Connections
+    /// from the subnet are refused and the connected peers in it are disconnected the next time
+    /// the peer manager is polled.
+    pub fn ban_ip_subnet(&self, subnet: IpNetwork) {
+        self.peers.write().ban_ip_subnet(subnet);
+    }
+
+    /// Protects a peer from score-based disconnection and banning until it is unprotected. The
+    /// peer still counts towards our peer limits but is never chosen for pruning.
+    pub fn protect_peer(&self, peer_id: &PeerId) {
+        self.peers.write().set_peer_protected(peer_id, true);
+    }
+
+    /// Removes the protection of a peer set by `protect_peer`.
+    pub fn unprotect_peer(&self, peer_id: &PeerId) {
+        self.peers.write().set_peer_protected(peer_id, false);
+    }
+
+    /// Returns the banned peers with the estimated time at which each one will be unbanned, or
+    /// `None` if it is not expected to be unbanned.
+    pub fn banned_peers(&self) -> Vec<(PeerId, Option<Instant>)> {
+        self.peers.read().banned_peers_with_unban_time()
+    }
+
     /// Updates the syncing state of the node.
     ///
     /// The old state is returned
@@ -149,3 +371,30 @@ impl<TSpec: EthSpec> NetworkGlobals<TSpec> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CombinedKeyExt;
+    use std::str::FromStr;
+    use types::MinimalEthSpec;
+
+    #[test]
+    fn test_local_enr_encodings() {
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        let globals = NetworkGlobals::<MinimalEthSpec>::new_test_globals(&log);
+
+        // the encodings follow updates of the local ENR
+        let keypair = libp2p::identity::Keypair::generate_secp256k1();
+        let enr_key = discv5::enr::CombinedKey::from_libp2p(&keypair).unwrap();
+        let enr = discv5::enr::EnrBuilder::new("v4")
+            .tcp(9001)
+            .build(&enr_key)
+            .unwrap();
+        *globals.local_enr.write() = enr.clone();
+
+        let base64 = globals.local_enr_base64();
+        assert_eq!(Enr::from_str(&base64).unwrap(), enr);
+        assert_eq!(globals.local_enr_rlp(), rlp::encode(&enr).to_vec());
+    }
+}
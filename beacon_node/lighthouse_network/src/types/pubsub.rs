@@ -6,7 +6,9 @@ use libp2p::gossipsub::{DataTransform, GossipsubMessage, RawGossipsubMessage};
 use snap::raw::{decompress_len, Decoder, Encoder};
 use ssz::{Decode, Encode};
 use std::boxed::Box;
+use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
+use std::sync::Arc;
 use types::{
     Attestation, AttesterSlashing, EthSpec, ForkContext, ForkName, ProposerSlashing,
     SignedAggregateAndProof, SignedBeaconBlock, SignedBeaconBlockAltair, SignedBeaconBlockBase,
@@ -32,19 +34,120 @@ pub enum PubsubMessage<T: EthSpec> {
     SignedContributionAndProof(Box<SignedContributionAndProof<T>>),
     /// Gossipsub message providing notification of unaggregated sync committee signatures with its subnet id.
     SyncCommitteeMessage(Box<(SyncSubnetId, SyncCommitteeMessage)>),
+    /// Gossipsub message of an application topic.
+    Application(Box<ApplicationMessage>),
+}
+
+/// A message published on an application topic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApplicationMessage {
+    /// The name of the application topic, without its prefix.
+    pub topic: String,
+    /// The payload of the message, as given to the encoder of the topic's `ApplicationCodec`.
+    pub payload: Vec<u8>,
+}
+
+type ApplicationCodecFn = Arc<dyn Fn(&[u8]) -> Result<Vec<u8>, String> + Send + Sync>;
+
+/// Converts the payloads of an application topic to and from the bytes that are gossiped. The
+/// encoded bytes are snappy compressed like every other gossipsub message.
+#[derive(Clone)]
+pub struct ApplicationCodec {
+    encode: ApplicationCodecFn,
+    decode: ApplicationCodecFn,
+}
+
+impl ApplicationCodec {
+    pub fn new(
+        encode: impl Fn(&[u8]) -> Result<Vec<u8>, String> + Send + Sync + 'static,
+        decode: impl Fn(&[u8]) -> Result<Vec<u8>, String> + Send + Sync + 'static,
+    ) -> Self {
+        ApplicationCodec {
+            encode: Arc::new(encode),
+            decode: Arc::new(decode),
+        }
+    }
+
+    /// Encodes a payload into the bytes to gossip.
+    pub fn encode(&self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        (self.encode)(payload)
+    }
+
+    /// Decodes received bytes into a payload, erroring if they are invalid.
+    pub fn decode(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        (self.decode)(data)
+    }
+}
+
+impl std::fmt::Debug for ApplicationCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ApplicationCodec")
+    }
 }
 
 // Implements the `DataTransform` trait of gossipsub to employ snappy compression
 pub struct SnappyTransform {
     /// Sets the maximum size we allow gossipsub messages to decompress to.
     max_size_per_message: usize,
+    /// Overrides `max_size_per_message` for the topics of these kinds.
+    max_size_per_kind: HashMap<GossipKind, usize>,
 }
 
 impl SnappyTransform {
     pub fn new(max_size_per_message: usize) -> Self {
         SnappyTransform {
             max_size_per_message,
+            max_size_per_kind: HashMap::new(),
+        }
+    }
+
+    /// Sets the maximum size of the messages of the given topic kinds. The subnet of attestation
+    /// and sync committee kinds is ignored: their limit applies to all subnets. A kind listed
+    /// more than once takes its last limit.
+    pub fn with_max_size_per_kind(
+        mut self,
+        max_size_per_kind: impl IntoIterator<Item = (GossipKind, usize)>,
+    ) -> Self {
+        self.max_size_per_kind = max_size_per_kind
+            .into_iter()
+            .map(|(kind, max_size)| (size_limit_kind(&kind), max_size))
+            .collect();
+        self
+    }
+
+    /// The largest message size allowed on any topic.
+    pub fn max_size(&self) -> usize {
+        self.max_size_per_kind
+            .values()
+            .copied()
+            .fold(self.max_size_per_message, std::cmp::max)
+    }
+
+    /// The maximum size of the messages of `topic`.
+    fn max_size_of(&self, topic: &TopicHash) -> usize {
+        if self.max_size_per_kind.is_empty() {
+            return self.max_size_per_message;
         }
+        GossipTopic::decode(topic.as_str())
+            .ok()
+            .and_then(|topic| {
+                self.max_size_per_kind
+                    .get(&size_limit_kind(topic.kind()))
+                    .copied()
+            })
+            .unwrap_or(self.max_size_per_message)
+    }
+}
+
+/// The kind whose size limit applies to the topics of `kind`: a single limit covers all the
+/// subnets of attestation and sync committee kinds.
+fn size_limit_kind(kind: &GossipKind) -> GossipKind {
+    match kind {
+        GossipKind::Attestation(_) => GossipKind::Attestation(SubnetId::new(0)),
+        GossipKind::SyncCommitteeMessage(_) => {
+            GossipKind::SyncCommitteeMessage(SyncSubnetId::new(0))
+        }
+        kind => kind.clone(),
     }
 }
 
@@ -56,10 +159,14 @@ impl DataTransform for SnappyTransform {
     ) -> Result<GossipsubMessage, std::io::Error> {
         // check the length of the raw bytes
         let len = decompress_len(&raw_message.data)?;
-        if len > self.max_size_per_message {
+        let max_size = self.max_size_of(&raw_message.topic);
+        if len > max_size {
             return Err(Error::new(
                 ErrorKind::InvalidData,
-                "ssz_snappy decoded data > GOSSIP_MAX_SIZE",
+                format!(
+                    "ssz_snappy decoded data of {} bytes > {} bytes limit of topic {}",
+                    len, max_size, raw_message.topic
+                ),
             ));
         }
 
@@ -78,15 +185,21 @@ impl DataTransform for SnappyTransform {
     /// Provides the snappy compression logic to gossipsub.
     fn outbound_transform(
         &self,
-        _topic: &TopicHash,
+        topic: &TopicHash,
         data: Vec<u8>,
     ) -> Result<Vec<u8>, std::io::Error> {
         // Currently we are not employing topic-based compression. Everything is expected to be
         // snappy compressed.
-        if data.len() > self.max_size_per_message {
+        let max_size = self.max_size_of(topic);
+        if data.len() > max_size {
             return Err(Error::new(
                 ErrorKind::InvalidData,
-                "ssz_snappy Encoded data > GOSSIP_MAX_SIZE",
+                format!(
+                    "ssz_snappy Encoded data of {} bytes > {} bytes limit of topic {}",
+                    data.len(),
+                    max_size,
+                    topic
+                ),
             ));
         }
         let mut encoder = Encoder::new();
@@ -114,10 +227,14 @@ impl<T: EthSpec> PubsubMessage<T> {
             PubsubMessage::AttesterSlashing(_) => GossipKind::AttesterSlashing,
             PubsubMessage::SignedContributionAndProof(_) => GossipKind::SignedContributionAndProof,
             PubsubMessage::SyncCommitteeMessage(data) => GossipKind::SyncCommitteeMessage(data.0),
+            PubsubMessage::Application(data) => GossipKind::Application(data.topic.clone()),
         }
     }
 
     /// This decodes `data` into a `PubsubMessage` given a topic.
+    ///
+    /// The payloads of application messages are returned as received, they still need to be
+    /// decoded with the codec of their topic.
     /* Note: This is assuming we are not hashing topics. If we choose to hash topics, these will
      * need to be modified.
      */
@@ -205,6 +322,12 @@ impl<T: EthSpec> PubsubMessage<T> {
                             sync_committee,
                         ))))
                     }
+                    GossipKind::Application(topic) => {
+                        Ok(PubsubMessage::Application(Box::new(ApplicationMessage {
+                            topic: topic.clone(),
+                            payload: data.to_vec(),
+                        })))
+                    }
                 }
             }
         }
@@ -212,6 +335,9 @@ impl<T: EthSpec> PubsubMessage<T> {
 
     /// Encodes a `PubsubMessage` based on the topic encodings. The first known encoding is used. If
     /// no encoding is known, and error is returned.
+    ///
+    /// The payloads of application messages are returned as is, the topic's codec is applied by
+    /// the behaviour.
     pub fn encode(&self, _encoding: GossipEncoding) -> Vec<u8> {
         // Currently do not employ encoding strategies based on the topic. All messages are ssz
         // encoded.
@@ -226,6 +352,7 @@ impl<T: EthSpec> PubsubMessage<T> {
             PubsubMessage::Attestation(data) => data.1.as_ssz_bytes(),
             PubsubMessage::SignedContributionAndProof(data) => data.as_ssz_bytes(),
             PubsubMessage::SyncCommitteeMessage(data) => data.1.as_ssz_bytes(),
+            PubsubMessage::Application(data) => data.payload.clone(),
         }
     }
 }
@@ -260,6 +387,70 @@ impl<T: EthSpec> std::fmt::Display for PubsubMessage<T> {
             PubsubMessage::SyncCommitteeMessage(data) => {
                 write!(f, "Sync committee message: subnet_id: {}", *data.0)
             }
+            PubsubMessage::Application(data) => {
+                write!(f, "Application message: topic: {}", data.topic)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::gossipsub::IdentTopic as Topic;
+
+    fn topic_hash(kind: GossipKind) -> TopicHash {
+        Topic::from(GossipTopic::new(kind, GossipEncoding::SSZSnappy, [0; 4])).hash()
+    }
+
+    #[test]
+    fn test_max_size_per_kind() {
+        let max_size_per_kind = vec![
+            (GossipKind::Attestation(SubnetId::new(0)), 100),
+            (GossipKind::Application("blobs".into()), 2000),
+        ];
+        let transform = SnappyTransform::new(1000).with_max_size_per_kind(max_size_per_kind);
+        assert_eq!(transform.max_size(), 2000);
+
+        // the attestation limit applies to all subnets
+        let attestation = topic_hash(GossipKind::Attestation(SubnetId::new(5)));
+        assert!(transform
+            .outbound_transform(&attestation, vec![0; 101])
+            .is_err());
+        assert!(transform
+            .outbound_transform(&attestation, vec![0; 100])
+            .is_ok());
+
+        // the application topic allows messages above the global limit
+        let application = topic_hash(GossipKind::Application("blobs".into()));
+        assert!(transform
+            .outbound_transform(&application, vec![0; 1500])
+            .is_ok());
+        assert!(transform
+            .outbound_transform(&application, vec![0; 2001])
+            .is_err());
+
+        // other topics keep the global limit
+        let block = topic_hash(GossipKind::BeaconBlock);
+        assert!(transform.outbound_transform(&block, vec![0; 1001]).is_err());
+    }
+
+    #[test]
+    fn test_overlapping_max_size_per_kind() {
+        // the limits of two attestation subnets cover the same topics, the last one applies
+        let max_size_per_kind = vec![
+            (GossipKind::Attestation(SubnetId::new(3)), 100),
+            (GossipKind::Attestation(SubnetId::new(0)), 200),
+        ];
+        let transform = SnappyTransform::new(1000).with_max_size_per_kind(max_size_per_kind);
+        for subnet in 0..8 {
+            let attestation = topic_hash(GossipKind::Attestation(SubnetId::new(subnet)));
+            assert!(transform
+                .outbound_transform(&attestation, vec![0; 200])
+                .is_ok());
+            assert!(transform
+                .outbound_transform(&attestation, vec![0; 201])
+                .is_err());
         }
     }
 }
@@ -18,6 +18,8 @@ pub const PROPOSER_SLASHING_TOPIC: &str = "proposer_slashing";
 pub const ATTESTER_SLASHING_TOPIC: &str = "attester_slashing";
 pub const SIGNED_CONTRIBUTION_AND_PROOF_TOPIC: &str = "sync_committee_contribution_and_proof";
 pub const SYNC_COMMITTEE_PREFIX_TOPIC: &str = "sync_committee_";
+/// Prefix of application topic names, keeping them apart from the eth2 topics.
+pub const APPLICATION_TOPIC_PREFIX: &str = "app_";
 
 pub const CORE_TOPICS: [GossipKind; 6] = [
     GossipKind::BeaconBlock,
@@ -63,6 +65,10 @@ pub enum GossipKind {
     /// Topic for publishing unaggregated sync committee signatures on a particular subnet.
     #[strum(serialize = "sync_committee")]
     SyncCommitteeMessage(SyncSubnetId),
+    /// Topic for publishing the messages of an application built on the eth2 gossip network.
+    /// The topic name is namespaced with `APPLICATION_TOPIC_PREFIX`.
+    #[strum(serialize = "application")]
+    Application(String),
 }
 
 impl std::fmt::Display for GossipKind {
@@ -72,11 +78,34 @@ impl std::fmt::Display for GossipKind {
             GossipKind::SyncCommitteeMessage(subnet_id) => {
                 write!(f, "sync_committee_{}", **subnet_id)
             }
+            GossipKind::Application(name) => write!(f, "{}{}", APPLICATION_TOPIC_PREFIX, name),
             x => f.write_str(x.as_ref()),
         }
     }
 }
 
+/// Restricts the gossipsub topics that may be subscribed to.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TopicFilterConfig {
+    /// If set, only these kinds of topics may be subscribed to.
+    pub allow: Option<Vec<GossipKind>>,
+    /// Kinds of topics that are never subscribed to. This takes precedence over `allow`.
+    pub deny: Vec<GossipKind>,
+}
+
+impl TopicFilterConfig {
+    /// Returns `true` if topics of the given kind may be subscribed to.
+    pub fn permits(&self, kind: &GossipKind) -> bool {
+        if self.deny.contains(kind) {
+            return false;
+        }
+        self.allow
+            .as_ref()
+            .map_or(true, |allowed| allowed.contains(kind))
+    }
+}
+
 /// The known encoding types for gossipsub messages.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum GossipEncoding {
@@ -146,7 +175,10 @@ impl GossipTopic {
                         Subnet::Attestation(s) => GossipKind::Attestation(s),
                         Subnet::SyncCommittee(s) => GossipKind::SyncCommitteeMessage(s),
                     },
-                    None => return Err(format!("Unknown topic: {}", topic)),
+                    None => match application_topic_name(topic) {
+                        Some(name) => GossipKind::Application(name.into()),
+                        None => return Err(format!("Unknown topic: {}", topic)),
+                    },
                 },
             };
 
@@ -192,6 +224,7 @@ impl From<GossipTopic> for String {
             GossipKind::SyncCommitteeMessage(index) => {
                 format!("{}{}", SYNC_COMMITTEE_PREFIX_TOPIC, *index)
             }
+            GossipKind::Application(name) => format!("{}{}", APPLICATION_TOPIC_PREFIX, name),
         };
         format!(
             "/{}/{}/{}/{}",
@@ -220,6 +253,7 @@ impl std::fmt::Display for GossipTopic {
             GossipKind::SyncCommitteeMessage(index) => {
                 format!("{}{}", SYNC_COMMITTEE_PREFIX_TOPIC, *index)
             }
+            GossipKind::Application(ref name) => format!("{}{}", APPLICATION_TOPIC_PREFIX, name),
         };
         write!(
             f,
@@ -268,6 +302,13 @@ fn committee_topic_index(topic: &str) -> Option<Subnet> {
     None
 }
 
+/// Returns the name of an application topic, without its prefix.
+fn application_topic_name(topic: &str) -> Option<&str> {
+    topic
+        .strip_prefix(APPLICATION_TOPIC_PREFIX)
+        .filter(|name| !name.is_empty())
+}
+
 #[cfg(test)]
 mod tests {
     use super::GossipKind::*;
@@ -292,6 +333,7 @@ mod tests {
                 VoluntaryExit,
                 ProposerSlashing,
                 AttesterSlashing,
+                Application("blobs".into()),
             ]
             .iter()
             {
@@ -346,6 +388,14 @@ mod tests {
         );
         assert!(GossipTopic::decode(bad_encoding_str.as_str()).is_err());
 
+        let nameless_application_str = create_topic(
+            TOPIC_PREFIX,
+            GOOD_FORK_DIGEST,
+            APPLICATION_TOPIC_PREFIX,
+            SSZ_SNAPPY_ENCODING_POSTFIX,
+        );
+        assert!(GossipTopic::decode(nameless_application_str.as_str()).is_err());
+
         // Extra parts
         assert!(
             GossipTopic::decode("/eth2/e1925f3b/beacon_block/ssz_snappy/yolo").is_err(),
@@ -357,6 +407,18 @@ mod tests {
         assert!(GossipTopic::decode("////").is_err());
     }
 
+    #[test]
+    fn test_application_topic() {
+        let topic = GossipTopic::new(
+            Application("blobs".into()),
+            GossipEncoding::SSZSnappy,
+            [1, 2, 3, 4],
+        );
+        let topic_str: String = topic.clone().into();
+        assert_eq!(topic_str, "/eth2/01020304/app_blobs/ssz_snappy");
+        assert_eq!(GossipTopic::decode(&topic_str), Ok(topic));
+    }
+
     #[test]
     fn test_subnet_from_topic_hash() {
         let topic_hash = TopicHash::from_raw("/eth2/e1925f3b/beacon_block/ssz_snappy");
@@ -394,5 +456,36 @@ mod tests {
         assert_eq!("voluntary_exit", VoluntaryExit.as_ref());
         assert_eq!("proposer_slashing", ProposerSlashing.as_ref());
         assert_eq!("attester_slashing", AttesterSlashing.as_ref());
+        assert_eq!("application", Application("blobs".into()).as_ref());
+    }
+
+    #[test]
+    fn test_topic_filter_allow() {
+        let filter = TopicFilterConfig {
+            allow: Some(vec![BeaconBlock, Attestation(SubnetId::new(1))]),
+            deny: vec![],
+        };
+        assert!(filter.permits(&BeaconBlock));
+        assert!(filter.permits(&Attestation(SubnetId::new(1))));
+        assert!(!filter.permits(&Attestation(SubnetId::new(2))));
+        assert!(!filter.permits(&VoluntaryExit));
+    }
+
+    #[test]
+    fn test_topic_filter_deny() {
+        let filter = TopicFilterConfig {
+            allow: None,
+            deny: vec![SignedContributionAndProof],
+        };
+        assert!(!filter.permits(&SignedContributionAndProof));
+        assert!(filter.permits(&BeaconBlock));
+
+        // denying takes precedence over allowing
+        let filter = TopicFilterConfig {
+            allow: Some(vec![BeaconBlock, VoluntaryExit]),
+            deny: vec![VoluntaryExit],
+        };
+        assert!(filter.permits(&BeaconBlock));
+        assert!(!filter.permits(&VoluntaryExit));
     }
 }
@@ -1,16 +1,17 @@
 #![cfg(test)]
+use libp2p::core::transport::MemoryTransport;
 use libp2p::gossipsub::GossipsubConfigBuilder;
 use lighthouse_network::Enr;
 use lighthouse_network::EnrExt;
 use lighthouse_network::Multiaddr;
 use lighthouse_network::Service as LibP2PService;
-use lighthouse_network::{Libp2pEvent, NetworkConfig};
+use lighthouse_network::{error, Libp2pEvent, NetworkConfig};
 use slog::{debug, error, o, Drain};
 use std::sync::Arc;
 use std::sync::Weak;
 use std::time::Duration;
 use tokio::runtime::Runtime;
-use types::{ChainSpec, EnrForkId, EthSpec, ForkContext, Hash256, MinimalEthSpec};
+use types::{ChainSpec, EnrForkId, EthSpec, ForkContext, ForkName, Hash256, MinimalEthSpec};
 use unused_port::unused_tcp_port;
 
 #[allow(clippy::type_complexity)]
@@ -93,25 +94,82 @@ pub async fn build_libp2p_instance(
 ) -> Libp2pInstance {
     let port = unused_tcp_port().unwrap();
     let config = build_config(port, boot_nodes);
+    build_libp2p_instance_from_config(rt, config, log).await
+}
+
+pub async fn build_libp2p_instance_from_config(
+    rt: Weak<Runtime>,
+    config: NetworkConfig,
+    log: slog::Logger,
+) -> Libp2pInstance {
+    try_build_libp2p_instance_from_config(rt, config, log)
+        .await
+        .expect("should build libp2p instance")
+}
+
+pub async fn try_build_libp2p_instance_from_config(
+    rt: Weak<Runtime>,
+    config: NetworkConfig,
+    log: slog::Logger,
+) -> error::Result<Libp2pInstance> {
     // launch libp2p service
 
     let (signal, exit) = exit_future::signal();
     let (shutdown_tx, _) = futures::channel::mpsc::channel(1);
     let executor = task_executor::TaskExecutor::new(rt, exit, log.clone(), shutdown_tx);
+    let fork_context = Arc::new(fork_context());
+    // advertise the genesis fork, so that its gossipsub topics pass the subscription filter
+    let enr_fork_id = EnrForkId {
+        fork_digest: fork_context
+            .to_context_bytes(ForkName::Base)
+            .expect("fork context has the base fork"),
+        ..EnrForkId::default()
+    };
+    let libp2p_context = lighthouse_network::Context {
+        config: &config,
+        enr_fork_id,
+        fork_context,
+        chain_spec: &ChainSpec::minimal(),
+        gossipsub_registry: None,
+    };
+    let (_, service) = LibP2PService::new(executor, libp2p_context, &log).await?;
+    Ok(Libp2pInstance(service, signal))
+}
+
+// Builds a node running over an in-memory transport, with discovery disabled so that no socket is
+// bound. The node listens on a random memory address, reported by a `NewListenAddr` event.
+#[allow(dead_code)]
+pub async fn build_memory_libp2p_instance(rt: Weak<Runtime>, log: slog::Logger) -> Libp2pInstance {
+    let mut config = build_config(unused_tcp_port().unwrap(), vec![]);
+    config.disable_discovery = true;
+
+    let (signal, exit) = exit_future::signal();
+    let (shutdown_tx, _) = futures::channel::mpsc::channel(1);
+    let executor = task_executor::TaskExecutor::new(rt, exit, log.clone(), shutdown_tx);
+    let fork_context = Arc::new(fork_context());
+    let enr_fork_id = EnrForkId {
+        fork_digest: fork_context
+            .to_context_bytes(ForkName::Base)
+            .expect("fork context has the base fork"),
+        ..EnrForkId::default()
+    };
     let libp2p_context = lighthouse_network::Context {
         config: &config,
-        enr_fork_id: EnrForkId::default(),
-        fork_context: Arc::new(fork_context()),
+        enr_fork_id,
+        fork_context,
         chain_spec: &ChainSpec::minimal(),
         gossipsub_registry: None,
     };
-    Libp2pInstance(
-        LibP2PService::new(executor, libp2p_context, &log)
-            .await
-            .expect("should build libp2p instance")
-            .1,
-        signal,
+    let (_, service) = LibP2PService::new_with_transport(
+        executor,
+        libp2p_context,
+        MemoryTransport::default(),
+        vec!["/memory/0".parse().unwrap()],
+        &log,
     )
+    .await
+    .expect("should build libp2p instance");
+    Libp2pInstance(service, signal)
 }
 
 #[allow(dead_code)]
@@ -161,7 +219,12 @@ pub async fn build_node_pair(
     let mut sender = build_libp2p_instance(rt.clone(), vec![], sender_log).await;
     let mut receiver = build_libp2p_instance(rt, vec![], receiver_log).await;
 
-    let receiver_multiaddr = receiver.swarm.behaviour_mut().local_enr().multiaddr()[1].clone();
+    let receiver_multiaddr = receiver
+        .swarm
+        .behaviour_mut()
+        .local_enr()
+        .multiaddr_p2p_tcp()[0]
+        .clone();
 
     // let the two nodes set up listeners
     let sender_fut = async {
@@ -187,7 +250,7 @@ pub async fn build_node_pair(
         _ = joined => {}
     }
 
-    match libp2p::Swarm::dial(&mut sender.swarm, receiver_multiaddr.clone()) {
+    match sender.dial_multiaddr(receiver_multiaddr.clone()) {
         Ok(()) => {
             debug!(log, "Sender dialed receiver"; "address" => format!("{:?}", receiver_multiaddr))
         }
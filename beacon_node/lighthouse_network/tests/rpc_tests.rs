@@ -1,19 +1,25 @@
 #![cfg(test)]
+use lighthouse_network::multiaddr::Protocol;
 use lighthouse_network::rpc::methods::*;
 use lighthouse_network::{
-    rpc::max_rpc_size, BehaviourEvent, Libp2pEvent, ReportSource, Request, Response,
+    metrics,
+    rpc::{max_rpc_size, Protocol as RPCProtocol, RPCError, RateLimiterConfig},
+    BehaviourEvent, EnrExt, EventKind, Libp2pEvent, ReportSource, Request, RequestAction,
+    RequestInterceptor, Response, StatusHook,
 };
-use slog::{debug, warn, Level};
+use slog::{debug, o, warn, Level};
 use ssz::Encode;
-use ssz_types::VariableList;
+use ssz_types::{FixedVector, VariableList};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::runtime::Runtime;
 use tokio::time::sleep;
 use types::{
-    BeaconBlock, BeaconBlockAltair, BeaconBlockBase, BeaconBlockMerge, Epoch, EthSpec, ForkContext,
-    Hash256, MinimalEthSpec, Signature, SignedBeaconBlock, Slot,
+    BeaconBlock, BeaconBlockAltair, BeaconBlockBase, BeaconBlockHeader, BeaconBlockMerge,
+    EnrForkId, Epoch, EthSpec, ForkContext, Hash256, MinimalEthSpec, Signature, SignedBeaconBlock,
+    Slot, SyncCommittee,
 };
+use unused_port::unused_tcp_port;
 
 mod common;
 
@@ -143,6 +149,349 @@ fn test_status_rpc() {
     })
 }
 
+// Tests that the status hook of the sender modifies the STATUS request received by its peer.
+#[test]
+fn test_status_hook_overrides_sent_status() {
+    let rt = Arc::new(Runtime::new().unwrap());
+    let log = common::build_log(Level::Debug, false);
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+        let receiver_peer_id = receiver.local_peer_id;
+
+        sender.set_status_hook(Some(StatusHook::new(move |peer_id, status| {
+            assert_eq!(*peer_id, receiver_peer_id);
+            status.head_slot = Slot::new(42);
+            status.head_root = Hash256::from_low_u64_be(42);
+        })));
+
+        let rpc_request = Request::Status(StatusMessage {
+            fork_digest: [0; 4],
+            finalized_root: Hash256::from_low_u64_be(0),
+            finalized_epoch: Epoch::new(1),
+            head_root: Hash256::from_low_u64_be(0),
+            head_slot: Slot::new(1),
+        });
+
+        let sender_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) =
+                    sender.next_event().await
+                {
+                    sender.send_request(peer_id, 10, rpc_request.clone());
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                    request: Request::Status(status),
+                    ..
+                }) = receiver.next_event().await
+                {
+                    return status;
+                }
+            }
+        };
+
+        let status = tokio::select! {
+            _ = sender_future => unreachable!(),
+            status = receiver_future => status,
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        };
+        assert_eq!(status.head_slot, Slot::new(42));
+        assert_eq!(status.head_root, Hash256::from_low_u64_be(42));
+        assert_eq!(status.finalized_epoch, Epoch::new(1));
+    })
+}
+
+// Tests a STATUS request between two nodes running over an in-memory transport.
+#[test]
+fn test_status_rpc_over_memory_transport() {
+    let rt = Arc::new(Runtime::new().unwrap());
+    let log = common::build_log(Level::Debug, false);
+
+    rt.block_on(async {
+        let mut sender = common::build_memory_libp2p_instance(
+            Arc::downgrade(&rt),
+            log.new(o!("who" => "sender")),
+        )
+        .await;
+        let mut receiver = common::build_memory_libp2p_instance(
+            Arc::downgrade(&rt),
+            log.new(o!("who" => "receiver")),
+        )
+        .await;
+
+        let mut receiver_multiaddr = loop {
+            if let Libp2pEvent::NewListenAddr(addr) = receiver.next_event().await {
+                break addr;
+            }
+        };
+        assert!(receiver_multiaddr
+            .iter()
+            .all(|protocol| matches!(protocol, Protocol::Memory(_))));
+        receiver_multiaddr.push(Protocol::P2p(receiver.local_peer_id.into()));
+        sender.dial_multiaddr(receiver_multiaddr).unwrap();
+
+        let status = StatusMessage {
+            fork_digest: [0; 4],
+            finalized_root: Hash256::from_low_u64_be(0),
+            finalized_epoch: Epoch::new(1),
+            head_root: Hash256::from_low_u64_be(0),
+            head_slot: Slot::new(1),
+        };
+        let rpc_request = Request::Status(status.clone());
+        let rpc_response = Response::Status(status);
+
+        let sender_future = async {
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        sender
+                            .swarm
+                            .behaviour_mut()
+                            .send_request(peer_id, 10, rpc_request.clone());
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                        id: 10,
+                        response,
+                        ..
+                    }) => {
+                        assert_eq!(response, rpc_response);
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                    peer_id,
+                    id,
+                    request,
+                }) = receiver.next_event().await
+                {
+                    if request == rpc_request {
+                        receiver.swarm.behaviour_mut().send_successful_response(
+                            peer_id,
+                            id,
+                            rpc_response.clone(),
+                        );
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a completed STATUS request is timed on both the requesting and the responding side.
+#[test]
+fn test_rpc_request_duration_metrics() {
+    let rt = Arc::new(Runtime::new().unwrap());
+    let log = common::build_log(Level::Debug, false);
+
+    // other tests of this binary may also be timing STATUS requests, so only an increase of the
+    // sample counts is checked
+    let sample_count = |histogram: &metrics::Result<metrics::HistogramVec>| {
+        metrics::get_histogram(histogram, &["status"])
+            .map(|h| h.get_sample_count())
+            .unwrap_or(0)
+    };
+    let outbound_before = sample_count(&metrics::RPC_OUTBOUND_REQUEST_DURATION);
+    let inbound_before = sample_count(&metrics::RPC_INBOUND_REQUEST_DURATION);
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+
+        let status = StatusMessage {
+            fork_digest: [0; 4],
+            finalized_root: Hash256::from_low_u64_be(0),
+            finalized_epoch: Epoch::new(1),
+            head_root: Hash256::from_low_u64_be(0),
+            head_slot: Slot::new(1),
+        };
+        let rpc_request = Request::Status(status.clone());
+        let rpc_response = Response::Status(status);
+
+        let sender_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) =
+                    sender.next_event().await
+                {
+                    sender
+                        .swarm
+                        .behaviour_mut()
+                        .send_request(peer_id, 10, rpc_request.clone());
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                    peer_id,
+                    id,
+                    request,
+                }) = receiver.next_event().await
+                {
+                    if request == rpc_request {
+                        receiver.swarm.behaviour_mut().send_successful_response(
+                            peer_id,
+                            id,
+                            rpc_response.clone(),
+                        );
+                    }
+                }
+            }
+        };
+
+        // the samples are recorded once the streams close, after the response is delivered
+        let metrics_future = async {
+            while sample_count(&metrics::RPC_OUTBOUND_REQUEST_DURATION) <= outbound_before
+                || sample_count(&metrics::RPC_INBOUND_REQUEST_DURATION) <= inbound_before
+            {
+                sleep(Duration::from_millis(50)).await;
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = metrics_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a STATUS with a fork digest different from the receiver's is reported once
+#[test]
+fn test_status_rpc_fork_digest_mismatch() {
+    // set up the logging. The level and enabled logging or not
+    let log_level = Level::Debug;
+    let enable_logging = false;
+
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    let log = common::build_log(log_level, enable_logging);
+
+    rt.block_on(async {
+        // get sender/receiver
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+        let sender_peer_id = sender.local_peer_id;
+
+        // the receiver is on a different fork than the sender
+        let receiver_fork_digest = [1, 2, 3, 4];
+        receiver
+            .swarm
+            .behaviour_mut()
+            .update_fork_version(EnrForkId {
+                fork_digest: receiver_fork_digest,
+                ..EnrForkId::default()
+            });
+
+        // Dummy STATUS RPC message, with the sender's fork digest
+        let status = StatusMessage {
+            fork_digest: [0; 4],
+            finalized_root: Hash256::from_low_u64_be(0),
+            finalized_epoch: Epoch::new(1),
+            head_root: Hash256::from_low_u64_be(0),
+            head_slot: Slot::new(1),
+        };
+        let rpc_request = Request::Status(status.clone());
+        let rpc_response = Response::Status(status);
+
+        // build the sender future, which sends two STATUS messages in sequence
+        let sender_future = async {
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        debug!(log, "Sending first RPC");
+                        sender
+                            .swarm
+                            .behaviour_mut()
+                            .send_request(peer_id, 10, rpc_request.clone());
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                        peer_id,
+                        id: 10,
+                        ..
+                    }) => {
+                        debug!(log, "Sending second RPC");
+                        sender
+                            .swarm
+                            .behaviour_mut()
+                            .send_request(peer_id, 11, rpc_request.clone());
+                    }
+                    _ => {}
+                }
+            }
+        };
+
+        // build the receiver future
+        let receiver_future = async {
+            let mut mismatches = 0;
+            let mut requests = 0;
+            loop {
+                match receiver.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::ForkDigestMismatch {
+                        peer_id,
+                        their_fork_digest,
+                        our_fork_digest,
+                    }) => {
+                        assert_eq!(peer_id, sender_peer_id);
+                        assert_eq!(their_fork_digest, [0; 4]);
+                        assert_eq!(our_fork_digest, receiver_fork_digest);
+                        mismatches += 1;
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                        peer_id,
+                        id,
+                        request,
+                    }) => {
+                        assert_eq!(request, rpc_request);
+                        requests += 1;
+                        if requests == 2 {
+                            // the first STATUS has been reported, the second one is not
+                            assert_eq!(mismatches, 1);
+                            return;
+                        }
+                        receiver.swarm.behaviour_mut().send_successful_response(
+                            peer_id,
+                            id,
+                            rpc_response.clone(),
+                        );
+                    }
+                    _ => {} // Ignore other events
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
 // Tests a streamed BlocksByRange RPC Message
 #[test]
 #[allow(clippy::single_match)]
@@ -165,7 +514,7 @@ fn test_blocks_by_range_chunked_rpc() {
         let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
             start_slot: 0,
             count: messages_to_send,
-            step: 0,
+            step: 1,
         });
 
         let spec = E::default_spec();
@@ -282,15 +631,15 @@ fn test_blocks_by_range_chunked_rpc() {
     })
 }
 
-// Tests rejection of blocks over `MAX_RPC_SIZE`.
+// Tests a streamed BlobsByRange RPC Message
 #[test]
 #[allow(clippy::single_match)]
-fn test_blocks_by_range_over_limit() {
+fn test_blobs_by_range_chunked_rpc() {
     // set up the logging. The level and enabled logging or not
     let log_level = Level::Debug;
     let enable_logging = false;
 
-    let messages_to_send = 5;
+    let messages_to_send = 3;
 
     let log = common::build_log(log_level, enable_logging);
 
@@ -300,25 +649,30 @@ fn test_blocks_by_range_over_limit() {
         // get sender/receiver
         let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
 
-        // BlocksByRange Request
-        let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
+        // BlobsByRange Request
+        let rpc_request = Request::BlobsByRange(BlobsByRangeRequest {
             start_slot: 0,
             count: messages_to_send,
-            step: 0,
         });
 
-        // BlocksByRange Response
-        let full_block = merge_block_large(&common::fork_context());
-        let signed_full_block = SignedBeaconBlock::from_block(full_block, Signature::empty());
-        let rpc_response_merge_large = Response::BlocksByRange(Some(Box::new(signed_full_block)));
+        // BlobsByRange Response
+        let blobs_sidecar = BlobsSidecar {
+            beacon_block_root: Hash256::zero(),
+            beacon_block_slot: Slot::new(0),
+            blobs: VariableList::from(vec![FixedVector::from(vec![42; 131072]); 2]),
+            kzg_aggregated_proof: FixedVector::from(vec![1; 48]),
+        };
+        let rpc_response = Response::BlobsByRange(Some(Box::new(blobs_sidecar)));
 
+        // keep count of the number of messages received
+        let mut messages_received = 0;
         let request_id = messages_to_send as usize;
         // build the sender future
         let sender_future = async {
             loop {
                 match sender.next_event().await {
                     Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
-                        // Send a STATUS message
+                        // Send a BlobsByRange request
                         debug!(log, "Sending RPC");
                         sender.swarm.behaviour_mut().send_request(
                             peer_id,
@@ -326,10 +680,26 @@ fn test_blocks_by_range_over_limit() {
                             rpc_request.clone(),
                         );
                     }
-                    // The request will fail because the sender will refuse to send anything > MAX_RPC_SIZE
-                    Libp2pEvent::Behaviour(BehaviourEvent::RPCFailed { id, .. }) => {
-                        assert_eq!(id, request_id);
-                        return;
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                        peer_id: _,
+                        id: _,
+                        response,
+                    }) => {
+                        warn!(log, "Sender received a response");
+                        match response {
+                            Response::BlobsByRange(Some(_)) => {
+                                assert_eq!(response, rpc_response.clone());
+                                messages_received += 1;
+                                warn!(log, "Chunk received");
+                            }
+                            Response::BlobsByRange(None) => {
+                                // should be exactly `messages_to_send` messages before terminating
+                                assert_eq!(messages_received, messages_to_send);
+                                // end the test
+                                return;
+                            }
+                            _ => panic!("Invalid RPC received"),
+                        }
                     }
                     _ => {} // Ignore other behaviour events
                 }
@@ -349,7 +719,6 @@ fn test_blocks_by_range_over_limit() {
                             // send the response
                             warn!(log, "Receiver got request");
                             for _ in 0..messages_to_send {
-                                let rpc_response = rpc_response_merge_large.clone();
                                 receiver.swarm.behaviour_mut().send_successful_response(
                                     peer_id,
                                     id,
@@ -360,7 +729,7 @@ fn test_blocks_by_range_over_limit() {
                             receiver.swarm.behaviour_mut().send_successful_response(
                                 peer_id,
                                 id,
-                                Response::BlocksByRange(None),
+                                Response::BlobsByRange(None),
                             );
                         }
                     }
@@ -379,29 +748,323 @@ fn test_blocks_by_range_over_limit() {
     })
 }
 
-// Tests that a streamed BlocksByRange RPC Message terminates when all expected chunks were received
+// Tests a LightClientBootstrap RPC message
 #[test]
-fn test_blocks_by_range_chunked_rpc_terminates_correctly() {
+#[allow(clippy::single_match)]
+fn test_light_client_bootstrap_rpc() {
     // set up the logging. The level and enabled logging or not
     let log_level = Level::Debug;
     let enable_logging = false;
 
-    let messages_to_send = 10;
-    let extra_messages_to_send = 10;
+    let rt = Arc::new(Runtime::new().unwrap());
 
     let log = common::build_log(log_level, enable_logging);
 
-    let rt = Arc::new(Runtime::new().unwrap());
-
     rt.block_on(async {
         // get sender/receiver
         let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
 
-        // BlocksByRange Request
-        let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
+        // LightClientBootstrap Request
+        let rpc_request = Request::LightClientBootstrap(LightClientBootstrapRequest {
+            root: Hash256::from_low_u64_be(42),
+        });
+
+        // LightClientBootstrap Response, a stub of the application's bootstrap
+        let bootstrap = LightClientBootstrap::<E> {
+            header: BeaconBlockHeader {
+                slot: Slot::new(8),
+                proposer_index: 1,
+                parent_root: Hash256::from_low_u64_be(41),
+                state_root: Hash256::from_low_u64_be(43),
+                body_root: Hash256::from_low_u64_be(44),
+            },
+            current_sync_committee: SyncCommittee::temporary().unwrap(),
+            current_sync_committee_branch: FixedVector::from(vec![Hash256::from_low_u64_be(1); 5]),
+        };
+        let rpc_response = Response::LightClientBootstrap(Box::new(bootstrap));
+
+        // build the sender future
+        let sender_future = async {
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        // Send a LightClientBootstrap request
+                        debug!(log, "Sending RPC");
+                        sender
+                            .swarm
+                            .behaviour_mut()
+                            .send_request(peer_id, 10, rpc_request.clone());
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                        peer_id: _,
+                        id: 10,
+                        response,
+                    }) => {
+                        // Should receive the decoded bootstrap
+                        debug!(log, "Sender Received");
+                        assert_eq!(response, rpc_response.clone());
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        };
+
+        // build the receiver future
+        let receiver_future = async {
+            loop {
+                match receiver.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                        peer_id,
+                        id,
+                        request,
+                    }) => {
+                        if request == rpc_request {
+                            // send the response
+                            debug!(log, "Receiver Received");
+                            receiver.swarm.behaviour_mut().send_successful_response(
+                                peer_id,
+                                id,
+                                rpc_response.clone(),
+                            );
+                        }
+                    }
+                    _ => {} // Ignore other events
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a slow consumer of a BlocksByRange response keeps the requesting handler from reading
+// the chunks sent by the peer far ahead of their consumption.
+#[test]
+fn test_blocks_by_range_response_back_pressure() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    // the response window of the RPC handler
+    let window = 2;
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+
+        let messages_to_send = 20;
+        let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
+            start_slot: 0,
+            count: messages_to_send,
+            step: 1,
+        });
+        let spec = E::default_spec();
+        let block = BeaconBlock::Base(BeaconBlockBase::<E>::full(&spec));
+        let rpc_response = Response::BlocksByRange(Some(Box::new(SignedBeaconBlock::from_block(
+            block,
+            Signature::empty(),
+        ))));
+
+        let sender_future = async {
+            let mut messages_received = 0;
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        sender
+                            .swarm
+                            .behaviour_mut()
+                            .send_request(peer_id, 10, rpc_request.clone());
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                        peer_id,
+                        response: Response::BlocksByRange(Some(block)),
+                        ..
+                    }) => {
+                        assert_eq!(Response::BlocksByRange(Some(block)), rpc_response);
+                        messages_received += 1;
+                        // the only stream to the peer reads no further ahead of us than its window
+                        let read_ahead = sender
+                            .swarm
+                            .behaviour()
+                            .unconsumed_response_chunks(&peer_id);
+                        assert!(read_ahead < window, "{} chunks read ahead", read_ahead);
+                        // consume slowly
+                        sleep(Duration::from_millis(20)).await;
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                        response: Response::BlocksByRange(None),
+                        ..
+                    }) => {
+                        assert_eq!(messages_received, messages_to_send);
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                    peer_id,
+                    id,
+                    request,
+                }) = receiver.next_event().await
+                {
+                    if request == rpc_request {
+                        // send the whole response at once
+                        for _ in 0..messages_to_send {
+                            receiver.swarm.behaviour_mut().send_successful_response(
+                                peer_id,
+                                id,
+                                rpc_response.clone(),
+                            );
+                        }
+                        receiver.swarm.behaviour_mut().send_successful_response(
+                            peer_id,
+                            id,
+                            Response::BlocksByRange(None),
+                        );
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    });
+}
+
+// Tests rejection of blocks over `MAX_RPC_SIZE`.
+#[test]
+#[allow(clippy::single_match)]
+fn test_blocks_by_range_over_limit() {
+    // set up the logging. The level and enabled logging or not
+    let log_level = Level::Debug;
+    let enable_logging = false;
+
+    let messages_to_send = 5;
+
+    let log = common::build_log(log_level, enable_logging);
+
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        // get sender/receiver
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+
+        // BlocksByRange Request
+        let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
+            start_slot: 0,
+            count: messages_to_send,
+            step: 1,
+        });
+
+        // BlocksByRange Response
+        let full_block = merge_block_large(&common::fork_context());
+        let signed_full_block = SignedBeaconBlock::from_block(full_block, Signature::empty());
+        let rpc_response_merge_large = Response::BlocksByRange(Some(Box::new(signed_full_block)));
+
+        let request_id = messages_to_send as usize;
+        // build the sender future
+        let sender_future = async {
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        // Send a STATUS message
+                        debug!(log, "Sending RPC");
+                        sender.swarm.behaviour_mut().send_request(
+                            peer_id,
+                            request_id,
+                            rpc_request.clone(),
+                        );
+                    }
+                    // The request will fail because the sender will refuse to send anything > MAX_RPC_SIZE
+                    Libp2pEvent::Behaviour(BehaviourEvent::RPCFailed { id, .. }) => {
+                        assert_eq!(id, request_id);
+                        return;
+                    }
+                    _ => {} // Ignore other behaviour events
+                }
+            }
+        };
+
+        // build the receiver future
+        let receiver_future = async {
+            loop {
+                match receiver.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                        peer_id,
+                        id,
+                        request,
+                    }) => {
+                        if request == rpc_request {
+                            // send the response
+                            warn!(log, "Receiver got request");
+                            for _ in 0..messages_to_send {
+                                let rpc_response = rpc_response_merge_large.clone();
+                                receiver.swarm.behaviour_mut().send_successful_response(
+                                    peer_id,
+                                    id,
+                                    rpc_response.clone(),
+                                );
+                            }
+                            // send the stream termination
+                            receiver.swarm.behaviour_mut().send_successful_response(
+                                peer_id,
+                                id,
+                                Response::BlocksByRange(None),
+                            );
+                        }
+                    }
+                    _ => {} // Ignore other events
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                    panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a streamed BlocksByRange RPC Message terminates when all expected chunks were received
+#[test]
+fn test_blocks_by_range_chunked_rpc_terminates_correctly() {
+    // set up the logging. The level and enabled logging or not
+    let log_level = Level::Debug;
+    let enable_logging = false;
+
+    let messages_to_send = 10;
+    let extra_messages_to_send = 10;
+
+    let log = common::build_log(log_level, enable_logging);
+
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        // get sender/receiver
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+
+        // BlocksByRange Request
+        let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
             start_slot: 0,
             count: messages_to_send,
-            step: 0,
+            step: 1,
         });
 
         // BlocksByRange Response
@@ -532,7 +1195,7 @@ fn test_blocks_by_range_single_empty_rpc() {
         let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
             start_slot: 0,
             count: 10,
-            step: 0,
+            step: 1,
         });
 
         // BlocksByRange Response
@@ -962,3 +1625,1047 @@ fn test_goodbye_rpc() {
         }
     })
 }
+
+// Tests that identical BlocksByRoot requests sent to a peer at the same time are sent once, with
+// the responses delivered for each request.
+#[test]
+#[allow(clippy::single_match)]
+fn test_identical_blocks_by_root_requests_are_coalesced() {
+    let log = common::build_log(Level::Debug, false);
+    let spec = E::default_spec();
+
+    let rt = Arc::new(Runtime::new().unwrap());
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+
+        let roots = vec![Hash256::from_low_u64_be(1), Hash256::from_low_u64_be(2)];
+        let rpc_request = Request::BlocksByRoot(BlocksByRootRequest {
+            block_roots: VariableList::from(roots.clone()),
+        });
+        // the same roots in another order
+        let reordered_request = Request::BlocksByRoot(BlocksByRootRequest {
+            block_roots: VariableList::from(roots.into_iter().rev().collect::<Vec<_>>()),
+        });
+
+        let full_block = BeaconBlock::Base(BeaconBlockBase::<E>::full(&spec));
+        let signed_full_block = SignedBeaconBlock::from_block(full_block, Signature::empty());
+        let rpc_response = Response::BlocksByRoot(Some(Box::new(signed_full_block)));
+
+        let mut requests_received = 0;
+
+        let sender_future = async {
+            let mut blocks_received = [0; 2];
+            let mut completed = [false; 2];
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        let behaviour = sender.swarm.behaviour_mut();
+                        behaviour.send_request(peer_id, 0, rpc_request.clone());
+                        behaviour.send_request(peer_id, 1, reordered_request.clone());
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                        id,
+                        response,
+                        ..
+                    }) => match response {
+                        Response::BlocksByRoot(Some(_)) => {
+                            assert_eq!(response, rpc_response);
+                            blocks_received[id] += 1;
+                        }
+                        Response::BlocksByRoot(None) => {
+                            assert_eq!(blocks_received[id], 1);
+                            completed[id] = true;
+                            if completed.iter().all(|done| *done) {
+                                return;
+                            }
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                match receiver.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                        peer_id,
+                        id,
+                        request: Request::BlocksByRoot(_),
+                    }) => {
+                        requests_received += 1;
+                        let behaviour = receiver.swarm.behaviour_mut();
+                        behaviour.send_successful_response(peer_id, id, rpc_response.clone());
+                        behaviour.send_successful_response(
+                            peer_id,
+                            id,
+                            Response::BlocksByRoot(None),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+        // both requests were answered by the single request that hit the wire
+        assert_eq!(requests_received, 1);
+    })
+}
+
+// Tests that a BlocksByRoot request made after an identical request in flight received its first
+// chunk is sent on its own, so that it receives the full response.
+#[test]
+#[allow(clippy::single_match)]
+fn test_blocks_by_root_request_is_not_coalesced_after_first_chunk() {
+    let log = common::build_log(Level::Debug, false);
+    let spec = E::default_spec();
+
+    let rt = Arc::new(Runtime::new().unwrap());
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+
+        let rpc_request = Request::BlocksByRoot(BlocksByRootRequest {
+            block_roots: VariableList::from(vec![Hash256::from_low_u64_be(1)]),
+        });
+
+        let full_block = BeaconBlock::Base(BeaconBlockBase::<E>::full(&spec));
+        let signed_full_block = SignedBeaconBlock::from_block(full_block, Signature::empty());
+        let rpc_response = Response::BlocksByRoot(Some(Box::new(signed_full_block)));
+
+        let mut requests_received = 0;
+
+        let sender_future = async {
+            let mut blocks_received = [0; 2];
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        sender
+                            .swarm
+                            .behaviour_mut()
+                            .send_request(peer_id, 0, rpc_request.clone());
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                        peer_id,
+                        id,
+                        response,
+                    }) => match response {
+                        Response::BlocksByRoot(Some(_)) => {
+                            blocks_received[id] += 1;
+                            // the first request is answering, its chunk would be missed
+                            if id == 0 {
+                                sender.swarm.behaviour_mut().send_request(
+                                    peer_id,
+                                    1,
+                                    rpc_request.clone(),
+                                );
+                            }
+                        }
+                        Response::BlocksByRoot(None) => {
+                            assert_eq!(blocks_received[id], 1);
+                            if id == 1 {
+                                return;
+                            }
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                match receiver.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                        peer_id,
+                        id,
+                        request: Request::BlocksByRoot(_),
+                    }) => {
+                        requests_received += 1;
+                        let behaviour = receiver.swarm.behaviour_mut();
+                        behaviour.send_successful_response(peer_id, id, rpc_response.clone());
+                        behaviour.send_successful_response(
+                            peer_id,
+                            id,
+                            Response::BlocksByRoot(None),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+        assert_eq!(requests_received, 2);
+    })
+}
+
+// Tests that block requests are refused while a node is not serving requests, while Status
+// requests are still answered.
+#[test]
+fn test_block_requests_refused_while_not_serving() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+        receiver.set_serving_requests(false);
+
+        let status = StatusMessage {
+            fork_digest: [0; 4],
+            finalized_root: Hash256::from_low_u64_be(0),
+            finalized_epoch: Epoch::new(1),
+            head_root: Hash256::from_low_u64_be(0),
+            head_slot: Slot::new(1),
+        };
+        let status_request_id = 1;
+        let blocks_request_id = 2;
+
+        let sender_future = async {
+            let mut status_answered = false;
+            let mut blocks_refused = false;
+            while !(status_answered && blocks_refused) {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        let behaviour = sender.swarm.behaviour_mut();
+                        behaviour.send_request(
+                            peer_id,
+                            status_request_id,
+                            Request::Status(status.clone()),
+                        );
+                        behaviour.send_request(
+                            peer_id,
+                            blocks_request_id,
+                            Request::BlocksByRange(BlocksByRangeRequest {
+                                start_slot: 0,
+                                count: 1,
+                                step: 1,
+                            }),
+                        );
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                        id,
+                        response,
+                        ..
+                    }) => {
+                        assert_eq!(id, status_request_id);
+                        assert_eq!(response, Response::Status(status.clone()));
+                        status_answered = true;
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::RPCFailed { id, .. }) => {
+                        assert_eq!(id, blocks_request_id);
+                        blocks_refused = true;
+                    }
+                    _ => {}
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                    peer_id,
+                    id,
+                    request,
+                }) = receiver.next_event().await
+                {
+                    match request {
+                        Request::Status(_) => {
+                            receiver.swarm.behaviour_mut().send_successful_response(
+                                peer_id,
+                                id,
+                                Response::Status(status.clone()),
+                            )
+                        }
+                        _ => panic!("Block request reached the application"),
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a request rejected by the request interceptor fails on the sender and never reaches
+// the application of the receiver.
+#[test]
+fn test_request_interceptor_rejects_oversized_ranges() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+        receiver.set_request_interceptor(Some(RequestInterceptor::new(
+            |_, request| match request {
+                Request::BlocksByRange(req) if req.count > 32 => {
+                    RequestAction::Reject(RPCError::ErrorResponse(
+                        RPCResponseErrorCode::InvalidRequest,
+                        "Range too large".into(),
+                    ))
+                }
+                _ => RequestAction::Allow,
+            },
+        )));
+
+        let request_id = 5;
+        let sender_future = async {
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        sender.swarm.behaviour_mut().send_request(
+                            peer_id,
+                            request_id,
+                            Request::BlocksByRange(BlocksByRangeRequest {
+                                start_slot: 0,
+                                count: 64,
+                                step: 1,
+                            }),
+                        );
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::RPCFailed { id, .. }) => {
+                        assert_eq!(id, request_id);
+                        return;
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived { .. }) => {
+                        panic!("Rejected request was answered")
+                    }
+                    _ => {}
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived { .. }) =
+                    receiver.next_event().await
+                {
+                    panic!("Rejected request reached the application");
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a BlocksByRange request for no blocks is answered without reaching the application
+// and without affecting the requests that follow it.
+#[test]
+fn test_blocks_by_range_zero_count() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+
+        let sender_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) =
+                    sender.next_event().await
+                {
+                    for (request_id, count) in [(1, 0), (2, 1)] {
+                        sender.swarm.behaviour_mut().send_request(
+                            peer_id,
+                            request_id,
+                            Request::BlocksByRange(BlocksByRangeRequest {
+                                start_slot: 0,
+                                count,
+                                step: 1,
+                            }),
+                        );
+                    }
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                    request: Request::BlocksByRange(req),
+                    ..
+                }) = receiver.next_event().await
+                {
+                    assert_eq!(req.count, 1, "Empty request reached the application");
+                    return;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that the bytes of an RPC response are accounted to the peer that sent it
+#[test]
+fn test_peer_bandwidth_counts_rpc_payload() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+
+        let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
+            start_slot: 0,
+            count: 1,
+            step: 1,
+        });
+
+        // random transactions, which snappy can't compress
+        let payload_len = 100 * 1024;
+        let mut block = BeaconBlockMerge::empty(&E::default_spec());
+        let txs = (0..100)
+            .map(|_| {
+                VariableList::from((0..1024).map(|_| rand::random::<u8>()).collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        block.body.execution_payload.transactions = VariableList::from(txs);
+        let signed_block =
+            SignedBeaconBlock::from_block(BeaconBlock::Merge(block), Signature::empty());
+        let rpc_response = Response::BlocksByRange(Some(Box::new(signed_block)));
+
+        let sender_future = async {
+            let mut before = None;
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        before = sender
+                            .swarm
+                            .behaviour()
+                            .network_globals()
+                            .peer_bandwidth(&peer_id);
+                        sender
+                            .swarm
+                            .behaviour_mut()
+                            .send_request(peer_id, 10, rpc_request.clone());
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                        peer_id,
+                        id: 10,
+                        response: Response::BlocksByRange(None),
+                    }) => {
+                        let before = before.expect("bandwidth is counted from the connection");
+                        let after = sender
+                            .swarm
+                            .behaviour()
+                            .network_globals()
+                            .peer_bandwidth(&peer_id)
+                            .unwrap();
+                        assert!(after.inbound - before.inbound >= payload_len);
+                        assert!(after.outbound > before.outbound);
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                    peer_id,
+                    id,
+                    request,
+                }) = receiver.next_event().await
+                {
+                    if request == rpc_request {
+                        let behaviour = receiver.swarm.behaviour_mut();
+                        behaviour.send_successful_response(peer_id, id, rpc_response.clone());
+                        behaviour.send_successful_response(
+                            peer_id,
+                            id,
+                            Response::BlocksByRange(None),
+                        );
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that the pending outbound requests to a peer are counted until the request times out.
+#[test]
+fn test_outbound_requests_counted_until_timeout() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+        let sender_globals = sender.swarm.behaviour().network_globals().clone();
+        let receiver_peer_id = *receiver.swarm.local_peer_id();
+
+        let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
+            start_slot: 0,
+            count: 1,
+            step: 1,
+        });
+
+        let sender_future = async {
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        assert_eq!(sender_globals.outbound_requests(&peer_id), 0);
+                        sender
+                            .swarm
+                            .behaviour_mut()
+                            .send_request(peer_id, 10, rpc_request.clone());
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::RPCFailed { id: 10, .. }) => return,
+                    _ => {}
+                }
+            }
+        };
+
+        // the receiver never responds
+        let receiver_future = async {
+            loop {
+                receiver.next_event().await;
+            }
+        };
+
+        // the handler accounts the request as soon as it is queued
+        let in_flight_future = async {
+            while sender_globals.outbound_requests(&receiver_peer_id) != 1 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+
+        tokio::select! {
+            _ = async { tokio::join!(sender_future, in_flight_future) } => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        // the timed out request is no longer counted
+        tokio::select! {
+            _ = async {
+                while sender_globals.outbound_requests(&receiver_peer_id) != 0 {
+                    sleep(Duration::from_millis(10)).await;
+                }
+            } => {}
+            _ = sleep(Duration::from_secs(5)) => {
+                panic!("The timed out request is still counted");
+            }
+        }
+    })
+}
+
+// Tests that a node filtering its events to RPC events is not given its peer connections.
+#[test]
+fn test_event_filter_only_yields_rpc_events() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+        receiver.set_event_filter([EventKind::Rpc]);
+
+        let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
+            start_slot: 0,
+            count: 1,
+            step: 1,
+        });
+
+        let sender_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) =
+                    sender.next_event().await
+                {
+                    sender
+                        .swarm
+                        .behaviour_mut()
+                        .send_request(peer_id, 10, rpc_request.clone());
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                let event = receiver.next_event().await;
+                assert_eq!(event.kind(), EventKind::Rpc);
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived { request, .. }) =
+                    event
+                {
+                    assert_eq!(request, rpc_request);
+                    return;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a BlocksByRange response is terminated before the block that would exceed the
+// response byte budget.
+#[test]
+fn test_blocks_by_range_response_byte_budget() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    let messages_to_send = 6;
+    let spec = E::default_spec();
+    let empty_block = BeaconBlock::empty(&spec);
+    let empty_signed = SignedBeaconBlock::from_block(empty_block, Signature::empty());
+    let block_bytes = empty_signed.as_ssz_bytes().len();
+    let rpc_response = Response::BlocksByRange(Some(Box::new(empty_signed)));
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        // room for three and a half blocks
+        config.rpc_response_byte_budget = Some(block_bytes * 7 / 2);
+        let mut receiver = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "receiver")),
+        )
+        .await;
+        let mut sender = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "sender")),
+        )
+        .await;
+        sender
+            .dial_multiaddr(common::get_enr(&receiver).multiaddr_p2p_tcp()[0].clone())
+            .unwrap();
+
+        let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
+            start_slot: 0,
+            count: messages_to_send,
+            step: 1,
+        });
+
+        let sender_future = async {
+            let mut messages_received = 0;
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        sender
+                            .swarm
+                            .behaviour_mut()
+                            .send_request(peer_id, 10, rpc_request.clone());
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                        response, ..
+                    }) => match response {
+                        Response::BlocksByRange(Some(_)) => messages_received += 1,
+                        Response::BlocksByRange(None) => return messages_received,
+                        _ => panic!("Invalid RPC received"),
+                    },
+                    _ => {}
+                }
+            }
+        };
+
+        let receiver_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                    peer_id,
+                    id,
+                    request,
+                }) = receiver.next_event().await
+                {
+                    assert_eq!(request, rpc_request);
+                    for _ in 0..messages_to_send {
+                        receiver.swarm.behaviour_mut().send_successful_response(
+                            peer_id,
+                            id,
+                            rpc_response.clone(),
+                        );
+                    }
+                    receiver.swarm.behaviour_mut().send_successful_response(
+                        peer_id,
+                        id,
+                        Response::BlocksByRange(None),
+                    );
+                }
+            }
+        };
+
+        tokio::select! {
+            messages_received = sender_future => {
+                assert_eq!(messages_received, 3);
+            }
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that the response timeout of a protocol can be overridden: a delayed Status response
+// times out after its 1 second override while a BlocksByRange response delayed as long arrives in
+// time.
+#[test]
+fn test_request_timeout_per_protocol() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.rpc_request_timeouts = [
+            (RPCProtocol::Status, Duration::from_secs(1)),
+            (RPCProtocol::BlocksByRange, Duration::from_secs(20)),
+        ]
+        .into_iter()
+        .collect();
+        let mut sender = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "sender")),
+        )
+        .await;
+        let mut receiver = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "receiver")),
+        )
+        .await;
+        let receiver_peer_id = *receiver.swarm.local_peer_id();
+        let receiver_multiaddr = receiver
+            .swarm
+            .behaviour_mut()
+            .local_enr()
+            .multiaddr_p2p_tcp()[0]
+            .clone();
+        sender.dial_multiaddr(receiver_multiaddr).unwrap();
+
+        let connected = async {
+            loop {
+                tokio::select! {
+                    event = sender.next_event() => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(_)) = event {
+                            return;
+                        }
+                    }
+                    _ = receiver.next_event() => {}
+                }
+            }
+        };
+        tokio::select! {
+            _ = connected => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        sender.swarm.behaviour_mut().send_request(
+            receiver_peer_id,
+            10,
+            Request::Status(StatusMessage {
+                fork_digest: [0; 4],
+                finalized_root: Hash256::from_low_u64_be(0),
+                finalized_epoch: Epoch::new(1),
+                head_root: Hash256::from_low_u64_be(0),
+                head_slot: Slot::new(1),
+            }),
+        );
+        let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
+            start_slot: 0,
+            count: 1,
+            step: 1,
+        });
+        sender
+            .swarm
+            .behaviour_mut()
+            .send_request(receiver_peer_id, 11, rpc_request.clone());
+
+        // the receiver is not polled, delaying both responses past the Status timeout
+        let stalled = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RPCFailed { id, error, .. }) =
+                    sender.next_event().await
+                {
+                    assert_eq!(id, 10, "BlocksByRange request failed");
+                    assert_eq!(error, RPCError::StreamTimeout);
+                    return;
+                }
+            }
+        };
+        tokio::select! {
+            _ = stalled => {}
+            _ = sleep(Duration::from_secs(4)) => {
+                panic!("Status request did not time out");
+            }
+        }
+
+        let responded = async {
+            loop {
+                tokio::select! {
+                    event = sender.next_event() => match event {
+                        Libp2pEvent::Behaviour(BehaviourEvent::ResponseReceived {
+                            id: 11,
+                            response,
+                            ..
+                        }) => {
+                            assert_eq!(response, Response::BlocksByRange(None));
+                            return;
+                        }
+                        Libp2pEvent::Behaviour(BehaviourEvent::RPCFailed { id: 11, .. }) => {
+                            panic!("BlocksByRange request timed out");
+                        }
+                        _ => {}
+                    },
+                    event = receiver.next_event() => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                            peer_id,
+                            id,
+                            request,
+                        }) = event
+                        {
+                            // the Status request has timed out already
+                            if request == rpc_request {
+                                receiver.swarm.behaviour_mut().send_successful_response(
+                                    peer_id,
+                                    id,
+                                    Response::BlocksByRange(None),
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        };
+        tokio::select! {
+            _ = responded => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that the inbound substreams a peer opens beyond the limit are reset without reaching the
+// application.
+#[test]
+fn test_max_inbound_substreams_per_peer() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.max_inbound_substreams_per_peer = Some(1);
+        let mut receiver = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "receiver")),
+        )
+        .await;
+        let mut sender = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "sender")),
+        )
+        .await;
+        let receiver_peer_id = *receiver.swarm.local_peer_id();
+        let receiver_multiaddr = receiver
+            .swarm
+            .behaviour_mut()
+            .local_enr()
+            .multiaddr_p2p_tcp()[0]
+            .clone();
+        sender.dial_multiaddr(receiver_multiaddr).unwrap();
+
+        // let the requests exchanged on connection complete
+        let connected = async {
+            loop {
+                tokio::select! {
+                    _ = sender.next_event() => {}
+                    _ = receiver.next_event() => {}
+                }
+            }
+        };
+        tokio::select! {
+            _ = connected => {}
+            _ = sleep(Duration::from_secs(2)) => {}
+        }
+
+        for request_id in 1..=3 {
+            sender.swarm.behaviour_mut().send_request(
+                receiver_peer_id,
+                request_id,
+                Request::BlocksByRange(BlocksByRangeRequest {
+                    start_slot: 0,
+                    count: 1,
+                    step: 1,
+                }),
+            );
+        }
+
+        // the receiver never responds, so only a single request may be awaiting its response
+        let exchange = async {
+            let mut requests_received = 0;
+            let mut requests_failed = 0;
+            while requests_failed < 2 {
+                tokio::select! {
+                    event = sender.next_event() => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::RPCFailed { .. }) = event {
+                            requests_failed += 1;
+                        }
+                    }
+                    event = receiver.next_event() => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                            request: Request::BlocksByRange(_),
+                            ..
+                        }) = event
+                        {
+                            requests_received += 1;
+                        }
+                    }
+                }
+            }
+            requests_received
+        };
+        tokio::select! {
+            requests_received = exchange => assert_eq!(requests_received, 1),
+            _ = sleep(Duration::from_secs(8)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that an inbound rate limit set in the network config is enforced on requests from a peer.
+#[test]
+fn test_configured_rpc_rate_limit() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        let mut rate_limits = RateLimiterConfig::default();
+        rate_limits
+            .quotas
+            .insert(RPCProtocol::BlocksByRange, (1, Duration::from_secs(60)));
+        config.rpc_rate_limits = Some(rate_limits);
+        let mut receiver = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "receiver")),
+        )
+        .await;
+        let mut sender = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "sender")),
+        )
+        .await;
+        let receiver_multiaddr = receiver
+            .swarm
+            .behaviour_mut()
+            .local_enr()
+            .multiaddr_p2p_tcp()[0]
+            .clone();
+        sender.dial_multiaddr(receiver_multiaddr).unwrap();
+
+        let rpc_request = Request::BlocksByRange(BlocksByRangeRequest {
+            start_slot: 0,
+            count: 1,
+            step: 1,
+        });
+
+        let sender_future = async {
+            loop {
+                match sender.next_event().await {
+                    Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                        // the quota allows a single request per minute
+                        for request_id in [1, 2] {
+                            sender.swarm.behaviour_mut().send_request(
+                                peer_id,
+                                request_id,
+                                rpc_request.clone(),
+                            );
+                        }
+                    }
+                    Libp2pEvent::Behaviour(BehaviourEvent::RPCFailed { error, .. }) => {
+                        assert!(matches!(
+                            error,
+                            RPCError::ErrorResponse(RPCResponseErrorCode::RateLimited, _)
+                        ));
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        };
+
+        let receiver_future = async {
+            let mut requests_received = 0;
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::RequestReceived {
+                    peer_id,
+                    id,
+                    request,
+                }) = receiver.next_event().await
+                {
+                    assert_eq!(request, rpc_request);
+                    requests_received += 1;
+                    assert_eq!(requests_received, 1, "Rate limited request was accepted");
+                    receiver.swarm.behaviour_mut().send_successful_response(
+                        peer_id,
+                        id,
+                        Response::BlocksByRange(None),
+                    );
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
@@ -0,0 +1,2732 @@
+#![cfg(test)]
+use lighthouse_network::behaviour::MAX_IDENTIFY_AGENT_VERSION_LEN;
+use lighthouse_network::libp2p::gossipsub::error::PublishError;
+use lighthouse_network::libp2p::gossipsub::{IdentTopic, MessageAcceptance};
+use lighthouse_network::multiaddr::Protocol;
+use lighthouse_network::rpc::GoodbyeReason;
+use lighthouse_network::types::{GossipEncoding, GossipKind, TopicFilterConfig, CORE_TOPICS};
+use lighthouse_network::{
+    gossip_max_size, metrics, ApplicationCodec, ApplicationMessage, BehaviourEvent, DialStats,
+    DisconnectReason, Enr, EnrExt, Eth2Enr, GossipTopic, Libp2pEvent, Multiaddr, PeerId,
+    PubsubMessage, SubscriptionChangeLimit,
+};
+use slog::{o, Level};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::time::sleep;
+use types::{
+    EnrForkId, Epoch, ForkName, MinimalEthSpec, Signature, SignedVoluntaryExit, SubnetId,
+    VoluntaryExit,
+};
+use unused_port::unused_tcp_port;
+
+mod common;
+
+type E = MinimalEthSpec;
+
+// Tests that subscribing to an attestation subnet sets its bit in our ENR and unsubscribing
+// clears it again.
+#[test]
+fn test_subscribe_to_subnet_updates_enr_attnets() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut node = common::build_libp2p_instance(Arc::downgrade(&rt), vec![], log).await;
+        let attnet_is_set = |node: &common::Libp2pInstance, subnet: usize| {
+            common::get_enr(node)
+                .attestation_bitfield::<E>()
+                .unwrap()
+                .get(subnet)
+                .unwrap()
+        };
+        let subnet_id = SubnetId::new(3);
+
+        assert!(!attnet_is_set(&node, 3));
+
+        node.subscribe_to_subnet(subnet_id).unwrap();
+        assert!(attnet_is_set(&node, 3));
+
+        node.unsubscribe_from_subnet(subnet_id).unwrap();
+        assert!(!attnet_is_set(&node, 3));
+
+        // subnets outside the attestation subnet count are rejected
+        assert!(node.subscribe_to_subnet(SubnetId::new(64)).is_err());
+        assert!(node.unsubscribe_from_subnet(SubnetId::new(64)).is_err());
+    })
+}
+
+// Tests that with an ENR update interval, a change of the attnets is published in the ENR, with
+// a new sequence number, on the next tick of the interval rather than immediately.
+#[test]
+fn test_enr_update_interval() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let interval = Duration::from_millis(200);
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.enr_update_interval = Some(interval);
+        let mut node =
+            common::build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log).await;
+        let initial_seq = common::get_enr(&node).seq();
+
+        node.subscribe_to_subnet(SubnetId::new(3)).unwrap();
+        let enr = common::get_enr(&node);
+        assert_eq!(enr.seq(), initial_seq);
+        assert!(!enr.attestation_bitfield::<E>().unwrap().get(3).unwrap());
+
+        let update_future = async {
+            loop {
+                tokio::select! {
+                    _ = node.next_event() => {}
+                    _ = sleep(Duration::from_millis(20)) => {}
+                }
+                let enr = common::get_enr(&node);
+                if enr.seq() > initial_seq {
+                    assert!(enr.attestation_bitfield::<E>().unwrap().get(3).unwrap());
+                    return;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = update_future => {}
+            _ = sleep(interval * 2) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that the sender of a node pair, which dials the receiver by its multiaddr, connects.
+#[test]
+fn test_dial_multiaddr() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+        let receiver_peer_id = receiver.local_peer_id;
+
+        let sender_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) =
+                    sender.next_event().await
+                {
+                    assert_eq!(peer_id, receiver_peer_id);
+                    return;
+                }
+            }
+        };
+        let receiver_future = async {
+            loop {
+                receiver.next_event().await;
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that raising the target peer count past its startup value up to the configured maximum
+// lets more peers connect, and that a target above the maximum is refused.
+#[test]
+fn test_raise_target_peers() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let num_dialers = 4;
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.target_peers = 1;
+        config.max_target_peers = Some(num_dialers);
+        let mut receiver = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "receiver")),
+        )
+        .await;
+        assert!(receiver.set_target_peers(num_dialers + 1).is_err());
+        receiver.set_target_peers(num_dialers).unwrap();
+
+        let receiver_multiaddr = receiver
+            .swarm
+            .behaviour_mut()
+            .local_enr()
+            .multiaddr_p2p_tcp()[0]
+            .clone();
+        let mut nodes = vec![receiver];
+        for i in 0..num_dialers {
+            let mut dialer = common::build_libp2p_instance(
+                Arc::downgrade(&rt),
+                vec![],
+                log.new(o!("who" => format!("dialer {}", i))),
+            )
+            .await;
+            dialer.dial_multiaddr(receiver_multiaddr.clone()).unwrap();
+            nodes.push(dialer);
+        }
+
+        let connected_future = async {
+            let mut interval = tokio::time::interval(Duration::from_millis(200));
+            loop {
+                // the startup target would only have let a single inbound peer in
+                if nodes[0]
+                    .swarm
+                    .behaviour()
+                    .network_globals()
+                    .connected_peers()
+                    == num_dialers
+                {
+                    return;
+                }
+                let events = futures::future::select_all(
+                    nodes.iter_mut().map(|node| Box::pin(node.next_event())),
+                );
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = events => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = connected_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that the peers of a node pair learn each other's agent string and RPC protocols through
+// identify.
+#[test]
+fn test_peer_protocols() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+        let sender_peer_id = sender.local_peer_id;
+        let receiver_peer_id = receiver.local_peer_id;
+
+        let identified = async {
+            while sender.peer_protocols(&receiver_peer_id).is_none()
+                || receiver.peer_protocols(&sender_peer_id).is_none()
+            {
+                tokio::select! {
+                    _ = sender.next_event() => {}
+                    _ = receiver.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = identified => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        for info in [
+            sender.peer_protocols(&receiver_peer_id).unwrap(),
+            receiver.peer_protocols(&sender_peer_id).unwrap(),
+        ] {
+            assert_eq!(
+                info.agent_version,
+                lighthouse_version::version_with_platform()
+            );
+            assert!(info
+                .rpc_protocols
+                .iter()
+                .any(|protocol| protocol.starts_with("/eth2/beacon_chain/req/status/")));
+        }
+    })
+}
+
+// Tests that a custom identify agent version is advertised to peers, and that an overlong one is
+// rejected.
+#[test]
+fn test_custom_identify_agent_version() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+    let agent_version = "my-fork/v1.0.0".to_string();
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.identify_agent_version = Some(agent_version.clone());
+        let mut node = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "node")),
+        )
+        .await;
+        let mut peer = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "peer")),
+        )
+        .await;
+        let node_peer_id = node.local_peer_id;
+
+        let peer_multiaddr = common::get_enr(&peer).multiaddr_p2p_tcp()[0].clone();
+        node.dial_multiaddr(peer_multiaddr).unwrap();
+
+        let identified = async {
+            while peer.peer_protocols(&node_peer_id).is_none() {
+                tokio::select! {
+                    _ = node.next_event() => {}
+                    _ = peer.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = identified => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        assert_eq!(
+            peer.peer_protocols(&node_peer_id).unwrap().agent_version,
+            agent_version
+        );
+
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.identify_agent_version = Some("a".repeat(MAX_IDENTIFY_AGENT_VERSION_LEN + 1));
+        assert!(
+            common::try_build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log)
+                .await
+                .is_err()
+        );
+    })
+}
+
+// Tests that a multiaddr without a peer id is not dialed.
+#[test]
+fn test_dial_multiaddr_requires_peer_id() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut node = common::build_libp2p_instance(Arc::downgrade(&rt), vec![], log).await;
+        let multiaddr = common::get_enr(&node).multiaddr_tcp()[0].clone();
+
+        assert!(node.dial_multiaddr(multiaddr).is_err());
+    })
+}
+
+// Tests that a message which could not be published for a lack of peers is published once a peer
+// subscribed to its topic connects.
+#[test]
+fn test_failed_publish_is_retried() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut publisher = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "publisher")),
+        )
+        .await;
+        let mut subscriber = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "subscriber")),
+        )
+        .await;
+
+        let exit = PubsubMessage::<E>::VoluntaryExit(Box::new(SignedVoluntaryExit {
+            message: VoluntaryExit {
+                epoch: Epoch::new(1),
+                validator_index: 2,
+            },
+            signature: Signature::empty(),
+        }));
+
+        // there are no peers to publish to, the message is cached
+        publisher.swarm.behaviour_mut().publish(vec![exit.clone()]);
+
+        assert!(subscriber
+            .swarm
+            .behaviour_mut()
+            .subscribe_kind(GossipKind::VoluntaryExit));
+        let subscriber_multiaddr = common::get_enr(&subscriber).multiaddr_p2p_tcp()[0].clone();
+        publisher.dial_multiaddr(subscriber_multiaddr).unwrap();
+
+        let publisher_future = async {
+            loop {
+                publisher.next_event().await;
+            }
+        };
+        let subscriber_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage { message, .. }) =
+                    subscriber.next_event().await
+                {
+                    assert_eq!(message, exit);
+                    return;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = publisher_future => {}
+            _ = subscriber_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a message whose timed retry fails for a lack of peers stays cached, and is published
+// once a peer subscribed to its topic connects.
+#[test]
+fn test_failed_timed_retry_keeps_message() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.gossip_publish_max_retries = 3;
+        let mut publisher = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "publisher")),
+        )
+        .await;
+        let mut subscriber = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "subscriber")),
+        )
+        .await;
+
+        let exit = PubsubMessage::<E>::VoluntaryExit(Box::new(SignedVoluntaryExit {
+            message: VoluntaryExit {
+                epoch: Epoch::new(1),
+                validator_index: 2,
+            },
+            signature: Signature::empty(),
+        }));
+
+        // there are no peers to publish to, the message is cached
+        publisher.swarm.behaviour_mut().publish(vec![exit.clone()]);
+
+        // wait past the first timed retry, due after 500ms, which still finds no peers
+        let retry_wait = async {
+            loop {
+                publisher.next_event().await;
+            }
+        };
+        tokio::select! {
+            _ = retry_wait => {}
+            _ = sleep(Duration::from_secs(1)) => {}
+        }
+
+        assert!(subscriber
+            .swarm
+            .behaviour_mut()
+            .subscribe_kind(GossipKind::VoluntaryExit));
+        let subscriber_multiaddr = common::get_enr(&subscriber).multiaddr_p2p_tcp()[0].clone();
+        publisher.dial_multiaddr(subscriber_multiaddr).unwrap();
+
+        let publisher_future = async {
+            loop {
+                publisher.next_event().await;
+            }
+        };
+        let subscriber_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage { message, .. }) =
+                    subscriber.next_event().await
+                {
+                    assert_eq!(message, exit);
+                    return;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = publisher_future => {}
+            _ = subscriber_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that raw bytes published on a topic reach a subscriber unchanged, and that oversized
+// payloads are rejected.
+#[test]
+fn test_publish_raw() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut publisher = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "publisher")),
+        )
+        .await;
+        let mut subscriber = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "subscriber")),
+        )
+        .await;
+
+        let fork_digest = common::get_enr(&publisher).eth2().unwrap().fork_digest;
+        let topic = GossipTopic::new(
+            GossipKind::VoluntaryExit,
+            GossipEncoding::SSZSnappy,
+            fork_digest,
+        );
+        let data = PubsubMessage::<E>::VoluntaryExit(Box::new(SignedVoluntaryExit {
+            message: VoluntaryExit {
+                epoch: Epoch::new(1),
+                validator_index: 2,
+            },
+            signature: Signature::empty(),
+        }))
+        .encode(GossipEncoding::SSZSnappy);
+
+        assert!(matches!(
+            publisher.publish_raw(topic.clone(), vec![0; gossip_max_size(true) + 1]),
+            Err(PublishError::TransformFailed(_))
+        ));
+
+        assert!(subscriber
+            .swarm
+            .behaviour_mut()
+            .subscribe_kind(GossipKind::VoluntaryExit));
+        let subscriber_multiaddr = common::get_enr(&subscriber).multiaddr_p2p_tcp()[0].clone();
+        publisher.dial_multiaddr(subscriber_multiaddr).unwrap();
+
+        let publisher_future = async {
+            // retry until the subscription of the subscriber is known
+            let mut published = false;
+            loop {
+                if !published {
+                    published = publisher.publish_raw(topic.clone(), data.clone()).is_ok();
+                }
+                tokio::select! {
+                    _ = publisher.next_event() => {}
+                    _ = sleep(Duration::from_millis(100)) => {}
+                }
+            }
+        };
+        let subscriber_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage { message, .. }) =
+                    subscriber.next_event().await
+                {
+                    assert_eq!(message.encode(GossipEncoding::SSZSnappy), data);
+                    return;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = publisher_future => {}
+            _ = subscriber_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a message published to chosen peers reaches them even though the publisher has no
+// mesh for the topic, that flood publishing still sends it to the other subscribed peers and that
+// unknown peers are skipped.
+#[test]
+fn test_publish_to_peers() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut publisher = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "publisher")),
+        )
+        .await;
+        let mut chosen = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "chosen")),
+        )
+        .await;
+        let mut other = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "other")),
+        )
+        .await;
+
+        let fork_digest = common::get_enr(&publisher).eth2().unwrap().fork_digest;
+        let topic = GossipTopic::new(
+            GossipKind::VoluntaryExit,
+            GossipEncoding::SSZSnappy,
+            fork_digest,
+        );
+        let topic_hash = IdentTopic::from(topic.clone()).hash();
+        let data = PubsubMessage::<E>::VoluntaryExit(Box::new(SignedVoluntaryExit {
+            message: VoluntaryExit {
+                epoch: Epoch::new(1),
+                validator_index: 2,
+            },
+            signature: Signature::empty(),
+        }))
+        .encode(GossipEncoding::SSZSnappy);
+
+        // only the receivers join the topic, so the publisher never forms a mesh for it
+        for receiver in [&mut chosen, &mut other] {
+            assert!(receiver
+                .swarm
+                .behaviour_mut()
+                .subscribe_kind(GossipKind::VoluntaryExit));
+            let multiaddr = common::get_enr(receiver).multiaddr_p2p_tcp()[0].clone();
+            publisher.dial_multiaddr(multiaddr).unwrap();
+        }
+        let peers = [chosen.local_peer_id, PeerId::random()];
+
+        let publisher_future = async {
+            // publish once the subscriptions of both receivers are known
+            let mut published = false;
+            loop {
+                if !published {
+                    let subscribed = publisher
+                        .swarm
+                        .behaviour()
+                        .gs()
+                        .all_peers()
+                        .filter(|(_, topics)| topics.contains(&&topic_hash))
+                        .count();
+                    if subscribed == 2 {
+                        published = publisher
+                            .publish_to_peers(topic.clone(), data.clone(), &peers)
+                            .is_ok();
+                    }
+                }
+                tokio::select! {
+                    _ = publisher.next_event() => {}
+                    _ = sleep(Duration::from_millis(100)) => {}
+                }
+            }
+        };
+        // the peer that is not one of `peers` must receive the message as well
+        let receivers_future = async {
+            let mut chosen_received = false;
+            let mut other_received = false;
+            while !(chosen_received && other_received) {
+                let (event, received) = tokio::select! {
+                    event = chosen.next_event() => (event, &mut chosen_received),
+                    event = other.next_event() => (event, &mut other_received),
+                };
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage { message, .. }) = event
+                {
+                    assert_eq!(message.encode(GossipEncoding::SSZSnappy), data);
+                    *received = true;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = publisher_future => {}
+            _ = receivers_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        assert!(!publisher.mesh_peers_per_topic().contains_key(&topic));
+    })
+}
+
+// Tests that a message of a flood published kind reaches every subscribed peer, while a message of
+// another kind only reaches the single fanout peer of its publisher.
+#[test]
+fn test_flood_publish_kinds() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let build_publisher = |flood_publish_kinds: Vec<GossipKind>, who: &'static str| {
+            let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+            config.flood_publish_kinds = Some(flood_publish_kinds);
+            config.gossipsub_mesh_n = Some(1);
+            config.gossipsub_mesh_n_low = Some(1);
+            config.gossipsub_mesh_n_high = Some(1);
+            config.gossipsub_mesh_outbound_min = Some(0);
+            // without IHAVE gossip, which would bring the message to the other subscribers
+            config.gossipsub_history_gossip = Some(0);
+            common::build_libp2p_instance_from_config(
+                Arc::downgrade(&rt),
+                config,
+                log.new(o!("who" => who)),
+            )
+        };
+        let mut nodes = vec![
+            build_publisher(vec![GossipKind::VoluntaryExit], "flood publisher").await,
+            build_publisher(vec![], "mesh publisher").await,
+        ];
+        let num_subscribers = 3;
+        for i in 0..num_subscribers {
+            let mut subscriber = common::build_libp2p_instance(
+                Arc::downgrade(&rt),
+                vec![],
+                log.new(o!("who" => format!("subscriber {}", i))),
+            )
+            .await;
+            assert!(subscriber
+                .swarm
+                .behaviour_mut()
+                .subscribe_kind(GossipKind::VoluntaryExit));
+            let multiaddr = common::get_enr(&subscriber).multiaddr_p2p_tcp()[0].clone();
+            for publisher in nodes.iter_mut().take(2) {
+                publisher.dial_multiaddr(multiaddr.clone()).unwrap();
+            }
+            nodes.push(subscriber);
+        }
+
+        let fork_digest = common::get_enr(&nodes[0]).eth2().unwrap().fork_digest;
+        let topic_hash = IdentTopic::from(GossipTopic::new(
+            GossipKind::VoluntaryExit,
+            GossipEncoding::SSZSnappy,
+            fork_digest,
+        ))
+        .hash();
+        let exit = |validator_index| {
+            PubsubMessage::<E>::VoluntaryExit(Box::new(SignedVoluntaryExit {
+                message: VoluntaryExit {
+                    epoch: Epoch::new(1),
+                    validator_index,
+                },
+                signature: Signature::empty(),
+            }))
+        };
+        let flood_message = exit(1);
+        let mesh_message = exit(2);
+
+        let mut flood_received = vec![false; num_subscribers];
+        let mut mesh_received = 0;
+        let publish_future = async {
+            let mut published = false;
+            let mut settle_deadline = None;
+            let mut interval = tokio::time::interval(Duration::from_millis(200));
+            loop {
+                // publish once the subscriptions of all the subscribers are known
+                if !published
+                    && nodes.iter().take(2).all(|publisher| {
+                        publisher
+                            .swarm
+                            .behaviour()
+                            .gs()
+                            .all_peers()
+                            .filter(|(_, topics)| topics.contains(&&topic_hash))
+                            .count()
+                            == num_subscribers
+                    })
+                {
+                    nodes[0]
+                        .swarm
+                        .behaviour_mut()
+                        .publish(vec![flood_message.clone()]);
+                    nodes[1]
+                        .swarm
+                        .behaviour_mut()
+                        .publish(vec![mesh_message.clone()]);
+                    published = true;
+                }
+                // leave time for the mesh published message to reach more subscribers
+                if settle_deadline.is_none()
+                    && flood_received.iter().all(|received| *received)
+                    && mesh_received > 0
+                {
+                    settle_deadline = Some(tokio::time::Instant::now() + Duration::from_secs(2));
+                }
+                if settle_deadline.map_or(false, |deadline| tokio::time::Instant::now() >= deadline)
+                {
+                    return;
+                }
+
+                let events = futures::future::select_all(
+                    nodes.iter_mut().map(|node| Box::pin(node.next_event())),
+                );
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    (event, index, _) = events => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage {
+                            message, ..
+                        }) = event
+                        {
+                            if message == flood_message {
+                                flood_received[index - 2] = true;
+                            } else if message == mesh_message {
+                                mesh_received += 1;
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = publish_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        assert_eq!(mesh_received, 1);
+    })
+}
+
+// Tests that a node listens on each configured address and advertises the port of both address
+// families in its ENR.
+#[test]
+fn test_listen_on_ipv4_and_ipv6() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let port = unused_tcp_port().unwrap();
+        let mut config = common::build_config(port, vec![]);
+        config.listen_address = "127.0.0.1".parse().unwrap();
+        config.extra_listen_addresses = vec!["::1".parse().unwrap()];
+        let mut node =
+            common::build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log).await;
+
+        let enr = common::get_enr(&node);
+        assert_eq!(enr.tcp(), Some(port));
+        assert_eq!(enr.ip6(), Some("::1".parse().unwrap()));
+        assert_eq!(enr.tcp6(), Some(port));
+
+        let mut listen_addrs = Vec::new();
+        let listen_future = async {
+            while listen_addrs.len() < 2 {
+                if let Libp2pEvent::NewListenAddr(addr) = node.next_event().await {
+                    listen_addrs.push(addr);
+                }
+            }
+        };
+        tokio::select! {
+            _ = listen_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        let expected: Vec<Multiaddr> = vec![
+            format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap(),
+            format!("/ip6/::1/tcp/{}", port).parse().unwrap(),
+        ];
+        for addr in expected {
+            assert!(listen_addrs.contains(&addr), "Not listening on {}", addr);
+        }
+    })
+}
+
+// Tests that gossip received while paused is buffered and delivered in order once resumed.
+#[test]
+fn test_pause_and_resume_gossip() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut publisher = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "publisher")),
+        )
+        .await;
+        let mut subscriber = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "subscriber")),
+        )
+        .await;
+
+        let fork_digest = common::get_enr(&publisher).eth2().unwrap().fork_digest;
+        let topic = GossipTopic::new(
+            GossipKind::VoluntaryExit,
+            GossipEncoding::SSZSnappy,
+            fork_digest,
+        );
+        let messages: Vec<_> = (0..3)
+            .map(|validator_index| {
+                PubsubMessage::<E>::VoluntaryExit(Box::new(SignedVoluntaryExit {
+                    message: VoluntaryExit {
+                        epoch: Epoch::new(1),
+                        validator_index,
+                    },
+                    signature: Signature::empty(),
+                }))
+            })
+            .collect();
+
+        subscriber.pause_gossip();
+        assert!(subscriber
+            .swarm
+            .behaviour_mut()
+            .subscribe_kind(GossipKind::VoluntaryExit));
+        let subscriber_multiaddr = common::get_enr(&subscriber).multiaddr_p2p_tcp()[0].clone();
+        publisher.dial_multiaddr(subscriber_multiaddr).unwrap();
+
+        let publisher_future = async {
+            // the first publish succeeds once the subscription of the subscriber is known
+            let mut published = 0;
+            loop {
+                if published < messages.len()
+                    && publisher
+                        .publish_raw(
+                            topic.clone(),
+                            messages[published].encode(GossipEncoding::SSZSnappy),
+                        )
+                        .is_ok()
+                {
+                    published += 1;
+                    continue;
+                }
+                tokio::select! {
+                    _ = publisher.next_event() => {}
+                    _ = sleep(Duration::from_millis(100)) => {}
+                }
+            }
+        };
+        let subscriber_future = async {
+            while subscriber.paused_gossip_messages() < messages.len() {
+                tokio::select! {
+                    event = subscriber.next_event() => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage { .. }) = event {
+                            panic!("Gossip delivered while paused");
+                        }
+                    }
+                    _ = sleep(Duration::from_millis(100)) => {}
+                }
+            }
+
+            subscriber.resume_gossip();
+            let mut received = Vec::new();
+            while received.len() < messages.len() {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage { message, .. }) =
+                    subscriber.next_event().await
+                {
+                    received.push(message);
+                }
+            }
+            assert_eq!(received, messages);
+        };
+
+        tokio::select! {
+            _ = publisher_future => {}
+            _ = subscriber_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that without a pause buffer, gossip received while paused is discarded and reported as
+// ignored.
+#[test]
+fn test_pause_gossip_without_buffer() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut publisher = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "publisher")),
+        )
+        .await;
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.paused_gossip_buffer_size = 0;
+        let mut subscriber = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "subscriber")),
+        )
+        .await;
+
+        let fork_digest = common::get_enr(&publisher).eth2().unwrap().fork_digest;
+        let topic = GossipTopic::new(
+            GossipKind::VoluntaryExit,
+            GossipEncoding::SSZSnappy,
+            fork_digest,
+        );
+        let data = PubsubMessage::<E>::VoluntaryExit(Box::new(SignedVoluntaryExit {
+            message: VoluntaryExit {
+                epoch: Epoch::new(1),
+                validator_index: 9,
+            },
+            signature: Signature::empty(),
+        }))
+        .encode(GossipEncoding::SSZSnappy);
+        // other tests of this binary may also report results, so only an increase is checked
+        let ignored = || {
+            metrics::get_int_counter(
+                &metrics::GOSSIP_VALIDATION_RESULTS_PER_TOPIC_KIND,
+                &["voluntary_exit", "ignore"],
+            )
+            .map_or(0, |counter| counter.get())
+        };
+        let ignored_before = ignored();
+
+        subscriber.pause_gossip();
+        assert!(subscriber
+            .swarm
+            .behaviour_mut()
+            .subscribe_kind(GossipKind::VoluntaryExit));
+        let subscriber_multiaddr = common::get_enr(&subscriber).multiaddr_p2p_tcp()[0].clone();
+        publisher.dial_multiaddr(subscriber_multiaddr).unwrap();
+
+        let publisher_future = async {
+            // retry until the subscription of the subscriber is known
+            let mut published = false;
+            loop {
+                if !published {
+                    published = publisher.publish_raw(topic.clone(), data.clone()).is_ok();
+                }
+                tokio::select! {
+                    _ = publisher.next_event() => {}
+                    _ = sleep(Duration::from_millis(100)) => {}
+                }
+            }
+        };
+        let subscriber_future = async {
+            while ignored() == ignored_before {
+                tokio::select! {
+                    event = subscriber.next_event() => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage { .. }) = event {
+                            panic!("Gossip delivered while paused");
+                        }
+                    }
+                    _ = sleep(Duration::from_millis(100)) => {}
+                }
+            }
+            assert_eq!(subscriber.paused_gossip_messages(), 0);
+        };
+
+        tokio::select! {
+            _ = publisher_future => {}
+            _ = subscriber_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Builds a node that accepts local addresses in its discovery table and gives up on queries
+// quickly.
+async fn build_discovery_node(
+    rt: Weak<Runtime>,
+    boot_nodes: Vec<Enr>,
+    log: slog::Logger,
+) -> common::Libp2pInstance {
+    let mut config = common::build_config(unused_tcp_port().unwrap(), boot_nodes);
+    config.discv5_config.table_filter = |_| true;
+    config.discv5_config.query_timeout = Duration::from_secs(5);
+    common::build_libp2p_instance_from_config(rt, config, log).await
+}
+
+// Tests that the ENRs returned by an ad-hoc subnet query all advertise the subnet.
+#[test]
+fn test_discover_peers_on_subnet() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let subnet_id = SubnetId::new(1);
+        let mut on_subnet = build_discovery_node(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "on_subnet")),
+        )
+        .await;
+        on_subnet.subscribe_to_subnet(subnet_id).unwrap();
+        let mut off_subnet = build_discovery_node(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "off_subnet")),
+        )
+        .await;
+        let boot_nodes = vec![common::get_enr(&on_subnet), common::get_enr(&off_subnet)];
+        let mut searcher = build_discovery_node(
+            Arc::downgrade(&rt),
+            boot_nodes,
+            log.new(o!("who" => "searcher")),
+        )
+        .await;
+
+        let mut found = searcher.discover_peers(subnet_id, 16);
+
+        let enrs = async {
+            loop {
+                tokio::select! {
+                    result = &mut found => return result.unwrap(),
+                    _ = searcher.next_event() => {}
+                    _ = on_subnet.next_event() => {}
+                    _ = off_subnet.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            enrs = enrs => {
+                assert!(!enrs.is_empty());
+                for enr in enrs {
+                    let attnets = enr.attestation_bitfield::<E>().unwrap();
+                    assert!(attnets.get(*subnet_id as usize).unwrap());
+                }
+            }
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that nodes joining a discovery network show up in the routing table stats of the boot
+// node.
+#[test]
+fn test_discovery_table_stats() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut boot_node = build_discovery_node(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "boot_node")),
+        )
+        .await;
+        let boot_nodes = vec![common::get_enr(&boot_node)];
+        let mut node_a = build_discovery_node(
+            Arc::downgrade(&rt),
+            boot_nodes.clone(),
+            log.new(o!("who" => "node_a")),
+        )
+        .await;
+        let mut node_b = build_discovery_node(
+            Arc::downgrade(&rt),
+            boot_nodes,
+            log.new(o!("who" => "node_b")),
+        )
+        .await;
+
+        let joined = async {
+            while boot_node.discovery_table_stats().total_entries == 0 {
+                tokio::select! {
+                    _ = boot_node.next_event() => {}
+                    _ = node_a.next_event() => {}
+                    _ = node_b.next_event() => {}
+                    _ = sleep(Duration::from_millis(100)) => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = joined => {
+                let stats = boot_node.discovery_table_stats();
+                assert_eq!(
+                    stats.total_entries,
+                    stats.connected_entries + stats.disconnected_entries
+                );
+                assert_eq!(stats.bucket_entries.iter().sum::<usize>(), stats.total_entries);
+            }
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that the ENRs exported from the routing table of a node populate the table of a fresh
+// node.
+#[test]
+fn test_export_import_enrs() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let boot_node = build_discovery_node(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "boot_node")),
+        )
+        .await;
+        let mut node = build_discovery_node(
+            Arc::downgrade(&rt),
+            vec![common::get_enr(&boot_node)],
+            log.new(o!("who" => "node")),
+        )
+        .await;
+
+        let exported = node.export_enrs();
+        assert!(exported
+            .iter()
+            .any(|enr| enr.node_id() == common::get_enr(&boot_node).node_id()));
+
+        let mut fresh_node = build_discovery_node(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "fresh_node")),
+        )
+        .await;
+        assert_eq!(fresh_node.discovery_table_stats().total_entries, 0);
+
+        fresh_node.import_enrs(exported.clone());
+        assert_eq!(
+            fresh_node.discovery_table_stats().total_entries,
+            exported.len()
+        );
+        let imported: Vec<_> = fresh_node.export_enrs().iter().map(Enr::node_id).collect();
+        assert!(exported.iter().all(|enr| imported.contains(&enr.node_id())));
+    })
+}
+
+// Tests that the listen addresses hold the port chosen by the OS when listening on port 0.
+#[test]
+fn test_listen_multiaddrs_on_port_zero() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.libp2p_port = 0;
+        let mut node =
+            common::build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log).await;
+
+        let listening = async {
+            loop {
+                if let Libp2pEvent::NewListenAddr(_) = node.next_event().await {
+                    return;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = listening => {
+                let listen_multiaddrs = node.swarm.behaviour().network_globals().listen_multiaddrs();
+                assert!(!listen_multiaddrs.is_empty());
+                for multiaddr in listen_multiaddrs {
+                    assert!(multiaddr
+                        .iter()
+                        .any(|protocol| matches!(protocol, Protocol::Tcp(port) if port != 0)));
+                }
+            }
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that the service refuses to start without any transport.
+#[test]
+fn test_disabled_tcp_transport() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.disable_tcp = true;
+
+        // TCP is the only transport, so no TCP listen address can ever be reported
+        assert!(
+            common::try_build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log)
+                .await
+                .is_err()
+        );
+    })
+}
+
+// Tests that a message published on an application topic reaches a subscribed peer, encoded and
+// decoded with the codec registered for the topic.
+#[test]
+fn test_application_topic_publish() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+    let topic = "blobs".to_string();
+
+    // prefixes payloads with a version byte
+    let codec = ApplicationCodec::new(
+        |payload| Ok([&[1][..], payload].concat()),
+        |data| match data.split_first() {
+            Some((&1, payload)) => Ok(payload.to_vec()),
+            _ => Err("Unknown version".into()),
+        },
+    );
+
+    rt.block_on(async {
+        let mut nodes = Vec::new();
+        for who in ["publisher", "subscriber"] {
+            let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+            config.application_topics = vec![topic.clone()];
+            let mut node = common::build_libp2p_instance_from_config(
+                Arc::downgrade(&rt),
+                config,
+                log.new(o!("who" => who)),
+            )
+            .await;
+            node.register_application_topic(topic.clone(), codec.clone());
+            assert!(node
+                .swarm
+                .behaviour_mut()
+                .subscribe_kind(GossipKind::Application(topic.clone())));
+            nodes.push(node);
+        }
+        let mut subscriber = nodes.pop().unwrap();
+        let mut publisher = nodes.pop().unwrap();
+
+        let subscriber_multiaddr = common::get_enr(&subscriber).multiaddr_p2p_tcp()[0].clone();
+        publisher.dial_multiaddr(subscriber_multiaddr).unwrap();
+
+        let message = PubsubMessage::<E>::Application(Box::new(ApplicationMessage {
+            topic: topic.clone(),
+            payload: b"blob".to_vec(),
+        }));
+
+        let publisher_future = async {
+            // publish until the subscriber's subscription is known
+            let mut interval = tokio::time::interval(Duration::from_millis(500));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        publisher.swarm.behaviour_mut().publish(vec![message.clone()]);
+                    }
+                    _ = publisher.next_event() => {}
+                }
+            }
+        };
+        let subscriber_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage {
+                    message: received,
+                    ..
+                }) = subscriber.next_event().await
+                {
+                    assert_eq!(received, message);
+                    return;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = publisher_future => {}
+            _ = subscriber_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that the mesh peer count of a topic reflects the connected peers subscribed to it.
+#[test]
+fn test_mesh_peers_per_topic() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let num_nodes = 3;
+        let mut nodes = common::build_full_mesh(Arc::downgrade(&rt), log, num_nodes).await;
+        for node in nodes.iter_mut() {
+            assert!(node
+                .swarm
+                .behaviour_mut()
+                .subscribe_kind(GossipKind::VoluntaryExit));
+        }
+
+        let exit_mesh_peers = |node: &common::Libp2pInstance| {
+            node.mesh_peers_per_topic()
+                .into_iter()
+                .find(|(topic, _)| *topic.kind() == GossipKind::VoluntaryExit)
+                .map(|(_, mesh_peers)| mesh_peers)
+        };
+        // the mesh is empty before connecting
+        assert_eq!(exit_mesh_peers(&nodes[0]), Some(0));
+
+        let mesh_future = async {
+            let mut interval = tokio::time::interval(Duration::from_millis(200));
+            loop {
+                // the other nodes of the full mesh are grafted by the gossipsub heartbeat
+                if exit_mesh_peers(&nodes[0]) == Some(num_nodes - 1) {
+                    return;
+                }
+                let events = futures::future::select_all(
+                    nodes.iter_mut().map(|node| Box::pin(node.next_event())),
+                );
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = events => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = mesh_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a node shutting down says goodbye to its peers.
+#[test]
+fn test_shutdown_sends_goodbye() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+        let sender_peer_id = sender.local_peer_id;
+
+        let sender_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(_)) =
+                    sender.next_event().await
+                {
+                    sender.shutdown().await;
+                    // keep the connection open until the receiver has seen the goodbye
+                    loop {
+                        sender.next_event().await;
+                    }
+                }
+            }
+        };
+        let receiver_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::GoodbyeReceived { peer_id, reason }) =
+                    receiver.next_event().await
+                {
+                    assert_eq!(peer_id, sender_peer_id);
+                    assert_eq!(reason, GoodbyeReason::ClientShutdown);
+                    return;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = sender_future => {}
+            _ = receiver_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that updating the fork version updates the local ENR and moves the gossipsub
+// subscriptions to the new fork's topics.
+#[test]
+fn test_update_fork_version() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut node = common::build_libp2p_instance(Arc::downgrade(&rt), vec![], log).await;
+        assert!(node
+            .swarm
+            .behaviour_mut()
+            .subscribe_kind(GossipKind::VoluntaryExit));
+        let old_enr = common::get_enr(&node);
+
+        let altair_digest = common::fork_context()
+            .to_context_bytes(ForkName::Altair)
+            .unwrap();
+        let new_enr_fork_id = EnrForkId {
+            fork_digest: altair_digest,
+            next_fork_version: [3, 0, 0, 1],
+            next_fork_epoch: Epoch::new(84),
+        };
+        node.update_fork_version(new_enr_fork_id.clone(), true);
+
+        let new_enr = common::get_enr(&node);
+        assert_ne!(old_enr.eth2().unwrap(), new_enr_fork_id);
+        assert_eq!(new_enr.eth2().unwrap(), new_enr_fork_id);
+        assert_eq!(new_enr.seq(), old_enr.seq() + 1);
+
+        let topics = node.mesh_peers_per_topic();
+        assert_eq!(topics.len(), 1);
+        assert!(topics.keys().all(|topic| topic.fork_digest == altair_digest
+            && *topic.kind() == GossipKind::VoluntaryExit));
+    })
+}
+
+// Tests that the network globals report the fork id given at construction and then the one set
+// by a fork version update.
+#[test]
+fn test_enr_fork_id() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut node = common::build_libp2p_instance(Arc::downgrade(&rt), vec![], log).await;
+        let initial_enr_fork_id = EnrForkId {
+            fork_digest: common::fork_context()
+                .to_context_bytes(ForkName::Base)
+                .unwrap(),
+            ..EnrForkId::default()
+        };
+        assert_eq!(
+            node.swarm.behaviour().network_globals().enr_fork_id(),
+            initial_enr_fork_id
+        );
+
+        let new_enr_fork_id = EnrForkId {
+            fork_digest: common::fork_context()
+                .to_context_bytes(ForkName::Altair)
+                .unwrap(),
+            next_fork_version: [3, 0, 0, 1],
+            next_fork_epoch: Epoch::new(84),
+        };
+        node.update_fork_version(new_enr_fork_id.clone(), false);
+        assert_eq!(
+            node.swarm.behaviour().network_globals().enr_fork_id(),
+            new_enr_fork_id
+        );
+    })
+}
+
+// Tests that nodes loading their key from the same file have the same peer id.
+#[test]
+fn test_network_key_file_gives_stable_peer_id() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let key_dir = tempfile::Builder::new()
+            .prefix("libp2p_network_key")
+            .tempdir()
+            .unwrap();
+        let key_file = key_dir.path().join("keys").join("node.key");
+
+        let mut peer_ids = Vec::new();
+        for _ in 0..2 {
+            let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+            config.network_key_file = Some(key_file.clone());
+            let node =
+                common::build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log.clone())
+                    .await;
+            assert_eq!(common::get_enr(&node).peer_id(), node.local_peer_id);
+            peer_ids.push(node.local_peer_id);
+        }
+
+        assert!(key_file.exists());
+        assert_eq!(peer_ids[0], peer_ids[1]);
+    })
+}
+
+// Tests that the core topics of a future fork can be subscribed to before the fork is active, and
+// that doing so twice changes nothing.
+#[test]
+fn test_subscribe_new_fork_topics() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut node = common::build_libp2p_instance(Arc::downgrade(&rt), vec![], log).await;
+        let merge_digest = common::fork_context()
+            .to_context_bytes(ForkName::Merge)
+            .unwrap();
+
+        assert!(node.subscribe_new_fork_topics(merge_digest).is_empty());
+        let subscribed_topics = |node: &common::Libp2pInstance| {
+            node.swarm
+                .behaviour()
+                .gs()
+                .topics()
+                .cloned()
+                .collect::<std::collections::HashSet<_>>()
+        };
+        let topics = subscribed_topics(&node);
+        for kind in CORE_TOPICS.iter() {
+            let topic: IdentTopic =
+                GossipTopic::new(kind.clone(), GossipEncoding::default(), merge_digest).into();
+            assert!(topics.contains(&topic.hash()), "missing topic {}", topic);
+        }
+
+        assert!(node.subscribe_new_fork_topics(merge_digest).is_empty());
+        assert_eq!(subscribed_topics(&node), topics);
+        assert_eq!(
+            node.swarm
+                .behaviour()
+                .network_globals()
+                .gossipsub_subscriptions
+                .read()
+                .len(),
+            CORE_TOPICS.len()
+        );
+    })
+}
+
+// Tests that published messages are listed in the gossip cache snapshot.
+#[test]
+fn test_gossip_cache_snapshot() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.track_gossip_message_cache = true;
+        let mut publisher = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "publisher")),
+        )
+        .await;
+        let mut subscriber = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "subscriber")),
+        )
+        .await;
+
+        let fork_digest = common::get_enr(&publisher).eth2().unwrap().fork_digest;
+        let topic = GossipTopic::new(
+            GossipKind::VoluntaryExit,
+            GossipEncoding::SSZSnappy,
+            fork_digest,
+        );
+        let exit = |validator_index| {
+            PubsubMessage::<E>::VoluntaryExit(Box::new(SignedVoluntaryExit {
+                message: VoluntaryExit {
+                    epoch: Epoch::new(1),
+                    validator_index,
+                },
+                signature: Signature::empty(),
+            }))
+            .encode(GossipEncoding::SSZSnappy)
+        };
+        assert!(publisher.gossip_cache_snapshot().is_empty());
+
+        assert!(subscriber
+            .swarm
+            .behaviour_mut()
+            .subscribe_kind(GossipKind::VoluntaryExit));
+        let subscriber_multiaddr = common::get_enr(&subscriber).multiaddr_p2p_tcp()[0].clone();
+        publisher.dial_multiaddr(subscriber_multiaddr).unwrap();
+
+        let publisher_future = async {
+            // retry until the subscription of the subscriber is known
+            let mut message_ids = Vec::new();
+            while message_ids.len() < 3 {
+                if let Ok(message_id) =
+                    publisher.publish_raw(topic.clone(), exit(message_ids.len() as u64))
+                {
+                    message_ids.push(message_id);
+                    continue;
+                }
+                tokio::select! {
+                    _ = publisher.next_event() => {}
+                    _ = sleep(Duration::from_millis(100)) => {}
+                }
+            }
+            message_ids
+        };
+        let subscriber_future = async {
+            loop {
+                subscriber.next_event().await;
+            }
+        };
+
+        let message_ids = tokio::select! {
+            message_ids = publisher_future => message_ids,
+            _ = subscriber_future => unreachable!(),
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        };
+
+        let snapshot = publisher.gossip_cache_snapshot();
+        assert_eq!(snapshot.len(), 3);
+        for (message_id, (cached_id, cached_topic)) in message_ids.iter().zip(snapshot.iter()) {
+            assert_eq!(message_id, cached_id);
+            assert_eq!(cached_topic, &topic);
+        }
+    })
+}
+
+// Tests that the round-trip time of the pings between two connected nodes is recorded.
+#[test]
+fn test_ping_latency_is_recorded() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let (mut sender, mut receiver) = common::build_node_pair(Arc::downgrade(&rt), &log).await;
+        let receiver_peer_id = receiver.local_peer_id;
+        let latency = |node: &common::Libp2pInstance| {
+            node.swarm
+                .behaviour()
+                .network_globals()
+                .peer_latency(&receiver_peer_id)
+        };
+        assert!(latency(&sender).is_none());
+
+        // The sender dialed the receiver, so it pings it after the outbound ping interval.
+        let pinged = async {
+            while latency(&sender).is_none() {
+                tokio::select! {
+                    _ = sender.next_event() => {}
+                    _ = receiver.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = pinged => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        let latency = latency(&sender).unwrap();
+        assert!(latency > Duration::ZERO && latency < Duration::from_secs(5));
+    })
+}
+
+// Tests that reporting a validation result for a received gossip message counts it against the
+// topic kind of the message.
+#[test]
+fn test_validation_result_metric() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut publisher = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "publisher")),
+        )
+        .await;
+        // the default config does not track the gossipsub message cache
+        let mut subscriber = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "subscriber")),
+        )
+        .await;
+
+        let fork_digest = common::get_enr(&publisher).eth2().unwrap().fork_digest;
+        let topic = GossipTopic::new(
+            GossipKind::VoluntaryExit,
+            GossipEncoding::SSZSnappy,
+            fork_digest,
+        );
+        let data = PubsubMessage::<E>::VoluntaryExit(Box::new(SignedVoluntaryExit {
+            message: VoluntaryExit {
+                epoch: Epoch::new(1),
+                validator_index: 7,
+            },
+            signature: Signature::empty(),
+        }))
+        .encode(GossipEncoding::SSZSnappy);
+        // other tests of this binary may also report results, so only an increase is checked
+        let rejected = || {
+            metrics::get_int_counter(
+                &metrics::GOSSIP_VALIDATION_RESULTS_PER_TOPIC_KIND,
+                &["voluntary_exit", "reject"],
+            )
+            .map_or(0, |counter| counter.get())
+        };
+        let rejected_before = rejected();
+
+        assert!(subscriber
+            .swarm
+            .behaviour_mut()
+            .subscribe_kind(GossipKind::VoluntaryExit));
+        let subscriber_multiaddr = common::get_enr(&subscriber).multiaddr_p2p_tcp()[0].clone();
+        publisher.dial_multiaddr(subscriber_multiaddr).unwrap();
+
+        let publisher_future = async {
+            // retry until the subscription of the subscriber is known
+            let mut published = false;
+            loop {
+                if !published {
+                    published = publisher.publish_raw(topic.clone(), data.clone()).is_ok();
+                }
+                tokio::select! {
+                    _ = publisher.next_event() => {}
+                    _ = sleep(Duration::from_millis(100)) => {}
+                }
+            }
+        };
+        let subscriber_future = async {
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage {
+                    id, source, ..
+                }) = subscriber.next_event().await
+                {
+                    subscriber.report_message_validation_result(
+                        &source,
+                        id,
+                        MessageAcceptance::Reject,
+                    );
+                    return;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = publisher_future => {}
+            _ = subscriber_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        assert_eq!(rejected(), rejected_before + 1);
+    })
+}
+
+// Tests that subscribing to and unsubscribing from a topic are reflected by `is_subscribed` and
+// `subscribed_topics`.
+#[test]
+fn test_is_subscribed() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut node = common::build_libp2p_instance(Arc::downgrade(&rt), vec![], log).await;
+        let fork_digest = common::get_enr(&node).eth2().unwrap().fork_digest;
+        let topic = GossipTopic::new(
+            GossipKind::ProposerSlashing,
+            GossipEncoding::default(),
+            fork_digest,
+        );
+        assert!(!node.is_subscribed(&topic));
+        assert!(!node.subscribed_topics().contains(&topic));
+
+        assert!(node.swarm.behaviour_mut().subscribe(topic.clone()));
+        assert!(node.is_subscribed(&topic));
+        assert_eq!(node.subscribed_topics(), vec![topic.clone()]);
+
+        assert!(node.swarm.behaviour_mut().unsubscribe(topic.clone()));
+        assert!(!node.is_subscribed(&topic));
+        assert!(node.subscribed_topics().is_empty());
+    })
+}
+
+// Tests that rapid subscription changes of a topic beyond the subscription change limit are
+// deferred, leaving the subscription as it was after the last allowed change.
+#[test]
+fn test_subscription_change_limit() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.subscription_change_limit = Some(SubscriptionChangeLimit {
+            max_changes: 3,
+            window: Duration::from_secs(60),
+        });
+        let mut node =
+            common::build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log).await;
+        let fork_digest = common::get_enr(&node).eth2().unwrap().fork_digest;
+        let topic = GossipTopic::new(
+            GossipKind::ProposerSlashing,
+            GossipEncoding::default(),
+            fork_digest,
+        );
+
+        let mut states = vec![];
+        for i in 0..10 {
+            if i % 2 == 0 {
+                node.subscribe(topic.clone());
+            } else {
+                node.unsubscribe(topic.clone());
+            }
+            states.push(node.is_subscribed(&topic));
+        }
+
+        // subscribe, unsubscribe and subscribe again are allowed, the later unsubscribes are
+        // deferred and the subscribes cancel them
+        assert_eq!(
+            states,
+            vec![true, false, true, true, true, true, true, true, true, true]
+        );
+        assert!(!node.unsubscribe(topic.clone()));
+        assert!(node.subscribe(topic));
+    })
+}
+
+// Tests that a subscription change deferred by the subscription change limit is applied once the
+// quota of the topic refills.
+#[test]
+fn test_deferred_subscription_change_is_applied() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.subscription_change_limit = Some(SubscriptionChangeLimit {
+            max_changes: 1,
+            window: Duration::from_secs(1),
+        });
+        let mut node =
+            common::build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log).await;
+        let fork_digest = common::get_enr(&node).eth2().unwrap().fork_digest;
+        let topic = GossipTopic::new(
+            GossipKind::ProposerSlashing,
+            GossipEncoding::default(),
+            fork_digest,
+        );
+
+        assert!(node.subscribe(topic.clone()));
+        assert!(!node.unsubscribe(topic.clone()));
+        assert!(node.is_subscribed(&topic));
+
+        // the change is applied while the node is polled, which need not return an event
+        let unsubscribed_future = async {
+            while node.is_subscribed(&topic) {
+                tokio::select! {
+                    _ = node.next_event() => {}
+                    _ = sleep(Duration::from_millis(100)) => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = unsubscribed_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a deferred subscription to an attestation topic of another fork is applied to that
+// exact topic, without advertising the subnet in the ENR.
+#[test]
+fn test_deferred_subscription_change_keeps_topic() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.subscription_change_limit = Some(SubscriptionChangeLimit {
+            max_changes: 2,
+            window: Duration::from_secs(1),
+        });
+        let mut node =
+            common::build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log).await;
+        let fork_digest = common::get_enr(&node).eth2().unwrap().fork_digest;
+        let other_fork_digest = [fork_digest[0].wrapping_add(1), 0, 0, 0];
+        let kind = GossipKind::Attestation(SubnetId::new(1));
+        let topic = GossipTopic::new(kind.clone(), GossipEncoding::default(), other_fork_digest);
+        let current_topic = GossipTopic::new(kind, GossipEncoding::default(), fork_digest);
+
+        assert!(node.subscribe(topic.clone()));
+        assert!(node.unsubscribe(topic.clone()));
+        assert!(!node.subscribe(topic.clone()));
+
+        // the change is applied while the node is polled, which need not return an event
+        let subscribed_future = async {
+            while !node.is_subscribed(&topic) {
+                tokio::select! {
+                    _ = node.next_event() => {}
+                    _ = sleep(Duration::from_millis(100)) => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = subscribed_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        assert!(!node.is_subscribed(&current_topic));
+        let attnets = common::get_enr(&node).attestation_bitfield::<E>().unwrap();
+        assert!(!attnets.get(1).unwrap());
+    })
+}
+
+// Tests that an external address observed by enough peers is reported and advertised in the ENR.
+#[test]
+fn test_observed_external_addr_updates_enr() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let port = unused_tcp_port().unwrap();
+        let mut config = common::build_config(port, vec![]);
+        config.enr_address = None;
+        config.update_enr_from_observed = true;
+        // all the peers are on the loopback subnet
+        config.observed_addr_quorum = 1;
+        let mut node = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "node")),
+        )
+        .await;
+        assert_eq!(common::get_enr(&node).ip(), None);
+
+        let node_multiaddr: Multiaddr =
+            format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", port, node.local_peer_id)
+                .parse()
+                .unwrap();
+        let mut peers = Vec::new();
+        for i in 0..2 {
+            let mut peer = common::build_libp2p_instance(
+                Arc::downgrade(&rt),
+                vec![],
+                log.new(o!("who" => format!("peer {}", i))),
+            )
+            .await;
+            peer.dial_multiaddr(node_multiaddr.clone()).unwrap();
+            peers.push(peer);
+        }
+        let (mut peer_a, mut peer_b) = (peers.remove(0), peers.remove(0));
+
+        let confirmed = async {
+            loop {
+                tokio::select! {
+                    event = node.next_event() => {
+                        if let Libp2pEvent::NewExternalAddr(address) = event {
+                            return address;
+                        }
+                    }
+                    _ = peer_a.next_event() => {}
+                    _ = peer_b.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            address = confirmed => {
+                assert_eq!(address, format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap());
+            }
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        let enr = common::get_enr(&node);
+        assert_eq!(enr.ip(), Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(enr.tcp(), Some(port));
+    })
+}
+
+// Tests that an external address observed by peers of fewer subnets than the quorum is neither
+// reported nor advertised in the ENR.
+#[test]
+fn test_observed_external_addr_needs_quorum() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let port = unused_tcp_port().unwrap();
+        let mut config = common::build_config(port, vec![]);
+        config.enr_address = None;
+        config.update_enr_from_observed = true;
+        config.observed_addr_quorum = 2;
+        let mut node = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "node")),
+        )
+        .await;
+
+        let node_multiaddr: Multiaddr =
+            format!("/ip4/127.0.0.1/tcp/{}/p2p/{}", port, node.local_peer_id)
+                .parse()
+                .unwrap();
+        let mut peers = Vec::new();
+        for i in 0..2 {
+            let mut peer = common::build_libp2p_instance(
+                Arc::downgrade(&rt),
+                vec![],
+                log.new(o!("who" => format!("peer {}", i))),
+            )
+            .await;
+            peer.dial_multiaddr(node_multiaddr.clone()).unwrap();
+            peers.push(peer);
+        }
+        let (mut peer_a, mut peer_b) = (peers.remove(0), peers.remove(0));
+
+        // both peers observe the same address, but from the same subnet
+        let observed = async {
+            loop {
+                tokio::select! {
+                    event = node.next_event() => {
+                        if let Libp2pEvent::NewExternalAddr(address) = event {
+                            panic!("External address {} confirmed", address);
+                        }
+                    }
+                    _ = peer_a.next_event() => {}
+                    _ = peer_b.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = observed => {}
+            _ = sleep(Duration::from_secs(5)) => {}
+        }
+
+        assert_eq!(common::get_enr(&node).ip(), None);
+    })
+}
+
+// Tests that a batch of peers is sent a goodbye with the given reason.
+#[test]
+fn test_goodbye_peers() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut node = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "node")),
+        )
+        .await;
+        let node_peer_id = node.local_peer_id;
+        let node_multiaddr = common::get_enr(&node).multiaddr_p2p_tcp()[0].clone();
+
+        let mut peer_a =
+            common::build_libp2p_instance(Arc::downgrade(&rt), vec![], log.new(o!("who" => "a")))
+                .await;
+        let mut peer_b =
+            common::build_libp2p_instance(Arc::downgrade(&rt), vec![], log.new(o!("who" => "b")))
+                .await;
+        let peers = vec![
+            peer_a.local_peer_id,
+            peer_b.local_peer_id,
+            peer_a.local_peer_id,
+        ];
+        peer_a.dial_multiaddr(node_multiaddr.clone()).unwrap();
+        peer_b.dial_multiaddr(node_multiaddr).unwrap();
+
+        let node_future = async {
+            let mut connected = 0;
+            loop {
+                if let Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedIncoming(_)) =
+                    node.next_event().await
+                {
+                    connected += 1;
+                    if connected == 2 {
+                        node.goodbye_peers(&peers, GoodbyeReason::IrrelevantNetwork);
+                    }
+                }
+            }
+        };
+        let peers_future = async {
+            let mut goodbyes = Vec::new();
+            while goodbyes.len() < 2 {
+                let event = tokio::select! {
+                    event = peer_a.next_event() => event,
+                    event = peer_b.next_event() => event,
+                };
+                if let Libp2pEvent::Behaviour(BehaviourEvent::GoodbyeReceived { peer_id, reason }) =
+                    event
+                {
+                    assert_eq!(peer_id, node_peer_id);
+                    goodbyes.push(reason);
+                }
+            }
+            goodbyes
+        };
+
+        tokio::select! {
+            _ = node_future => {}
+            goodbyes = peers_future => {
+                assert_eq!(goodbyes, vec![GoodbyeReason::IrrelevantNetwork; 2]);
+            }
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that the dial stats count a successful and a failed dial.
+#[test]
+fn test_dial_stats() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut node = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "node")),
+        )
+        .await;
+        let mut peer = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "peer")),
+        )
+        .await;
+        assert_eq!(
+            node.swarm.behaviour().network_globals().dial_stats(),
+            DialStats::default()
+        );
+
+        node.dial_multiaddr(common::get_enr(&peer).multiaddr_p2p_tcp()[0].clone())
+            .unwrap();
+        // nothing listens on this port
+        let unreachable: Multiaddr = format!(
+            "/ip4/127.0.0.1/tcp/{}/p2p/{}",
+            unused_tcp_port().unwrap(),
+            PeerId::random()
+        )
+        .parse()
+        .unwrap();
+        node.dial_multiaddr(unreachable).unwrap();
+
+        let dials_finished = async {
+            loop {
+                let dial_stats = node.swarm.behaviour().network_globals().dial_stats();
+                if dial_stats.successes + dial_stats.failures == 2 {
+                    return dial_stats;
+                }
+                tokio::select! {
+                    _ = node.next_event() => {}
+                    _ = peer.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            dial_stats = dials_finished => {
+                assert_eq!(dial_stats.attempts, 2);
+                assert_eq!(dial_stats.successes, 1);
+                assert_eq!(dial_stats.failures, 1);
+                assert_eq!(dial_stats.failures_by_kind.get("transport"), Some(&1));
+            }
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that reloading the trusted peers file replaces the trusted peers with the ones it lists.
+#[test]
+fn test_reload_trusted_peers() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    let removed_peer = PeerId::random();
+    let kept_peer = PeerId::random();
+    let added_peer = PeerId::random();
+
+    let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+    let trusted_peers_file = config.network_dir.join("trusted_peers");
+    std::fs::write(
+        &trusted_peers_file,
+        format!("# trusted peers\n{}\n\n{}\n", removed_peer, kept_peer),
+    )
+    .unwrap();
+    config.trusted_peers_file = Some(trusted_peers_file.clone());
+
+    rt.block_on(async {
+        let mut node =
+            common::build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log).await;
+        let is_trusted = |node: &common::Libp2pInstance, peer_id: &PeerId| {
+            node.swarm
+                .behaviour()
+                .network_globals()
+                .peers
+                .read()
+                .peer_info(peer_id)
+                .map_or(false, |info| info.is_trusted())
+        };
+        assert!(is_trusted(&node, &removed_peer));
+        assert!(is_trusted(&node, &kept_peer));
+        assert!(!is_trusted(&node, &added_peer));
+
+        std::fs::write(
+            &trusted_peers_file,
+            format!("{}\n{}\n", kept_peer, added_peer),
+        )
+        .unwrap();
+        node.reload_trusted_peers().unwrap();
+
+        assert!(!is_trusted(&node, &removed_peer));
+        assert!(is_trusted(&node, &kept_peer));
+        assert!(is_trusted(&node, &added_peer));
+
+        // an unreadable file leaves the trusted peers unchanged
+        std::fs::remove_file(&trusted_peers_file).unwrap();
+        assert!(node.reload_trusted_peers().is_err());
+        assert!(is_trusted(&node, &added_peer));
+    })
+}
+
+// Tests that a gossip message forwarded by a relay is delivered with the relay as its propagation
+// source, and with the message id the relay reported its validation result for.
+#[test]
+fn test_forwarded_message_propagation_source() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        // without discovery, the publisher and the subscriber only learn of each other through the
+        // relay
+        let build_node = |who: &'static str| {
+            let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+            config.disable_discovery = true;
+            common::build_libp2p_instance_from_config(
+                Arc::downgrade(&rt),
+                config,
+                log.new(o!("who" => who)),
+            )
+        };
+        let mut publisher = build_node("publisher").await;
+        let mut relay = build_node("relay").await;
+        let mut subscriber = build_node("subscriber").await;
+
+        for node in [&mut relay, &mut subscriber] {
+            assert!(node
+                .swarm
+                .behaviour_mut()
+                .subscribe_kind(GossipKind::VoluntaryExit));
+        }
+        let relay_multiaddr = common::get_enr(&relay).multiaddr_p2p_tcp()[0].clone();
+        subscriber.dial_multiaddr(relay_multiaddr.clone()).unwrap();
+
+        // let the relay graft the subscriber into its mesh
+        let mesh_future = async {
+            loop {
+                tokio::select! {
+                    _ = relay.next_event() => {}
+                    _ = subscriber.next_event() => {}
+                }
+            }
+        };
+        tokio::select! {
+            _ = mesh_future => {}
+            _ = sleep(Duration::from_secs(2)) => {}
+        }
+
+        let exit = PubsubMessage::<E>::VoluntaryExit(Box::new(SignedVoluntaryExit {
+            message: VoluntaryExit {
+                epoch: Epoch::new(1),
+                validator_index: 2,
+            },
+            signature: Signature::empty(),
+        }));
+        // the message is published once the relay is connected
+        publisher.swarm.behaviour_mut().publish(vec![exit.clone()]);
+        publisher.dial_multiaddr(relay_multiaddr).unwrap();
+
+        let publisher_peer_id = publisher.local_peer_id;
+        let relay_peer_id = relay.local_peer_id;
+        let mut relayed_id = None;
+        let forward_future = async {
+            loop {
+                tokio::select! {
+                    _ = publisher.next_event() => {}
+                    event = relay.next_event() => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage {
+                            id,
+                            source,
+                            ..
+                        }) = event
+                        {
+                            assert_eq!(source, publisher_peer_id);
+                            relayed_id = Some(id.clone());
+                            relay.report_message_validation_result(
+                                &source,
+                                id,
+                                MessageAcceptance::Accept,
+                            );
+                        }
+                    }
+                    event = subscriber.next_event() => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage {
+                            id,
+                            source,
+                            message,
+                            ..
+                        }) = event
+                        {
+                            assert_eq!(message, exit);
+                            assert_eq!(source, relay_peer_id);
+                            assert_eq!(Some(id), relayed_id);
+                            return;
+                        }
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = forward_future => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a Lighthouse peer advertising a version older than the minimum peer version is
+// disconnected once identified.
+#[test]
+fn test_min_peer_protocol_version() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.min_peer_protocol_version = Some("v2.0.0".into());
+        let mut node = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "node")),
+        )
+        .await;
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.identify_agent_version = Some("Lighthouse/v1.5.0-1234abcd/x86_64-linux".into());
+        let mut peer = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "peer")),
+        )
+        .await;
+        let peer_id = peer.local_peer_id;
+
+        let node_multiaddr = common::get_enr(&node).multiaddr_p2p_tcp()[0].clone();
+        peer.dial_multiaddr(node_multiaddr).unwrap();
+
+        let disconnected = async {
+            loop {
+                tokio::select! {
+                    event = node.next_event() => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::PeerDisconnected(disconnected)) = event {
+                            assert_eq!(disconnected, peer_id);
+                            return;
+                        }
+                    }
+                    _ = peer.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = disconnected => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        assert_eq!(
+            node.swarm
+                .behaviour()
+                .network_globals()
+                .last_disconnect_reason(&peer_id),
+            Some(DisconnectReason::OutdatedClient)
+        );
+
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.min_peer_protocol_version = Some("latest".into());
+        assert!(
+            common::try_build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log)
+                .await
+                .is_err()
+        );
+    })
+}
+
+// Tests that resetting the network disconnects all peers, reconnects to the boot nodes and keeps
+// the gossipsub subscriptions.
+#[test]
+fn test_reset_network() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut boot_node = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "boot_node")),
+        )
+        .await;
+        let mut node = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![common::get_enr(&boot_node)],
+            log.new(o!("who" => "node")),
+        )
+        .await;
+        for node in [&mut boot_node, &mut node] {
+            assert!(node
+                .swarm
+                .behaviour_mut()
+                .subscribe_kind(GossipKind::VoluntaryExit));
+        }
+        let boot_node_peer_id = boot_node.local_peer_id;
+
+        let mut reset = false;
+        let mut disconnected = false;
+        let reconnected = async {
+            loop {
+                tokio::select! {
+                    event = node.next_event() => match event {
+                        Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                            assert_eq!(peer_id, boot_node_peer_id);
+                            if disconnected {
+                                return;
+                            }
+                            assert!(!reset);
+                            node.reset_network();
+                            reset = true;
+                        }
+                        Libp2pEvent::Behaviour(BehaviourEvent::PeerDisconnected(peer_id)) => {
+                            assert_eq!(peer_id, boot_node_peer_id);
+                            assert!(reset);
+                            disconnected = true;
+                        }
+                        _ => {}
+                    },
+                    _ = boot_node.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = reconnected => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+
+        // the node is still subscribed, so the boot node's message reaches it
+        let exit = PubsubMessage::<E>::VoluntaryExit(Box::new(SignedVoluntaryExit {
+            message: VoluntaryExit {
+                epoch: Epoch::new(1),
+                validator_index: 2,
+            },
+            signature: Signature::empty(),
+        }));
+        boot_node.swarm.behaviour_mut().publish(vec![exit.clone()]);
+
+        let received = async {
+            loop {
+                tokio::select! {
+                    event = node.next_event() => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::PubsubMessage { message, .. }) = event {
+                            assert_eq!(message, exit);
+                            return;
+                        }
+                    }
+                    _ = boot_node.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = received => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a node whose first boot node can't be reached falls back to the next one.
+#[test]
+fn test_boot_node_fallback() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        // the node of the unreachable boot node stops listening once dropped
+        let unreachable_enr = common::get_enr(
+            &common::build_libp2p_instance(
+                Arc::downgrade(&rt),
+                vec![],
+                log.new(o!("who" => "unreachable")),
+            )
+            .await,
+        );
+        let mut boot_node = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "boot_node")),
+        )
+        .await;
+        let boot_node_peer_id = boot_node.local_peer_id;
+
+        // a weight of 0 dials the reachable boot node last
+        let mut config = common::build_config(
+            unused_tcp_port().unwrap(),
+            vec![unreachable_enr, common::get_enr(&boot_node)],
+        );
+        config.boot_node_weights = vec![(boot_node_peer_id.to_string().parse().unwrap(), 0)];
+        let mut node = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "node")),
+        )
+        .await;
+
+        let connected = async {
+            loop {
+                tokio::select! {
+                    event = node.next_event() => {
+                        if let Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) = event {
+                            assert_eq!(peer_id, boot_node_peer_id);
+                            return;
+                        }
+                    }
+                    _ = boot_node.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = connected => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that a node whose first boot node can't be reached still falls back to the next one while a
+// peer that dialed it is connected.
+#[test]
+fn test_boot_node_fallback_with_inbound_peer() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        // the node of the unreachable boot node stops listening once dropped
+        let unreachable_enr = common::get_enr(
+            &common::build_libp2p_instance(
+                Arc::downgrade(&rt),
+                vec![],
+                log.new(o!("who" => "unreachable")),
+            )
+            .await,
+        );
+        let mut boot_node = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "boot_node")),
+        )
+        .await;
+        let boot_node_peer_id = boot_node.local_peer_id;
+
+        // a weight of 0 dials the reachable boot node last
+        let mut config = common::build_config(
+            unused_tcp_port().unwrap(),
+            vec![unreachable_enr, common::get_enr(&boot_node)],
+        );
+        config.boot_node_weights = vec![(boot_node_peer_id.to_string().parse().unwrap(), 0)];
+        let mut node = common::build_libp2p_instance_from_config(
+            Arc::downgrade(&rt),
+            config,
+            log.new(o!("who" => "node")),
+        )
+        .await;
+        let mut inbound_peer = common::build_libp2p_instance(
+            Arc::downgrade(&rt),
+            vec![],
+            log.new(o!("who" => "inbound_peer")),
+        )
+        .await;
+        inbound_peer
+            .dial_multiaddr(common::get_enr(&node).multiaddr_p2p_tcp()[0].clone())
+            .unwrap();
+
+        let connected = async {
+            let mut inbound_connected = false;
+            loop {
+                tokio::select! {
+                    event = node.next_event() => match event {
+                        Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedIncoming(_)) => {
+                            inbound_connected = true;
+                        }
+                        Libp2pEvent::Behaviour(BehaviourEvent::PeerConnectedOutgoing(peer_id)) => {
+                            assert!(inbound_connected);
+                            assert_eq!(peer_id, boot_node_peer_id);
+                            return;
+                        }
+                        _ => {}
+                    },
+                    _ = boot_node.next_event() => {}
+                    _ = inbound_peer.next_event() => {}
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = connected => {}
+            _ = sleep(Duration::from_secs(30)) => {
+                panic!("Future timed out");
+            }
+        }
+    })
+}
+
+// Tests that the topic filter in the network config blocks the subscriptions it does not permit,
+// both from the configured topics and when subscribing later.
+#[test]
+fn test_topic_filter_blocks_subscriptions() {
+    let log = common::build_log(Level::Debug, false);
+    let rt = Arc::new(Runtime::new().unwrap());
+
+    rt.block_on(async {
+        let mut config = common::build_config(unused_tcp_port().unwrap(), vec![]);
+        config.topics = vec![GossipKind::BeaconBlock, GossipKind::VoluntaryExit];
+        config.gossip_topic_filter = Some(TopicFilterConfig {
+            allow: None,
+            deny: vec![GossipKind::VoluntaryExit, GossipKind::ProposerSlashing],
+        });
+        let mut node =
+            common::build_libp2p_instance_from_config(Arc::downgrade(&rt), config, log).await;
+
+        assert!(!node
+            .swarm
+            .behaviour_mut()
+            .subscribe_kind(GossipKind::ProposerSlashing));
+        assert!(node
+            .swarm
+            .behaviour_mut()
+            .subscribe_kind(GossipKind::AttesterSlashing));
+
+        let subscribed_kinds = node
+            .swarm
+            .behaviour()
+            .network_globals()
+            .gossipsub_subscriptions
+            .read()
+            .iter()
+            .map(|topic| topic.kind().clone())
+            .collect::<Vec<_>>();
+        assert!(subscribed_kinds.contains(&GossipKind::BeaconBlock));
+        assert!(subscribed_kinds.contains(&GossipKind::AttesterSlashing));
+        assert!(!subscribed_kinds.contains(&GossipKind::VoluntaryExit));
+        assert!(!subscribed_kinds.contains(&GossipKind::ProposerSlashing));
+    })
+}
@@ -168,6 +168,12 @@ impl<T: BeaconChainTypes> Router<T> {
             Request::BlocksByRoot(request) => self
                 .processor
                 .on_blocks_by_root_request(peer_id, id, request),
+            Request::BlobsByRange(request) => self
+                .processor
+                .on_blobs_by_range_request(peer_id, id, request),
+            Request::LightClientBootstrap(request) => self
+                .processor
+                .on_light_client_bootstrap_request(peer_id, id, request),
         }
     }
 
@@ -192,6 +198,14 @@ impl<T: BeaconChainTypes> Router<T> {
                 self.processor
                     .on_blocks_by_root_response(peer_id, request_id, beacon_block);
             }
+            Response::BlobsByRange(blobs_sidecar) => {
+                self.processor
+                    .on_blobs_by_range_response(peer_id, request_id, blobs_sidecar);
+            }
+            Response::LightClientBootstrap(bootstrap) => {
+                self.processor
+                    .on_light_client_bootstrap_response(peer_id, request_id, bootstrap);
+            }
         }
     }
 
@@ -276,6 +290,15 @@ impl<T: BeaconChainTypes> Router<T> {
                     sync_committtee_msg.0,
                 );
             }
+            PubsubMessage::Application(application_msg) => {
+                // The beacon node does not subscribe to application topics.
+                debug!(
+                    self.log,
+                    "Ignoring application gossip message";
+                    "peer_id" => %peer_id,
+                    "topic" => application_msg.topic
+                );
+            }
         }
     }
 }
@@ -234,6 +234,77 @@ impl<T: BeaconChainTypes> Processor<T> {
         });
     }
 
+    /// Handle a `BlobsByRange` request from the peer.
+    ///
+    /// Blobs sidecars are not stored by the beacon chain, the request is answered with a server
+    /// error. Requesters score a `ResourceUnavailable` response as fatal, which does not fit a
+    /// responder that serves no blobs at all.
+    pub fn on_blobs_by_range_request(
+        &mut self,
+        peer_id: PeerId,
+        request_id: PeerRequestId,
+        req: BlobsByRangeRequest,
+    ) {
+        debug!(self.log, "Received BlobsByRange Request"; "peer_id" => %peer_id, "start_slot" => req.start_slot, "count" => req.count);
+        self.network.send_error_response(
+            peer_id,
+            request_id,
+            RPCResponseErrorCode::ServerError,
+            "Blobs sidecars are not available".into(),
+        )
+    }
+
+    /// Handle a `BlobsByRange` response from the peer.
+    ///
+    /// No `BlobsByRange` requests are sent by this node, the response is ignored.
+    pub fn on_blobs_by_range_response(
+        &mut self,
+        peer_id: PeerId,
+        _request_id: RequestId,
+        _blobs_sidecar: Option<Box<BlobsSidecar>>,
+    ) {
+        debug!(
+            self.log,
+            "Ignoring BlobsByRange Response";
+            "peer" => %peer_id,
+        );
+    }
+
+    /// Handle a `LightClientBootstrap` request from the peer.
+    ///
+    /// Light client bootstraps are not produced by the beacon chain, the request is answered with
+    /// an error.
+    pub fn on_light_client_bootstrap_request(
+        &mut self,
+        peer_id: PeerId,
+        request_id: PeerRequestId,
+        req: LightClientBootstrapRequest,
+    ) {
+        debug!(self.log, "Received LightClientBootstrap Request"; "peer_id" => %peer_id, "root" => ?req.root);
+        self.network.send_error_response(
+            peer_id,
+            request_id,
+            RPCResponseErrorCode::ResourceUnavailable,
+            "Light client bootstraps are not available".into(),
+        )
+    }
+
+    /// Handle a `LightClientBootstrap` response from the peer.
+    ///
+    /// No `LightClientBootstrap` requests are sent by this node, the response is ignored.
+    pub fn on_light_client_bootstrap_response(
+        &mut self,
+        peer_id: PeerId,
+        _request_id: RequestId,
+        _bootstrap: Box<LightClientBootstrap<T::EthSpec>>,
+    ) {
+        debug!(
+            self.log,
+            "Ignoring LightClientBootstrap Response";
+            "peer" => %peer_id,
+        );
+    }
+
     /// Process a gossip message declaring a new block.
     ///
     /// Attempts to apply to block to the beacon chain. May queue the block for later processing.
@@ -427,7 +498,7 @@ impl<T: EthSpec> HandlerNetworkContext<T> {
     }
 
     /// Sends an error response to the network task.
-    pub fn _send_error_response(
+    pub fn send_error_response(
         &mut self,
         peer_id: PeerId,
         id: PeerRequestId,
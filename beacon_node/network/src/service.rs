@@ -171,6 +171,26 @@ impl<T: BeaconChainTypes> NetworkService<T> {
     ) -> error::Result<(
         Arc<NetworkGlobals<T::EthSpec>>,
         mpsc::UnboundedSender<NetworkMessage<T::EthSpec>>,
+    )> {
+        let (network_service, network_globals, network_send) =
+            Self::build(beacon_chain, config, executor.clone(), gossipsub_registry).await?;
+
+        network_service.spawn_service(executor);
+
+        Ok((network_globals, network_send))
+    }
+
+    /// Builds the network service and its derived services, without spawning the network service.
+    #[allow(clippy::type_complexity)]
+    async fn build(
+        beacon_chain: Arc<BeaconChain<T>>,
+        config: &NetworkConfig,
+        executor: task_executor::TaskExecutor,
+        gossipsub_registry: Option<&'_ mut Registry>,
+    ) -> error::Result<(
+        NetworkService<T>,
+        Arc<NetworkGlobals<T::EthSpec>>,
+        mpsc::UnboundedSender<NetworkMessage<T::EthSpec>>,
     )> {
         let network_log = executor.log().clone();
         // build the network channel
@@ -245,7 +265,7 @@ impl<T: BeaconChainTypes> NetworkService<T> {
             beacon_chain.clone(),
             network_globals.clone(),
             network_send.clone(),
-            executor.clone(),
+            executor,
             network_log.clone(),
         )?;
 
@@ -263,7 +283,7 @@ impl<T: BeaconChainTypes> NetworkService<T> {
         // create a timer for updating gossipsub parameters
         let gossipsub_parameter_update = tokio::time::interval(Duration::from_secs(60));
 
-        // create the network service and spawn the task
+        // create the network service
         let network_log = network_log.new(o!("service" => "network"));
         let network_service = NetworkService {
             beacon_chain,
@@ -288,9 +308,7 @@ impl<T: BeaconChainTypes> NetworkService<T> {
             log: network_log,
         };
 
-        network_service.spawn_service(executor);
-
-        Ok((network_globals, network_send))
+        Ok((network_service, network_globals, network_send))
     }
 
     /// Returns the required fork digests that gossipsub needs to subscribe to based on the current slot.
@@ -338,11 +356,18 @@ impl<T: BeaconChainTypes> NetworkService<T> {
 
     fn spawn_service(mut self, executor: task_executor::TaskExecutor) {
         let mut shutdown_sender = executor.shutdown_sender();
+        let mut exit = executor.exit();
 
         // spawn on the current executor
         let service_fut = async move {
             loop {
                 tokio::select! {
+                    // say goodbye to our peers before the service is dropped
+                    _ = &mut exit => {
+                        self.libp2p.shutdown().await;
+                        break;
+                    }
+
                     _ = self.metrics_update.tick(), if self.metrics_enabled => {
                         // update various network metrics
                         metrics::update_gossip_metrics::<T::EthSpec>(
@@ -391,7 +416,8 @@ impl<T: BeaconChainTypes> NetworkService<T> {
                 metrics::update_bandwidth_metrics(self.libp2p.bandwidth.clone());
             }
         };
-        executor.spawn(service_fut, "network");
+        // the service handles the exit signal itself, to disconnect from its peers gracefully
+        executor.spawn_without_exit(service_fut, "network");
     }
 
     /// Handle an event received from the network.
@@ -407,7 +433,12 @@ impl<T: BeaconChainTypes> NetworkService<T> {
                 }
                 BehaviourEvent::PeerConnectedIncoming(_)
                 | BehaviourEvent::PeerBanned(_)
-                | BehaviourEvent::PeerUnbanned(_) => {
+                | BehaviourEvent::PeerUnbanned(_)
+                | BehaviourEvent::ForkDigestMismatch { .. }
+                | BehaviourEvent::GossipScoreThresholdCrossed { .. }
+                | BehaviourEvent::GoodbyeReceived { .. }
+                | BehaviourEvent::DiscoveredBannedPeer { .. }
+                | BehaviourEvent::ExternalAddrConfirmed(_) => {
                     // No action required for these events.
                 }
                 BehaviourEvent::PeerDisconnected(peer_id) => {
@@ -435,7 +466,7 @@ impl<T: BeaconChainTypes> NetworkService<T> {
                         response,
                     });
                 }
-                BehaviourEvent::RPCFailed { id, peer_id } => {
+                BehaviourEvent::RPCFailed { id, peer_id, .. } => {
                     self.send_to_router(RouterMessage::RPCFailed {
                         peer_id,
                         request_id: id,
@@ -476,11 +507,11 @@ impl<T: BeaconChainTypes> NetworkService<T> {
                     }
                 }
             },
-            Libp2pEvent::NewListenAddr(multiaddr) => {
-                self.network_globals
-                    .listen_multiaddrs
-                    .write()
-                    .push(multiaddr);
+            Libp2pEvent::NewListenAddr(_) => {
+                // The listen addresses of the network globals are kept by the libp2p service.
+            }
+            Libp2pEvent::NewExternalAddr(_) => {
+                // The ENR is updated by the libp2p service if configured to do so.
             }
             Libp2pEvent::ZeroListeners => {
                 let _ = shutdown_sender
@@ -571,14 +602,11 @@ impl<T: BeaconChainTypes> NetworkService<T> {
                     "message_id" => %message_id,
                     "validation_result" => ?validation_result
                 );
-                self.libp2p
-                    .swarm
-                    .behaviour_mut()
-                    .report_message_validation_result(
-                        &propagation_source,
-                        message_id,
-                        validation_result,
-                    );
+                self.libp2p.report_message_validation_result(
+                    &propagation_source,
+                    message_id,
+                    validation_result,
+                );
             }
             NetworkMessage::Publish { messages } => {
                 let mut topic_kinds = Vec::new();
@@ -647,7 +675,7 @@ impl<T: BeaconChainTypes> NetworkService<T> {
                             GossipEncoding::default(),
                             fork_digest,
                         );
-                        if self.libp2p.swarm.behaviour_mut().subscribe(topic.clone()) {
+                        if self.libp2p.subscribe(topic.clone()) {
                             subscribed_topics.push(topic);
                         } else {
                             warn!(self.log, "Could not subscribe to topic"; "topic" => %topic);
@@ -663,7 +691,7 @@ impl<T: BeaconChainTypes> NetworkService<T> {
                         self.libp2p.swarm.behaviour_mut().update_enr_subnet(subnet, true);
                         for fork_digest in self.required_gossip_fork_digests() {
                             let topic = GossipTopic::new(subnet.into(), GossipEncoding::default(), fork_digest);
-                            if self.libp2p.swarm.behaviour_mut().subscribe(topic.clone()) {
+                            if self.libp2p.subscribe(topic.clone()) {
                                 subscribed_topics.push(topic);
                             } else {
                                 warn!(self.log, "Could not subscribe to topic"; "topic" => %topic);
@@ -684,7 +712,7 @@ impl<T: BeaconChainTypes> NetworkService<T> {
                                 GossipEncoding::default(),
                                 fork_digest,
                             );
-                            if self.libp2p.swarm.behaviour_mut().subscribe(topic.clone()) {
+                            if self.libp2p.subscribe(topic.clone()) {
                                 subscribed_topics.push(topic);
                             } else {
                                 warn!(self.log, "Could not subscribe to topic"; "topic" => %topic);
@@ -752,14 +780,14 @@ impl<T: BeaconChainTypes> NetworkService<T> {
                 for fork_digest in self.required_gossip_fork_digests() {
                     let topic =
                         GossipTopic::new(subnet.into(), GossipEncoding::default(), fork_digest);
-                    self.libp2p.swarm.behaviour_mut().subscribe(topic);
+                    self.libp2p.subscribe(topic);
                 }
             }
             SubnetServiceMessage::Unsubscribe(subnet) => {
                 for fork_digest in self.required_gossip_fork_digests() {
                     let topic =
                         GossipTopic::new(subnet.into(), GossipEncoding::default(), fork_digest);
-                    self.libp2p.swarm.behaviour_mut().unsubscribe(topic);
+                    self.libp2p.unsubscribe(topic);
                 }
             }
             SubnetServiceMessage::EnrAdd(subnet) => {
@@ -789,14 +817,14 @@ impl<T: BeaconChainTypes> NetworkService<T> {
                 for fork_digest in self.required_gossip_fork_digests() {
                     let topic =
                         GossipTopic::new(subnet.into(), GossipEncoding::default(), fork_digest);
-                    self.libp2p.swarm.behaviour_mut().subscribe(topic);
+                    self.libp2p.subscribe(topic);
                 }
             }
             SubnetServiceMessage::Unsubscribe(subnet) => {
                 for fork_digest in self.required_gossip_fork_digests() {
                     let topic =
                         GossipTopic::new(subnet.into(), GossipEncoding::default(), fork_digest);
-                    self.libp2p.swarm.behaviour_mut().unsubscribe(topic);
+                    self.libp2p.unsubscribe(topic);
                 }
             }
             SubnetServiceMessage::EnrAdd(subnet) => {
@@ -833,10 +861,8 @@ impl<T: BeaconChainTypes> NetworkService<T> {
             );
             fork_context.update_current_fork(*new_fork_name);
 
-            self.libp2p
-                .swarm
-                .behaviour_mut()
-                .update_fork_version(new_enr_fork_id);
+            // old fork topics are kept until `next_unsubscribe`
+            self.libp2p.update_fork_version(new_enr_fork_id, false);
             // Reinitialize the next_fork_update
             self.next_fork_update = Box::pin(next_fork_delay(&self.beacon_chain).into());
 
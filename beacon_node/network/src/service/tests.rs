@@ -2,15 +2,18 @@
 #[cfg(test)]
 mod tests {
     use crate::persisted_dht::load_dht;
+    use crate::subnet_service::SubnetServiceMessage;
     use crate::{NetworkConfig, NetworkService};
     use beacon_chain::test_utils::BeaconChainHarness;
-    use lighthouse_network::Enr;
+    use lighthouse_network::types::GossipKind;
+    use lighthouse_network::{Enr, Subnet, SubscriptionChangeLimit};
     use slog::{o, Drain, Level, Logger};
     use sloggers::{null::NullLoggerBuilder, Build};
     use std::str::FromStr;
     use std::sync::Arc;
+    use std::time::Duration;
     use tokio::runtime::Runtime;
-    use types::MinimalEthSpec;
+    use types::{MinimalEthSpec, SubnetId};
 
     fn get_logger(actual_log: bool) -> Logger {
         if actual_log {
@@ -89,4 +92,70 @@ mod tests {
             "should have persisted the second ENR to store"
         );
     }
+
+    // Tests that the subnet subscription changes requested by the attestation service are subject
+    // to the subscription change limit of the libp2p service.
+    #[test]
+    fn test_subnet_subscription_changes_are_rate_limited() {
+        let log = get_logger(false);
+
+        let beacon_chain = BeaconChainHarness::builder(MinimalEthSpec)
+            .default_spec()
+            .deterministic_keypairs(8)
+            .fresh_ephemeral_store()
+            .build()
+            .chain;
+
+        let runtime = Arc::new(Runtime::new().unwrap());
+
+        let (signal, exit) = exit_future::signal();
+        let (shutdown_tx, _) = futures::channel::mpsc::channel(1);
+        let executor = task_executor::TaskExecutor::new(
+            Arc::downgrade(&runtime),
+            exit,
+            log.clone(),
+            shutdown_tx,
+        );
+
+        let mut config = NetworkConfig::default();
+        config.libp2p_port = 21213;
+        config.upnp_enabled = false;
+        config.discovery_port = 21213;
+        config.disable_discovery = true;
+        config.subscription_change_limit = Some(SubscriptionChangeLimit {
+            max_changes: 1,
+            window: Duration::from_secs(60),
+        });
+        runtime.block_on(async move {
+            let (mut network_service, network_globals, _network_send) =
+                NetworkService::build(beacon_chain, &config, executor, None)
+                    .await
+                    .unwrap();
+            let subnet_id = SubnetId::new(1);
+            let subnet_topics = || {
+                network_globals
+                    .gossipsub_subscriptions
+                    .read()
+                    .iter()
+                    .filter(|topic| topic.kind() == &GossipKind::Attestation(subnet_id))
+                    .count()
+            };
+
+            network_service.on_attestation_service_msg(SubnetServiceMessage::Subscribe(
+                Subnet::Attestation(subnet_id),
+            ));
+            let subscribed_topics = subnet_topics();
+            assert_eq!(
+                subscribed_topics,
+                network_service.required_gossip_fork_digests().len()
+            );
+
+            // the unsubscribe exceeds the limit of one change per minute and is deferred
+            network_service.on_attestation_service_msg(SubnetServiceMessage::Unsubscribe(
+                Subnet::Attestation(subnet_id),
+            ));
+            assert_eq!(subnet_topics(), subscribed_topics);
+            drop(signal);
+        });
+    }
 }